@@ -12,11 +12,13 @@ fn main() -> Result<()> {
         .type_attribute(".", "#[derive(serde::Serialize)]")
         .type_attribute("meshtastic.CrisislabMessage.MeshSettings", "#[derive(serde::Deserialize)] #[serde(deny_unknown_fields)]")
         .out_dir(out_dir)
+        // crisislab.proto lives here rather than in the protobufs submodule, which only has the
+        // upstream Meshtastic definitions it imports
         .compile_protos(
             &[
-                "../protobufs/meshtastic/crisislab.proto",
+                "proto/meshtastic/crisislab.proto",
             ],
-            &["../protobufs"],
+            &["proto", "../protobufs"],
         )?;
 
     Ok(())
@@ -57,6 +57,11 @@ const randomLiveData = (HardwareModel, LocSource, AltSource) => {
 			channel_utilization: parseFloat((Math.random() * 5 + 2).toFixed(2)),
 			air_util_tx: parseFloat(Math.random().toFixed(3)),
 			uptime_seconds: Math.floor(Date.now() / 1000) - startupTime,
+		},
+		environment_metrics: {
+			temperature: parseFloat((Math.random() * 15 + 5).toFixed(2)), // in °C
+			relative_humidity: parseFloat((Math.random() * 50 + 40).toFixed(2)), // in %
+			barometric_pressure: parseFloat((Math.random() * 30 + 995).toFixed(2)), // in hPa
 		}
 	}
 }
@@ -5971,9 +5971,12 @@ impl ExcludedModules {
 #[derive(serde::Serialize)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CrisislabMessage {
+    /// only the node with this ID should act on the message, every node does if it isn't set
+    #[prost(uint32, optional, tag = "12")]
+    pub destination: ::core::option::Option<u32>,
     #[prost(
         oneof = "crisislab_message::Message",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31"
     )]
     pub message: ::core::option::Option<crisislab_message::Message>,
 }
@@ -6051,6 +6054,321 @@ pub mod crisislab_message {
         pub position: ::core::option::Option<super::Position>,
         #[prost(message, optional, tag = "5")]
         pub device_metrics: ::core::option::Option<super::DeviceMetrics>,
+        /// only present on nodes with environmental sensors (e.g. BME280)
+        #[prost(message, optional, tag = "6")]
+        pub environment_metrics: ::core::option::Option<super::EnvironmentMetrics>,
+        /// e.g. "2.5.6"
+        #[prost(string, optional, tag = "7")]
+        pub firmware_version: ::core::option::Option<::prost::alloc::string::String>,
+        /// e.g. the commit the firmware was built from
+        #[prost(string, optional, tag = "8")]
+        pub firmware_build: ::core::option::Option<::prost::alloc::string::String>,
+    }
+    /// Sent by a node once it's received a command addressed to it (with `destination`)
+    #[derive(serde::Serialize)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct CommandAck {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        /// the tag of the `message` being acknowledged, e.g. 13 for `reboot`
+        #[prost(uint32, tag = "2")]
+        pub message_tag: u32,
+    }
+    /// Tells the node in `destination` to update its firmware. Only the gateway in `via_gateway`
+    /// relays it, since it's the one closest to the node.
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct FirmwareUpdate {
+        /// e.g. "2.5.6"
+        #[prost(string, tag = "1")]
+        pub version: ::prost::alloc::string::String,
+        /// where the firmware can be downloaded from, if it isn't the default
+        #[prost(string, optional, tag = "2")]
+        pub url: ::core::option::Option<::prost::alloc::string::String>,
+        #[prost(uint32, tag = "3")]
+        pub via_gateway: u32,
+    }
+    /// Sent by a node while it updates its firmware
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct FirmwareUpdateProgress {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        /// 0 to 100
+        #[prost(uint32, tag = "2")]
+        pub percent: u32,
+        /// set once the new firmware is installed and running
+        #[prost(bool, tag = "3")]
+        pub done: bool,
+        /// set if the update failed, in which case the node keeps its old firmware
+        #[prost(string, optional, tag = "4")]
+        pub error: ::core::option::Option<::prost::alloc::string::String>,
+    }
+    /// Sent by every node which hears a `discovery_request`
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DiscoveryResponse {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        /// e.g. "RAK4631"
+        #[prost(string, optional, tag = "2")]
+        pub hardware_model: ::core::option::Option<::prost::alloc::string::String>,
+        /// e.g. "2.5.6"
+        #[prost(string, optional, tag = "3")]
+        pub firmware_version: ::core::option::Option<::prost::alloc::string::String>,
+    }
+    /// Sent by a node in response to `get_capabilities`, or when it starts up
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Capabilities {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        /// e.g. "seismometer" or "bme280"
+        #[prost(string, repeated, tag = "2")]
+        pub sensors: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        /// e.g. "relay_output" or "siren"
+        #[prost(string, repeated, tag = "3")]
+        pub modules: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    }
+    #[derive(serde::Serialize)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct TracerouteHop {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        /// the SNR this hop received the response at, which isn't set for the node which sent it
+        #[prost(float, optional, tag = "2")]
+        pub snr: ::core::option::Option<f32>,
+    }
+    /// Sent by a node in response to `traceroute`. Each node which relays it towards a gateway adds
+    /// itself to `hops`, so it ends with the gateway which published it.
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TracerouteResponse {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        /// starts with the node which sent it
+        #[prost(message, repeated, tag = "2")]
+        pub hops: ::prost::alloc::vec::Vec<TracerouteHop>,
+    }
+    #[derive(serde::Serialize)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct EchoRequest {
+        /// copied into the reply so that it can be matched up with the request
+        #[prost(uint32, tag = "1")]
+        pub id: u32,
+    }
+    /// Sent by a node in response to `echo_request`
+    #[derive(serde::Serialize)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct EchoReply {
+        #[prost(uint32, tag = "1")]
+        pub node_num: u32,
+        #[prost(uint32, tag = "2")]
+        pub id: u32,
+        /// incremented by each node which relays the reply
+        #[prost(uint32, tag = "3")]
+        pub hop_count: u32,
+        /// set by the gateway which publishes the reply
+        #[prost(uint32, tag = "4")]
+        pub via_gateway: u32,
+    }
+    /// Shown on the displays of (or sounded on the buzzers of) the nodes which receive it
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TextMessage {
+        #[prost(string, tag = "1")]
+        pub text: ::prost::alloc::string::String,
+        /// included in delivery reports so that they can be matched up with the message
+        #[prost(uint32, tag = "2")]
+        pub id: u32,
+    }
+    /// A high priority warning which nodes show (and sound) until it expires or is cancelled. It's
+    /// published again every so often while it's active, for nodes which missed it.
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct EmergencyAlert {
+        /// the same for every time the alert is published, and included in delivery reports
+        #[prost(uint32, tag = "1")]
+        pub id: u32,
+        #[prost(enumeration = "emergency_alert::Severity", tag = "2")]
+        pub severity: i32,
+        #[prost(string, tag = "3")]
+        pub text: ::prost::alloc::string::String,
+        /// seconds since unix epoch
+        #[prost(uint64, tag = "4")]
+        pub expires_at: u64,
+        /// set when the alert is cancelled, so that nodes stop showing it
+        #[prost(bool, tag = "5")]
+        pub cancelled: bool,
+        /// set for exercises, so that nodes can show that it isn't a real warning
+        #[prost(bool, tag = "6")]
+        pub drill: bool,
+        /// the language of `text`, e.g. "en" or "mi", if the alert was given one. Alerts with
+        /// translations are published once for each language, and a node which has been sent a copy
+        /// addressed to it (in its preferred language) should ignore the other copies.
+        #[prost(string, tag = "7")]
+        pub language: ::prost::alloc::string::String,
+    }
+    /// Nested message and enum types in `EmergencyAlert`.
+    pub mod emergency_alert {
+        #[derive(serde::Serialize)]
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            ::prost::Enumeration
+        )]
+        #[repr(i32)]
+        pub enum Severity {
+            Info = 0,
+            Warning = 1,
+            Critical = 2,
+        }
+        impl Severity {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    Self::Info => "INFO",
+                    Self::Warning => "WARNING",
+                    Self::Critical => "CRITICAL",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "INFO" => Some(Self::Info),
+                    "WARNING" => Some(Self::Warning),
+                    "CRITICAL" => Some(Self::Critical),
+                    _ => None,
+                }
+            }
+        }
+    }
+    /// Sent by a node when it receives an `emergency_alert`, and again when someone at the node
+    /// acknowledges it
+    #[derive(serde::Serialize)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct AlertAck {
+        #[prost(uint32, tag = "1")]
+        pub alert_id: u32,
+        #[prost(uint32, tag = "2")]
+        pub node_num: u32,
+        #[prost(enumeration = "alert_ack::Kind", tag = "3")]
+        pub kind: i32,
+    }
+    /// Nested message and enum types in `AlertAck`.
+    pub mod alert_ack {
+        #[derive(serde::Serialize)]
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            ::prost::Enumeration
+        )]
+        #[repr(i32)]
+        pub enum Kind {
+            /// sent automatically once the alert is shown
+            Received = 0,
+            /// someone pressed the node's button
+            Acknowledged = 1,
+        }
+        impl Kind {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    Self::Received => "RECEIVED",
+                    Self::Acknowledged => "ACKNOWLEDGED",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "RECEIVED" => Some(Self::Received),
+                    "ACKNOWLEDGED" => Some(Self::Acknowledged),
+                    _ => None,
+                }
+            }
+        }
+    }
+    /// Tells the node in `destination` to start or stop the siren or relay it drives. The node
+    /// replies with a `command_ack`.
+    #[derive(serde::Serialize)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct Actuate {
+        #[prost(enumeration = "actuate::Action", tag = "1")]
+        pub action: i32,
+        /// how long to keep the actuator on for, ignored when stopping it
+        #[prost(uint32, tag = "2")]
+        pub duration_seconds: u32,
+        /// set for exercises, e.g. so that a siren can use a test tone
+        #[prost(bool, tag = "3")]
+        pub drill: bool,
+    }
+    /// Nested message and enum types in `Actuate`.
+    pub mod actuate {
+        #[derive(serde::Serialize)]
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            ::prost::Enumeration
+        )]
+        #[repr(i32)]
+        pub enum Action {
+            /// the default, so that a message missing its action can't set anything off
+            Stop = 0,
+            Start = 1,
+        }
+        impl Action {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    Self::Stop => "STOP",
+                    Self::Start => "START",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "STOP" => Some(Self::Stop),
+                    "START" => Some(Self::Start),
+                    _ => None,
+                }
+            }
+        }
+    }
+    /// Sent by a gateway once nodes have acknowledged receiving a `text_message`
+    #[derive(serde::Serialize)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DeliveryReport {
+        #[prost(uint32, tag = "1")]
+        pub message_id: u32,
+        #[prost(uint32, repeated, tag = "2")]
+        pub node_nums: ::prost::alloc::vec::Vec<u32>,
     }
     #[derive(serde::Serialize)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
@@ -6077,5 +6395,109 @@ pub mod crisislab_message {
         Telemetry(Telemetry),
         #[prost(uint32, tag = "11")]
         GetAdHocTelemetry(u32),
+        /// the node in `destination` acknowledges this and then restarts
+        #[prost(message, tag = "13")]
+        Reboot(Empty),
+        /// the node in `destination` acknowledges this and then powers off until it's restarted on
+        /// site
+        #[prost(message, tag = "14")]
+        Shutdown(Empty),
+        #[prost(message, tag = "15")]
+        CommandAck(CommandAck),
+        #[prost(message, tag = "16")]
+        FirmwareUpdate(FirmwareUpdate),
+        #[prost(message, tag = "17")]
+        FirmwareUpdateProgress(FirmwareUpdateProgress),
+        /// sets the fixed position of the node in `destination`, like Meshtastic's
+        /// `set_fixed_position` admin message
+        #[prost(message, tag = "18")]
+        SetPosition(super::Position),
+        /// every node which hears this replies with a `discovery_response`
+        #[prost(message, tag = "19")]
+        DiscoveryRequest(Empty),
+        #[prost(message, tag = "20")]
+        DiscoveryResponse(DiscoveryResponse),
+        /// the node in `destination` replies with its `capabilities`
+        #[prost(message, tag = "21")]
+        GetCapabilities(Empty),
+        #[prost(message, tag = "22")]
+        Capabilities(Capabilities),
+        /// the node in `destination` replies with a `traceroute_response`
+        #[prost(message, tag = "23")]
+        Traceroute(Empty),
+        #[prost(message, tag = "24")]
+        TracerouteResponse(TracerouteResponse),
+        /// the node in `destination` replies with an `echo_reply`
+        #[prost(message, tag = "25")]
+        EchoRequest(EchoRequest),
+        #[prost(message, tag = "26")]
+        EchoReply(EchoReply),
+        #[prost(message, tag = "27")]
+        TextMessage(TextMessage),
+        #[prost(message, tag = "28")]
+        DeliveryReport(DeliveryReport),
+        #[prost(message, tag = "29")]
+        EmergencyAlert(EmergencyAlert),
+        #[prost(message, tag = "30")]
+        AlertAck(AlertAck),
+        #[prost(message, tag = "31")]
+        Actuate(Actuate),
     }
 }
+/// A CrisislabMessage sent by the server, signed with a key shared with the gateways so that they
+/// can reject commands from anyone else with access to the MQTT broker
+#[derive(serde::Serialize)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SignedCrisislabMessage {
+    /// the encoded CrisislabMessage
+    #[prost(bytes = "vec", tag = "1")]
+    pub message: ::prost::alloc::vec::Vec<u8>,
+    /// which of the mesh's keys the message was signed with
+    #[prost(uint32, tag = "2")]
+    pub key_id: u32,
+    /// HMAC-SHA256 of `counter` and `timestamp` (each as 8 big-endian bytes) followed by `message`
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+    /// goes up by one with every command, so gateways should reject any command whose counter
+    /// isn't higher than the last one they accepted
+    #[prost(uint64, tag = "4")]
+    pub counter: u64,
+    /// seconds since unix epoch that the command was sent
+    #[prost(uint64, tag = "5")]
+    pub timestamp: u64,
+}
+/// A CrisislabMessage encrypted with AES-256-GCM using a key shared with the gateways, so that
+/// other users of a shared MQTT broker can't read it
+#[derive(serde::Serialize)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EncryptedCrisislabMessage {
+    /// which of the mesh's keys the message was encrypted with
+    #[prost(uint32, tag = "1")]
+    pub key_id: u32,
+    /// 12 random bytes, never reused with the same key
+    #[prost(bytes = "vec", tag = "2")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+    /// the encrypted CrisislabMessage (or SignedCrisislabMessage), followed by the GCM tag
+    #[prost(bytes = "vec", tag = "3")]
+    pub ciphertext: ::prost::alloc::vec::Vec<u8>,
+}
+/// A batch of accelerometer samples from a sensor node, published on its own MQTT topic because of
+/// how much data it carries
+#[derive(serde::Serialize)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SeismicChunk {
+    #[prost(uint32, tag = "1")]
+    pub node_num: u32,
+    /// milliseconds since unix epoch of the first sample
+    #[prost(uint64, tag = "2")]
+    pub start_timestamp_millis: u64,
+    #[prost(float, tag = "3")]
+    pub sample_rate_hz: f32,
+    /// acceleration in m/s^2, all three axes have the same number of samples
+    #[prost(float, repeated, tag = "4")]
+    pub x: ::prost::alloc::vec::Vec<f32>,
+    #[prost(float, repeated, tag = "5")]
+    pub y: ::prost::alloc::vec::Vec<f32>,
+    #[prost(float, repeated, tag = "6")]
+    pub z: ::prost::alloc::vec::Vec<f32>,
+}
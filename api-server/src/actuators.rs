@@ -0,0 +1,259 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{self, AuthedUser, Role},
+    command_history::{self, CommandOutcome},
+    config::CONFIG,
+    drill, nodes,
+    pathfinding::NodeId,
+    proto::meshtastic::{
+        crisislab_message::{self, actuate::Action},
+        CrisislabMessage,
+    },
+    utils::{
+        await_mesh_response, send_command_protobuf, unix_time_seconds, FallibleJsonResponse,
+        JsonBody,
+    },
+    AppState,
+};
+
+/// The `CrisislabMessage.message` tag which nodes send back in a `CommandAck`
+const ACTUATE_TAG: u32 = 31;
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ActuatorAction {
+    Start,
+    Stop,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ActuateBody {
+    action: ActuatorAction,
+    /// required when starting, up to `ACTUATION_MAX_DURATION_SECONDS`
+    #[serde(default)]
+    duration_seconds: u32,
+    /// whether this is an exercise rather than a real warning, which is always the case while
+    /// drill mode is on
+    #[serde(default)]
+    drill: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ActuateTaggedBody {
+    tag: String,
+    action: ActuatorAction,
+    #[serde(default)]
+    duration_seconds: u32,
+    #[serde(default)]
+    drill: bool,
+}
+
+#[derive(Serialize)]
+pub struct ActuationResult {
+    drill: bool,
+    /// nodes which acknowledged the command
+    confirmed: BTreeSet<NodeId>,
+    /// nodes which didn't acknowledge it before the timeout, or which it couldn't be published to
+    unconfirmed: BTreeSet<NodeId>,
+}
+
+/// Checks the request makes sense and that the user is allowed to make it. Only commanders can set
+/// off sirens for real, since a false alarm can cause as much harm as a missed one, but anyone
+/// can stop them.
+fn check_actuation(user: &AuthedUser, body: &ActuateBody) -> Result<(), (StatusCode, String)> {
+    if body.action == ActuatorAction::Stop {
+        return Ok(());
+    }
+
+    if body.duration_seconds == 0 || body.duration_seconds > CONFIG.actuation_max_duration_seconds {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "duration_seconds must be between 1 and {}",
+                CONFIG.actuation_max_duration_seconds
+            ),
+        ));
+    }
+
+    if !body.drill && user.role < Role::Commander && !auth::is_open_to_anyone(Role::Commander) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Only commanders can start actuators outside of a drill".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Publishes the command to each node and waits (up to `command_ack_timeout_seconds`) for them to
+/// acknowledge it. Actuations aren't queued for offline nodes, since a siren going off hours late
+/// would be worse than it not going off at all.
+async fn actuate_nodes(
+    state: &AppState,
+    user: &AuthedUser,
+    node_ids: Vec<NodeId>,
+    body: &ActuateBody,
+) -> ActuationResult {
+    let action = match body.action {
+        ActuatorAction::Start => Action::Start,
+        ActuatorAction::Stop => Action::Stop,
+    };
+
+    info!(
+        "{} sent actuator action {:?} ({} seconds, drill: {}) to nodes {:?}",
+        user, body.action, body.duration_seconds, body.drill, node_ids
+    );
+
+    // subscribe before sending the commands so that a quick acknowledgement can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+    let sent_at = unix_time_seconds();
+
+    let mut unconfirmed = BTreeSet::new();
+    let mut published = Vec::new();
+
+    for node_id in node_ids {
+        let crisislab_message = CrisislabMessage {
+            message: Some(crisislab_message::Message::Actuate(
+                crisislab_message::Actuate {
+                    action: action as i32,
+                    duration_seconds: body.duration_seconds,
+                    drill: body.drill,
+                },
+            )),
+            destination: Some(node_id),
+        };
+
+        match send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await {
+            Ok(()) => published.push((node_id, crisislab_message)),
+            Err(error_message) => {
+                unconfirmed.insert(node_id);
+
+                command_history::record(
+                    state,
+                    node_id,
+                    &crisislab_message,
+                    &user.name,
+                    sent_at,
+                    CommandOutcome::Failed,
+                    Some(error_message),
+                )
+                .await;
+            }
+        }
+    }
+
+    let mut confirmed = BTreeSet::new();
+
+    // there's nothing to wait for if every publish failed
+    if !published.is_empty() {
+        let timeout_duration =
+            Duration::from_secs(state.app_settings.lock().await.command_ack_timeout_seconds);
+
+        // whichever nodes haven't acknowledged it by the timeout are reported as unconfirmed
+        // rather than failing the whole request
+        let _ = await_mesh_response(&mut mesh_receiver, timeout_duration, |message| {
+            if let Some(crisislab_message::Message::CommandAck(ack)) = message.message {
+                if ack.message_tag == ACTUATE_TAG
+                    && published
+                        .iter()
+                        .any(|(node_id, _)| *node_id == ack.node_num)
+                {
+                    confirmed.insert(ack.node_num);
+                }
+            }
+
+            (confirmed.len() == published.len()).then_some(())
+        })
+        .await;
+    }
+
+    for (node_id, crisislab_message) in &published {
+        let outcome = if confirmed.contains(node_id) {
+            CommandOutcome::Responded
+        } else {
+            warn!("Node {} didn't confirm the actuation", node_id);
+            unconfirmed.insert(*node_id);
+
+            CommandOutcome::TimedOut
+        };
+
+        command_history::record(
+            state,
+            *node_id,
+            crisislab_message,
+            &user.name,
+            sent_at,
+            outcome,
+            None,
+        )
+        .await;
+    }
+
+    ActuationResult {
+        drill: body.drill,
+        confirmed,
+        unconfirmed,
+    }
+}
+
+/// /admin/nodes/{id}/actuate
+pub async fn actuate_node(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(mut body): JsonBody<ActuateBody>,
+) -> FallibleJsonResponse<ActuationResult> {
+    body.drill |= drill::is_on(&state).await;
+
+    if let Err((status_code, error_message)) = check_actuation(&user, &body) {
+        return FallibleJsonResponse::Err(status_code, error_message);
+    }
+
+    let result = actuate_nodes(&state, &user, vec![node_id], &body).await;
+
+    if result.confirmed.contains(&node_id) {
+        FallibleJsonResponse::Ok(result)
+    } else {
+        FallibleJsonResponse::Err(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("Node {} didn't confirm the actuation", node_id),
+        )
+        .log()
+    }
+}
+
+/// /admin/actuate
+pub async fn actuate_tagged_nodes(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<ActuateTaggedBody>,
+) -> FallibleJsonResponse<ActuationResult> {
+    let actuation = ActuateBody {
+        action: body.action,
+        duration_seconds: body.duration_seconds,
+        drill: body.drill || drill::is_on(&state).await,
+    };
+
+    if let Err((status_code, error_message)) = check_actuation(&user, &actuation) {
+        return FallibleJsonResponse::Err(status_code, error_message);
+    }
+
+    let node_ids = match nodes::nodes_with_tag(&state, &body.tag).await {
+        Ok(node_ids) => node_ids,
+        Err(error_message) => {
+            return FallibleJsonResponse::Err(StatusCode::NOT_FOUND, error_message)
+        }
+    };
+
+    FallibleJsonResponse::Ok(actuate_nodes(&state, &user, node_ids, &actuation).await)
+}
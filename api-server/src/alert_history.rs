@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alerts::{self, AlertEvent, AlertRuleId, AlertSeverity, AlertState},
+    auth::AuthedUser,
+    config::CONFIG,
+    emergency_alerts::{EmergencyAlert, EmergencyAlertId},
+    pathfinding::NodeId,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody},
+    AppState,
+};
+
+pub type AlertRecordId = u32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStage {
+    Fired,
+    /// by an operator, or for emergency alerts, the first node whose button was pressed
+    Acknowledged,
+    Escalated,
+    Resolved,
+    Cancelled,
+    Expired,
+}
+
+impl AlertStage {
+    /// Whether the alert is over once it reaches this stage
+    fn ends(self) -> bool {
+        matches!(
+            self,
+            AlertStage::Resolved | AlertStage::Cancelled | AlertStage::Expired
+        )
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LifecycleEntry {
+    pub stage: AlertStage,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+    /// the user, or the node for acknowledgements of emergency alerts
+    pub by: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case", tag = "source")]
+pub enum AlertSource {
+    /// an alert rule's condition started holding for a node
+    Rule {
+        rule_id: AlertRuleId,
+        rule_name: Option<String>,
+        condition: String,
+        node_id: NodeId,
+    },
+    /// an emergency alert broadcast to every node
+    Emergency {
+        alert_id: EmergencyAlertId,
+        text: String,
+        drill: bool,
+    },
+}
+
+/// An alert from when it fired until it ended, for looking back on after the fact
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AlertRecord {
+    pub id: AlertRecordId,
+    #[serde(flatten)]
+    pub source: AlertSource,
+    /// raised a level each time the alert is escalated
+    pub severity: AlertSeverity,
+    /// seconds since unix epoch
+    pub fired_at: u64,
+    /// seconds since unix epoch, once it's been resolved, cancelled or has expired
+    pub ended_at: Option<u64>,
+    /// whether it fired during a maintenance window, so wasn't sent on
+    pub muted: bool,
+    /// every stage it's been through, oldest first, starting with `fired`
+    pub lifecycle: Vec<LifecycleEntry>,
+}
+
+impl AlertRecord {
+    fn has_reached(&self, stage: AlertStage) -> bool {
+        self.lifecycle.iter().any(|entry| entry.stage == stage)
+    }
+
+    fn advance(&mut self, stage: AlertStage, by: Option<String>, note: Option<String>, now: u64) {
+        if stage.ends() {
+            self.ended_at = Some(now);
+        }
+
+        self.lifecycle.push(LifecycleEntry {
+            stage,
+            timestamp: now,
+            by,
+            note,
+        });
+    }
+}
+
+/// The most recent `ALERT_HISTORY_CAPACITY` alerts, oldest first. They're saved to
+/// `alert-history.json` in the data directory whenever one changes.
+#[derive(Default)]
+pub struct AlertHistory {
+    records: VecDeque<AlertRecord>,
+}
+
+impl AlertHistory {
+    pub fn restore(&mut self, records: Vec<AlertRecord>) {
+        self.records.clear();
+
+        for record in records {
+            self.push(record);
+        }
+    }
+
+    pub fn records(&self) -> impl DoubleEndedIterator<Item = &AlertRecord> {
+        self.records.iter()
+    }
+
+    fn next_id(&self) -> AlertRecordId {
+        self.records.back().map_or(1, |record| record.id + 1)
+    }
+
+    fn push(&mut self, record: AlertRecord) {
+        self.records.push_back(record);
+
+        while self.records.len() > CONFIG.alert_history_capacity {
+            self.records.pop_front();
+        }
+    }
+
+    fn fire(&mut self, source: AlertSource, severity: AlertSeverity, muted: bool, now: u64) {
+        let mut record = AlertRecord {
+            id: self.next_id(),
+            source,
+            severity,
+            fired_at: now,
+            ended_at: None,
+            muted,
+            lifecycle: Vec::new(),
+        };
+
+        record.advance(AlertStage::Fired, None, None, now);
+
+        self.push(record);
+    }
+
+    fn get_mut(&mut self, id: AlertRecordId) -> Option<&mut AlertRecord> {
+        self.records.iter_mut().find(|record| record.id == id)
+    }
+
+    /// The records for a rule which haven't been resolved yet, for every node or just one
+    fn open_rule_records_mut(
+        &mut self,
+        rule_id: AlertRuleId,
+        node_id: Option<NodeId>,
+    ) -> impl Iterator<Item = &mut AlertRecord> {
+        self.records.iter_mut().filter(move |record| {
+            record.ended_at.is_none()
+                && matches!(
+                    record.source,
+                    AlertSource::Rule { rule_id: record_rule_id, node_id: record_node_id, .. }
+                        if record_rule_id == rule_id
+                            && node_id.is_none_or(|node_id| node_id == record_node_id)
+                )
+        })
+    }
+
+    pub fn emergency_record(&self, alert_id: EmergencyAlertId) -> Option<&AlertRecord> {
+        self.records.iter().rev().find(|record| {
+            matches!(
+                record.source,
+                AlertSource::Emergency { alert_id: record_alert_id, .. } if record_alert_id == alert_id
+            )
+        })
+    }
+
+    fn emergency_record_mut(&mut self, alert_id: EmergencyAlertId) -> Option<&mut AlertRecord> {
+        self.records.iter_mut().rev().find(|record| {
+            matches!(
+                record.source,
+                AlertSource::Emergency { alert_id: record_alert_id, .. } if record_alert_id == alert_id
+            )
+        })
+    }
+}
+
+async fn save(state: &AppState) {
+    if let Err(error_message) = persistence::save_alert_history(state).await {
+        error!("{}", error_message);
+    }
+}
+
+/// Records a rule's alert firing or being resolved for a node
+pub async fn record_rule_event(state: &AppState, event: &AlertEvent, muted: bool) {
+    {
+        let mut history = state.alert_history.lock().await;
+
+        match event.state {
+            AlertState::Fired => history.fire(
+                AlertSource::Rule {
+                    rule_id: event.rule_id,
+                    rule_name: event.rule_name.clone(),
+                    condition: event.condition.clone(),
+                    node_id: event.node_id,
+                },
+                event.severity,
+                muted,
+                event.timestamp,
+            ),
+            AlertState::Resolved => {
+                for record in history.open_rule_records_mut(event.rule_id, Some(event.node_id)) {
+                    record.advance(AlertStage::Resolved, None, None, event.timestamp);
+                }
+            }
+        }
+    }
+
+    save(state).await;
+}
+
+/// Resolves a deleted rule's open alerts, since they'd otherwise never be
+pub async fn record_rule_removed(state: &AppState, rule_id: AlertRuleId, removed_by: &str) {
+    {
+        let now = unix_time_seconds();
+        let mut history = state.alert_history.lock().await;
+
+        for record in history.open_rule_records_mut(rule_id, None) {
+            record.advance(
+                AlertStage::Resolved,
+                Some(removed_by.to_owned()),
+                Some("The rule was deleted".to_owned()),
+                now,
+            );
+        }
+    }
+
+    save(state).await;
+}
+
+pub async fn record_emergency_alert(state: &AppState, alert: &EmergencyAlert) {
+    state.alert_history.lock().await.fire(
+        AlertSource::Emergency {
+            alert_id: alert.id,
+            text: alert.text.clone(),
+            drill: alert.drill,
+        },
+        alert.severity,
+        false,
+        alert.issued_at,
+    );
+
+    save(state).await;
+}
+
+/// Records an emergency alert being acknowledged, cancelled or expiring. Only the first node to
+/// acknowledge it is recorded, since `GET /alerts/{id}/acks` has the rest.
+pub async fn record_emergency_stage(
+    state: &AppState,
+    alert_id: EmergencyAlertId,
+    stage: AlertStage,
+    by: Option<String>,
+) {
+    {
+        let mut history = state.alert_history.lock().await;
+
+        let Some(record) = history.emergency_record_mut(alert_id) else {
+            return;
+        };
+
+        if stage == AlertStage::Acknowledged && record.has_reached(stage) {
+            return;
+        }
+
+        record.advance(stage, by, None, unix_time_seconds());
+    }
+
+    save(state).await;
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertHistoryQuery {
+    /// seconds since unix epoch, compared with when alerts fired
+    from: Option<u64>,
+    to: Option<u64>,
+    severity: Option<AlertSeverity>,
+}
+
+impl AlertHistoryQuery {
+    fn matches(&self, record: &AlertRecord) -> bool {
+        self.from.is_none_or(|from| record.fired_at >= from)
+            && self.to.is_none_or(|to| record.fired_at <= to)
+            && self
+                .severity
+                .is_none_or(|severity| record.severity == severity)
+    }
+}
+
+/// /alerts/history
+pub async fn get_alert_history(
+    State(state): State<AppState>,
+    Query(query): Query<AlertHistoryQuery>,
+) -> Json<Vec<AlertRecord>> {
+    Json(
+        state
+            .alert_history
+            .lock()
+            .await
+            .records()
+            .filter(|record| query.matches(record))
+            .cloned()
+            .collect(),
+    )
+}
+
+#[derive(Default, Serialize)]
+pub struct AlertCounts {
+    fired: usize,
+    acknowledged: usize,
+    escalated: usize,
+    resolved: usize,
+    cancelled: usize,
+    expired: usize,
+    /// fired but not ended yet
+    open: usize,
+    muted: usize,
+    mean_seconds_to_acknowledge: Option<f64>,
+    mean_seconds_to_end: Option<f64>,
+    #[serde(skip)]
+    total_seconds_to_acknowledge: u64,
+    #[serde(skip)]
+    total_seconds_to_end: u64,
+}
+
+impl AlertCounts {
+    fn add(&mut self, record: &AlertRecord) {
+        self.fired += 1;
+        self.muted += usize::from(record.muted);
+
+        self.acknowledged += usize::from(record.has_reached(AlertStage::Acknowledged));
+        self.escalated += usize::from(record.has_reached(AlertStage::Escalated));
+        self.resolved += usize::from(record.has_reached(AlertStage::Resolved));
+        self.cancelled += usize::from(record.has_reached(AlertStage::Cancelled));
+        self.expired += usize::from(record.has_reached(AlertStage::Expired));
+
+        if let Some(acknowledged) = record
+            .lifecycle
+            .iter()
+            .find(|entry| entry.stage == AlertStage::Acknowledged)
+        {
+            self.total_seconds_to_acknowledge +=
+                acknowledged.timestamp.saturating_sub(record.fired_at);
+            self.mean_seconds_to_acknowledge =
+                Some(self.total_seconds_to_acknowledge as f64 / self.acknowledged as f64);
+        }
+
+        match record.ended_at {
+            Some(ended_at) => {
+                self.total_seconds_to_end += ended_at.saturating_sub(record.fired_at);
+
+                let ended = self.resolved + self.cancelled + self.expired;
+                self.mean_seconds_to_end = Some(self.total_seconds_to_end as f64 / ended as f64);
+            }
+            None => self.open += 1,
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct RuleReport {
+    /// as of the rule's most recent alert
+    rule_name: Option<String>,
+    condition: String,
+    #[serde(flatten)]
+    counts: AlertCounts,
+}
+
+/// Alert counts over a period, e.g. for a monthly review
+#[derive(Default, Serialize)]
+pub struct AlertReport {
+    from: Option<u64>,
+    to: Option<u64>,
+    /// every alert apart from drills
+    total: AlertCounts,
+    by_severity: BTreeMap<AlertSeverity, AlertCounts>,
+    by_node: BTreeMap<NodeId, AlertCounts>,
+    by_rule: BTreeMap<AlertRuleId, RuleReport>,
+    emergency: AlertCounts,
+    drills: AlertCounts,
+}
+
+impl AlertReport {
+    fn add(&mut self, record: &AlertRecord) {
+        match &record.source {
+            AlertSource::Emergency { drill: true, .. } => {
+                self.drills.add(record);
+
+                return;
+            }
+            AlertSource::Emergency { .. } => self.emergency.add(record),
+            AlertSource::Rule {
+                rule_id,
+                rule_name,
+                condition,
+                node_id,
+            } => {
+                self.by_node.entry(*node_id).or_default().add(record);
+
+                let rule_report = self.by_rule.entry(*rule_id).or_default();
+
+                rule_report.rule_name = rule_name.clone();
+                rule_report.condition = condition.clone();
+                rule_report.counts.add(record);
+            }
+        }
+
+        self.total.add(record);
+        self.by_severity
+            .entry(record.severity)
+            .or_default()
+            .add(record);
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertReportQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+/// /alerts/report
+pub async fn get_alert_report(
+    State(state): State<AppState>,
+    Query(query): Query<AlertReportQuery>,
+) -> Json<AlertReport> {
+    let filter = AlertHistoryQuery {
+        from: query.from,
+        to: query.to,
+        severity: None,
+    };
+
+    let mut report = AlertReport {
+        from: query.from,
+        to: query.to,
+        ..Default::default()
+    };
+
+    for record in state.alert_history.lock().await.records() {
+        if filter.matches(record) {
+            report.add(record);
+        }
+    }
+
+    Json(report)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AlertStageBody {
+    note: Option<String>,
+}
+
+/// Records an operator acknowledging or escalating an alert. Escalating raises its severity a
+/// level and sends it to `ALERT_WEBHOOK_URLS` again, so that whoever's on call next hears about it.
+async fn advance_alert(
+    state: &AppState,
+    id: AlertRecordId,
+    stage: AlertStage,
+    user: &AuthedUser,
+    note: Option<String>,
+) -> Result<AlertRecord, (StatusCode, String)> {
+    let record = {
+        let mut history = state.alert_history.lock().await;
+
+        let Some(record) = history.get_mut(id) else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("No alert in the history with ID {}", id),
+            ));
+        };
+
+        if record.ended_at.is_some() {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Alert {} has already ended", id),
+            ));
+        }
+
+        if stage == AlertStage::Acknowledged && record.has_reached(stage) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Alert {} has already been acknowledged", id),
+            ));
+        }
+
+        if stage == AlertStage::Escalated {
+            record.severity = match record.severity {
+                AlertSeverity::Info => AlertSeverity::Warning,
+                AlertSeverity::Warning | AlertSeverity::Critical => AlertSeverity::Critical,
+            };
+        }
+
+        record.advance(stage, Some(user.name.clone()), note, unix_time_seconds());
+        record.clone()
+    };
+
+    info!("{} marked alert {} as {:?}", user, id, stage);
+
+    if stage == AlertStage::Escalated {
+        alerts::send_to_webhooks(state, &record);
+    }
+
+    save(state).await;
+
+    Ok(record)
+}
+
+/// /admin/alerts/history/{id}/acknowledge
+pub async fn acknowledge_alert(
+    State(state): State<AppState>,
+    Path(id): Path<AlertRecordId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<AlertStageBody>,
+) -> FallibleJsonResponse<AlertRecord> {
+    match advance_alert(&state, id, AlertStage::Acknowledged, &user, body.note).await {
+        Ok(record) => FallibleJsonResponse::Ok(record),
+        Err((status_code, error_message)) => FallibleJsonResponse::Err(status_code, error_message),
+    }
+}
+
+/// /admin/alerts/history/{id}/escalate
+pub async fn escalate_alert(
+    State(state): State<AppState>,
+    Path(id): Path<AlertRecordId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<AlertStageBody>,
+) -> FallibleJsonResponse<AlertRecord> {
+    match advance_alert(&state, id, AlertStage::Escalated, &user, body.note).await {
+        Ok(record) => FallibleJsonResponse::Ok(record),
+        Err((status_code, error_message)) => FallibleJsonResponse::Err(status_code, error_message),
+    }
+}
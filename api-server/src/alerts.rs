@@ -0,0 +1,484 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alert_history,
+    auth::AuthedUser,
+    config::CONFIG,
+    events::ServerEvent,
+    maintenance_windows,
+    pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::{SignalData, Telemetry},
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+pub type AlertRuleId = u32;
+
+/// A value that can be read from packets coming from the mesh and compared against a threshold
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertField {
+    Battery,
+    Voltage,
+    ChannelUtilization,
+    AirUtilTx,
+    Temperature,
+    Humidity,
+    Pressure,
+    Snr,
+    Rssi,
+}
+
+impl FromStr for AlertField {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "battery" => Ok(AlertField::Battery),
+            "voltage" => Ok(AlertField::Voltage),
+            "channel_utilization" => Ok(AlertField::ChannelUtilization),
+            "air_util_tx" => Ok(AlertField::AirUtilTx),
+            "temperature" => Ok(AlertField::Temperature),
+            "humidity" => Ok(AlertField::Humidity),
+            "pressure" => Ok(AlertField::Pressure),
+            "snr" => Ok(AlertField::Snr),
+            "rssi" => Ok(AlertField::Rssi),
+            _ => Err(format!("Unknown alert field: {}", string)),
+        }
+    }
+}
+
+impl Display for AlertField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AlertField::Battery => "battery",
+            AlertField::Voltage => "voltage",
+            AlertField::ChannelUtilization => "channel_utilization",
+            AlertField::AirUtilTx => "air_util_tx",
+            AlertField::Temperature => "temperature",
+            AlertField::Humidity => "humidity",
+            AlertField::Pressure => "pressure",
+            AlertField::Snr => "snr",
+            AlertField::Rssi => "rssi",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Debug)]
+pub enum Comparison {
+    #[serde(rename = "<")]
+    LessThan,
+    #[serde(rename = "<=")]
+    LessThanOrEqual,
+    #[serde(rename = ">")]
+    GreaterThan,
+    #[serde(rename = ">=")]
+    GreaterThanOrEqual,
+}
+
+impl Comparison {
+    pub fn holds<T: PartialOrd>(&self, value: T, threshold: T) -> bool {
+        match self {
+            Comparison::LessThan => value < threshold,
+            Comparison::LessThanOrEqual => value <= threshold,
+            Comparison::GreaterThan => value > threshold,
+            Comparison::GreaterThanOrEqual => value >= threshold,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "<" => Ok(Comparison::LessThan),
+            "<=" => Ok(Comparison::LessThanOrEqual),
+            ">" => Ok(Comparison::GreaterThan),
+            ">=" => Ok(Comparison::GreaterThanOrEqual),
+            _ => Err(format!("Unknown comparison operator: {}", string)),
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Comparison::LessThan => "<",
+            Comparison::LessThanOrEqual => "<=",
+            Comparison::GreaterThan => ">",
+            Comparison::GreaterThanOrEqual => ">=",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Every value in a telemetry packet that alert rules can refer to
+pub fn telemetry_values(telemetry: &Telemetry) -> Vec<(AlertField, f32)> {
+    let mut values = Vec::new();
+
+    if let Some(device_metrics) = telemetry.device_metrics {
+        values.extend([
+            (
+                AlertField::Battery,
+                device_metrics.battery_level.map(|value| value as f32),
+            ),
+            (AlertField::Voltage, device_metrics.voltage),
+            (
+                AlertField::ChannelUtilization,
+                device_metrics.channel_utilization,
+            ),
+            (AlertField::AirUtilTx, device_metrics.air_util_tx),
+        ]);
+    }
+
+    if let Some(environment_metrics) = telemetry.environment_metrics {
+        values.extend([
+            (AlertField::Temperature, environment_metrics.temperature),
+            (AlertField::Humidity, environment_metrics.relative_humidity),
+            (
+                AlertField::Pressure,
+                environment_metrics.barometric_pressure,
+            ),
+        ]);
+    }
+
+    values
+        .into_iter()
+        .filter_map(|(field, value)| Some((field, value?)))
+        .collect()
+}
+
+/// A parsed condition such as `battery < 20`
+#[derive(Clone, Copy, PartialEq, Serialize, Debug)]
+pub struct AlertCondition {
+    pub field: AlertField,
+    pub comparison: Comparison,
+    pub threshold: f32,
+}
+
+impl FromStr for AlertCondition {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let parts = string.split_whitespace().collect::<Vec<_>>();
+
+        if parts.len() != 3 {
+            return Err(format!(
+                "Condition must look like \"<field> <operator> <threshold>\", got \"{}\"",
+                string
+            ));
+        }
+
+        Ok(AlertCondition {
+            field: parts[0].parse()?,
+            comparison: parts[1].parse()?,
+            threshold: parts[2]
+                .parse()
+                .map_err(|_| format!("Invalid threshold: {}", parts[2]))?,
+        })
+    }
+}
+
+impl Display for AlertCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.field, self.comparison, self.threshold)
+    }
+}
+
+/// How urgently an alert needs attention, so that clients can decide how loudly to notify
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// Structure that clients should send new alert rules in as JSON body
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRuleBody {
+    pub condition: String,
+    /// the rule applies to every node if neither this nor `tag` is given
+    pub node_id: Option<NodeId>,
+    /// the rule applies to every node with this tag in the registry
+    pub tag: Option<String>,
+    /// shown to users instead of the condition if given
+    pub name: Option<String>,
+    #[serde(default)]
+    pub severity: AlertSeverity,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct AlertRule {
+    pub id: AlertRuleId,
+    pub condition: AlertCondition,
+    pub node_id: Option<NodeId>,
+    pub tag: Option<String>,
+    pub name: Option<String>,
+    pub severity: AlertSeverity,
+}
+
+#[derive(Clone, Copy, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Fired,
+    Resolved,
+}
+
+/// A change in whether a rule's condition holds for a particular node
+#[derive(Clone, Serialize, Debug)]
+pub struct AlertEvent {
+    pub rule_id: AlertRuleId,
+    pub rule_name: Option<String>,
+    pub severity: AlertSeverity,
+    pub node_id: NodeId,
+    pub condition: String,
+    pub threshold: f32,
+    pub value: f32,
+    pub state: AlertState,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+}
+
+pub struct AlertStore {
+    rules: BTreeMap<AlertRuleId, AlertRule>,
+    next_rule_id: AlertRuleId,
+    /// (rule, node) pairs whose condition currently holds, so each crossing only fires once
+    active: HashSet<(AlertRuleId, NodeId)>,
+}
+
+impl Default for AlertStore {
+    fn default() -> Self {
+        Self {
+            rules: BTreeMap::new(),
+            next_rule_id: 1,
+            active: HashSet::new(),
+        }
+    }
+}
+
+impl AlertStore {
+    pub fn add_rule(&mut self, body: AlertRuleBody) -> Result<AlertRule, String> {
+        let rule = AlertRule {
+            id: self.next_rule_id,
+            condition: body.condition.parse()?,
+            node_id: body.node_id,
+            tag: body.tag,
+            name: body.name,
+            severity: body.severity,
+        };
+
+        self.next_rule_id += 1;
+        self.rules.insert(rule.id, rule.clone());
+
+        Ok(rule)
+    }
+
+    pub fn remove_rule(&mut self, id: AlertRuleId) -> Option<AlertRule> {
+        self.active.retain(|(rule_id, _)| *rule_id != id);
+        self.rules.remove(&id)
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &AlertRule> + Clone {
+        self.rules.values()
+    }
+
+    /// Checks every rule that applies to the given node and field against a new value, returning
+    /// events for any rules which started or stopped holding
+    fn evaluate(
+        &mut self,
+        node_id: NodeId,
+        node_tags: &BTreeSet<String>,
+        field: AlertField,
+        value: f32,
+    ) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for rule in self.rules.values() {
+            if rule.condition.field != field
+                || rule.node_id.is_some_and(|id| id != node_id)
+                || rule
+                    .tag
+                    .as_ref()
+                    .is_some_and(|tag| !node_tags.contains(tag))
+            {
+                continue;
+            }
+
+            let holds = rule
+                .condition
+                .comparison
+                .holds(value, rule.condition.threshold);
+
+            let state = match (holds, self.active.contains(&(rule.id, node_id))) {
+                (true, false) => {
+                    self.active.insert((rule.id, node_id));
+                    AlertState::Fired
+                }
+                (false, true) => {
+                    self.active.remove(&(rule.id, node_id));
+                    AlertState::Resolved
+                }
+                _ => continue,
+            };
+
+            events.push(AlertEvent {
+                rule_id: rule.id,
+                rule_name: rule.name.clone(),
+                severity: rule.severity,
+                node_id,
+                condition: rule.condition.to_string(),
+                threshold: rule.condition.threshold,
+                value,
+                state,
+                timestamp: unix_time_seconds(),
+            });
+        }
+
+        events
+    }
+
+    /// `node_tags` are the node's tags in the registry, for rules which apply to a tag
+    pub fn evaluate_telemetry(
+        &mut self,
+        telemetry: &Telemetry,
+        node_tags: &BTreeSet<String>,
+    ) -> Vec<AlertEvent> {
+        telemetry_values(telemetry)
+            .into_iter()
+            .flat_map(|(field, value)| self.evaluate(telemetry.node_num, node_tags, field, value))
+            .collect()
+    }
+
+    /// Link readings are attributed to the receiving node, using its weakest link so that a node
+    /// with several links doesn't flip between fired and resolved within one packet
+    pub fn evaluate_signal_data(
+        &mut self,
+        signal_data: &SignalData,
+        node_tags: &BTreeSet<String>,
+    ) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        let weakest_snr = signal_data
+            .links
+            .iter()
+            .map(|edge| edge.snr)
+            .reduce(f32::min);
+        let weakest_rssi = signal_data.links.iter().map(|edge| edge.rssi).min();
+
+        if let Some(snr) = weakest_snr {
+            events.extend(self.evaluate(signal_data.to, node_tags, AlertField::Snr, snr));
+        }
+
+        if let Some(rssi) = weakest_rssi {
+            events.extend(self.evaluate(signal_data.to, node_tags, AlertField::Rssi, rssi as f32));
+        }
+
+        events
+    }
+}
+
+/// Pushes alert events to live websocket clients and any configured webhooks
+pub async fn dispatch(state: &AppState, events: Vec<AlertEvent>) {
+    for event in events {
+        info!(
+            "{:?} alert {:?} for node {}: {} (value {})",
+            event.severity, event.state, event.node_id, event.condition, event.value
+        );
+
+        let server_event = ServerEvent::Alert(event.clone());
+        let muted = maintenance_windows::mute(state, &server_event).await;
+
+        alert_history::record_rule_event(state, &event, muted).await;
+
+        if muted {
+            continue;
+        }
+
+        send_to_webhooks(state, &event);
+
+        // an error here just means there aren't any websocket clients connected
+        let _ = state.server_events.send(server_event);
+    }
+}
+
+/// POSTs the payload as JSON to every URL in `ALERT_WEBHOOK_URLS`, without waiting for them
+pub fn send_to_webhooks<T: Serialize + Clone + Send + 'static>(state: &AppState, payload: &T) {
+    for url in &CONFIG.alert_webhook_urls {
+        let client = state.http_client.clone();
+        let webhook_deliveries = state.webhook_deliveries.clone();
+        let url = url.clone();
+        let payload = payload.clone();
+
+        tokio::spawn(async move {
+            // the semaphore is never closed
+            let _permit = webhook_deliveries.acquire_owned().await;
+
+            match client.post(&url).json(&payload).send().await {
+                Ok(response) => debug!(
+                    "Alert webhook {} responded with status {}",
+                    url,
+                    response.status()
+                ),
+                Err(error) => error!("Failed to send alert to webhook {}: {:?}", url, error),
+            }
+        });
+    }
+}
+
+/// /alerts/rules
+pub async fn get_alert_rules(State(state): State<AppState>) -> Json<Vec<AlertRule>> {
+    Json(state.alerts.lock().await.rules().cloned().collect())
+}
+
+/// /admin/alerts/rules
+pub async fn add_alert_rule(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<AlertRuleBody>,
+) -> FallibleJsonResponse<AlertRule> {
+    info!("{} is adding alert rule: {:?}", user, body);
+
+    match state.alerts.lock().await.add_rule(body) {
+        Ok(rule) => FallibleJsonResponse::Ok(rule),
+        Err(error_message) => FallibleJsonResponse::Err(StatusCode::BAD_REQUEST, error_message),
+    }
+}
+
+/// /admin/alerts/rules/{id}
+pub async fn delete_alert_rule(
+    State(state): State<AppState>,
+    Path(id): Path<AlertRuleId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    info!("{} is deleting alert rule {}", user, id);
+
+    if state.alerts.lock().await.remove_rule(id).is_some() {
+        alert_history::record_rule_removed(&state, id, &user.name).await;
+
+        StringOrEmptyResponse::Ok
+    } else {
+        StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No alert rule with ID {}", id),
+        )
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, VecDeque};
+
+use axum::{extract::State, Json};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    alerts::{telemetry_values, AlertField},
+    config::CONFIG,
+    events::ServerEvent,
+    maintenance_windows,
+    pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::{SignalData, Telemetry},
+    utils::{unix_time_seconds, RingBuffer},
+    AppState,
+};
+
+/// Readings are compared against the history of the same field from the same node (and the same
+/// neighbour, for link readings)
+type SeriesKey = (NodeId, AlertField, Option<NodeId>);
+
+/// A reading that was unusually far from the recent average
+#[derive(Clone, Serialize, Debug)]
+pub struct Anomaly {
+    pub node_id: NodeId,
+    /// the other end of the link, for SNR and RSSI readings
+    pub neighbour_id: Option<NodeId>,
+    pub field: AlertField,
+    pub value: f32,
+    pub mean: f32,
+    pub z_score: f32,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+}
+
+/// Flags readings whose rolling z-score exceeds `ANOMALY_Z_SCORE_THRESHOLD`
+pub struct AnomalyDetector {
+    series: HashMap<SeriesKey, VecDeque<f32>>,
+    anomalies: RingBuffer<Anomaly>,
+}
+
+impl AnomalyDetector {
+    pub fn new(history_capacity: usize) -> Self {
+        Self {
+            series: HashMap::new(),
+            anomalies: RingBuffer::new(history_capacity),
+        }
+    }
+
+    pub fn anomalies(&self) -> &RingBuffer<Anomaly> {
+        &self.anomalies
+    }
+
+    fn check(&mut self, key: SeriesKey, value: f32) -> Option<Anomaly> {
+        let window = self.series.entry(key).or_default();
+
+        let mut result = None;
+
+        if window.len() >= CONFIG.anomaly_min_samples {
+            let count = window.len() as f32;
+            let mean = window.iter().sum::<f32>() / count;
+            let variance = window.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / count;
+            let standard_deviation = variance.sqrt();
+
+            // a perfectly flat series (e.g. a powered node's battery level) has no meaningful
+            // z-score, so only flag it once it's actually varying
+            if standard_deviation > f32::EPSILON {
+                let z_score = (value - mean) / standard_deviation;
+
+                if z_score.abs() > CONFIG.anomaly_z_score_threshold {
+                    let (node_id, field, neighbour_id) = key;
+
+                    result = Some(Anomaly {
+                        node_id,
+                        neighbour_id,
+                        field,
+                        value,
+                        mean,
+                        z_score,
+                        timestamp: unix_time_seconds(),
+                    });
+                }
+            }
+        }
+
+        // anomalous readings still go into the window so that a lasting change becomes the new
+        // normal instead of being flagged forever
+        if window.len() >= CONFIG.anomaly_window_size {
+            window.pop_front();
+        }
+        window.push_back(value);
+
+        if let Some(anomaly) = &result {
+            self.anomalies.write(anomaly.clone());
+        }
+
+        result
+    }
+
+    pub fn check_telemetry(&mut self, telemetry: &Telemetry) -> Vec<Anomaly> {
+        telemetry_values(telemetry)
+            .into_iter()
+            .filter_map(|(field, value)| self.check((telemetry.node_num, field, None), value))
+            .collect()
+    }
+
+    pub fn check_signal_data(&mut self, signal_data: &SignalData) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+
+        for edge in &signal_data.links {
+            anomalies
+                .extend(self.check((signal_data.to, AlertField::Snr, Some(edge.from)), edge.snr));
+            anomalies.extend(self.check(
+                (signal_data.to, AlertField::Rssi, Some(edge.from)),
+                edge.rssi as f32,
+            ));
+        }
+
+        anomalies
+    }
+}
+
+/// Pushes anomalies to live websocket clients
+pub async fn dispatch(state: &AppState, anomalies: Vec<Anomaly>) {
+    for anomaly in anomalies {
+        info!(
+            "Anomalous {} reading from node {}: {} (mean {}, z-score {})",
+            anomaly.field, anomaly.node_id, anomaly.value, anomaly.mean, anomaly.z_score
+        );
+
+        let event = ServerEvent::Anomaly(anomaly);
+
+        if maintenance_windows::mute(state, &event).await {
+            continue;
+        }
+
+        let _ = state.server_events.send(event);
+    }
+}
+
+/// /info/anomalies
+pub async fn get_anomalies(State(state): State<AppState>) -> Json<Vec<Anomaly>> {
+    Json(
+        state
+            .anomaly_detector
+            .lock()
+            .await
+            .anomalies()
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
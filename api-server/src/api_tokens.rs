@@ -0,0 +1,223 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::{AuthedUser, Role},
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+/// every token starts with this so that they're easy to recognise (e.g. by secret scanners)
+const TOKEN_PREFIX: &str = "mst_";
+
+pub type ApiTokenId = u32;
+
+/// What a token can be used for
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// the same as a viewer
+    ReadOnly,
+    /// only the `/telemetry/*` routes a viewer can use
+    TelemetryOnly,
+    /// the same as an admin
+    Admin,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ApiToken {
+    pub id: ApiTokenId,
+    /// who or what the token is for, e.g. the partner organisation's name
+    pub name: String,
+    pub scope: TokenScope,
+    pub created_by: String,
+    /// seconds since unix epoch
+    pub created_at: u64,
+}
+
+impl ApiToken {
+    fn user(&self) -> AuthedUser {
+        AuthedUser {
+            id: format!("api-token:{}", self.id),
+            name: format!("API token {} ({})", self.id, self.name),
+            role: match self.scope {
+                TokenScope::ReadOnly | TokenScope::TelemetryOnly => Role::Viewer,
+                TokenScope::Admin => Role::Admin,
+            },
+            telemetry_only: self.scope == TokenScope::TelemetryOnly,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoredApiToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// hex SHA-256 of the token, which is enough since tokens are long and random
+    hash: String,
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Tokens which partners can use to call the API without sharing anyone's credentials. Only their
+/// hashes are kept, so a token can't be recovered after it's been created.
+#[derive(Default)]
+pub struct ApiTokenStore {
+    tokens: Vec<StoredApiToken>,
+    next_id: ApiTokenId,
+}
+
+impl ApiTokenStore {
+    pub fn restore(&mut self, tokens: Vec<StoredApiToken>) {
+        self.next_id = tokens
+            .iter()
+            .map(|stored_token| stored_token.token.id + 1)
+            .max()
+            .unwrap_or(0);
+        self.tokens = tokens;
+    }
+
+    pub fn stored_tokens(&self) -> &[StoredApiToken] {
+        &self.tokens
+    }
+
+    /// Creates a token, returning it along with the secret to give to whoever it's for
+    fn create(
+        &mut self,
+        name: String,
+        scope: TokenScope,
+        created_by: String,
+    ) -> (ApiToken, String) {
+        let secret = format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            hex::encode(rand::random::<[u8; 32]>())
+        );
+
+        let token = ApiToken {
+            id: self.next_id,
+            name,
+            scope,
+            created_by,
+            created_at: unix_time_seconds(),
+        };
+
+        self.next_id += 1;
+        self.tokens.push(StoredApiToken {
+            token: token.clone(),
+            hash: hash_token(&secret),
+        });
+
+        (token, secret)
+    }
+
+    fn revoke(&mut self, id: ApiTokenId) -> Option<ApiToken> {
+        let index = self
+            .tokens
+            .iter()
+            .position(|stored_token| stored_token.token.id == id)?;
+
+        Some(self.tokens.remove(index).token)
+    }
+
+    /// Who's using the token, if it's one of these
+    pub fn authenticate(&self, secret: &str) -> Option<AuthedUser> {
+        if !secret.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+
+        let hash = hash_token(secret);
+
+        self.tokens
+            .iter()
+            .find(|stored_token| stored_token.hash == hash)
+            .map(|stored_token| stored_token.token.user())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ApiTokenBody {
+    name: String,
+    scope: TokenScope,
+}
+
+#[derive(Serialize)]
+pub struct NewApiToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// only ever returned here
+    secret: String,
+}
+
+/// /admin/api-tokens (POST)
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<ApiTokenBody>,
+) -> FallibleJsonResponse<NewApiToken> {
+    let (token, secret) =
+        state
+            .api_tokens
+            .lock()
+            .await
+            .create(body.name, body.scope, user.name.clone());
+
+    info!(
+        "{} created API token {} ({}) with {:?} scope",
+        user, token.id, token.name, token.scope
+    );
+
+    // a token which wasn't saved would stop working whenever the server restarts
+    if let Err(error_message) = persistence::save_api_tokens(&state).await {
+        state.api_tokens.lock().await.revoke(token.id);
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(NewApiToken { token, secret })
+}
+
+/// /admin/api-tokens (GET)
+pub async fn get_api_tokens(State(state): State<AppState>) -> Json<Vec<ApiToken>> {
+    Json(
+        state
+            .api_tokens
+            .lock()
+            .await
+            .stored_tokens()
+            .iter()
+            .map(|stored_token| stored_token.token.clone())
+            .collect(),
+    )
+}
+
+/// /admin/api-tokens/{id}
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    Path(id): Path<ApiTokenId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    let Some(token) = state.api_tokens.lock().await.revoke(id) else {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No API token with ID {}", id),
+        );
+    };
+
+    info!("{} revoked API token {} ({})", user, token.id, token.name);
+
+    if let Err(error_message) = persistence::save_api_tokens(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
@@ -0,0 +1,531 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    mem::size_of,
+    time::Duration,
+};
+
+use axum::{extract::State, Json};
+use log::debug;
+use prost::Message;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::CONFIG,
+    pathfinding::NodeId,
+    proto::meshtastic::{
+        crisislab_message::Telemetry, DeviceMetrics, EnvironmentMetrics, Position,
+    },
+    utils::unix_time_seconds,
+    AppState,
+};
+
+// missing values are stored as sentinels so that columns don't need an `Option` per entry (floats
+// use NaN)
+const MISSING_BATTERY_LEVEL: u8 = u8::MAX;
+const MISSING_UPTIME: u32 = u32::MAX;
+const MISSING_COORDINATE: i32 = i32::MIN;
+
+/// Stored as the delta of an entry whose timestamp is too far from the previous one to fit, in
+/// which case the timestamp itself is kept in `absolute_timestamps`
+const ABSOLUTE_TIMESTAMP: i32 = i32::MIN;
+
+fn from_f32(value: f32) -> Option<f32> {
+    (!value.is_nan()).then_some(value)
+}
+
+fn from_coordinate(value: i32) -> Option<i32> {
+    (value != MISSING_COORDINATE).then_some(value)
+}
+
+/// Archived telemetry from one node, stored as one column per field rather than one struct per
+/// packet. Only the fields the server actually uses are kept.
+#[derive(Default)]
+struct NodeColumns {
+    first_timestamp: u64,
+    last_timestamp: u64,
+    /// difference from the previous entry's timestamp (0 for the first entry), which is much
+    /// smaller than the timestamp itself
+    timestamp_deltas: VecDeque<i32>,
+    /// timestamps of the entries whose delta is `ABSOLUTE_TIMESTAMP`, in order
+    absolute_timestamps: VecDeque<u64>,
+    battery_level: VecDeque<u8>,
+    voltage: VecDeque<f32>,
+    channel_utilization: VecDeque<f32>,
+    air_util_tx: VecDeque<f32>,
+    uptime_seconds: VecDeque<u32>,
+    latitude_i: VecDeque<i32>,
+    longitude_i: VecDeque<i32>,
+    altitude: VecDeque<i32>,
+    temperature: VecDeque<f32>,
+    relative_humidity: VecDeque<f32>,
+    barometric_pressure: VecDeque<f32>,
+}
+
+/// Approximate memory used by each archived entry
+const ENTRY_BYTES: usize =
+    size_of::<i32>() * 4 + size_of::<u8>() + size_of::<f32>() * 6 + size_of::<u32>();
+
+impl NodeColumns {
+    fn len(&self) -> usize {
+        self.timestamp_deltas.len()
+    }
+
+    fn push(&mut self, telemetry: &Telemetry) {
+        if self.timestamp_deltas.is_empty() {
+            self.first_timestamp = telemetry.timestamp;
+            self.last_timestamp = telemetry.timestamp;
+            self.timestamp_deltas.push_back(0);
+        } else {
+            // nodes' clocks can jump around so the delta may be negative, or too big to store
+            match i32::try_from(telemetry.timestamp as i128 - self.last_timestamp as i128) {
+                Ok(delta) if delta != ABSOLUTE_TIMESTAMP => self.timestamp_deltas.push_back(delta),
+                _ => {
+                    self.timestamp_deltas.push_back(ABSOLUTE_TIMESTAMP);
+                    self.absolute_timestamps.push_back(telemetry.timestamp);
+                }
+            }
+
+            self.last_timestamp = telemetry.timestamp;
+        }
+
+        let device_metrics = telemetry.device_metrics.unwrap_or_default();
+        let environment_metrics = telemetry.environment_metrics.unwrap_or_default();
+        let position = telemetry.position.unwrap_or_default();
+
+        self.battery_level.push_back(
+            device_metrics
+                .battery_level
+                .and_then(|level| u8::try_from(level).ok())
+                .unwrap_or(MISSING_BATTERY_LEVEL),
+        );
+        self.voltage
+            .push_back(device_metrics.voltage.unwrap_or(f32::NAN));
+        self.channel_utilization
+            .push_back(device_metrics.channel_utilization.unwrap_or(f32::NAN));
+        self.air_util_tx
+            .push_back(device_metrics.air_util_tx.unwrap_or(f32::NAN));
+        self.uptime_seconds
+            .push_back(device_metrics.uptime_seconds.unwrap_or(MISSING_UPTIME));
+        self.latitude_i
+            .push_back(position.latitude_i.unwrap_or(MISSING_COORDINATE));
+        self.longitude_i
+            .push_back(position.longitude_i.unwrap_or(MISSING_COORDINATE));
+        self.altitude
+            .push_back(position.altitude.unwrap_or(MISSING_COORDINATE));
+        self.temperature
+            .push_back(environment_metrics.temperature.unwrap_or(f32::NAN));
+        self.relative_humidity
+            .push_back(environment_metrics.relative_humidity.unwrap_or(f32::NAN));
+        self.barometric_pressure
+            .push_back(environment_metrics.barometric_pressure.unwrap_or(f32::NAN));
+    }
+
+    fn pop_front(&mut self) {
+        self.timestamp_deltas.pop_front();
+
+        // the next entry becomes the first, so its delta is folded into the first timestamp
+        if let Some(delta) = self.timestamp_deltas.front_mut() {
+            self.first_timestamp = if *delta == ABSOLUTE_TIMESTAMP {
+                self.absolute_timestamps
+                    .pop_front()
+                    .expect("Every absolute delta has a timestamp")
+            } else {
+                self.first_timestamp.saturating_add_signed(*delta as i64)
+            };
+            *delta = 0;
+        }
+
+        self.battery_level.pop_front();
+        self.voltage.pop_front();
+        self.channel_utilization.pop_front();
+        self.air_util_tx.pop_front();
+        self.uptime_seconds.pop_front();
+        self.latitude_i.pop_front();
+        self.longitude_i.pop_front();
+        self.altitude.pop_front();
+        self.temperature.pop_front();
+        self.relative_humidity.pop_front();
+        self.barometric_pressure.pop_front();
+    }
+
+    fn timestamps(&self) -> impl Iterator<Item = u64> + '_ {
+        let mut absolute_timestamps = self.absolute_timestamps.iter();
+
+        self.timestamp_deltas
+            .iter()
+            .scan(self.first_timestamp, move |timestamp, delta| {
+                *timestamp = if *delta == ABSOLUTE_TIMESTAMP {
+                    *absolute_timestamps.next()?
+                } else {
+                    timestamp.saturating_add_signed(*delta as i64)
+                };
+                Some(*timestamp)
+            })
+    }
+
+    /// Rebuilds telemetry packets from the columns, oldest first
+    fn telemetry(&self, node_id: NodeId) -> impl Iterator<Item = Telemetry> + '_ {
+        self.timestamps().enumerate().map(move |(i, timestamp)| {
+            let device_metrics = DeviceMetrics {
+                battery_level: (self.battery_level[i] != MISSING_BATTERY_LEVEL)
+                    .then_some(self.battery_level[i] as u32),
+                voltage: from_f32(self.voltage[i]),
+                channel_utilization: from_f32(self.channel_utilization[i]),
+                air_util_tx: from_f32(self.air_util_tx[i]),
+                uptime_seconds: (self.uptime_seconds[i] != MISSING_UPTIME)
+                    .then_some(self.uptime_seconds[i]),
+            };
+
+            let environment_metrics = EnvironmentMetrics {
+                temperature: from_f32(self.temperature[i]),
+                relative_humidity: from_f32(self.relative_humidity[i]),
+                barometric_pressure: from_f32(self.barometric_pressure[i]),
+                ..Default::default()
+            };
+
+            let position = Position {
+                latitude_i: from_coordinate(self.latitude_i[i]),
+                longitude_i: from_coordinate(self.longitude_i[i]),
+                altitude: from_coordinate(self.altitude[i]),
+                ..Default::default()
+            };
+
+            Telemetry {
+                node_num: node_id,
+                timestamp,
+                user: None,
+                position: (position != Position::default()).then_some(position),
+                device_metrics: (device_metrics != DeviceMetrics::default())
+                    .then_some(device_metrics),
+                environment_metrics: (environment_metrics != EnvironmentMetrics::default())
+                    .then_some(environment_metrics),
+                // these are kept in the node registry instead
+                firmware_version: None,
+                firmware_build: None,
+            }
+        })
+    }
+}
+
+/// A second, more compact tier for telemetry older than `TELEMETRY_ARCHIVE_AFTER_MINUTES` (or which
+/// has fallen out of the full telemetry cache before then), so that more history can be kept in the
+/// same amount of memory. Disabled if the capacity is 0.
+pub struct TelemetryArchive {
+    nodes: HashMap<NodeId, NodeColumns>,
+    len: usize,
+    capacity: usize,
+}
+
+impl TelemetryArchive {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            len: 0,
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn approximate_bytes(&self) -> usize {
+        self.len * ENTRY_BYTES
+    }
+
+    pub fn record(&mut self, telemetry: &Telemetry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.len >= self.capacity {
+            self.remove_oldest();
+        }
+
+        self.nodes
+            .entry(telemetry.node_num)
+            .or_default()
+            .push(telemetry);
+        self.len += 1;
+    }
+
+    fn remove_oldest(&mut self) {
+        let Some(oldest_node_id) = self
+            .nodes
+            .iter()
+            .min_by_key(|(_, columns)| columns.first_timestamp)
+            .map(|(node_id, _)| *node_id)
+        else {
+            return;
+        };
+
+        let columns = self
+            .nodes
+            .get_mut(&oldest_node_id)
+            .expect("Node was just found in the archive");
+
+        columns.pop_front();
+        self.len -= 1;
+
+        if columns.len() == 0 {
+            self.nodes.remove(&oldest_node_id);
+        }
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        if let Some(columns) = self.nodes.remove(&node_id) {
+            self.len -= columns.len();
+        }
+    }
+
+    /// Timestamps of every archived packet from the node, in the order they were archived
+    pub fn timestamps(&self, node_id: NodeId) -> impl Iterator<Item = u64> + '_ {
+        self.nodes
+            .get(&node_id)
+            .into_iter()
+            .flat_map(|columns| columns.timestamps())
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    /// Every archived packet, rebuilt as telemetry (without the fields that aren't archived)
+    pub fn telemetry(&self) -> impl Iterator<Item = Telemetry> + '_ {
+        self.nodes
+            .iter()
+            .flat_map(|(node_id, columns)| columns.telemetry(*node_id))
+    }
+}
+
+/// Spawns the task which moves telemetry older than `TELEMETRY_ARCHIVE_AFTER_MINUTES` from the
+/// telemetry cache to the archive every minute
+pub fn archive_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting telemetry archive task");
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            let mut telemetry_cache = state.telemetry_cache.lock().await;
+            let mut archive = state.telemetry_archive.lock().await;
+
+            if archive.capacity() == 0 {
+                continue;
+            }
+
+            let cutoff = unix_time_seconds()
+                .saturating_sub(CONFIG.telemetry_archive_after_minutes.saturating_mul(60));
+            let old_telemetry =
+                telemetry_cache.take_oldest_while(|telemetry| telemetry.timestamp < cutoff);
+
+            if !old_telemetry.is_empty() {
+                debug!("Archiving {} old telemetry packets", old_telemetry.len());
+            }
+
+            for telemetry in &old_telemetry {
+                archive.record(telemetry);
+            }
+        }
+    })
+}
+
+#[derive(Serialize)]
+pub struct TierStats {
+    entries: usize,
+    capacity: usize,
+    approximate_bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct StorageStats {
+    cache: TierStats,
+    archive: TierStats,
+    /// how many packets can be kept across both tiers
+    effective_capacity: usize,
+}
+
+/// /telemetry/storage-stats
+pub async fn get_storage_stats(State(state): State<AppState>) -> Json<StorageStats> {
+    let telemetry_cache = state.telemetry_cache.lock().await;
+    let archive = state.telemetry_archive.lock().await;
+
+    let cache_bytes = telemetry_cache
+        .into_iter()
+        .map(|telemetry| {
+            size_of::<Telemetry>() + telemetry.user.as_ref().map_or(0, |user| user.encoded_len())
+        })
+        .sum();
+
+    Json(StorageStats {
+        cache: TierStats {
+            entries: telemetry_cache.len(),
+            capacity: telemetry_cache.capacity(),
+            approximate_bytes: cache_bytes,
+        },
+        archive: TierStats {
+            entries: archive.len(),
+            capacity: archive.capacity(),
+            approximate_bytes: archive.approximate_bytes(),
+        },
+        effective_capacity: telemetry_cache.capacity() + archive.capacity(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE_ID: NodeId = 1;
+
+    fn telemetry(node_num: NodeId, timestamp: u64) -> Telemetry {
+        Telemetry {
+            node_num,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn archive_of(timestamps: &[u64], capacity: usize) -> TelemetryArchive {
+        let mut archive = TelemetryArchive::new(capacity);
+
+        for timestamp in timestamps {
+            archive.record(&telemetry(NODE_ID, *timestamp));
+        }
+
+        archive
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        let timestamps = [
+            1_000,
+            1_010,
+            // backwards
+            990,
+            // too far ahead for the delta to fit
+            990 + (1 << 33),
+            990 + (1 << 33) + 5,
+            // too far behind
+            3,
+            3_000_000_000,
+            // exactly i32::MIN behind, which is the absolute marker rather than a delta
+            3_000_000_000 - (1 << 31),
+            0,
+            u64::MAX,
+        ];
+
+        let archive = archive_of(&timestamps, 100);
+
+        assert_eq!(archive.len(), timestamps.len());
+        assert_eq!(archive.timestamps(NODE_ID).collect::<Vec<_>>(), timestamps);
+        assert_eq!(
+            archive
+                .telemetry()
+                .map(|telemetry| telemetry.timestamp)
+                .collect::<Vec<_>>(),
+            timestamps
+        );
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let full = Telemetry {
+            node_num: NODE_ID,
+            timestamp: 1_000,
+            user: None,
+            position: Some(Position {
+                latitude_i: Some(-412_865_000),
+                longitude_i: Some(1_747_762_000),
+                altitude: Some(12),
+                ..Default::default()
+            }),
+            device_metrics: Some(DeviceMetrics {
+                battery_level: Some(87),
+                voltage: Some(4.1),
+                channel_utilization: Some(12.5),
+                air_util_tx: Some(1.5),
+                uptime_seconds: Some(3_600),
+            }),
+            environment_metrics: Some(EnvironmentMetrics {
+                temperature: Some(18.5),
+                relative_humidity: Some(60.0),
+                barometric_pressure: Some(1013.0),
+                ..Default::default()
+            }),
+            firmware_version: None,
+            firmware_build: None,
+        };
+        let empty = telemetry(NODE_ID, 1_060);
+
+        let mut archive = TelemetryArchive::new(10);
+        archive.record(&full);
+        archive.record(&empty);
+
+        assert_eq!(archive.telemetry().collect::<Vec<_>>(), [full, empty]);
+    }
+
+    #[test]
+    fn pop_front_keeps_later_timestamps() {
+        let timestamps = [
+            100,
+            100 + (1 << 33),
+            100 + (1 << 33) + 7,
+            100 + (1 << 33) + 4,
+        ];
+
+        let mut archive = archive_of(&timestamps, 3);
+
+        // the second entry's timestamp was absolute, so it becomes the first timestamp
+        assert_eq!(archive.len(), 3);
+        assert_eq!(
+            archive.timestamps(NODE_ID).collect::<Vec<_>>(),
+            timestamps[1..]
+        );
+
+        // and then the third's delta is folded into it
+        archive.record(&telemetry(NODE_ID, 50));
+        assert_eq!(
+            archive.timestamps(NODE_ID).collect::<Vec<_>>(),
+            [timestamps[2], timestamps[3], 50]
+        );
+
+        let columns = &archive.nodes[&NODE_ID];
+        assert_eq!(columns.timestamp_deltas.front(), Some(&0));
+        // just the newest entry's, since it was far behind the one before
+        assert_eq!(columns.absolute_timestamps, [50]);
+    }
+
+    #[test]
+    fn removes_the_oldest_node_entry_first() {
+        let mut archive = TelemetryArchive::new(3);
+
+        archive.record(&telemetry(1, 200));
+        archive.record(&telemetry(2, 100));
+        archive.record(&telemetry(1, 300));
+        archive.record(&telemetry(2, 400));
+
+        assert_eq!(archive.len(), 3);
+        assert_eq!(archive.timestamps(1).collect::<Vec<_>>(), [200, 300]);
+        assert_eq!(archive.timestamps(2).collect::<Vec<_>>(), [400]);
+
+        archive.record(&telemetry(1, 500));
+        archive.record(&telemetry(1, 600));
+
+        assert_eq!(archive.timestamps(1).collect::<Vec<_>>(), [500, 600]);
+        assert_eq!(archive.timestamps(2).collect::<Vec<_>>(), [400]);
+    }
+
+    #[test]
+    fn zero_capacity_is_disabled() {
+        let archive = archive_of(&[1, 2, 3], 0);
+
+        assert_eq!(archive.len(), 0);
+        assert_eq!(archive.telemetry().count(), 0);
+    }
+}
@@ -0,0 +1,736 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, Extensions, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    config::CONFIG,
+    lockout::{self, AuthEventKind},
+    oidc, users,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+/// Short-lived, single use tokens which allow a client to connect to the live websocket. Browsers
+/// can't set headers on websocket requests, so clients authenticate over HTTP to get a token and
+/// then pass it as a query parameter.
+#[derive(Default)]
+pub struct WsTokenStore {
+    /// token -> (expiry (seconds since unix epoch), whether it was issued for a telemetry-only
+    /// API token)
+    tokens: HashMap<String, (u64, bool)>,
+}
+
+impl WsTokenStore {
+    pub fn issue(&mut self, now: u64, telemetry_only: bool) -> (String, u64) {
+        // forget about tokens which were never used
+        self.tokens.retain(|_, (expires_at, _)| *expires_at > now);
+
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        let expires_at = now + CONFIG.ws_token_ttl_seconds;
+
+        self.tokens
+            .insert(token.clone(), (expires_at, telemetry_only));
+
+        (token, expires_at)
+    }
+
+    /// Uses up the token, returning whether it's telemetry-only if it was valid
+    pub fn redeem(&mut self, token: &str, now: u64) -> Option<bool> {
+        self.tokens
+            .remove(token)
+            .filter(|(expires_at, _)| *expires_at > now)
+            .map(|(_, telemetry_only)| telemetry_only)
+    }
+}
+
+/// The key from the request's `Authorization: Bearer <key>` header, if it has one
+pub fn bearer_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Whether the request has an `Authorization: Bearer <key>` header with the given key
+pub fn has_bearer_key(headers: &HeaderMap, key: &str) -> bool {
+    bearer_key(headers).is_some_and(|provided_key| secrets_match(provided_key, key))
+}
+
+/// Compares a secret someone provided with the real one in constant time, so that how long the
+/// comparison takes doesn't give away how much of it was right
+pub fn secrets_match(provided: &str, expected: &str) -> bool {
+    provided.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+/// What a user is allowed to do. Viewers can read telemetry and mesh info, admins can also change
+/// settings and control the mesh, and commanders can also set off sirens for real rather than as
+/// a drill.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Admin,
+    Commander,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Admin => "admin",
+            Role::Commander => "commander",
+        }
+    }
+}
+
+/// The claims the server expects in a JWT, which must be signed with `JWT_SECRET` using HS256
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    role: Role,
+    /// seconds since unix epoch
+    exp: u64,
+}
+
+struct Session {
+    user: AuthedUser,
+    /// whether the user logged in with a password from `USERS_FILE`, rather than through OIDC
+    from_user_store: bool,
+    expires_at: u64,
+}
+
+/// Refresh tokens for users who have logged in, which can be swapped for a new access token (and
+/// refresh token) until they expire or the user logs out. They're only kept in memory, so
+/// everyone has to log in again after the server restarts.
+#[derive(Default)]
+pub struct SessionStore {
+    /// refresh token -> session
+    sessions: HashMap<String, Session>,
+}
+
+impl SessionStore {
+    fn start(&mut self, user: AuthedUser, from_user_store: bool, now: u64) -> String {
+        // forget about sessions which were abandoned without logging out
+        self.sessions.retain(|_, session| session.expires_at > now);
+
+        let refresh_token = hex::encode(rand::random::<[u8; 32]>());
+
+        self.sessions.insert(
+            refresh_token.clone(),
+            Session {
+                user,
+                from_user_store,
+                expires_at: now + CONFIG.refresh_token_ttl_seconds,
+            },
+        );
+
+        refresh_token
+    }
+
+    /// Uses up the refresh token, returning its session if it was valid
+    fn end(&mut self, refresh_token: &str, now: u64) -> Option<Session> {
+        self.sessions
+            .remove(refresh_token)
+            .filter(|session| session.expires_at > now)
+    }
+}
+
+/// Who made a request, for handlers which want to log who did what. Requests with one of
+/// `ADMIN_API_KEYS` are admins, requests with a JWT get the role in its claims, and requests with
+/// a token from the OIDC provider get the role their groups are mapped to.
+#[derive(Clone, Debug)]
+pub struct AuthedUser {
+    /// who the request was made by, which (unlike the name) is different for each admin API key
+    /// and API token, e.g. `api-key:0`, `api-token:3` or `user:<JWT subject>`
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+    /// whether the user can only use the `/telemetry/*` routes, for API tokens scoped to them
+    pub telemetry_only: bool,
+}
+
+impl Display for AuthedUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.name, self.role)
+    }
+}
+
+/// The ID of a user who logged in, or whose JWT has `name` as its subject. The server's own JWTs
+/// have the user's name as their subject, so this is the same however they authenticated.
+pub fn user_id(name: &str) -> String {
+    format!("user:{}", name)
+}
+
+/// Whether routes needing the role are open to anyone because nothing is configured to
+/// authenticate users with
+pub fn is_open_to_anyone(role: Role) -> bool {
+    let can_check_tokens = CONFIG.jwt_secret.is_some() || oidc::is_enabled();
+
+    match role {
+        Role::Viewer => !can_check_tokens,
+        Role::Admin | Role::Commander => !can_check_tokens && CONFIG.admin_api_keys.is_empty(),
+    }
+}
+
+/// Why a request couldn't be authenticated
+struct AuthFailure {
+    message: String,
+    /// whether the request had a key or token which must have been guessed, rather than none, an
+    /// expired one or one from the OIDC provider (which can't be forged by guessing), which counts
+    /// towards locking the client out
+    is_guess: bool,
+}
+
+impl AuthFailure {
+    fn new(message: impl Into<String>, is_guess: bool) -> Self {
+        Self {
+            message: message.into(),
+            is_guess,
+        }
+    }
+}
+
+async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+    required_role: Role,
+) -> Result<AuthedUser, AuthFailure> {
+    if is_open_to_anyone(required_role) {
+        return Ok(AuthedUser {
+            id: "anonymous".to_owned(),
+            name: "anonymous".to_owned(),
+            role: required_role,
+            telemetry_only: false,
+        });
+    }
+
+    let Some(provided_key) = bearer_key(headers) else {
+        return Err(AuthFailure::new("Missing API key or token", false));
+    };
+
+    // every key is compared (rather than stopping at a match) so that the time taken doesn't give
+    // away which one matched
+    let admin_api_key_index =
+        CONFIG
+            .admin_api_keys
+            .iter()
+            .enumerate()
+            .fold(None, |matched, (index, key)| {
+                let is_match = secrets_match(provided_key, key);
+                matched.or(is_match.then_some(index))
+            });
+
+    if let Some(index) = admin_api_key_index {
+        return Ok(AuthedUser {
+            id: format!("api-key:{}", index),
+            name: "API key".to_owned(),
+            role: Role::Admin,
+            telemetry_only: false,
+        });
+    }
+
+    if let Some(user) = state.api_tokens.lock().await.authenticate(provided_key) {
+        return Ok(user);
+    }
+
+    // anything else has to be a JWT
+    let Ok(header) = jsonwebtoken::decode_header(provided_key) else {
+        return Err(AuthFailure::new("Invalid API key or token", true));
+    };
+
+    // the server's own tokens are signed with a shared secret, whereas the OIDC provider's are
+    // signed with its private keys
+    match &CONFIG.jwt_secret {
+        Some(secret) if header.alg == Algorithm::HS256 => jsonwebtoken::decode::<Claims>(
+            provided_key,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        )
+        .map(|token_data| AuthedUser {
+            id: user_id(&token_data.claims.sub),
+            name: token_data.claims.sub,
+            role: token_data.claims.role,
+            telemetry_only: false,
+        })
+        .map_err(|error| {
+            AuthFailure::new(
+                format!("Invalid API key or token: {}", error),
+                *error.kind() == ErrorKind::InvalidSignature,
+            )
+        }),
+        _ if oidc::is_enabled() => {
+            oidc::authenticate(state, provided_key, None)
+                .await
+                .map_err(|error_message| {
+                    AuthFailure::new(
+                        format!("Invalid API key or token: {}", error_message),
+                        false,
+                    )
+                })
+        }
+        _ => Err(AuthFailure::new("Invalid API key or token", false)),
+    }
+}
+
+/// Authenticates a request, rejecting it with 401 Unauthorized if that fails, or 429 Too Many
+/// Requests if the client is locked out from failing too many times
+async fn authenticate_request(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: Option<IpAddr>,
+    path: &str,
+    required_role: Role,
+) -> Result<AuthedUser, Response> {
+    if bearer_key(headers).is_some() {
+        lockout::check(state, ip, None, path).await?;
+    }
+
+    match authenticate(state, headers, required_role).await {
+        Ok(user) => Ok(user),
+        Err(failure) => {
+            warn!("Rejected request to {}: {}", path, failure.message);
+
+            if failure.is_guess {
+                lockout::record_failure(state, AuthEventKind::InvalidCredentials, ip, None, path)
+                    .await;
+            }
+
+            Err(
+                FallibleJsonResponse::<()>::Err(StatusCode::UNAUTHORIZED, failure.message)
+                    .into_response(),
+            )
+        }
+    }
+}
+
+/// Checks that a websocket client is at least a viewer, either by redeeming the token it got from
+/// /auth/ws-token or, for clients which can set headers, by authenticating the upgrade request
+/// itself. With `WS_TOKEN_KEY` set, clients without a bearer key or token always need a websocket
+/// token, even if the viewer routes are open to anyone. Returns whether the client may only be
+/// sent telemetry, because it used a telemetry-only API token.
+pub async fn authorise_websocket(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: IpAddr,
+    path: &str,
+    ws_token: Option<&str>,
+) -> Result<bool, Response> {
+    if let Some(ws_token) = ws_token {
+        return state
+            .ws_tokens
+            .lock()
+            .await
+            .redeem(ws_token, unix_time_seconds())
+            .ok_or_else(|| {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    "Expired or already used websocket token",
+                )
+                    .into_response()
+            });
+    }
+
+    if CONFIG.ws_token_key.is_some() && bearer_key(headers).is_none() {
+        return Err((StatusCode::UNAUTHORIZED, "Missing websocket token").into_response());
+    }
+
+    authenticate_request(state, headers, Some(ip), path, Role::Viewer)
+        .await
+        .map(|user| user.telemetry_only)
+}
+
+/// The IP address a request came from, if the server is keeping track of it
+fn client_ip(extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(remote_address)| remote_address.ip())
+}
+
+/// Rejects requests from users without the role, letting the others through with an
+/// `AuthedUser` in the request's extensions
+async fn require_role(
+    state: &AppState,
+    required_role: Role,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user = match authenticate_request(
+        state,
+        request.headers(),
+        client_ip(request.extensions()),
+        request.uri().path(),
+        required_role,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    if user.role < required_role {
+        warn!("Rejected request to {} from {}", request.uri(), user);
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::FORBIDDEN,
+            "Only admins can do that".to_owned(),
+        )
+        .into_response();
+    }
+
+    if user.telemetry_only && !request.uri().path().starts_with("/telemetry/") {
+        warn!("Rejected request to {} from {}", request.uri(), user);
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::FORBIDDEN,
+            "This token can only be used for telemetry".to_owned(),
+        )
+        .into_response();
+    }
+
+    request.extensions_mut().insert(user);
+
+    next.run(request).await
+}
+
+/// Middleware for the admin routes which rejects requests from outside `ADMIN_ALLOWED_NETWORKS`,
+/// whatever credentials they have
+pub async fn require_allowed_network(
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // IPv4 clients show up as IPv4-mapped IPv6 addresses when listening on IPv6
+    let address = remote_address.ip().to_canonical();
+
+    if !CONFIG.admin_allowed_networks.is_empty()
+        && !CONFIG
+            .admin_allowed_networks
+            .iter()
+            .any(|network| network.contains(&address))
+    {
+        warn!(
+            "Rejected request to {} from {}, which isn't in ADMIN_ALLOWED_NETWORKS",
+            request.uri(),
+            address
+        );
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::FORBIDDEN,
+            "Admin routes can't be used from this network".to_owned(),
+        )
+        .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Middleware for routes which change settings or control the mesh. They're left open if none of
+/// `ADMIN_API_KEYS`, `JWT_SECRET` and OIDC are set up.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    require_role(&state, Role::Admin, request, next).await
+}
+
+/// Middleware for routes which read from the mesh. They're left open if neither `JWT_SECRET` nor
+/// OIDC is set up.
+pub async fn require_viewer(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    require_role(&state, Role::Viewer, request, next).await
+}
+
+impl FromRequestParts<AppState> for AuthedUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthedUser>() {
+            return Ok(user.clone());
+        }
+
+        authenticate_request(
+            state,
+            &parts.headers,
+            client_ip(&parts.extensions),
+            parts.uri.path(),
+            Role::Viewer,
+        )
+        .await
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginBody {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshBody {
+    refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    /// JWT to send as `Authorization: Bearer <access_token>`
+    pub access_token: String,
+    /// when the access token expires, in seconds since unix epoch
+    pub expires_at: u64,
+    pub refresh_token: String,
+    pub role: Role,
+}
+
+/// Issues an access token and refresh token for the user
+pub async fn start_session(
+    state: &AppState,
+    user: AuthedUser,
+    from_user_store: bool,
+) -> FallibleJsonResponse<SessionResponse> {
+    let Some(secret) = &CONFIG.jwt_secret else {
+        return FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            "Logging in isn't enabled".to_owned(),
+        );
+    };
+
+    let now = unix_time_seconds();
+    let expires_at = now + CONFIG.access_token_ttl_seconds;
+
+    let claims = Claims {
+        sub: user.name.clone(),
+        role: user.role,
+        exp: expires_at,
+    };
+
+    let access_token = match jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    ) {
+        Ok(access_token) => access_token,
+        Err(error) => {
+            return FallibleJsonResponse::Err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to issue access token: {}", error),
+            )
+            .log()
+        }
+    };
+
+    let role = user.role;
+    let refresh_token = state
+        .sessions
+        .lock()
+        .await
+        .start(user, from_user_store, now);
+
+    FallibleJsonResponse::Ok(SessionResponse {
+        access_token,
+        expires_at,
+        refresh_token,
+        role,
+    })
+}
+
+/// /auth/login
+pub async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    JsonBody(body): JsonBody<LoginBody>,
+) -> Response {
+    if CONFIG.jwt_secret.is_none() || CONFIG.users_file.is_none() {
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::NOT_FOUND,
+            "Logging in isn't enabled".to_owned(),
+        )
+        .into_response();
+    }
+
+    let ip = Some(remote_address.ip());
+
+    if let Err(response) = lockout::check(&state, ip, Some(&body.username), "/auth/login").await {
+        return response;
+    }
+
+    let user = state.users.lock().await.get(&body.username).cloned();
+
+    let is_password_correct = match &user {
+        Some(user) => {
+            let password_hash = user.password_hash.clone();
+
+            tokio::task::spawn_blocking(move || {
+                users::verify_password(&body.password, &password_hash)
+            })
+            .await
+            .unwrap_or(false)
+        }
+        None => false,
+    };
+
+    let Some(user) = user.filter(|_| is_password_correct) else {
+        warn!("Failed login attempt for {}", body.username);
+
+        lockout::record_failure(
+            &state,
+            AuthEventKind::FailedLogin,
+            ip,
+            Some(&body.username),
+            "/auth/login",
+        )
+        .await;
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::UNAUTHORIZED,
+            "Incorrect username or password".to_owned(),
+        )
+        .into_response();
+    };
+
+    info!("{} logged in", user.username);
+
+    lockout::record_success(&state, &user.username).await;
+
+    let user = AuthedUser {
+        id: user_id(&user.username),
+        name: user.username,
+        role: user.role,
+        telemetry_only: false,
+    };
+
+    start_session(&state, user, true).await.into_response()
+}
+
+/// /auth/refresh
+pub async fn refresh(
+    State(state): State<AppState>,
+    JsonBody(body): JsonBody<RefreshBody>,
+) -> FallibleJsonResponse<SessionResponse> {
+    let session = state
+        .sessions
+        .lock()
+        .await
+        .end(&body.refresh_token, unix_time_seconds());
+
+    let Some(session) = session else {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNAUTHORIZED,
+            "Invalid or expired refresh token".to_owned(),
+        );
+    };
+
+    let from_user_store = session.from_user_store;
+
+    let user = if from_user_store {
+        // look the user up again in case they've been removed or their role has changed
+        state
+            .users
+            .lock()
+            .await
+            .get(&session.user.name)
+            .map(|user| AuthedUser {
+                id: user_id(&user.username),
+                name: user.username.clone(),
+                role: user.role,
+                telemetry_only: false,
+            })
+    } else {
+        // OIDC users' groups are only checked again when they next log in
+        Some(session.user)
+    };
+
+    let Some(user) = user else {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNAUTHORIZED,
+            "User no longer exists".to_owned(),
+        );
+    };
+
+    start_session(&state, user, from_user_store).await
+}
+
+/// /auth/logout
+pub async fn logout(
+    State(state): State<AppState>,
+    JsonBody(body): JsonBody<RefreshBody>,
+) -> StringOrEmptyResponse {
+    if let Some(session) = state
+        .sessions
+        .lock()
+        .await
+        .end(&body.refresh_token, unix_time_seconds())
+    {
+        info!("{} logged out", session.user.name);
+    }
+
+    StringOrEmptyResponse::Ok
+}
+
+#[derive(Serialize)]
+pub struct WsTokenResponse {
+    token: String,
+    expires_at: u64,
+}
+
+/// /auth/ws-token
+pub async fn issue_ws_token(
+    State(state): State<AppState>,
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    let has_ws_token_key = CONFIG
+        .ws_token_key
+        .as_ref()
+        .is_some_and(|key| has_bearer_key(&headers, key));
+
+    // anyone who could read telemetry over HTTP can have a token, though telemetry-only API
+    // tokens get websocket tokens which are only sent telemetry
+    let (issued_to, telemetry_only) = if has_ws_token_key {
+        ("WS_TOKEN_KEY".to_owned(), false)
+    } else {
+        match authenticate_request(
+            &state,
+            &headers,
+            Some(remote_address.ip()),
+            "/auth/ws-token",
+            Role::Viewer,
+        )
+        .await
+        {
+            Ok(user) => (user.to_string(), user.telemetry_only),
+            Err(response) => return response,
+        }
+    };
+
+    let (token, expires_at) = state
+        .ws_tokens
+        .lock()
+        .await
+        .issue(unix_time_seconds(), telemetry_only);
+
+    info!(
+        "Issued websocket token to {} expiring at {}",
+        issued_to, expires_at
+    );
+
+    FallibleJsonResponse::Ok(WsTokenResponse { token, expires_at }).into_response()
+}
@@ -0,0 +1,192 @@
+use std::collections::{HashMap, VecDeque};
+
+use axum::{extract::State, Json};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    config::CONFIG, events::ServerEvent, maintenance_windows, pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::Telemetry, AppState,
+};
+
+/// Meshtastic reports a battery level of 101 for nodes running on external power
+const EXTERNAL_POWER_LEVEL: u32 = 101;
+
+#[derive(Clone, Copy, Serialize, Debug)]
+pub struct BatterySample {
+    /// seconds since unix epoch
+    pub timestamp: u64,
+    pub level: u32,
+    pub voltage: Option<f32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeWarningKind {
+    LowBattery,
+    PredictedDepletion,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct NodeWarning {
+    pub node_id: NodeId,
+    pub kind: NodeWarningKind,
+    pub battery_level: u32,
+    /// negative when the battery is charging
+    pub drain_percent_per_hour: Option<f32>,
+    pub hours_remaining: Option<f32>,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+}
+
+/// Least squares slope of battery level over time, in percent per hour (positive means draining).
+/// Returns `None` if there isn't enough of a time span to say anything useful.
+pub fn drain_rate_percent_per_hour<'a>(
+    samples: impl Iterator<Item = &'a BatterySample> + Clone,
+) -> Option<f32> {
+    let count = samples.clone().count() as f64;
+
+    if count < 2.0 {
+        return None;
+    }
+
+    let mean_time = samples.clone().map(|s| s.timestamp as f64).sum::<f64>() / count;
+    let mean_level = samples.clone().map(|s| s.level as f64).sum::<f64>() / count;
+
+    let (covariance, variance) = samples.fold((0.0, 0.0), |(covariance, variance), sample| {
+        let dt = sample.timestamp as f64 - mean_time;
+        let dl = sample.level as f64 - mean_level;
+        (covariance + dt * dl, variance + dt * dt)
+    });
+
+    if variance == 0.0 {
+        return None;
+    }
+
+    // slope is in percent per second, flip the sign so that draining is positive
+    Some((-(covariance / variance) * 3600.0) as f32)
+}
+
+/// Keeps a window of battery readings per node and derives warnings from them
+#[derive(Default)]
+pub struct BatteryTracker {
+    samples: HashMap<NodeId, VecDeque<BatterySample>>,
+    warnings: HashMap<NodeId, Vec<NodeWarning>>,
+}
+
+impl BatteryTracker {
+    pub fn warnings(&self) -> impl Iterator<Item = &NodeWarning> {
+        self.warnings.values().flatten()
+    }
+
+    /// Drain rate over the trend window in percent per hour (positive means draining)
+    pub fn drain_rate(&self, node_id: NodeId) -> Option<f32> {
+        drain_rate_percent_per_hour(self.samples.get(&node_id)?.iter())
+    }
+
+    /// Records the battery reading from a telemetry packet (if it has one) and returns any
+    /// warnings which weren't already active for the node
+    pub fn record(&mut self, telemetry: &Telemetry) -> Vec<NodeWarning> {
+        let Some(device_metrics) = telemetry.device_metrics else {
+            return Vec::new();
+        };
+
+        let Some(level) = device_metrics.battery_level else {
+            return Vec::new();
+        };
+
+        let node_id = telemetry.node_num;
+        let window_seconds = CONFIG.battery_trend_window_hours * 60 * 60;
+
+        let samples = self.samples.entry(node_id).or_default();
+
+        samples.push_back(BatterySample {
+            timestamp: telemetry.timestamp,
+            level,
+            voltage: device_metrics.voltage,
+        });
+
+        while samples.front().is_some_and(|oldest| {
+            telemetry.timestamp.saturating_sub(oldest.timestamp) > window_seconds
+        }) {
+            samples.pop_front();
+        }
+
+        let mut current_warnings = Vec::new();
+
+        if level < EXTERNAL_POWER_LEVEL {
+            let drain_percent_per_hour = drain_rate_percent_per_hour(samples.iter());
+            let hours_remaining = drain_percent_per_hour
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| level as f32 / rate);
+
+            let warning = |kind| NodeWarning {
+                node_id,
+                kind,
+                battery_level: level,
+                drain_percent_per_hour,
+                hours_remaining,
+                timestamp: telemetry.timestamp,
+            };
+
+            if level < CONFIG.low_battery_threshold_percent {
+                current_warnings.push(warning(NodeWarningKind::LowBattery));
+            }
+
+            if hours_remaining
+                .is_some_and(|hours| hours < (CONFIG.battery_depletion_warning_days * 24) as f32)
+            {
+                current_warnings.push(warning(NodeWarningKind::PredictedDepletion));
+            }
+        }
+
+        let previous_warnings = self.warnings.remove(&node_id).unwrap_or_default();
+
+        let new_warnings = current_warnings
+            .iter()
+            .filter(|warning| {
+                !previous_warnings
+                    .iter()
+                    .any(|previous| previous.kind == warning.kind)
+            })
+            .cloned()
+            .collect();
+
+        if !current_warnings.is_empty() {
+            self.warnings.insert(node_id, current_warnings);
+        }
+
+        new_warnings
+    }
+}
+
+/// Pushes newly raised warnings to live websocket clients
+pub async fn dispatch(state: &AppState, warnings: Vec<NodeWarning>) {
+    for warning in warnings {
+        info!(
+            "Node {} warning: {:?} (battery {}%, {:?} hours remaining)",
+            warning.node_id, warning.kind, warning.battery_level, warning.hours_remaining
+        );
+
+        let event = ServerEvent::NodeWarning(warning);
+
+        if maintenance_windows::mute(state, &event).await {
+            continue;
+        }
+
+        let _ = state.server_events.send(event);
+    }
+}
+
+/// /info/node-warnings
+pub async fn get_node_warnings(State(state): State<AppState>) -> Json<Vec<NodeWarning>> {
+    Json(
+        state
+            .battery_tracker
+            .lock()
+            .await
+            .warnings()
+            .cloned()
+            .collect(),
+    )
+}
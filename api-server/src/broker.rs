@@ -0,0 +1,70 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use log::{error, info, warn};
+use rumqttd::{Broker, ConnectionSettings, RouterConfig, ServerSettings};
+
+use crate::config::CONFIG;
+
+/// Starts an MQTT broker in its own threads, listening on `MQTT_PORT` at
+/// `EMBEDDED_MQTT_BROKER_ADDRESS` (only on localhost by default). It accepts the server's
+/// credentials and the gateways' ones if they're set. It's meant for trying the server out or
+/// small deployments, and has none of Mosquitto's ACLs or TLS.
+pub fn start() {
+    let listen = SocketAddr::from((CONFIG.embedded_mqtt_broker_address, CONFIG.mqtt_port));
+
+    let mut credentials =
+        HashMap::from([(CONFIG.mqtt_username.clone(), CONFIG.mqtt_password.clone())]);
+
+    match (
+        &CONFIG.embedded_mqtt_broker_gateway_username,
+        &CONFIG.embedded_mqtt_broker_gateway_password,
+    ) {
+        (Some(username), Some(password)) => {
+            credentials.insert(username.clone(), password.clone());
+        }
+        _ if !listen.ip().is_loopback() => {
+            warn!("EMBEDDED_MQTT_BROKER_GATEWAY_USERNAME and EMBEDDED_MQTT_BROKER_GATEWAY_PASSWORD aren't set, so gateways have to log in to the embedded broker with the server's credentials");
+        }
+        _ => {}
+    }
+
+    let server = ServerSettings {
+        name: "embedded".to_owned(),
+        listen,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 60_000,
+            max_payload_size: 256 * 1024,
+            max_inflight_count: 100,
+            auth: Some(credentials),
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+
+    let config = rumqttd::Config {
+        router: RouterConfig {
+            max_connections: 1000,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 100 * 1024 * 1024,
+            max_segment_count: 10,
+            ..Default::default()
+        },
+        v4: Some(HashMap::from([("embedded".to_owned(), server)])),
+        ..Default::default()
+    };
+
+    let mut broker = Broker::new(config);
+
+    std::thread::Builder::new()
+        .name("mqtt-broker".to_owned())
+        .spawn(move || {
+            if let Err(error) = broker.start() {
+                error!("Embedded MQTT broker stopped: {:?}", error);
+            }
+        })
+        .expect("Failed to start the embedded MQTT broker");
+
+    info!("Started embedded MQTT broker on {}", listen);
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    command_history::{self, CommandOutcome},
+    pathfinding::NodeId,
+    persistence,
+    proto::meshtastic::{crisislab_message, CrisislabMessage},
+    utils::{await_mesh_response, send_command_protobuf, unix_time_seconds, FallibleJsonResponse},
+    AppState,
+};
+
+/// The sensors and modules a node says it has, kept in the node registry so that clients only
+/// offer actions the node supports
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct NodeCapabilities {
+    /// e.g. "seismometer" or "bme280"
+    pub sensors: Vec<String>,
+    /// e.g. "relay_output" or "siren"
+    pub modules: Vec<String>,
+    /// seconds since unix epoch that the node was first heard reporting these
+    pub since: u64,
+}
+
+/// Records the capabilities a node reported in the node registry
+pub async fn record_capabilities(state: &AppState, capabilities: crisislab_message::Capabilities) {
+    let node_id = capabilities.node_num;
+
+    if !state.node_registry.lock().await.record_capabilities(
+        node_id,
+        capabilities.sensors,
+        capabilities.modules,
+        unix_time_seconds(),
+    ) {
+        return;
+    }
+
+    info!("Node {}'s capabilities have changed", node_id);
+
+    if let Err(error_message) = persistence::save_node_registry(state).await {
+        error!("{}", error_message);
+    }
+}
+
+/// /admin/nodes/{id}/query-capabilities
+pub async fn query_capabilities(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> FallibleJsonResponse<NodeCapabilities> {
+    info!("{} is asking node {} for its capabilities", user, node_id);
+
+    // subscribe before sending the request so that a quick response can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+
+    let crisislab_message = CrisislabMessage {
+        message: Some(crisislab_message::Message::GetCapabilities(
+            crisislab_message::Empty {},
+        )),
+        destination: Some(node_id),
+    };
+
+    let sent_at = unix_time_seconds();
+
+    if let Err(error_message) =
+        send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await
+    {
+        command_history::record(
+            &state,
+            node_id,
+            &crisislab_message,
+            &user.name,
+            sent_at,
+            CommandOutcome::Failed,
+            Some(error_message.clone()),
+        )
+        .await;
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let timeout_duration =
+        Duration::from_secs(state.app_settings.lock().await.command_ack_timeout_seconds);
+
+    match await_mesh_response(
+        &mut mesh_receiver,
+        timeout_duration,
+        |message| match message.message {
+            Some(crisislab_message::Message::Capabilities(capabilities))
+                if capabilities.node_num == node_id =>
+            {
+                Some(capabilities)
+            }
+            _ => None,
+        },
+    )
+    .await
+    {
+        Ok(capabilities) => {
+            command_history::record(
+                &state,
+                node_id,
+                &crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::Responded,
+                None,
+            )
+            .await;
+
+            let reported = NodeCapabilities {
+                sensors: capabilities.sensors.clone(),
+                modules: capabilities.modules.clone(),
+                since: unix_time_seconds(),
+            };
+
+            // the ingest task records them too, but it may not have got to them yet
+            record_capabilities(&state, capabilities).await;
+
+            FallibleJsonResponse::Ok(
+                state
+                    .node_registry
+                    .lock()
+                    .await
+                    .nodes()
+                    .get(&node_id)
+                    .and_then(|info| info.capabilities.clone())
+                    .unwrap_or(reported),
+            )
+        }
+        Err(error_message) => {
+            command_history::record(
+                &state,
+                node_id,
+                &crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::TimedOut,
+                Some(error_message.clone()),
+            )
+            .await;
+
+            FallibleJsonResponse::Err(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "Node {} didn't report its capabilities: {}",
+                    node_id, error_message
+                ),
+            )
+            .log()
+        }
+    }
+}
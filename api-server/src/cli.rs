@@ -0,0 +1,58 @@
+use clap::{Parser, Subcommand};
+
+/// The server between the MQTT broker and CRISiSLab's Meshtastic Portal. Settings not given here
+/// are read from environment variables and the config file.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// The TOML config file, instead of `config.toml` (same as CONFIG_FILE)
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// The port to serve the API on (same as SERVER_PORT)
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Which logs to show, e.g. `debug` or `info,api_server=debug` (same as RUST_LOG)
+    #[arg(long, value_name = "FILTER")]
+    pub log_level: Option<String>,
+
+    /// Run an MQTT broker in the server on MQTT_PORT (same as EMBEDDED_MQTT_BROKER=true)
+    #[arg(long)]
+    pub embedded_broker: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Check the config is valid, then exit
+    CheckConfig,
+    /// Read a password from stdin and print its hash for USERS_FILE
+    HashPassword,
+}
+
+impl Cli {
+    /// Applies the arguments which stand in for settings by setting their environment variables,
+    /// which take precedence over the config file. This has to happen before the config is read,
+    /// and before the tokio runtime starts, since setting environment variables while other
+    /// threads might be reading them is undefined behaviour.
+    pub fn apply_to_env(&self) {
+        if let Some(config) = &self.config {
+            std::env::set_var("CONFIG_FILE", config);
+        }
+
+        if let Some(port) = self.port {
+            std::env::set_var("SERVER_PORT", port.to_string());
+        }
+
+        if let Some(log_level) = &self.log_level {
+            std::env::set_var("RUST_LOG", log_level);
+        }
+
+        if self.embedded_broker {
+            std::env::set_var("EMBEDDED_MQTT_BROKER", "true");
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG,
+    drill, outbox,
+    pathfinding::NodeId,
+    persistence,
+    proto::meshtastic::{crisislab_message::Message, CrisislabMessage},
+    utils::{send_command_protobuf, unix_time_seconds},
+    AppState,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandOutcome {
+    /// published to the mesh, for commands nodes don't reply to
+    Sent,
+    /// the node replied or acknowledged it
+    Responded,
+    /// the node didn't reply in time
+    TimedOut,
+    /// it couldn't be published
+    Failed,
+    /// waiting for a second admin to approve it
+    HeldForApproval,
+    /// the node was offline, so it's waiting in the outbox until the node is heard from
+    Queued,
+    /// it was still in the outbox when it expired
+    Expired,
+    /// it was removed from the outbox before it was published
+    Cancelled,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CommandRecord {
+    pub node_id: NodeId,
+    /// the CrisislabMessage's `message`, e.g. "reboot" or "mesh_settings"
+    pub command: String,
+    /// the contents of the message, e.g. the settings that were sent
+    pub details: Option<serde_json::Value>,
+    pub sent_by: String,
+    /// seconds since unix epoch
+    pub sent_at: u64,
+    pub outcome: CommandOutcome,
+    /// seconds since unix epoch that the outcome was known
+    pub finished_at: u64,
+    pub error: Option<String>,
+    /// whether drill mode was on when it was sent
+    #[serde(default)]
+    pub drill: bool,
+}
+
+/// The commands the server has sent to each node, oldest first, up to `COMMAND_HISTORY_PER_NODE`
+/// each. They're saved to `command-history.json` in the data directory whenever one is recorded.
+#[derive(Default)]
+pub struct CommandHistory {
+    commands: BTreeMap<NodeId, VecDeque<CommandRecord>>,
+}
+
+impl CommandHistory {
+    pub fn restore(&mut self, records: Vec<CommandRecord>) {
+        self.commands.clear();
+
+        for record in records {
+            self.push(record);
+        }
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &CommandRecord> {
+        self.commands.values().flatten()
+    }
+
+    fn push(&mut self, record: CommandRecord) {
+        let records = self.commands.entry(record.node_id).or_default();
+
+        records.push_back(record);
+
+        while records.len() > CONFIG.command_history_per_node {
+            records.pop_front();
+        }
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.commands.remove(&node_id);
+    }
+}
+
+pub fn command_name(message: &Message) -> &'static str {
+    match message {
+        Message::MeshSettings(_) => "mesh_settings",
+        Message::GetMeshSettingsRequest(_) => "get_mesh_settings_request",
+        Message::ServerSettings(_) => "server_settings",
+        Message::UpdateNextHopsRequest(_) => "update_next_hops_request",
+        Message::Ping(_) => "ping",
+        Message::SignalData(_) => "signal_data",
+        Message::UpdatedNextHops(_) => "updated_next_hops",
+        Message::StartLiveTelemetry(_) => "start_live_telemetry",
+        Message::StopLiveTelemetry(_) => "stop_live_telemetry",
+        Message::Telemetry(_) => "telemetry",
+        Message::GetAdHocTelemetry(_) => "get_ad_hoc_telemetry",
+        Message::Reboot(_) => "reboot",
+        Message::Shutdown(_) => "shutdown",
+        Message::CommandAck(_) => "command_ack",
+        Message::FirmwareUpdate(_) => "firmware_update",
+        Message::FirmwareUpdateProgress(_) => "firmware_update_progress",
+        Message::SetPosition(_) => "set_position",
+        Message::DiscoveryRequest(_) => "discovery_request",
+        Message::DiscoveryResponse(_) => "discovery_response",
+        Message::GetCapabilities(_) => "get_capabilities",
+        Message::Capabilities(_) => "capabilities",
+        Message::Traceroute(_) => "traceroute",
+        Message::TracerouteResponse(_) => "traceroute_response",
+        Message::EchoRequest(_) => "echo_request",
+        Message::EchoReply(_) => "echo_reply",
+        Message::TextMessage(_) => "text_message",
+        Message::DeliveryReport(_) => "delivery_report",
+        Message::EmergencyAlert(_) => "emergency_alert",
+        Message::AlertAck(_) => "alert_ack",
+        Message::Actuate(_) => "actuate",
+    }
+}
+
+/// The fields of whichever message is inside the oneof, leaving out empty ones
+pub fn command_details(message: &Message) -> Option<serde_json::Value> {
+    match serde_json::to_value(message).ok()? {
+        serde_json::Value::Object(variant) => variant
+            .into_iter()
+            .next()
+            .map(|(_, fields)| fields)
+            .filter(|fields| fields.as_object().is_none_or(|fields| !fields.is_empty())),
+        _ => None,
+    }
+}
+
+/// Records a command sent to a node. `sent_at` is when it was published, since some commands wait
+/// for a response before their outcome is known.
+pub async fn record(
+    state: &AppState,
+    node_id: NodeId,
+    crisislab_message: &CrisislabMessage,
+    sent_by: &str,
+    sent_at: u64,
+    outcome: CommandOutcome,
+    error: Option<String>,
+) {
+    let Some(message) = &crisislab_message.message else {
+        return;
+    };
+
+    let drill = drill::is_on(state).await;
+
+    state.command_history.lock().await.push(CommandRecord {
+        node_id,
+        command: command_name(message).to_owned(),
+        details: command_details(message),
+        sent_by: sent_by.to_owned(),
+        sent_at,
+        outcome,
+        finished_at: unix_time_seconds(),
+        error,
+        drill,
+    });
+
+    if let Err(error_message) = persistence::save_command_history(state).await {
+        error!("{}", error_message);
+    }
+}
+
+/// Publishes a command for a node which it doesn't reply to, and records it. Commands for nodes
+/// which are offline are queued in the outbox until the node is heard from, in which case
+/// `CommandOutcome::Queued` is returned.
+pub async fn send_to_node(
+    state: &AppState,
+    node_id: NodeId,
+    crisislab_message: CrisislabMessage,
+    sent_by: &str,
+) -> Result<CommandOutcome, String> {
+    if outbox::is_offline(state, node_id).await {
+        outbox::queue(state, node_id, crisislab_message, sent_by).await?;
+
+        return Ok(CommandOutcome::Queued);
+    }
+
+    let sent_at = unix_time_seconds();
+    let result = send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await;
+
+    let (outcome, error) = match &result {
+        Ok(()) => (CommandOutcome::Sent, None),
+        Err(error_message) => (CommandOutcome::Failed, Some(error_message.clone())),
+    };
+
+    record(
+        state,
+        node_id,
+        &crisislab_message,
+        sent_by,
+        sent_at,
+        outcome,
+        error,
+    )
+    .await;
+
+    result.map(|()| CommandOutcome::Sent)
+}
+
+/// /nodes/{id}/commands
+pub async fn get_node_commands(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+) -> Json<Vec<CommandRecord>> {
+    Json(
+        state
+            .command_history
+            .lock()
+            .await
+            .commands
+            .get(&node_id)
+            .map(|records| records.iter().rev().cloned().collect())
+            .unwrap_or_default(),
+    )
+}
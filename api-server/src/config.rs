@@ -1,7 +1,23 @@
-use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    ops::Deref,
+    str::FromStr,
+    sync::{PoisonError, RwLock},
+};
+
+use axum::http::HeaderValue;
+use ipnet::IpNet;
+use once_cell::sync::OnceCell;
 use rumqttc::mqttbytes::QoS;
+use serde::Serialize;
+use toml::{Table, Value};
 
-use crate::pathfinding::EdgeWeight;
+use crate::{
+    pathfinding::{EdgeWeight, NodeId},
+    vault,
+};
 
 pub struct Config {
     pub mqtt_username: String,
@@ -11,63 +27,1069 @@ pub struct Config {
     pub mqtt_qos: QoS,
     pub mqtt_outgoing_topic: String,
     pub mqtt_incoming_topic: String,
+    /// runs an MQTT broker in the server on `mqtt_port`, which the server connects to itself
+    pub embedded_mqtt_broker: bool,
+    /// the address the embedded broker listens on, which only lets the server itself connect
+    /// unless it's changed (e.g. to `0.0.0.0`) for the gateways
+    pub embedded_mqtt_broker_address: IpAddr,
+    /// if both are set, the gateways can log in to the embedded broker with these rather than the
+    /// server's credentials
+    pub embedded_mqtt_broker_gateway_username: Option<String>,
+    pub embedded_mqtt_broker_gateway_password: Option<String>,
     pub channel_capacity: usize,
     pub server_port: u16,
+    /// if both are set, the server is served over HTTPS using this PEM certificate and key
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// when serving over HTTPS, plain HTTP requests to this port are redirected to HTTPS
+    pub http_redirect_port: Option<u16>,
     pub default_get_settings_timeout_seconds: u64,
     pub default_signal_data_timeout_seconds: u64,
     pub default_route_cost_weight: EdgeWeight,
     pub default_route_hops_weight: EdgeWeight,
+    pub default_sensor_relay_penalty: EdgeWeight,
     pub telemetry_cache_capacity: usize,
+    /// telemetry which falls out of the cache is kept in a more compact form, 0 disables this
+    pub telemetry_archive_capacity: usize,
+    /// telemetry older than this is moved from the cache to the archive (if it's enabled)
+    pub telemetry_archive_after_minutes: u64,
     pub default_ad_hoc_telemetry_timeout_seconds: u64,
+    pub default_command_ack_timeout_seconds: u64,
+    pub default_discovery_timeout_seconds: u64,
+    pub telemetry_gap_threshold_seconds: u64,
+    pub alert_webhook_urls: Vec<String>,
+    pub alert_history_capacity: usize,
+    pub low_battery_threshold_percent: u32,
+    pub battery_depletion_warning_days: u64,
+    pub battery_trend_window_hours: u64,
+    pub node_offline_after_seconds: u64,
+    pub expected_nodes_check_seconds: u64,
+    /// clients are told the mesh has gone quiet if no gateway is heard from for this long
+    pub gateway_silence_seconds: u64,
+    pub position_history_capacity: usize,
+    /// seismic data is ignored if this isn't set
+    pub mqtt_seismic_topic: Option<String>,
+    pub seismic_buffer_samples: usize,
+    pub seismic_channel_capacity: usize,
+    /// a Raspberry Shake's UDP datacast is received on this address (e.g. `0.0.0.0:8888`) if it's
+    /// set, and its data is treated as coming from `raspberry_shake_node_id`
+    pub raspberry_shake_udp_address: Option<String>,
+    pub raspberry_shake_node_id: Option<NodeId>,
+    /// counts per m/s^2 of the Shake's accelerometer
+    pub raspberry_shake_sensitivity: f64,
+    pub raspberry_shake_sample_rate_hz: f64,
+    /// the STA/LTA detector's averaging windows
+    pub sta_lta_short_seconds: f64,
+    pub sta_lta_long_seconds: f64,
+    /// the detector triggers once the ratio of the averages reaches this, and the trigger ends once
+    /// it falls below `sta_lta_detrigger_ratio`
+    pub sta_lta_trigger_ratio: f64,
+    pub sta_lta_detrigger_ratio: f64,
+    pub seismic_trigger_history_capacity: usize,
+    /// the peak ground acceleration (in m/s^2) a triggered node has to feel to count towards a
+    /// shake event
+    pub shake_trigger_pga: f64,
+    /// how many nodes have to feel it within `shake_window_seconds` for it to be a shake event
+    pub shake_min_nodes: usize,
+    pub shake_window_seconds: u64,
+    pub seismic_event_history_capacity: usize,
+    pub anomaly_z_score_threshold: f32,
+    pub anomaly_window_size: usize,
+    pub anomaly_min_samples: usize,
+    pub anomaly_history_capacity: usize,
+    /// clients must get a token from /auth/ws-token using this key before connecting to the live
+    /// websocket, which is open to anyone if it isn't set
+    pub ws_token_key: Option<String>,
+    pub ws_token_ttl_seconds: u64,
+    /// keys which are allowed to use the admin routes, which are open to anyone if there aren't
+    /// any
+    pub admin_api_keys: Vec<String>,
+    /// networks which admin routes can be called from, regardless of credentials. Admin routes
+    /// can be called from anywhere if there aren't any.
+    pub admin_allowed_networks: Vec<IpNet>,
+    /// secret used to verify the HS256 JWTs users can authenticate with, whose `role` claim is
+    /// either `admin` or `viewer`. Viewer routes are open to anyone if it isn't set.
+    pub jwt_secret: Option<String>,
+    /// JSON file of users who can log in to get a JWT, see `users::User`
+    pub users_file: Option<String>,
+    pub access_token_ttl_seconds: u64,
+    /// OpenID Connect provider users can log in with, e.g.
+    /// `https://login.microsoftonline.com/<tenant>/v2.0` for Azure AD
+    pub oidc_issuer_url: Option<String>,
+    pub oidc_client_id: Option<String>,
+    pub oidc_client_secret: Option<String>,
+    /// the URL of /auth/oidc/callback, as registered with the provider
+    pub oidc_redirect_url: Option<String>,
+    /// the audience provider-issued tokens sent to the API must have, defaults to the client ID
+    pub oidc_audience: Option<String>,
+    /// the claim listing a user's groups, such as `groups` or `roles`
+    pub oidc_groups_claim: String,
+    pub oidc_commander_groups: Vec<String>,
+    pub oidc_admin_groups: Vec<String>,
+    pub oidc_viewer_groups: Vec<String>,
+    /// where to send users after they log in with OIDC, with their tokens in the URL's fragment
+    pub oidc_dashboard_url: Option<String>,
+    pub refresh_token_ttl_seconds: u64,
+    /// origins which browsers are allowed to call the API from, or `*` to allow any origin while
+    /// developing
+    pub cors_allowed_origins: Vec<String>,
+    /// key shared with the gateways which commands are signed with, if set. Gateways must be
+    /// expecting signed commands, since they're wrapped in a `SignedCrisislabMessage`.
+    pub mesh_signing_key: Option<Vec<u8>>,
+    /// sent alongside signatures so that gateways can tell which key was used while rotating keys
+    pub mesh_signing_key_id: u32,
+    /// 32 byte AES-256-GCM key shared with the gateways, if set. CrisislabMessages are encrypted
+    /// with it before being published, and messages from the mesh must be encrypted with it.
+    pub mesh_encryption_key: Option<Vec<u8>>,
+    pub mesh_encryption_key_id: u32,
+    /// larger request bodies are rejected with 413 Payload Too Large
+    pub max_request_body_bytes: usize,
+    /// requests which haven't been responded to after this long (including reading the body) are
+    /// abandoned, so it should be longer than the timeouts for waiting on the mesh
+    pub request_timeout_seconds: u64,
+    /// how many requests each client can make to each expensive route (such as
+    /// /admin/update-routes) in every window
+    pub rate_limit_max_requests: u32,
+    pub rate_limit_window_seconds: u64,
+    /// how many times a client (an IP address or username) can fail to authenticate before being
+    /// locked out
+    pub lockout_free_attempts: u32,
+    /// the first lockout, which doubles with every further failure
+    pub lockout_base_seconds: u64,
+    pub lockout_max_seconds: u64,
+    pub auth_event_history_capacity: usize,
+    /// whether high-impact commands (such as channel changes) have to be approved by a second
+    /// admin before they're published
+    pub dual_control: bool,
+    pub pending_action_ttl_seconds: u64,
+    /// how many messages can be waiting to be sent to a websocket client before telemetry starts
+    /// being dropped
+    pub websocket_queue_capacity: usize,
+    /// how many recent packets are kept for clients resuming after a reconnect
+    pub websocket_resume_capacity: usize,
+    pub websocket_ping_interval_seconds: u64,
+    pub websocket_max_missed_pongs: u32,
+    /// telemetry is sent to a websocket client in batches while it's arriving faster than this,
+    /// 0 disables batching
+    pub websocket_batch_rate_per_second: u32,
+    pub websocket_batch_interval_ms: u64,
+    /// clients which send more than this are disconnected
+    pub websocket_max_inbound_messages_per_second: u32,
+    pub websocket_max_inbound_message_bytes: usize,
+    /// where state that should survive restarts is kept
+    pub data_directory: String,
+    /// how often nodes are expected to report telemetry, used to work out uptime
+    pub expected_report_interval_seconds: u64,
+    /// how far back telemetry regularity and reboots are looked at for health scores
+    pub health_window_hours: u64,
+    pub health_recalculation_seconds: u64,
+    /// how many of the most recent commands sent to each node are kept
+    pub command_history_per_node: usize,
+    /// how much each new signal reading moves a link's smoothed weight, from 0 (never) to 1
+    /// (replaced by the latest reading)
+    pub link_weight_smoothing: EdgeWeight,
+    /// longer text messages are rejected, since they have to fit in a single mesh packet
+    pub text_message_max_bytes: usize,
+    /// how many recent messages have their delivery tracked
+    pub message_history_capacity: usize,
+    /// nodes which haven't acknowledged a message after this long are counted as failed
+    pub message_delivery_timeout_seconds: u64,
+    /// commands queued for offline nodes are dropped if the node isn't heard from within this long
+    pub outbox_ttl_seconds: u64,
+    /// commands for an offline node are rejected once this many are queued for it
+    pub outbox_max_commands_per_node: usize,
+    /// how often active emergency alerts are published again, for nodes which missed them
+    pub emergency_alert_repeat_seconds: u64,
+    /// how long emergency alerts last if the request doesn't say
+    pub emergency_alert_default_duration_seconds: u64,
+    /// the longest a siren or relay can be started for in one go
+    pub actuation_max_duration_seconds: u32,
+    /// how many earthquake early warning decisions are kept
+    pub eew_decision_history_capacity: usize,
+    /// how long an earthquake is remembered for after an alert is broadcast for it, so that later
+    /// warnings about it are duplicates
+    pub eew_dedupe_window_seconds: u64,
+    /// how far a signed webhook's timestamp can be from now before it's rejected as a replay
+    pub webhook_max_age_seconds: u64,
+    /// each emergency alert's delivery report is POSTed here when the alert ends, if it's set
+    pub delivery_report_webhook_url: Option<String>,
+    /// for outgoing HTTP requests, e.g. webhooks and OIDC discovery
+    pub http_connect_timeout_seconds: u64,
+    /// for outgoing HTTP requests, including reading the response
+    pub http_request_timeout_seconds: u64,
+    /// how many outgoing webhook requests can be in flight at once, the rest wait for a turn
+    pub webhook_max_concurrent_deliveries: usize,
+}
+
+/// The config file is read from here if `CONFIG_FILE` isn't set, and it's fine for it not to exist
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Settings which are only used when the server starts, so changing them in the config file
+/// doesn't do anything until it's restarted
+const RESTART_REQUIRED: &[&str] = &[
+    "MQTT_USERNAME",
+    "MQTT_PASSWORD",
+    "MQTT_HOST",
+    "MQTT_PORT",
+    "MQTT_QOS",
+    "MQTT_OUTGOING_TOPIC",
+    "MQTT_INCOMING_TOPIC",
+    "MQTT_SEISMIC_TOPIC",
+    "EMBEDDED_MQTT_BROKER",
+    "EMBEDDED_MQTT_BROKER_ADDRESS",
+    "EMBEDDED_MQTT_BROKER_GATEWAY_USERNAME",
+    "EMBEDDED_MQTT_BROKER_GATEWAY_PASSWORD",
+    "CHANNEL_CAPACITY",
+    "SEISMIC_CHANNEL_CAPACITY",
+    "SERVER_PORT",
+    "TLS_CERT_PATH",
+    "TLS_KEY_PATH",
+    "HTTP_REDIRECT_PORT",
+    "TELEMETRY_CACHE_CAPACITY",
+    "TELEMETRY_ARCHIVE_CAPACITY",
+    "SEISMIC_BUFFER_SAMPLES",
+    "ANOMALY_HISTORY_CAPACITY",
+    "AUTH_EVENT_HISTORY_CAPACITY",
+    "RASPBERRY_SHAKE_UDP_ADDRESS",
+    "RASPBERRY_SHAKE_NODE_ID",
+    "DATA_DIRECTORY",
+    "USERS_FILE",
+    "JWT_SECRET",
+    "OIDC_ISSUER_URL",
+    "OIDC_CLIENT_ID",
+    "MAX_REQUEST_BODY_BYTES",
+    "HTTP_CONNECT_TIMEOUT_SECONDS",
+    "HTTP_REQUEST_TIMEOUT_SECONDS",
+    "WEBHOOK_MAX_CONCURRENT_DELIVERIES",
+];
+
+/// Something wrong with a setting
+struct ConfigProblem {
+    name: String,
+    /// what's wrong with it, e.g. `isn't set` or `is "abc"`
+    issue: String,
+    /// what it should be, e.g. `a whole number`
+    expected: String,
+    example: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}. It should be {}, e.g. {}",
+            self.name, self.issue, self.expected, self.example
+        )
+    }
+}
+
+/// Everything wrong with the settings, so that they can all be fixed at once rather than one
+/// restart at a time
+struct ConfigProblems(Vec<ConfigProblem>);
+
+impl fmt::Display for ConfigProblems {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.len() {
+            1 => writeln!(f, "There's a problem with the config:")?,
+            count => writeln!(f, "There are {} problems with the config:", count)?,
+        }
+
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+
+        write!(
+            f,
+            "Settings can be set as environment variables, or in the config file in lowercase (e.g. \
+             mqtt_host = \"localhost\")"
+        )
+    }
+}
+
+/// What a setting parsed as `T` should look like, for describing problems with it
+fn describe_type<T>() -> &'static str {
+    match std::any::type_name::<T>() {
+        "u16" => "a whole number from 0 to 65535",
+        "u32" | "u64" | "usize" => "a whole number",
+        "f32" | "f64" => "a number",
+        "bool" => "true or false",
+        _ => "valid",
+    }
+}
+
+fn read_config_file() -> Result<Table, ConfigProblem> {
+    let (path, required) = match std::env::var("CONFIG_FILE") {
+        Ok(path) => (path, true),
+        Err(_) => (DEFAULT_CONFIG_FILE.to_owned(), false),
+    };
+
+    let problem = |issue: String| ConfigProblem {
+        name: "CONFIG_FILE".to_owned(),
+        issue,
+        expected: "the path of a TOML file".to_owned(),
+        example: DEFAULT_CONFIG_FILE.to_owned(),
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => contents.parse::<Table>().map_err(|error| {
+            problem(format!(
+                "is {:?}, which isn't valid TOML ({}{})",
+                path,
+                error.message().trim().replace('\n', ", "),
+                error
+                    .span()
+                    .map(|span| format!(
+                        " on line {}",
+                        contents[..span.start].matches('\n').count() + 1
+                    ))
+                    .unwrap_or_default()
+            ))
+        }),
+        Err(error) if !required && error.kind() == std::io::ErrorKind::NotFound => Ok(Table::new()),
+        Err(error) => Err(problem(format!(
+            "is {:?}, which couldn't be read: {}",
+            path, error
+        ))),
+    }
+}
+
+/// A value from the config file as the string its environment variable would have, with arrays
+/// joined with commas. Tables and dates don't have one.
+fn setting_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => Some(value.to_string()),
+        Value::Array(items) => items
+            .iter()
+            .map(setting_to_string)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        Value::Datetime(_) | Value::Table(_) => None,
+    }
+}
+
+fn get_optional_env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Reads a secret from a file, such as a Docker secret, ignoring a trailing newline
+pub fn read_secret_file(name: &str, path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path)
+        .map(|secret| secret.trim_end_matches(['\r', '\n']).to_owned())
+        .map_err(|error| format!("Failed to read {} from {}: {}", name, path, error))
+}
+
+fn split_on_commas(value: String) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
 }
 
-fn get_env_var(name: &str) -> String {
-    std::env::var(name).expect(&format!("Environment variable {}", name))
+/// Reads settings from the environment and the config file, keeping track of what each one was so
+/// that reloads can tell which have changed
+struct SettingsReader {
+    file: Table,
+    /// settings which have to keep the values they had when the server started
+    pinned: BTreeMap<String, Option<String>>,
+    /// what each setting is set to now
+    current: BTreeMap<String, Option<String>>,
+    /// what each setting is in the config being read, which is the same as `current` apart from
+    /// pinned settings
+    in_effect: BTreeMap<String, Option<String>>,
+    /// invalid settings are recorded here and replaced with a default, so that reading carries on
+    /// and finds every problem
+    problems: Vec<ConfigProblem>,
 }
 
-fn qos_from_str(string: &str) -> Result<QoS, String> {
+impl SettingsReader {
+    fn new(pinned: BTreeMap<String, Option<String>>) -> Self {
+        let mut problems = Vec::new();
+
+        let file = read_config_file().unwrap_or_else(|problem| {
+            problems.push(problem);
+            Table::new()
+        });
+
+        SettingsReader {
+            file,
+            pinned,
+            current: BTreeMap::new(),
+            in_effect: BTreeMap::new(),
+            problems,
+        }
+    }
+
+    fn problem(&mut self, name: &str, issue: String, expected: &str, example: &str) {
+        self.problems.push(ConfigProblem {
+            name: name.to_owned(),
+            issue,
+            expected: expected.to_owned(),
+            example: example.to_owned(),
+        });
+    }
+
+    fn record(&mut self, name: &str, value: Option<String>) -> Option<String> {
+        let in_effect = match self.pinned.get(name) {
+            Some(pinned) => pinned.clone(),
+            None => value.clone(),
+        };
+
+        self.current.insert(name.to_owned(), value);
+        self.in_effect.insert(name.to_owned(), in_effect.clone());
+
+        in_effect
+    }
+
+    fn get_file_setting(&mut self, name: &str) -> Option<String> {
+        let key = name.to_lowercase();
+        let value = self.file.get(&key)?;
+
+        let setting = setting_to_string(value);
+
+        if setting.is_none() {
+            self.problem(
+                name,
+                format!("is a {} in the config file", value.type_str()),
+                "a string, number, boolean or array",
+                &format!("{} = \"...\"", key),
+            );
+        }
+
+        setting
+    }
+
+    /// Gets a setting from its environment variable, or otherwise the config file
+    fn get_optional_setting(&mut self, name: &str) -> Option<String> {
+        let value = get_optional_env_var(name).or_else(|| self.get_file_setting(name));
+
+        self.record(name, value)
+    }
+
+    /// Reads a file named by a setting, recording a problem if it can't be
+    fn read_secret_setting_file(&mut self, name: &str, path: String) -> Option<String> {
+        match read_secret_file(name, &path) {
+            Ok(secret) => Some(secret),
+            Err(error_message) => {
+                self.problem(
+                    name,
+                    format!("is {:?}, which couldn't be read ({})", path, error_message),
+                    "the path of a file containing the secret",
+                    "/run/secrets/mqtt_password",
+                );
+                None
+            }
+        }
+    }
+
+    /// Gets a secret from the environment variable, the file named by the environment variable
+    /// with `_FILE` on the end, the config file (either directly or as a file name), or Vault, in
+    /// that order
+    fn get_secret_setting(&mut self, name: &str) -> Option<String> {
+        let file_variable_name = format!("{}_FILE", name);
+
+        let value = match get_optional_env_var(name) {
+            Some(value) => Some(value),
+            None => match get_optional_env_var(&file_variable_name) {
+                Some(path) => self.read_secret_setting_file(&file_variable_name, path),
+                None => match self.get_file_setting(name) {
+                    Some(value) => Some(value),
+                    None => match self.get_file_setting(&file_variable_name) {
+                        Some(path) => self.read_secret_setting_file(&file_variable_name, path),
+                        None => vault::get_secret(name),
+                    },
+                },
+            },
+        };
+
+        self.record(name, value)
+    }
+
+    /// Splits an optional setting on commas, ignoring empty items
+    fn get_comma_separated_setting(&mut self, name: &str) -> Option<Vec<String>> {
+        self.get_optional_setting(name).map(split_on_commas)
+    }
+
+    /// Parses a setting with `parse`, recording a problem if it's invalid, or if it's `required`
+    /// and isn't set
+    fn parse_setting_with<T>(
+        &mut self,
+        name: &str,
+        required: bool,
+        expected: &str,
+        example: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let Some(value) = self.get_optional_setting(name) else {
+            if required {
+                self.problem(name, "isn't set".to_owned(), expected, example);
+            }
+
+            return None;
+        };
+
+        let parsed = parse(&value);
+
+        if parsed.is_none() {
+            self.problem(name, format!("is {:?}", value), expected, example);
+        }
+
+        parsed
+    }
+
+    fn get_setting(&mut self, name: &str, expected: &str, example: &str) -> String {
+        self.parse_setting_with(name, true, expected, example, |value| {
+            Some(value.to_owned())
+        })
+        .unwrap_or_default()
+    }
+
+    fn parse_optional_setting<T: FromStr>(&mut self, name: &str, example: &str) -> Option<T> {
+        self.parse_setting_with(name, false, describe_type::<T>(), example, |value| {
+            value.parse().ok()
+        })
+    }
+
+    /// Parses an optional setting, falling back to `default` if it isn't set (or is invalid)
+    fn parse_setting_or<T: FromStr + fmt::Display>(&mut self, name: &str, default: T) -> T {
+        let example = default.to_string();
+
+        self.parse_optional_setting(name, &example)
+            .unwrap_or(default)
+    }
+
+    /// Parses an optional setting which has to be more than 0, such as a capacity or an interval,
+    /// falling back to `default` if it isn't set (or is invalid)
+    fn parse_positive_setting_or<T: FromStr + fmt::Display + PartialOrd + Default>(
+        &mut self,
+        name: &str,
+        default: T,
+    ) -> T {
+        let example = default.to_string();
+        let expected = format!("{} more than 0", describe_type::<T>());
+
+        self.parse_setting_with(name, false, &expected, &example, |value| {
+            value.parse().ok().filter(|parsed| *parsed > T::default())
+        })
+        .unwrap_or(default)
+    }
+}
+
+fn qos_from_str(string: &str) -> Option<QoS> {
     match string {
-        "AtMostOnce" => Ok(QoS::AtMostOnce),
-        "AtLeastOnce" => Ok(QoS::AtLeastOnce),
-        "ExactlyOnce" => Ok(QoS::ExactlyOnce),
-        _ => Err(format!("Invalid QoS: {}", string)),
-    }
-}
-
-pub static CONFIG: Lazy<Config> = Lazy::new(|| Config {
-    mqtt_username: get_env_var("MQTT_USERNAME"),
-    mqtt_password: get_env_var("MQTT_PASSWORD"),
-    mqtt_host: get_env_var("MQTT_HOST"),
-    mqtt_port: get_env_var("MQTT_PORT")
-        .parse::<u16>()
-        .expect("MQTT_PORT must be a u16"),
-    mqtt_qos: qos_from_str(get_env_var("MQTT_QOS").as_str()).unwrap(),
-    mqtt_outgoing_topic: get_env_var("MQTT_OUTGOING_TOPIC"),
-    mqtt_incoming_topic: get_env_var("MQTT_INCOMING_TOPIC"),
-    channel_capacity: get_env_var("CHANNEL_CAPACITY")
-        .parse::<usize>()
-        .expect("CHANNEL_CAPACITY must be a usize"),
-    server_port: get_env_var("SERVER_PORT")
-        .parse::<u16>()
-        .expect("SERVER_PORT must be a u16"),
-    default_get_settings_timeout_seconds: get_env_var("DEFAULT_GET_SETTINGS_TIMEOUT_SECONDS")
-        .parse::<u64>()
-        .expect("DEFAULT_GET_SETTINGS_TIMEOUT_SECONDS must be a u32"),
-    default_signal_data_timeout_seconds: get_env_var("DEFAULT_SIGNAL_DATA_TIMEOUT_SECONDS")
-        .parse::<u64>()
-        .expect("DEFAULT_SIGNAL_DATA_TIMEOUT_SECONDS must be a u32"),
-    default_route_cost_weight: get_env_var("DEFAULT_ROUTE_COST_WEIGHT")
-        .parse::<EdgeWeight>()
-        .expect("DEFAULT_ROUTE_COST_WEIGHT must be an EdgeWeight"),
-    default_route_hops_weight: get_env_var("DEFAULT_ROUTE_HOPS_WEIGHT")
-        .parse::<EdgeWeight>()
-        .expect("DEFAULT_ROUTE_HOPS_WEIGHT must be an EdgeWeight"),
-    telemetry_cache_capacity: get_env_var("TELEMETRY_CACHE_CAPACITY")
-        .parse::<usize>()
-        .expect("TELEMETRY_CACHE_CAPACITY must be a usize"),
-    default_ad_hoc_telemetry_timeout_seconds: get_env_var(
-        "DEFAULT_AD_HOC_TELEMETRY_TIMEOUT_SECONDS",
-    )
-    .parse::<u64>()
-    .expect("DEFAULT_AD_HOC_TELEMETRY_TIMEOUT_SECONDS must be a u32"),
-});
+        "AtMostOnce" => Some(QoS::AtMostOnce),
+        "AtLeastOnce" => Some(QoS::AtLeastOnce),
+        "ExactlyOnce" => Some(QoS::ExactlyOnce),
+        _ => None,
+    }
+}
+
+impl Config {
+    fn read(reader: &mut SettingsReader) -> Config {
+        let embedded_mqtt_broker = reader.parse_setting_or("EMBEDDED_MQTT_BROKER", false);
+
+        let config = Config {
+            mqtt_username: reader.get_setting(
+                "MQTT_USERNAME",
+                "the username the server logs in to the MQTT broker with",
+                "server",
+            ),
+            mqtt_password: reader
+                .get_secret_setting("MQTT_PASSWORD")
+                .unwrap_or_else(|| {
+                    reader.problem(
+                        "MQTT_PASSWORD",
+                        "isn't set (and neither is MQTT_PASSWORD_FILE)".to_owned(),
+                        "the password the server logs in to the MQTT broker with",
+                        "MQTT_PASSWORD_FILE=/run/secrets/mqtt_password",
+                    );
+                    String::new()
+                }),
+            // the server connects to the embedded broker itself
+            mqtt_host: reader
+                .parse_setting_with(
+                    "MQTT_HOST",
+                    !embedded_mqtt_broker,
+                    "the MQTT broker's host name or IP address",
+                    "localhost",
+                    |host| Some(host.to_owned()),
+                )
+                .unwrap_or_else(|| "localhost".to_owned()),
+            mqtt_port: reader.parse_setting_or("MQTT_PORT", 1883),
+            mqtt_qos: reader
+                .parse_setting_with(
+                    "MQTT_QOS",
+                    false,
+                    "AtMostOnce, AtLeastOnce or ExactlyOnce",
+                    "AtLeastOnce",
+                    qos_from_str,
+                )
+                .unwrap_or(QoS::AtLeastOnce),
+            // the topics the ACLs in mqtt-broker/permissions.acl allow
+            mqtt_outgoing_topic: reader
+                .get_optional_setting("MQTT_OUTGOING_TOPIC")
+                .unwrap_or_else(|| "for-mesh".to_owned()),
+            mqtt_incoming_topic: reader
+                .get_optional_setting("MQTT_INCOMING_TOPIC")
+                .unwrap_or_else(|| "for-server".to_owned()),
+            embedded_mqtt_broker,
+            embedded_mqtt_broker_address: reader
+                .parse_setting_with(
+                    "EMBEDDED_MQTT_BROKER_ADDRESS",
+                    false,
+                    "an IP address",
+                    "0.0.0.0",
+                    |address| address.parse().ok(),
+                )
+                .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            embedded_mqtt_broker_gateway_username: reader
+                .get_optional_setting("EMBEDDED_MQTT_BROKER_GATEWAY_USERNAME"),
+            embedded_mqtt_broker_gateway_password: reader
+                .get_secret_setting("EMBEDDED_MQTT_BROKER_GATEWAY_PASSWORD"),
+            channel_capacity: reader.parse_positive_setting_or("CHANNEL_CAPACITY", 64),
+            server_port: reader.parse_setting_or("SERVER_PORT", 8080),
+            tls_cert_path: reader.get_optional_setting("TLS_CERT_PATH"),
+            tls_key_path: reader.get_optional_setting("TLS_KEY_PATH"),
+            http_redirect_port: reader.parse_optional_setting("HTTP_REDIRECT_PORT", "80"),
+            default_get_settings_timeout_seconds: reader
+                .parse_positive_setting_or("DEFAULT_GET_SETTINGS_TIMEOUT_SECONDS", 30),
+            default_signal_data_timeout_seconds: reader
+                .parse_positive_setting_or("DEFAULT_SIGNAL_DATA_TIMEOUT_SECONDS", 30),
+            default_route_cost_weight: reader.parse_setting_or("DEFAULT_ROUTE_COST_WEIGHT", 1.0),
+            default_route_hops_weight: reader.parse_setting_or("DEFAULT_ROUTE_HOPS_WEIGHT", 1.0),
+            default_sensor_relay_penalty: reader
+                .parse_setting_or("DEFAULT_SENSOR_RELAY_PENALTY", 2.0),
+            telemetry_cache_capacity: reader
+                .parse_positive_setting_or("TELEMETRY_CACHE_CAPACITY", 10000),
+            telemetry_archive_capacity: reader.parse_setting_or("TELEMETRY_ARCHIVE_CAPACITY", 0),
+            telemetry_archive_after_minutes: reader
+                .parse_positive_setting_or("TELEMETRY_ARCHIVE_AFTER_MINUTES", 60),
+            default_ad_hoc_telemetry_timeout_seconds: reader
+                .parse_positive_setting_or("DEFAULT_AD_HOC_TELEMETRY_TIMEOUT_SECONDS", 30),
+            default_command_ack_timeout_seconds: reader
+                .parse_positive_setting_or("DEFAULT_COMMAND_ACK_TIMEOUT_SECONDS", 30),
+            default_discovery_timeout_seconds: reader
+                .parse_positive_setting_or("DEFAULT_DISCOVERY_TIMEOUT_SECONDS", 30),
+            telemetry_gap_threshold_seconds: reader
+                .parse_positive_setting_or("TELEMETRY_GAP_THRESHOLD_SECONDS", 300),
+            alert_webhook_urls: reader
+                .get_optional_setting("ALERT_WEBHOOK_URLS")
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|url| url.trim().to_owned())
+                        .filter(|url| !url.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            alert_history_capacity: reader
+                .parse_positive_setting_or("ALERT_HISTORY_CAPACITY", 10000),
+            low_battery_threshold_percent: reader
+                .parse_setting_or("LOW_BATTERY_THRESHOLD_PERCENT", 20),
+            battery_depletion_warning_days: reader
+                .parse_positive_setting_or("BATTERY_DEPLETION_WARNING_DAYS", 3),
+            battery_trend_window_hours: reader
+                .parse_positive_setting_or("BATTERY_TREND_WINDOW_HOURS", 24),
+            node_offline_after_seconds: reader
+                .parse_positive_setting_or("NODE_OFFLINE_AFTER_SECONDS", 900),
+            expected_nodes_check_seconds: reader
+                .parse_positive_setting_or("EXPECTED_NODES_CHECK_SECONDS", 60),
+            gateway_silence_seconds: reader
+                .parse_positive_setting_or("GATEWAY_SILENCE_SECONDS", 300),
+            position_history_capacity: reader
+                .parse_positive_setting_or("POSITION_HISTORY_CAPACITY", 500),
+            mqtt_seismic_topic: reader.get_optional_setting("MQTT_SEISMIC_TOPIC"),
+            // 10 minutes at 100 Hz
+            seismic_buffer_samples: reader
+                .parse_positive_setting_or("SEISMIC_BUFFER_SAMPLES", 60_000),
+            seismic_channel_capacity: reader
+                .parse_positive_setting_or("SEISMIC_CHANNEL_CAPACITY", 1024),
+            raspberry_shake_udp_address: reader.get_optional_setting("RASPBERRY_SHAKE_UDP_ADDRESS"),
+            raspberry_shake_node_id: reader
+                .parse_optional_setting("RASPBERRY_SHAKE_NODE_ID", "1234"),
+            // the Raspberry Shake 4D's accelerometer
+            raspberry_shake_sensitivity: reader
+                .parse_positive_setting_or("RASPBERRY_SHAKE_SENSITIVITY", 384_500.0),
+            raspberry_shake_sample_rate_hz: reader
+                .parse_positive_setting_or("RASPBERRY_SHAKE_SAMPLE_RATE_HZ", 100.0),
+            sta_lta_short_seconds: reader.parse_positive_setting_or("STA_LTA_SHORT_SECONDS", 1.0),
+            sta_lta_long_seconds: reader.parse_positive_setting_or("STA_LTA_LONG_SECONDS", 30.0),
+            sta_lta_trigger_ratio: reader.parse_positive_setting_or("STA_LTA_TRIGGER_RATIO", 4.0),
+            sta_lta_detrigger_ratio: reader
+                .parse_positive_setting_or("STA_LTA_DETRIGGER_RATIO", 1.5),
+            seismic_trigger_history_capacity: reader
+                .parse_positive_setting_or("SEISMIC_TRIGGER_HISTORY_CAPACITY", 1000),
+            // about 1% of g, which people nearby would feel
+            shake_trigger_pga: reader.parse_positive_setting_or("SHAKE_TRIGGER_PGA", 0.1),
+            shake_min_nodes: reader.parse_positive_setting_or("SHAKE_MIN_NODES", 3),
+            shake_window_seconds: reader.parse_positive_setting_or("SHAKE_WINDOW_SECONDS", 10),
+            seismic_event_history_capacity: reader
+                .parse_positive_setting_or("SEISMIC_EVENT_HISTORY_CAPACITY", 100),
+            anomaly_z_score_threshold: reader
+                .parse_positive_setting_or("ANOMALY_Z_SCORE_THRESHOLD", 3.0),
+            anomaly_window_size: reader.parse_positive_setting_or("ANOMALY_WINDOW_SIZE", 50),
+            anomaly_min_samples: reader.parse_positive_setting_or("ANOMALY_MIN_SAMPLES", 10),
+            anomaly_history_capacity: reader
+                .parse_positive_setting_or("ANOMALY_HISTORY_CAPACITY", 1000),
+            ws_token_key: reader.get_secret_setting("WS_TOKEN_KEY"),
+            ws_token_ttl_seconds: reader.parse_positive_setting_or("WS_TOKEN_TTL_SECONDS", 60),
+            admin_api_keys: reader
+                .get_secret_setting("ADMIN_API_KEYS")
+                .map(split_on_commas)
+                .unwrap_or_default(),
+            admin_allowed_networks: reader
+                .get_comma_separated_setting("ADMIN_ALLOWED_NETWORKS")
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|network| {
+                    // allow single addresses without a prefix length
+                    let parsed = network
+                        .parse::<IpNet>()
+                        .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                        .ok();
+
+                    if parsed.is_none() {
+                        reader.problem(
+                            "ADMIN_ALLOWED_NETWORKS",
+                            format!("contains {:?}", network),
+                            "a comma-separated list of networks or IP addresses",
+                            "10.0.0.0/8,192.168.1.10",
+                        );
+                    }
+
+                    parsed
+                })
+                .collect(),
+            jwt_secret: reader.get_secret_setting("JWT_SECRET"),
+            users_file: reader.get_optional_setting("USERS_FILE"),
+            access_token_ttl_seconds: reader
+                .parse_positive_setting_or("ACCESS_TOKEN_TTL_SECONDS", 900),
+            oidc_issuer_url: reader.get_optional_setting("OIDC_ISSUER_URL"),
+            oidc_client_id: reader.get_optional_setting("OIDC_CLIENT_ID"),
+            oidc_client_secret: reader.get_secret_setting("OIDC_CLIENT_SECRET"),
+            oidc_redirect_url: reader.get_optional_setting("OIDC_REDIRECT_URL"),
+            oidc_audience: reader.get_optional_setting("OIDC_AUDIENCE"),
+            oidc_groups_claim: reader
+                .get_optional_setting("OIDC_GROUPS_CLAIM")
+                .unwrap_or_else(|| "groups".to_owned()),
+            oidc_commander_groups: reader
+                .get_comma_separated_setting("OIDC_COMMANDER_GROUPS")
+                .unwrap_or_default(),
+            oidc_admin_groups: reader
+                .get_comma_separated_setting("OIDC_ADMIN_GROUPS")
+                .unwrap_or_default(),
+            oidc_viewer_groups: reader
+                .get_comma_separated_setting("OIDC_VIEWER_GROUPS")
+                .unwrap_or_default(),
+            oidc_dashboard_url: reader.get_optional_setting("OIDC_DASHBOARD_URL"),
+            refresh_token_ttl_seconds: reader
+                .parse_positive_setting_or("REFRESH_TOKEN_TTL_SECONDS", 7 * 24 * 60 * 60),
+            cors_allowed_origins: reader
+                .get_comma_separated_setting("CORS_ALLOWED_ORIGINS")
+                .unwrap_or_else(|| {
+                    vec![
+                        "http://localhost:8000".to_owned(),
+                        "http://127.0.0.1:8000".to_owned(),
+                    ]
+                })
+                .into_iter()
+                .filter(|origin| {
+                    let valid = origin == "*" || HeaderValue::from_str(origin).is_ok();
+
+                    if !valid {
+                        reader.problem(
+                            "CORS_ALLOWED_ORIGINS",
+                            format!("contains {:?}", origin),
+                            "a comma-separated list of origins, or *",
+                            "https://dashboard.example.com",
+                        );
+                    }
+
+                    valid
+                })
+                .collect(),
+            mesh_signing_key: reader
+                .get_secret_setting("MESH_SIGNING_KEY")
+                .and_then(|key| {
+                    let key = hex::decode(key).ok();
+
+                    if key.is_none() {
+                        // the key itself isn't shown, since it's a secret
+                        reader.problem(
+                            "MESH_SIGNING_KEY",
+                            "isn't hex".to_owned(),
+                            "the key shared with the gateways, as hex",
+                            "00112233445566778899aabbccddeeff",
+                        );
+                    }
+
+                    key
+                }),
+            mesh_signing_key_id: reader.parse_setting_or("MESH_SIGNING_KEY_ID", 0),
+            mesh_encryption_key: reader
+                .get_secret_setting("MESH_ENCRYPTION_KEY")
+                .and_then(|key| {
+                    let key = hex::decode(key).ok().filter(|key| key.len() == 32);
+
+                    if key.is_none() {
+                        reader.problem(
+                            "MESH_ENCRYPTION_KEY",
+                            "isn't 64 hex characters".to_owned(),
+                            "the 32 byte key shared with the gateways, as hex",
+                            &"00112233445566778899aabbccddeeff".repeat(2),
+                        );
+                    }
+
+                    key
+                }),
+            mesh_encryption_key_id: reader.parse_setting_or("MESH_ENCRYPTION_KEY_ID", 0),
+            max_request_body_bytes: reader
+                .parse_positive_setting_or("MAX_REQUEST_BODY_BYTES", 64 * 1024),
+            request_timeout_seconds: reader
+                .parse_positive_setting_or("REQUEST_TIMEOUT_SECONDS", 60),
+            rate_limit_max_requests: reader.parse_positive_setting_or("RATE_LIMIT_MAX_REQUESTS", 5),
+            rate_limit_window_seconds: reader
+                .parse_positive_setting_or("RATE_LIMIT_WINDOW_SECONDS", 60),
+            lockout_free_attempts: reader.parse_setting_or("LOCKOUT_FREE_ATTEMPTS", 5),
+            lockout_base_seconds: reader.parse_positive_setting_or("LOCKOUT_BASE_SECONDS", 30),
+            lockout_max_seconds: reader.parse_positive_setting_or("LOCKOUT_MAX_SECONDS", 60 * 60),
+            auth_event_history_capacity: reader
+                .parse_positive_setting_or("AUTH_EVENT_HISTORY_CAPACITY", 1000),
+            dual_control: reader.parse_setting_or("DUAL_CONTROL", false),
+            pending_action_ttl_seconds: reader
+                .parse_positive_setting_or("PENDING_ACTION_TTL_SECONDS", 15 * 60),
+            websocket_queue_capacity: reader
+                .parse_positive_setting_or("WEBSOCKET_QUEUE_CAPACITY", 256),
+            websocket_resume_capacity: reader
+                .parse_positive_setting_or("WEBSOCKET_RESUME_CAPACITY", 1000),
+            websocket_ping_interval_seconds: reader
+                .parse_positive_setting_or("WEBSOCKET_PING_INTERVAL_SECONDS", 30),
+            websocket_max_missed_pongs: reader
+                .parse_positive_setting_or("WEBSOCKET_MAX_MISSED_PONGS", 3),
+            websocket_batch_rate_per_second: reader
+                .parse_setting_or("WEBSOCKET_BATCH_RATE_PER_SECOND", 20),
+            websocket_batch_interval_ms: reader
+                .parse_positive_setting_or("WEBSOCKET_BATCH_INTERVAL_MS", 250),
+            websocket_max_inbound_messages_per_second: reader
+                .parse_positive_setting_or("WEBSOCKET_MAX_INBOUND_MESSAGES_PER_SECOND", 10),
+            websocket_max_inbound_message_bytes: reader
+                .parse_positive_setting_or("WEBSOCKET_MAX_INBOUND_MESSAGE_BYTES", 16 * 1024),
+            data_directory: reader
+                .get_optional_setting("DATA_DIRECTORY")
+                .unwrap_or_else(|| "data".to_owned()),
+            expected_report_interval_seconds: reader
+                .parse_positive_setting_or("EXPECTED_REPORT_INTERVAL_SECONDS", 60),
+            health_window_hours: reader.parse_positive_setting_or("HEALTH_WINDOW_HOURS", 24),
+            health_recalculation_seconds: reader
+                .parse_positive_setting_or("HEALTH_RECALCULATION_SECONDS", 60),
+            command_history_per_node: reader
+                .parse_positive_setting_or("COMMAND_HISTORY_PER_NODE", 100),
+            link_weight_smoothing: reader
+                .parse_setting_or("LINK_WEIGHT_SMOOTHING", 0.3_f32)
+                .clamp(0.0, 1.0),
+            text_message_max_bytes: reader.parse_positive_setting_or("TEXT_MESSAGE_MAX_BYTES", 200),
+            message_history_capacity: reader
+                .parse_positive_setting_or("MESSAGE_HISTORY_CAPACITY", 1000),
+            message_delivery_timeout_seconds: reader
+                .parse_positive_setting_or("MESSAGE_DELIVERY_TIMEOUT_SECONDS", 300),
+            outbox_ttl_seconds: reader
+                .parse_positive_setting_or("OUTBOX_TTL_SECONDS", 24 * 60 * 60),
+            outbox_max_commands_per_node: reader
+                .parse_positive_setting_or("OUTBOX_MAX_COMMANDS_PER_NODE", 100),
+            emergency_alert_repeat_seconds: reader
+                .parse_positive_setting_or("EMERGENCY_ALERT_REPEAT_SECONDS", 300),
+            emergency_alert_default_duration_seconds: reader
+                .parse_positive_setting_or("EMERGENCY_ALERT_DEFAULT_DURATION_SECONDS", 60 * 60),
+            actuation_max_duration_seconds: reader
+                .parse_positive_setting_or("ACTUATION_MAX_DURATION_SECONDS", 600),
+            eew_decision_history_capacity: reader
+                .parse_positive_setting_or("EEW_DECISION_HISTORY_CAPACITY", 1000),
+            eew_dedupe_window_seconds: reader
+                .parse_positive_setting_or("EEW_DEDUPE_WINDOW_SECONDS", 24 * 60 * 60),
+            webhook_max_age_seconds: reader
+                .parse_positive_setting_or("WEBHOOK_MAX_AGE_SECONDS", 300),
+            delivery_report_webhook_url: reader.get_optional_setting("DELIVERY_REPORT_WEBHOOK_URL"),
+            http_connect_timeout_seconds: reader
+                .parse_positive_setting_or("HTTP_CONNECT_TIMEOUT_SECONDS", 10),
+            http_request_timeout_seconds: reader
+                .parse_positive_setting_or("HTTP_REQUEST_TIMEOUT_SECONDS", 30),
+            webhook_max_concurrent_deliveries: reader
+                .parse_positive_setting_or("WEBHOOK_MAX_CONCURRENT_DELIVERIES", 16),
+        };
+
+        match (&config.tls_cert_path, &config.tls_key_path) {
+            (Some(_), None) => reader.problem(
+                "TLS_KEY_PATH",
+                "isn't set, but TLS_CERT_PATH is".to_owned(),
+                "the path of the certificate's PEM private key",
+                "/etc/ssl/private/server.key",
+            ),
+            (None, Some(_)) => reader.problem(
+                "TLS_CERT_PATH",
+                "isn't set, but TLS_KEY_PATH is".to_owned(),
+                "the path of a PEM certificate (chain)",
+                "/etc/ssl/certs/server.pem",
+            ),
+            _ => {}
+        }
+
+        match (
+            &config.embedded_mqtt_broker_gateway_username,
+            &config.embedded_mqtt_broker_gateway_password,
+        ) {
+            (Some(_), None) => reader.problem(
+                "EMBEDDED_MQTT_BROKER_GATEWAY_PASSWORD",
+                "isn't set, but EMBEDDED_MQTT_BROKER_GATEWAY_USERNAME is".to_owned(),
+                "the password the gateways log in to the embedded broker with",
+                "EMBEDDED_MQTT_BROKER_GATEWAY_PASSWORD_FILE=/run/secrets/gateway_password",
+            ),
+            (None, Some(_)) => reader.problem(
+                "EMBEDDED_MQTT_BROKER_GATEWAY_USERNAME",
+                "isn't set, but EMBEDDED_MQTT_BROKER_GATEWAY_PASSWORD is".to_owned(),
+                "the username the gateways log in to the embedded broker with",
+                "gateway",
+            ),
+            _ => {}
+        }
+
+        // the server's own tokens are what users get once they've logged in through the provider
+        if config.oidc_issuer_url.is_some()
+            && config.oidc_client_id.is_some()
+            && config.oidc_redirect_url.is_some()
+            && config.jwt_secret.is_none()
+        {
+            reader.problem(
+                "JWT_SECRET",
+                "isn't set, but OIDC logins are (with OIDC_REDIRECT_URL)".to_owned(),
+                "a long random secret to sign tokens with",
+                "JWT_SECRET_FILE=/run/secrets/jwt_secret",
+            );
+        }
+
+        if config.raspberry_shake_udp_address.is_some() && config.raspberry_shake_node_id.is_none()
+        {
+            reader.problem(
+                "RASPBERRY_SHAKE_NODE_ID",
+                "isn't set, but RASPBERRY_SHAKE_UDP_ADDRESS is".to_owned(),
+                "the node ID the Raspberry Shake's data is treated as coming from",
+                "1234",
+            );
+        }
+
+        config
+    }
+}
+
+/// The settings currently in effect, and what they were read from
+struct LoadedConfig {
+    config: &'static Config,
+    in_effect: BTreeMap<String, Option<String>>,
+}
+
+/// Set by `load` when the server starts
+static LOADED: OnceCell<RwLock<LoadedConfig>> = OnceCell::new();
+
+/// Gives the settings currently in effect, which change when the config is reloaded
+pub struct ConfigHandle;
+
+pub static CONFIG: ConfigHandle = ConfigHandle;
+
+impl Deref for ConfigHandle {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        LOADED
+            .get()
+            .expect("The config is used before it's loaded")
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .config
+    }
+}
+
+/// The settings which changed when the config was reloaded
+#[derive(Serialize, Default, Debug)]
+pub struct ConfigChanges {
+    pub applied: Vec<String>,
+    /// settings which won't change until the server is restarted
+    pub requires_restart: Vec<String>,
+}
+
+/// Reads the settings, giving a report of everything wrong with them if any are invalid
+fn try_read(pinned: BTreeMap<String, Option<String>>) -> Result<(Config, SettingsReader), String> {
+    let mut reader = SettingsReader::new(pinned);
+    let config = Config::read(&mut reader);
+
+    match reader.problems.is_empty() {
+        true => Ok((config, reader)),
+        false => Err(ConfigProblems(std::mem::take(&mut reader.problems)).to_string()),
+    }
+}
+
+/// Reads the settings, which has to happen (after Vault's secrets are fetched) before `CONFIG` is
+/// used. Every setting is checked, so that the server doesn't fail later on when one is first used.
+pub fn load() -> Result<(), String> {
+    let (config, reader) = try_read(BTreeMap::new())?;
+
+    LOADED
+        .set(RwLock::new(LoadedConfig {
+            config: Box::leak(Box::new(config)),
+            in_effect: reader.in_effect,
+        }))
+        .map_err(|_| "The config has already been loaded".to_owned())
+}
+
+/// Loads the default settings, with made up MQTT credentials, for tests of code which uses `CONFIG`
+#[cfg(test)]
+pub fn load_defaults_for_tests() {
+    static LOAD: std::sync::Once = std::sync::Once::new();
+
+    LOAD.call_once(|| {
+        std::env::set_var("MQTT_USERNAME", "test");
+        std::env::set_var("MQTT_PASSWORD", "test");
+        std::env::set_var("MQTT_HOST", "localhost");
+
+        load().expect("The default settings should be valid");
+    });
+}
+
+/// Reads the config file and environment again, and swaps in the new settings apart from those in
+/// `RESTART_REQUIRED`, which keep their values. Nothing changes if the new settings are invalid.
+pub fn reload() -> Result<ConfigChanges, String> {
+    let mut loaded = LOADED
+        .get()
+        .ok_or("The config hasn't been loaded yet")?
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    let pinned = loaded
+        .in_effect
+        .iter()
+        .filter(|(name, _)| RESTART_REQUIRED.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    let (config, reader) = try_read(pinned)?;
+
+    let mut changes = ConfigChanges::default();
+
+    for (name, value) in &reader.current {
+        if loaded.in_effect.get(name) == Some(value) {
+            continue;
+        }
+
+        if RESTART_REQUIRED.contains(&name.as_str()) {
+            changes.requires_restart.push(name.clone());
+        } else {
+            changes.applied.push(name.clone());
+        }
+    }
+
+    // each reload leaks the old settings, since they could still be borrowed anywhere. They're
+    // small and reloads are rare, so it's not worth counting references on every access.
+    loaded.config = Box::leak(Box::new(config));
+    loaded.in_effect = reader.in_effect;
+
+    Ok(changes)
+}
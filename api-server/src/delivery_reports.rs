@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alert_history::{AlertStage, LifecycleEntry},
+    alerts::AlertSeverity,
+    config::CONFIG,
+    emergency_alerts::{EmergencyAlertId, EmergencyAlertState},
+    pathfinding::NodeId,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse},
+    AppState,
+};
+
+/// How long nodes took to receive or acknowledge an alert, in seconds after it was issued
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LatencyDistribution {
+    pub count: usize,
+    pub min: u64,
+    pub median: u64,
+    pub p90: u64,
+    pub max: u64,
+    pub mean: f64,
+}
+
+impl LatencyDistribution {
+    fn from_latencies(mut latencies: Vec<u64>) -> Option<Self> {
+        latencies.sort_unstable();
+
+        let percentile = |percent: usize| latencies[(latencies.len() - 1) * percent / 100];
+
+        Some(LatencyDistribution {
+            count: latencies.len(),
+            min: *latencies.first()?,
+            median: percentile(50),
+            p90: percentile(90),
+            max: *latencies.last()?,
+            mean: latencies.iter().sum::<u64>() as f64 / latencies.len() as f64,
+        })
+    }
+}
+
+/// When a node received and acknowledged an alert, in seconds since unix epoch
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct NodeDelivery {
+    /// whether it was one of the nodes the alert was sent to, rather than one which happened to
+    /// hear it
+    pub targeted: bool,
+    pub received_at: Option<u64>,
+    pub acknowledged_at: Option<u64>,
+}
+
+/// A record of how an emergency alert was delivered, generated when it ends
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DeliveryReport {
+    pub alert_id: EmergencyAlertId,
+    pub severity: AlertSeverity,
+    pub text: String,
+    pub drill: bool,
+    /// whether it was only sent to the nodes in an area
+    pub geofenced: bool,
+    pub issued_by: String,
+    /// seconds since unix epoch
+    pub issued_at: u64,
+    pub ended_at: u64,
+    /// `expired` or `cancelled`
+    pub outcome: EmergencyAlertState,
+    pub cancelled_by: Option<String>,
+    pub publish_count: u32,
+    pub targets: usize,
+    /// targets which received it, including those which acknowledged it
+    pub received: usize,
+    pub acknowledged: usize,
+    /// how long after it was issued each node first said it had the alert
+    pub receipt_latency: Option<LatencyDistribution>,
+    /// how long after it was issued someone at each node acknowledged it
+    pub acknowledgement_latency: Option<LatencyDistribution>,
+    /// targets which never said they received it
+    pub unreached: Vec<NodeId>,
+    pub nodes: BTreeMap<NodeId, NodeDelivery>,
+    /// every time an operator escalated it, from the alert history
+    pub escalations: Vec<LifecycleEntry>,
+    /// seconds since unix epoch
+    pub generated_at: u64,
+}
+
+/// The delivery reports for the most recent `ALERT_HISTORY_CAPACITY` emergency alerts, oldest
+/// first. They're saved to `delivery-reports.json` in the data directory whenever one is
+/// generated.
+#[derive(Default)]
+pub struct DeliveryReportStore {
+    reports: VecDeque<DeliveryReport>,
+}
+
+impl DeliveryReportStore {
+    pub fn restore(&mut self, reports: Vec<DeliveryReport>) {
+        self.reports = reports.into();
+
+        while self.reports.len() > CONFIG.alert_history_capacity {
+            self.reports.pop_front();
+        }
+    }
+
+    pub fn reports(&self) -> impl Iterator<Item = &DeliveryReport> {
+        self.reports.iter()
+    }
+
+    fn push(&mut self, report: DeliveryReport) {
+        self.reports.push_back(report);
+
+        while self.reports.len() > CONFIG.alert_history_capacity {
+            self.reports.pop_front();
+        }
+    }
+}
+
+/// Generates the delivery report for an alert which has just ended, saves it, and sends it to
+/// `DELIVERY_REPORT_WEBHOOK_URL` if that's set
+pub async fn generate(state: &AppState, alert_id: EmergencyAlertId) {
+    let Some(alert) = state.emergency_alerts.lock().await.get(alert_id).cloned() else {
+        return;
+    };
+
+    let Some(ended_at) = alert.ended_at else {
+        return;
+    };
+
+    let escalations = state
+        .alert_history
+        .lock()
+        .await
+        .emergency_record(alert_id)
+        .map(|record| {
+            record
+                .lifecycle
+                .iter()
+                .filter(|entry| entry.stage == AlertStage::Escalated)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let nodes = alert
+        .acks
+        .iter()
+        .map(|(node_id, ack)| {
+            (
+                *node_id,
+                NodeDelivery {
+                    targeted: alert.targets.contains(node_id),
+                    received_at: ack.received_at,
+                    acknowledged_at: ack.acknowledged_at,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let targeted = || nodes.values().filter(|delivery| delivery.targeted);
+    let latency = |timestamp: Option<u64>| {
+        timestamp.map(|timestamp| timestamp.saturating_sub(alert.issued_at))
+    };
+
+    let report = DeliveryReport {
+        alert_id,
+        severity: alert.severity,
+        text: alert.text.clone(),
+        drill: alert.drill,
+        geofenced: alert.area.is_some(),
+        issued_by: alert.issued_by.clone(),
+        issued_at: alert.issued_at,
+        ended_at,
+        outcome: alert.state,
+        cancelled_by: alert.cancelled_by.clone(),
+        publish_count: alert.publish_count,
+        targets: alert.targets.len(),
+        received: targeted()
+            .filter(|delivery| delivery.received_at.is_some())
+            .count(),
+        acknowledged: targeted()
+            .filter(|delivery| delivery.acknowledged_at.is_some())
+            .count(),
+        receipt_latency: LatencyDistribution::from_latencies(
+            nodes
+                .values()
+                .filter_map(|delivery| latency(delivery.received_at))
+                .collect(),
+        ),
+        acknowledgement_latency: LatencyDistribution::from_latencies(
+            nodes
+                .values()
+                .filter_map(|delivery| latency(delivery.acknowledged_at))
+                .collect(),
+        ),
+        unreached: nodes
+            .iter()
+            .filter(|(_, delivery)| delivery.targeted && delivery.received_at.is_none())
+            .map(|(node_id, _)| *node_id)
+            .collect(),
+        nodes,
+        escalations,
+        generated_at: unix_time_seconds(),
+    };
+
+    info!(
+        "Emergency alert {} reached {} of {} nodes, {} of which acknowledged it",
+        alert_id, report.received, report.targets, report.acknowledged
+    );
+
+    state.delivery_reports.lock().await.push(report.clone());
+
+    if let Err(error_message) = persistence::save_delivery_reports(state).await {
+        error!("{}", error_message);
+    }
+
+    if let Some(url) = &CONFIG.delivery_report_webhook_url {
+        let client = state.http_client.clone();
+        let webhook_deliveries = state.webhook_deliveries.clone();
+
+        tokio::spawn(async move {
+            // the semaphore is never closed
+            let _permit = webhook_deliveries.acquire_owned().await;
+
+            match client.post(url).json(&report).send().await {
+                Ok(response) => debug!(
+                    "Delivery report webhook responded with status {}",
+                    response.status()
+                ),
+                Err(error) => error!("Failed to send delivery report to webhook: {:?}", error),
+            }
+        });
+    }
+}
+
+/// /alerts/{id}/report
+pub async fn get_delivery_report(
+    State(state): State<AppState>,
+    Path(alert_id): Path<EmergencyAlertId>,
+) -> FallibleJsonResponse<DeliveryReport> {
+    if let Some(report) = state
+        .delivery_reports
+        .lock()
+        .await
+        .reports()
+        .find(|report| report.alert_id == alert_id)
+    {
+        return FallibleJsonResponse::Ok(report.clone());
+    }
+
+    if state
+        .emergency_alerts
+        .lock()
+        .await
+        .get(alert_id)
+        .is_some_and(|alert| alert.state == EmergencyAlertState::Active)
+    {
+        return FallibleJsonResponse::Err(
+            StatusCode::CONFLICT,
+            format!(
+                "Emergency alert {} is still active, so doesn't have a report yet",
+                alert_id
+            ),
+        );
+    }
+
+    FallibleJsonResponse::Err(
+        StatusCode::NOT_FOUND,
+        format!("No delivery report for emergency alert {}", alert_id),
+    )
+}
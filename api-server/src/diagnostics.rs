@@ -0,0 +1,239 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::{
+    auth::AuthedUser,
+    command_history::{self, CommandOutcome},
+    pathfinding::NodeId,
+    proto::meshtastic::{
+        crisislab_message::{self, EchoRequest, TracerouteHop},
+        CrisislabMessage,
+    },
+    utils::{await_mesh_response, send_command_protobuf, unix_time_seconds, FallibleJsonResponse},
+    AppState,
+};
+
+#[derive(Serialize)]
+pub struct TracerouteResult {
+    /// the path the response took, starting with the node and ending with the gateway which
+    /// published it
+    hops: Vec<TracerouteHop>,
+    /// the node's best route to a gateway as of the last route update, `None` if it doesn't have
+    /// one
+    expected_route: Option<Vec<NodeId>>,
+    /// whether the response went the way the published next hops say it should, `None` if the
+    /// node doesn't have a route
+    follows_routes: Option<bool>,
+}
+
+/// /admin/nodes/{id}/traceroute
+pub async fn traceroute(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> FallibleJsonResponse<TracerouteResult> {
+    info!("{} started a traceroute to node {}", user, node_id);
+
+    // subscribe before sending the request so that a quick response can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+
+    let crisislab_message = CrisislabMessage {
+        message: Some(crisislab_message::Message::Traceroute(
+            crisislab_message::Empty {},
+        )),
+        destination: Some(node_id),
+    };
+
+    let sent_at = unix_time_seconds();
+
+    if let Err(error_message) =
+        send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await
+    {
+        command_history::record(
+            &state,
+            node_id,
+            &crisislab_message,
+            &user.name,
+            sent_at,
+            CommandOutcome::Failed,
+            Some(error_message.clone()),
+        )
+        .await;
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let timeout_duration =
+        Duration::from_secs(state.app_settings.lock().await.command_ack_timeout_seconds);
+
+    let response = await_mesh_response(
+        &mut mesh_receiver,
+        timeout_duration,
+        |message| match message.message {
+            Some(crisislab_message::Message::TracerouteResponse(response))
+                if response.node_num == node_id =>
+            {
+                Some(response)
+            }
+            _ => None,
+        },
+    )
+    .await;
+
+    let (outcome, error) = match &response {
+        Ok(_) => (CommandOutcome::Responded, None),
+        Err(error_message) => (CommandOutcome::TimedOut, Some(error_message.clone())),
+    };
+
+    command_history::record(
+        &state,
+        node_id,
+        &crisislab_message,
+        &user.name,
+        sent_at,
+        outcome,
+        error,
+    )
+    .await;
+
+    let response = match response {
+        Ok(response) => response,
+        Err(error_message) => {
+            return FallibleJsonResponse::Err(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "Node {} didn't respond to the traceroute: {}",
+                    node_id, error_message
+                ),
+            )
+            .log();
+        }
+    };
+
+    debug!("Traceroute response: {:?}", response);
+
+    let expected_route = state.topology.lock().await.best_route(node_id);
+
+    let follows_routes = expected_route.as_ref().map(|expected_route| {
+        response
+            .hops
+            .iter()
+            .map(|hop| hop.node_num)
+            .eq(expected_route.iter().copied())
+    });
+
+    if follows_routes == Some(false) {
+        warn!(
+            "Traceroute from node {} didn't follow its published route {:?}",
+            node_id, expected_route
+        );
+    }
+
+    FallibleJsonResponse::Ok(TracerouteResult {
+        hops: response.hops,
+        expected_route,
+        follows_routes,
+    })
+}
+
+#[derive(Serialize)]
+pub struct PingResult {
+    round_trip_ms: u64,
+    /// the gateway which published the reply
+    via_gateway: NodeId,
+    /// how many nodes relayed the reply on its way to the gateway
+    hop_count: u32,
+}
+
+/// /nodes/{id}/ping
+pub async fn ping(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> FallibleJsonResponse<PingResult> {
+    debug!("{} is pinging node {}", user, node_id);
+
+    // subscribe before sending the request so that a quick reply can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+
+    let id = rand::random::<u32>();
+
+    let crisislab_message = CrisislabMessage {
+        message: Some(crisislab_message::Message::EchoRequest(EchoRequest { id })),
+        destination: Some(node_id),
+    };
+
+    let sent_at = unix_time_seconds();
+    let started = Instant::now();
+
+    if let Err(error_message) =
+        send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await
+    {
+        command_history::record(
+            &state,
+            node_id,
+            &crisislab_message,
+            &user.name,
+            sent_at,
+            CommandOutcome::Failed,
+            Some(error_message.clone()),
+        )
+        .await;
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let timeout_duration =
+        Duration::from_secs(state.app_settings.lock().await.command_ack_timeout_seconds);
+
+    let reply = await_mesh_response(
+        &mut mesh_receiver,
+        timeout_duration,
+        |message| match message.message {
+            Some(crisislab_message::Message::EchoReply(reply))
+                if reply.node_num == node_id && reply.id == id =>
+            {
+                Some(reply)
+            }
+            _ => None,
+        },
+    )
+    .await;
+
+    let round_trip = started.elapsed();
+
+    let (outcome, error) = match &reply {
+        Ok(_) => (CommandOutcome::Responded, None),
+        Err(error_message) => (CommandOutcome::TimedOut, Some(error_message.clone())),
+    };
+
+    command_history::record(
+        &state,
+        node_id,
+        &crisislab_message,
+        &user.name,
+        sent_at,
+        outcome,
+        error,
+    )
+    .await;
+
+    match reply {
+        Ok(reply) => FallibleJsonResponse::Ok(PingResult {
+            round_trip_ms: round_trip.as_millis() as u64,
+            via_gateway: reply.via_gateway,
+            hop_count: reply.hop_count,
+        }),
+        Err(error_message) => FallibleJsonResponse::Err(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("Node {} didn't reply: {}", node_id, error_message),
+        )
+        .log(),
+    }
+}
@@ -0,0 +1,171 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use axum::{extract::State, http::StatusCode};
+use log::{debug, error, info};
+use serde::Serialize;
+
+use crate::{
+    auth::AuthedUser,
+    pathfinding::NodeId,
+    persistence,
+    proto::meshtastic::{
+        crisislab_message::{self, DiscoveryResponse},
+        CrisislabMessage,
+    },
+    utils::{await_mesh_response, send_command_protobuf, unix_time_seconds, FallibleJsonResponse},
+    AppState,
+};
+
+/// Where what a node said about itself differs from its registry entry
+#[derive(Serialize)]
+pub struct RegistryDiff {
+    node_id: NodeId,
+    /// "hardware_model" or "firmware_version"
+    field: &'static str,
+    registered: Option<String>,
+    reported: String,
+}
+
+#[derive(Serialize)]
+pub struct DiscoveryResult {
+    /// every node which responded
+    responded: Vec<NodeId>,
+    /// nodes which weren't in the registry, which have been added as pending
+    new_nodes: Vec<NodeId>,
+    /// nodes in the registry which didn't respond
+    missing: Vec<NodeId>,
+    changes: Vec<RegistryDiff>,
+}
+
+/// /admin/discover
+pub async fn discover(
+    State(state): State<AppState>,
+    user: AuthedUser,
+) -> FallibleJsonResponse<DiscoveryResult> {
+    let _guard = match state.discovering_lock.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            return FallibleJsonResponse::Err(
+                StatusCode::CONFLICT,
+                "A discovery sweep has already been started by another client".to_owned(),
+            );
+        }
+    };
+
+    info!("{} started a discovery sweep", user);
+
+    // nodes get an empty registry entry as soon as they're heard from, so this has to be taken
+    // before any responses arrive
+    let known_nodes = state.node_registry.lock().await.nodes().clone();
+
+    // subscribe before sending the request so that quick responses can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+
+    let discovery_message = CrisislabMessage {
+        message: Some(crisislab_message::Message::DiscoveryRequest(
+            crisislab_message::Empty {},
+        )),
+        destination: None,
+    };
+
+    if let Err(error_message) =
+        send_command_protobuf(discovery_message, &state.mesh_interface).await
+    {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let timeout_duration =
+        Duration::from_secs(state.app_settings.lock().await.discovery_timeout_seconds);
+
+    debug!(
+        "Discovery handler waiting for responses... (timeout after {:?})",
+        timeout_duration
+    );
+
+    let mut responses = BTreeMap::<NodeId, DiscoveryResponse>::new();
+
+    let _ = await_mesh_response(&mut mesh_receiver, timeout_duration, |message| {
+        if let Some(crisislab_message::Message::DiscoveryResponse(response)) = message.message {
+            debug!("Discovery response: {:?}", response);
+
+            responses.insert(response.node_num, response);
+        }
+
+        None::<()>
+    })
+    .await;
+
+    let now = unix_time_seconds();
+    let mut new_nodes = Vec::new();
+    let mut changes = Vec::new();
+
+    {
+        let mut node_registry = state.node_registry.lock().await;
+
+        for (node_id, response) in &responses {
+            let Some(info) = known_nodes.get(node_id) else {
+                node_registry.add_pending(*node_id, response.hardware_model.clone(), now);
+
+                if let Some(version) = &response.firmware_version {
+                    node_registry.record_firmware(*node_id, version.clone(), None, now);
+                }
+
+                new_nodes.push(*node_id);
+                continue;
+            };
+
+            // the hardware model is set by operators, so it's only reported rather than changed
+            if let Some(hardware_model) = &response.hardware_model {
+                if info.hardware_model.as_ref() != Some(hardware_model) {
+                    changes.push(RegistryDiff {
+                        node_id: *node_id,
+                        field: "hardware_model",
+                        registered: info.hardware_model.clone(),
+                        reported: hardware_model.clone(),
+                    });
+                }
+            }
+
+            if let Some(version) = &response.firmware_version {
+                let registered = info.firmware.as_ref().map(|firmware| &firmware.version);
+
+                if registered != Some(version) {
+                    changes.push(RegistryDiff {
+                        node_id: *node_id,
+                        field: "firmware_version",
+                        registered: registered.cloned(),
+                        reported: version.clone(),
+                    });
+
+                    node_registry.record_firmware(*node_id, version.clone(), None, now);
+                }
+            }
+        }
+    }
+
+    if !new_nodes.is_empty() || !changes.is_empty() {
+        if let Err(error_message) = persistence::save_node_registry(&state).await {
+            error!("{}", error_message);
+        }
+    }
+
+    let missing = known_nodes
+        .keys()
+        .filter(|node_id| !responses.contains_key(node_id))
+        .copied()
+        .collect::<Vec<_>>();
+
+    info!(
+        "Discovery sweep heard {} nodes, {} of them new, and {} registered nodes didn't respond",
+        responses.len(),
+        new_nodes.len(),
+        missing.len()
+    );
+
+    FallibleJsonResponse::Ok(DiscoveryResult {
+        responded: responses.into_keys().collect(),
+        new_nodes,
+        missing,
+        changes,
+    })
+}
@@ -0,0 +1,107 @@
+use axum::{extract::State, http::StatusCode, Json};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    events::ServerEvent,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody},
+    AppState,
+};
+
+/// Who turned drill mode on and when. While it's on, every emergency alert and actuation is
+/// marked as a drill, as are websocket packets and command history entries, so that an exercise
+/// can't be mistaken for a real event.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DrillMode {
+    pub enabled_by: String,
+    /// seconds since unix epoch
+    pub enabled_at: u64,
+}
+
+pub async fn is_on(state: &AppState) -> bool {
+    state.drill_mode.lock().await.is_some()
+}
+
+/// Turns drill mode on or off, letting websocket clients know through a mesh status packet
+pub async fn set(state: &AppState, drill_mode: Option<DrillMode>) {
+    let enabled = drill_mode.is_some();
+
+    *state.drill_mode.lock().await = drill_mode;
+
+    let mesh_status = {
+        let mut mesh_status = state.mesh_status.lock().await;
+        mesh_status.drill_mode = enabled;
+        *mesh_status
+    };
+
+    // an error here just means there aren't any websocket clients connected
+    let _ = state
+        .server_events
+        .send(ServerEvent::MeshStatus(mesh_status));
+}
+
+#[derive(Serialize)]
+pub struct DrillModeStatus {
+    enabled: bool,
+    enabled_by: Option<String>,
+    enabled_at: Option<u64>,
+}
+
+impl From<Option<DrillMode>> for DrillModeStatus {
+    fn from(drill_mode: Option<DrillMode>) -> Self {
+        Self {
+            enabled: drill_mode.is_some(),
+            enabled_by: drill_mode
+                .as_ref()
+                .map(|drill_mode| drill_mode.enabled_by.clone()),
+            enabled_at: drill_mode.map(|drill_mode| drill_mode.enabled_at),
+        }
+    }
+}
+
+/// /info/drill-mode
+pub async fn get_drill_mode(State(state): State<AppState>) -> Json<DrillModeStatus> {
+    Json(state.drill_mode.lock().await.clone().into())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DrillModeBody {
+    enabled: bool,
+}
+
+/// /admin/drill-mode
+pub async fn set_drill_mode(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<DrillModeBody>,
+) -> FallibleJsonResponse<DrillModeStatus> {
+    let previous = state.drill_mode.lock().await.clone();
+
+    // turning it on again keeps the original details
+    let drill_mode = match (body.enabled, previous) {
+        (true, Some(previous)) => Some(previous),
+        (true, None) => Some(DrillMode {
+            enabled_by: user.name.clone(),
+            enabled_at: unix_time_seconds(),
+        }),
+        (false, _) => None,
+    };
+
+    warn!(
+        target: "audit",
+        "{} turned drill mode {}",
+        user,
+        if body.enabled { "on" } else { "off" }
+    );
+
+    set(&state, drill_mode.clone()).await;
+
+    if let Err(error_message) = persistence::save_drill_mode(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(drill_mode.into())
+}
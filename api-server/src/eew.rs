@@ -0,0 +1,612 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    alerts::AlertSeverity,
+    auth::AuthedUser,
+    config::CONFIG,
+    emergency_alerts::{self, EmergencyAlertBody, EmergencyAlertId},
+    message_templates, persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    webhooks::VerifiedWebhookSource,
+    AppState,
+};
+
+const DEFAULT_ALERT_TEXT: &str =
+    "Earthquake warning: magnitude {{magnitude}} near {{location}}. Drop, cover and hold on.";
+
+/// How to read a webhook source's earthquake early warnings, and when they're worth broadcasting.
+/// The fields are found with JSON pointers (e.g. `/properties/mag`), so that each provider's
+/// payloads can be used as they are.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct EewMapping {
+    /// the webhook source whose payloads this applies to
+    pub source: String,
+    /// identifies the earthquake, so that updates about it aren't broadcast again
+    pub event_id_pointer: String,
+    pub magnitude_pointer: Option<String>,
+    pub intensity_pointer: Option<String>,
+    /// a description of where the earthquake is, e.g. a region name
+    pub location_pointer: Option<String>,
+    pub min_magnitude: Option<f64>,
+    pub min_intensity: Option<f64>,
+    pub severity: AlertSeverity,
+    /// the alert's text, with `{{magnitude}}`, `{{intensity}}` and `{{location}}` filled in
+    pub alert_text: String,
+    /// `EMERGENCY_ALERT_DEFAULT_DURATION_SECONDS` if not given
+    pub expires_in_seconds: Option<u64>,
+    pub updated_by: String,
+    /// seconds since unix epoch
+    pub updated_at: u64,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case", tag = "outcome")]
+pub enum EewOutcome {
+    /// an emergency alert was broadcast
+    Broadcast { alert_id: EmergencyAlertId },
+    /// the warning didn't meet any of the mapping's thresholds
+    BelowThreshold,
+    /// an alert has already been broadcast for the earthquake
+    Duplicate,
+    /// there isn't a mapping for the source
+    NoMapping,
+    /// a field couldn't be read from the payload
+    InvalidPayload { error: String },
+    /// the alert couldn't be broadcast
+    Failed { error: String },
+}
+
+/// What happened to a warning, and why
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct EewDecision {
+    pub source: String,
+    /// seconds since unix epoch
+    pub received_at: u64,
+    pub event_id: Option<String>,
+    pub magnitude: Option<f64>,
+    pub intensity: Option<f64>,
+    pub location: Option<String>,
+    #[serde(flatten)]
+    pub outcome: EewOutcome,
+    /// each step of the decision, in order
+    pub reasons: Vec<String>,
+    /// the payload exactly as it was received
+    pub payload: Value,
+}
+
+/// An earthquake which an alert has been (or is being) broadcast for
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BroadcastEvent {
+    pub source: String,
+    pub event_id: String,
+    /// seconds since unix epoch
+    pub broadcast_at: u64,
+}
+
+/// The mappings for each webhook source, the most recent `EEW_DECISION_HISTORY_CAPACITY`
+/// decisions (oldest first), and the earthquakes alerts were broadcast for in the last
+/// `EEW_DEDUPE_WINDOW_SECONDS`. They're all saved to the data directory whenever they change.
+#[derive(Default)]
+pub struct EewStore {
+    mappings: BTreeMap<String, EewMapping>,
+    decisions: VecDeque<EewDecision>,
+    /// (source, event ID) -> when it was broadcast (seconds since unix epoch), kept separately
+    /// from the decisions so that a busy provider can't push an earthquake out of the history and
+    /// get it broadcast again
+    broadcast_events: HashMap<(String, String), u64>,
+}
+
+impl EewStore {
+    pub fn restore(
+        &mut self,
+        mappings: Vec<EewMapping>,
+        decisions: Vec<EewDecision>,
+        broadcast_events: Vec<BroadcastEvent>,
+    ) {
+        self.mappings = mappings
+            .into_iter()
+            .map(|mapping| (mapping.source.clone(), mapping))
+            .collect();
+
+        self.decisions.clear();
+        self.broadcast_events.clear();
+
+        // decisions saved before broadcast events were saved separately still count
+        for decision in &decisions {
+            if let (EewOutcome::Broadcast { .. }, Some(event_id)) =
+                (&decision.outcome, &decision.event_id)
+            {
+                self.broadcast_events.insert(
+                    (decision.source.clone(), event_id.clone()),
+                    decision.received_at,
+                );
+            }
+        }
+
+        for event in broadcast_events {
+            self.broadcast_events
+                .insert((event.source, event.event_id), event.broadcast_at);
+        }
+
+        for decision in decisions {
+            self.record(decision);
+        }
+
+        self.forget_old_events(unix_time_seconds());
+    }
+
+    pub fn mappings(&self) -> impl Iterator<Item = &EewMapping> {
+        self.mappings.values()
+    }
+
+    pub fn decisions(&self) -> impl DoubleEndedIterator<Item = &EewDecision> {
+        self.decisions.iter()
+    }
+
+    fn record(&mut self, decision: EewDecision) {
+        self.decisions.push_back(decision);
+
+        while self.decisions.len() > CONFIG.eew_decision_history_capacity {
+            self.decisions.pop_front();
+        }
+    }
+
+    pub fn broadcast_events(&self) -> impl Iterator<Item = BroadcastEvent> + '_ {
+        self.broadcast_events
+            .iter()
+            .map(|((source, event_id), broadcast_at)| BroadcastEvent {
+                source: source.clone(),
+                event_id: event_id.clone(),
+                broadcast_at: *broadcast_at,
+            })
+    }
+
+    fn forget_old_events(&mut self, now: u64) {
+        self.broadcast_events.retain(|_, broadcast_at| {
+            now.saturating_sub(*broadcast_at) < CONFIG.eew_dedupe_window_seconds
+        });
+    }
+
+    /// Whether an alert has already been broadcast (or is being broadcast) for the earthquake
+    fn has_broadcast(&self, source: &str, event_id: &str) -> bool {
+        self.broadcast_events
+            .contains_key(&(source.to_owned(), event_id.to_owned()))
+    }
+}
+
+/// Reads the value at a JSON pointer as a number, accepting numbers sent as strings since some
+/// providers send them that way
+fn number_at(payload: &Value, pointer: &str) -> Result<Option<f64>, String> {
+    match payload.pointer(pointer) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(number)) => Ok(number.as_f64()),
+        Some(Value::String(string)) => string
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} isn't a number: {:?}", pointer, string)),
+        Some(value) => Err(format!("{} isn't a number: {}", pointer, value)),
+    }
+}
+
+fn string_at(payload: &Value, pointer: &str) -> Option<String> {
+    match payload.pointer(pointer)? {
+        Value::Null => None,
+        Value::String(string) => Some(string.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Works out what to do with a warning, without doing it. The decision's outcome is `Broadcast`
+/// with an ID of 0 if an alert should be broadcast.
+fn decide(
+    store: &EewStore,
+    source: &str,
+    payload: Value,
+    now: u64,
+) -> (EewDecision, Option<EewMapping>) {
+    let mut decision = EewDecision {
+        source: source.to_owned(),
+        received_at: now,
+        event_id: None,
+        magnitude: None,
+        intensity: None,
+        location: None,
+        outcome: EewOutcome::NoMapping,
+        reasons: Vec::new(),
+        payload,
+    };
+
+    let Some(mapping) = store.mappings.get(source) else {
+        decision
+            .reasons
+            .push(format!("There's no EEW mapping for source {}", source));
+
+        return (decision, None);
+    };
+
+    let read_fields = || -> Result<_, String> {
+        let event_id = string_at(&decision.payload, &mapping.event_id_pointer)
+            .ok_or_else(|| format!("{} is missing", mapping.event_id_pointer))?;
+
+        let magnitude = match &mapping.magnitude_pointer {
+            Some(pointer) => number_at(&decision.payload, pointer)?,
+            None => None,
+        };
+
+        let intensity = match &mapping.intensity_pointer {
+            Some(pointer) => number_at(&decision.payload, pointer)?,
+            None => None,
+        };
+
+        let location = mapping
+            .location_pointer
+            .as_ref()
+            .and_then(|pointer| string_at(&decision.payload, pointer));
+
+        Ok((event_id, magnitude, intensity, location))
+    };
+
+    let (event_id, magnitude, intensity, location) = match read_fields() {
+        Ok(fields) => fields,
+        Err(error) => {
+            decision
+                .reasons
+                .push(format!("The payload couldn't be read: {}", error));
+            decision.outcome = EewOutcome::InvalidPayload { error };
+
+            return (decision, None);
+        }
+    };
+
+    decision.event_id = Some(event_id.clone());
+    decision.magnitude = magnitude;
+    decision.intensity = intensity;
+    decision.location = location;
+
+    decision.reasons.push(format!(
+        "Read event {} with magnitude {:?} and intensity {:?}",
+        event_id, magnitude, intensity
+    ));
+
+    if store.has_broadcast(source, &event_id) {
+        decision
+            .reasons
+            .push("An alert has already been broadcast for this event".to_owned());
+        decision.outcome = EewOutcome::Duplicate;
+
+        return (decision, None);
+    }
+
+    // a warning is broadcast if it meets any of the thresholds, so that a strong shake from a
+    // small nearby earthquake isn't missed
+    let thresholds = [
+        ("magnitude", magnitude, mapping.min_magnitude),
+        ("intensity", intensity, mapping.min_intensity),
+    ];
+
+    let mut has_threshold = false;
+    let mut meets_threshold = false;
+
+    for (name, value, threshold) in thresholds {
+        let Some(threshold) = threshold else {
+            continue;
+        };
+
+        has_threshold = true;
+
+        match value {
+            Some(value) if value >= threshold => {
+                meets_threshold = true;
+                decision.reasons.push(format!(
+                    "The {} of {} meets the threshold of {}",
+                    name, value, threshold
+                ));
+            }
+            Some(value) => decision.reasons.push(format!(
+                "The {} of {} is below the threshold of {}",
+                name, value, threshold
+            )),
+            None => decision.reasons.push(format!(
+                "There's no {} to compare with the threshold of {}",
+                name, threshold
+            )),
+        }
+    }
+
+    if !has_threshold {
+        meets_threshold = true;
+        decision
+            .reasons
+            .push("The mapping has no thresholds, so every warning is broadcast".to_owned());
+    }
+
+    if !meets_threshold {
+        decision.outcome = EewOutcome::BelowThreshold;
+
+        return (decision, None);
+    }
+
+    decision.outcome = EewOutcome::Broadcast { alert_id: 0 };
+
+    (decision, Some(mapping.clone()))
+}
+
+fn alert_text(
+    template: &str,
+    magnitude: Option<f64>,
+    intensity: Option<f64>,
+    location: Option<&str>,
+) -> Result<String, String> {
+    let format_number =
+        |number: Option<f64>| number.map_or_else(|| "unknown".to_owned(), |n| format!("{:.1}", n));
+
+    let variables = BTreeMap::from([
+        ("magnitude".to_owned(), format_number(magnitude)),
+        ("intensity".to_owned(), format_number(intensity)),
+        (
+            "location".to_owned(),
+            location.unwrap_or("unknown location").to_owned(),
+        ),
+    ]);
+
+    message_templates::render(template, &variables).map_err(|missing| {
+        format!(
+            "The alert text uses unknown variables: {}",
+            missing.into_iter().collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+async fn save_broadcast_events(state: &AppState) {
+    if let Err(error_message) = persistence::save_eew_broadcast_events(state).await {
+        error!("{}", error_message);
+    }
+}
+
+async fn record_decision(state: &AppState, decision: EewDecision) {
+    warn!(
+        target: "audit",
+        "EEW from {} for event {:?}: {:?} ({})",
+        decision.source,
+        decision.event_id,
+        decision.outcome,
+        decision.reasons.join("; ")
+    );
+
+    state.eew.lock().await.record(decision);
+
+    if let Err(error_message) = persistence::save_eew_decisions(state).await {
+        error!("{}", error_message);
+    }
+}
+
+/// /integrations/eew
+pub async fn receive_warning(
+    State(state): State<AppState>,
+    Extension(VerifiedWebhookSource(source)): Extension<VerifiedWebhookSource>,
+    JsonBody(payload): JsonBody<Value>,
+) -> FallibleJsonResponse<EewDecision> {
+    info!("Received an earthquake early warning from {}", source);
+
+    let now = unix_time_seconds();
+
+    let (mut decision, mapping) = {
+        let mut eew = state.eew.lock().await;
+
+        eew.forget_old_events(now);
+
+        let (decision, mapping) = decide(&eew, &source, payload, now);
+
+        // the event is claimed before the lock is released, so that another warning about it
+        // arriving while this one is being broadcast is a duplicate
+        if let (Some(_), Some(event_id)) = (&mapping, &decision.event_id) {
+            eew.broadcast_events
+                .insert((source.clone(), event_id.clone()), now);
+        }
+
+        (decision, mapping)
+    };
+
+    if let Some(mapping) = mapping {
+        save_broadcast_events(&state).await;
+
+        let result = match alert_text(
+            &mapping.alert_text,
+            decision.magnitude,
+            decision.intensity,
+            decision.location.as_deref(),
+        ) {
+            Ok(text) => emergency_alerts::broadcast(
+                &state,
+                &format!("{} (EEW)", source),
+                EmergencyAlertBody {
+                    severity: mapping.severity,
+                    text,
+                    language: None,
+                    translations: BTreeMap::new(),
+                    expires_in_seconds: mapping.expires_in_seconds,
+                },
+                None,
+                // an earthquake is real whether or not a drill is going on
+                false,
+            )
+            .await
+            .map_err(|(_, error_message)| error_message),
+            Err(error_message) => Err(error_message),
+        };
+
+        match result {
+            Ok(alert) => {
+                decision
+                    .reasons
+                    .push(format!("Broadcast emergency alert {}", alert.id));
+                decision.outcome = EewOutcome::Broadcast { alert_id: alert.id };
+            }
+            Err(error) => {
+                decision
+                    .reasons
+                    .push(format!("The alert couldn't be broadcast: {}", error));
+                decision.outcome = EewOutcome::Failed { error };
+
+                // so that the provider can retry
+                if let Some(event_id) = &decision.event_id {
+                    state
+                        .eew
+                        .lock()
+                        .await
+                        .broadcast_events
+                        .remove(&(source.clone(), event_id.clone()));
+                }
+
+                save_broadcast_events(&state).await;
+            }
+        }
+    }
+
+    record_decision(&state, decision.clone()).await;
+
+    match &decision.outcome {
+        EewOutcome::NoMapping => FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("There's no EEW mapping for source {}", source),
+        ),
+        EewOutcome::InvalidPayload { error } => {
+            FallibleJsonResponse::Err(StatusCode::UNPROCESSABLE_ENTITY, error.clone())
+        }
+        EewOutcome::Failed { error } => {
+            FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error.clone()).log()
+        }
+        _ => FallibleJsonResponse::Ok(decision),
+    }
+}
+
+/// /admin/eew-mappings
+pub async fn get_eew_mappings(State(state): State<AppState>) -> Json<Vec<EewMapping>> {
+    Json(state.eew.lock().await.mappings().cloned().collect())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EewMappingBody {
+    event_id_pointer: String,
+    magnitude_pointer: Option<String>,
+    intensity_pointer: Option<String>,
+    location_pointer: Option<String>,
+    min_magnitude: Option<f64>,
+    min_intensity: Option<f64>,
+    #[serde(default)]
+    severity: AlertSeverity,
+    alert_text: Option<String>,
+    expires_in_seconds: Option<u64>,
+}
+
+/// /admin/eew-mappings/{source} (PUT)
+pub async fn set_eew_mapping(
+    State(state): State<AppState>,
+    Path(source): Path<String>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<EewMappingBody>,
+) -> FallibleJsonResponse<EewMapping> {
+    let pointers = [
+        Some(&body.event_id_pointer),
+        body.magnitude_pointer.as_ref(),
+        body.intensity_pointer.as_ref(),
+        body.location_pointer.as_ref(),
+    ];
+
+    if let Some(pointer) = pointers
+        .into_iter()
+        .flatten()
+        .find(|pointer| !pointer.is_empty() && !pointer.starts_with('/'))
+    {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{:?} isn't a JSON pointer, which starts with /", pointer),
+        );
+    }
+
+    if body.min_magnitude.is_some() && body.magnitude_pointer.is_none()
+        || body.min_intensity.is_some() && body.intensity_pointer.is_none()
+    {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A threshold needs the pointer to the value it's compared with".to_owned(),
+        );
+    }
+
+    let alert_text_template = body
+        .alert_text
+        .unwrap_or_else(|| DEFAULT_ALERT_TEXT.to_owned());
+
+    // catch typos in the variables now, rather than when an earthquake happens
+    if let Err(error_message) = alert_text(&alert_text_template, None, None, None) {
+        return FallibleJsonResponse::Err(StatusCode::UNPROCESSABLE_ENTITY, error_message);
+    }
+
+    let mapping = EewMapping {
+        source: source.clone(),
+        event_id_pointer: body.event_id_pointer,
+        magnitude_pointer: body.magnitude_pointer,
+        intensity_pointer: body.intensity_pointer,
+        location_pointer: body.location_pointer,
+        min_magnitude: body.min_magnitude,
+        min_intensity: body.min_intensity,
+        severity: body.severity,
+        alert_text: alert_text_template,
+        expires_in_seconds: body.expires_in_seconds,
+        updated_by: user.name.clone(),
+        updated_at: unix_time_seconds(),
+    };
+
+    info!("{} set the EEW mapping for {}: {:?}", user, source, mapping);
+
+    state
+        .eew
+        .lock()
+        .await
+        .mappings
+        .insert(source, mapping.clone());
+
+    if let Err(error_message) = persistence::save_eew_mappings(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(mapping)
+}
+
+/// /admin/eew-mappings/{source} (DELETE)
+pub async fn remove_eew_mapping(
+    State(state): State<AppState>,
+    Path(source): Path<String>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state.eew.lock().await.mappings.remove(&source).is_none() {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No EEW mapping for source {}", source),
+        );
+    }
+
+    info!("{} removed the EEW mapping for {}", user, source);
+
+    if let Err(error_message) = persistence::save_eew_mappings(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
+
+/// /admin/eew-decisions
+pub async fn get_eew_decisions(State(state): State<AppState>) -> Json<Vec<EewDecision>> {
+    Json(state.eew.lock().await.decisions().rev().cloned().collect())
+}
@@ -0,0 +1,906 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+
+use crate::{
+    alert_history::{self, AlertStage},
+    alerts::AlertSeverity,
+    auth::AuthedUser,
+    config::CONFIG,
+    delivery_reports, drill,
+    events::ServerEvent,
+    geofence,
+    messages::{self, TrackedMessage},
+    pathfinding::NodeId,
+    pending_actions, positions,
+    proto::meshtastic::{
+        crisislab_message::{self, alert_ack::Kind, emergency_alert::Severity},
+        CrisislabMessage,
+    },
+    utils::{send_command_protobuf, unix_time_seconds, FallibleJsonResponse, JsonBody},
+    AppState,
+};
+
+/// The same as the ID of the message tracking the alert's delivery
+pub type EmergencyAlertId = u32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyAlertState {
+    /// still being published every `EMERGENCY_ALERT_REPEAT_SECONDS`
+    Active,
+    Expired,
+    Cancelled,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct AlertArea {
+    /// as it was given
+    pub geojson: Value,
+    /// the nodes whose positions were inside it when the alert was sent
+    pub node_ids: BTreeSet<NodeId>,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct EmergencyAlert {
+    pub id: EmergencyAlertId,
+    pub severity: AlertSeverity,
+    pub text: String,
+    /// the language of `text`, e.g. "en"
+    pub language: Option<String>,
+    /// the alert in other languages, keyed by language code
+    pub translations: BTreeMap<String, String>,
+    /// where it applies, if it wasn't for the whole mesh
+    pub area: Option<AlertArea>,
+    pub issued_by: String,
+    /// seconds since unix epoch
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub state: EmergencyAlertState,
+    /// whether it was broadcast while drill mode was on
+    pub drill: bool,
+    /// how many times it has been published, including the first
+    pub publish_count: u32,
+    pub last_published_at: u64,
+    pub cancelled_by: Option<String>,
+    /// when it expired or was cancelled
+    pub ended_at: Option<u64>,
+    /// the nodes which were expected to receive it
+    #[serde(skip)]
+    pub targets: BTreeSet<NodeId>,
+    /// every node which was expected to receive it, and any others which said they did
+    #[serde(skip)]
+    pub acks: BTreeMap<NodeId, NodeAck>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AckState {
+    Pending,
+    /// the node has shown the alert
+    Received,
+    /// someone at the node has acknowledged the alert
+    Acknowledged,
+}
+
+/// When a node received and acknowledged an alert, in seconds since unix epoch
+#[derive(Clone, Copy, Default, Serialize, Debug)]
+pub struct NodeAck {
+    pub received_at: Option<u64>,
+    pub acknowledged_at: Option<u64>,
+}
+
+impl NodeAck {
+    pub fn state(&self) -> AckState {
+        match (self.received_at, self.acknowledged_at) {
+            (_, Some(_)) => AckState::Acknowledged,
+            (Some(_), None) => AckState::Received,
+            (None, None) => AckState::Pending,
+        }
+    }
+}
+
+/// Sent to live websocket clients whenever a node's acknowledgement of an alert changes
+#[derive(Clone, Serialize, Debug)]
+pub struct AlertAckEvent {
+    pub alert_id: EmergencyAlertId,
+    pub node_id: NodeId,
+    pub state: AckState,
+    /// whether the alert is a drill
+    pub drill: bool,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+}
+
+/// Every emergency alert broadcast since the server started, along with the tasks re-publishing
+/// the active ones
+#[derive(Default)]
+pub struct EmergencyAlertStore {
+    alerts: BTreeMap<EmergencyAlertId, EmergencyAlert>,
+    repeat_tasks: HashMap<EmergencyAlertId, JoinHandle<()>>,
+}
+
+impl EmergencyAlertStore {
+    pub fn get(&self, id: EmergencyAlertId) -> Option<&EmergencyAlert> {
+        self.alerts.get(&id)
+    }
+
+    fn record_published(&mut self, id: EmergencyAlertId, now: u64) {
+        if let Some(alert) = self.alerts.get_mut(&id) {
+            alert.publish_count += 1;
+            alert.last_published_at = now;
+        }
+    }
+
+    /// Records that a node received or acknowledged an alert, returning the node's new state if it
+    /// changed. Acknowledging an alert implies receiving it.
+    fn record_ack(
+        &mut self,
+        id: EmergencyAlertId,
+        node_id: NodeId,
+        kind: Kind,
+        now: u64,
+    ) -> Option<(AckState, bool)> {
+        let alert = self.alerts.get_mut(&id)?;
+        let drill = alert.drill;
+        let node_ack = alert.acks.entry(node_id).or_default();
+        let previous_state = node_ack.state();
+
+        node_ack.received_at.get_or_insert(now);
+
+        if kind == Kind::Acknowledged {
+            node_ack.acknowledged_at.get_or_insert(now);
+        }
+
+        Some((node_ack.state(), drill)).filter(|(state, _)| *state != previous_state)
+    }
+
+    /// Checks that there's an alert with the ID which hasn't ended yet
+    fn check_active(&self, id: EmergencyAlertId) -> Result<(), (StatusCode, String)> {
+        match self.alerts.get(&id) {
+            None => Err((
+                StatusCode::NOT_FOUND,
+                format!("No emergency alert with ID {}", id),
+            )),
+            Some(alert) if alert.state != EmergencyAlertState::Active => Err((
+                StatusCode::CONFLICT,
+                format!("Emergency alert {} has already ended", id),
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Marks an alert as ended, returning `false` if it already had
+    fn end(
+        &mut self,
+        id: EmergencyAlertId,
+        state: EmergencyAlertState,
+        cancelled_by: Option<String>,
+        now: u64,
+    ) -> bool {
+        let Some(alert) = self.alerts.get_mut(&id) else {
+            return false;
+        };
+
+        if alert.state != EmergencyAlertState::Active {
+            return false;
+        }
+
+        alert.state = state;
+        alert.cancelled_by = cancelled_by;
+        alert.ended_at = Some(now);
+
+        true
+    }
+}
+
+async fn record_node_ack(state: &AppState, id: EmergencyAlertId, node_id: NodeId, kind: Kind) {
+    let now = unix_time_seconds();
+
+    let Some((ack_state, drill)) = state
+        .emergency_alerts
+        .lock()
+        .await
+        .record_ack(id, node_id, kind, now)
+    else {
+        return;
+    };
+
+    info!(
+        "Node {} has {:?} emergency alert {}",
+        node_id, ack_state, id
+    );
+
+    if ack_state == AckState::Acknowledged {
+        alert_history::record_emergency_stage(
+            state,
+            id,
+            AlertStage::Acknowledged,
+            Some(format!("node {}", node_id)),
+        )
+        .await;
+    }
+
+    // an error here just means there aren't any websocket clients connected
+    let _ = state
+        .server_events
+        .send(ServerEvent::AlertAck(AlertAckEvent {
+            alert_id: id,
+            node_id,
+            state: ack_state,
+            drill,
+            timestamp: now,
+        }));
+}
+
+/// Records an acknowledgement sent by a node. Acknowledgements for alerts the server doesn't know
+/// about (e.g. since it restarted) are ignored.
+pub async fn record_ack(state: &AppState, ack: crisislab_message::AlertAck) {
+    debug!("Alert ack: {:?}", ack);
+
+    let kind = Kind::try_from(ack.kind).unwrap_or(Kind::Received);
+
+    record_node_ack(state, ack.alert_id, ack.node_num, kind).await;
+}
+
+/// Counts the nodes in a gateway's delivery report as having received the alert, if the report is
+/// for one. This covers nodes whose firmware doesn't send `alert_ack`s.
+pub async fn record_delivery_report(state: &AppState, report: &crisislab_message::DeliveryReport) {
+    if !state
+        .emergency_alerts
+        .lock()
+        .await
+        .alerts
+        .contains_key(&report.message_id)
+    {
+        return;
+    }
+
+    for node_id in &report.node_nums {
+        record_node_ack(state, report.message_id, *node_id, Kind::Received).await;
+    }
+}
+
+fn severity(severity: AlertSeverity) -> Severity {
+    match severity {
+        AlertSeverity::Info => Severity::Info,
+        AlertSeverity::Warning => Severity::Warning,
+        AlertSeverity::Critical => Severity::Critical,
+    }
+}
+
+fn alert_message(alert: &EmergencyAlert, cancelled: bool) -> CrisislabMessage {
+    localised_message(
+        alert,
+        alert.language.as_deref(),
+        &alert.text,
+        None,
+        cancelled,
+    )
+}
+
+fn localised_message(
+    alert: &EmergencyAlert,
+    language: Option<&str>,
+    text: &str,
+    destination: Option<NodeId>,
+    cancelled: bool,
+) -> CrisislabMessage {
+    CrisislabMessage {
+        message: Some(crisislab_message::Message::EmergencyAlert(
+            crisislab_message::EmergencyAlert {
+                id: alert.id,
+                severity: severity(alert.severity) as i32,
+                text: text.to_owned(),
+                expires_at: alert.expires_at,
+                cancelled,
+                drill: alert.drill,
+                language: language.unwrap_or_default().to_owned(),
+            },
+        )),
+        destination,
+    }
+}
+
+/// The copies of an alert for a node, or for every node if `destination` is `None`: just the
+/// node's preferred language if the alert has it, and otherwise every language
+fn copies_for(
+    alert: &EmergencyAlert,
+    preferred_language: Option<&str>,
+    destination: Option<NodeId>,
+) -> Vec<CrisislabMessage> {
+    let preferred_text = preferred_language.and_then(|language| {
+        if alert.language.as_deref() == Some(language) {
+            Some(alert.text.as_str())
+        } else {
+            alert.translations.get(language).map(String::as_str)
+        }
+    });
+
+    if let Some(text) = preferred_text {
+        return vec![localised_message(
+            alert,
+            preferred_language,
+            text,
+            destination,
+            false,
+        )];
+    }
+
+    let mut copies = vec![localised_message(
+        alert,
+        alert.language.as_deref(),
+        &alert.text,
+        destination,
+        false,
+    )];
+
+    copies.extend(alert.translations.iter().map(|(language, text)| {
+        localised_message(alert, Some(language), text, destination, false)
+    }));
+
+    copies
+}
+
+/// The copies of an alert to publish. Alerts for an area are addressed to each node in it.
+/// Otherwise, without translations, that's one copy for every node. With them, each node whose
+/// registry language the alert has is sent a copy in just that language, addressed to it, and
+/// every language is published to every node as well, unless every node in the registry has been
+/// sent its own copy and no node outside the registry (or pending in it) has been heard.
+async fn alert_messages(state: &AppState, alert: &EmergencyAlert) -> Vec<CrisislabMessage> {
+    let heard_node_ids = state
+        .presence
+        .lock()
+        .await
+        .nodes()
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+
+    let node_registry = state.node_registry.lock().await;
+    let preferred_language =
+        |node_id: &NodeId| node_registry.nodes().get(node_id)?.language.as_deref();
+
+    if let Some(area) = &alert.area {
+        return area
+            .node_ids
+            .iter()
+            .flat_map(|node_id| copies_for(alert, preferred_language(node_id), Some(*node_id)))
+            .collect();
+    }
+
+    if alert.translations.is_empty() {
+        return vec![alert_message(alert, false)];
+    }
+
+    let mut messages = Vec::new();
+    // nobody has a copy until a node in the registry has been sent one
+    let mut everyone_has_a_copy = !node_registry.nodes().is_empty()
+        && heard_node_ids
+            .iter()
+            .all(|node_id| node_registry.nodes().contains_key(node_id));
+
+    for (node_id, info) in node_registry.nodes() {
+        if info.pending {
+            everyone_has_a_copy = false;
+            continue;
+        }
+
+        let copies = copies_for(alert, info.language.as_deref(), Some(*node_id));
+
+        // a node without a preference (or one the alert doesn't have) is left to the copies for
+        // every node, rather than being sent every language by itself
+        if copies.len() == 1 {
+            messages.extend(copies);
+        } else {
+            everyone_has_a_copy = false;
+        }
+    }
+
+    if !everyone_has_a_copy {
+        messages.extend(copies_for(alert, None, None));
+    }
+
+    messages
+}
+
+/// Publishes every copy, carrying on past any which fail and returning the last error
+async fn publish_messages(
+    state: &AppState,
+    messages: impl IntoIterator<Item = CrisislabMessage>,
+) -> Result<(), String> {
+    let mut result = Ok(());
+
+    for message in messages {
+        if let Err(error_message) = send_command_protobuf(message, &state.mesh_interface).await {
+            result = Err(error_message);
+        }
+    }
+
+    result
+}
+
+/// Spawns the task which publishes an alert again every `EMERGENCY_ALERT_REPEAT_SECONDS` until it
+/// expires. It's aborted if the alert is cancelled.
+fn repeat_task(state: AppState, id: EmergencyAlertId, expires_at: u64) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let repeat_interval = Duration::from_secs(CONFIG.emergency_alert_repeat_seconds.max(1));
+
+        loop {
+            let remaining = Duration::from_secs(expires_at.saturating_sub(unix_time_seconds()));
+
+            tokio::time::sleep(repeat_interval.min(remaining)).await;
+
+            let now = unix_time_seconds();
+
+            if now >= expires_at {
+                let has_ended = {
+                    let mut emergency_alerts = state.emergency_alerts.lock().await;
+
+                    // this task's own handle, which is only aborted when the alert is cancelled
+                    emergency_alerts.repeat_tasks.remove(&id);
+                    emergency_alerts.end(id, EmergencyAlertState::Expired, None, now)
+                };
+
+                if has_ended {
+                    info!("Emergency alert {} has expired", id);
+
+                    // the report comes first since it's the record of the activation that matters
+                    // most, should anything below fail
+                    delivery_reports::generate(&state, id).await;
+                    alert_history::record_emergency_stage(&state, id, AlertStage::Expired, None)
+                        .await;
+                }
+
+                return;
+            }
+
+            // a cancellation which raced the broadcast may not have found this task to abort it
+            let Some(alert) = state
+                .emergency_alerts
+                .lock()
+                .await
+                .alerts
+                .get(&id)
+                .filter(|alert| alert.state == EmergencyAlertState::Active)
+                .cloned()
+            else {
+                return;
+            };
+
+            debug!("Re-publishing emergency alert {}", id);
+
+            match publish_messages(&state, alert_messages(&state, &alert).await).await {
+                Ok(()) => state
+                    .emergency_alerts
+                    .lock()
+                    .await
+                    .record_published(id, now),
+                Err(error_message) => error!(
+                    "Failed to re-publish emergency alert {}: {}",
+                    id, error_message
+                ),
+            }
+        }
+    })
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EmergencyAlertBody {
+    #[serde(default)]
+    pub severity: AlertSeverity,
+    pub text: String,
+    /// the language of `text`
+    pub language: Option<String>,
+    /// keyed by language code
+    #[serde(default)]
+    pub translations: BTreeMap<String, String>,
+    /// `EMERGENCY_ALERT_DEFAULT_DURATION_SECONDS` if not given
+    pub expires_in_seconds: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoEmergencyAlertBody {
+    /// a GeoJSON Polygon or MultiPolygon, or a Feature with one as its geometry
+    area: Value,
+    #[serde(default)]
+    severity: AlertSeverity,
+    text: String,
+    language: Option<String>,
+    #[serde(default)]
+    translations: BTreeMap<String, String>,
+    expires_in_seconds: Option<u64>,
+}
+
+/// /admin/alerts/broadcast
+pub async fn broadcast_alert(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<EmergencyAlertBody>,
+) -> FallibleJsonResponse<EmergencyAlert> {
+    let drill = drill::is_on(&state).await;
+
+    match broadcast(&state, &user.name, body, None, drill).await {
+        Ok(alert) => FallibleJsonResponse::Ok(alert),
+        Err((status_code, error_message)) => {
+            FallibleJsonResponse::Err(status_code, error_message).log()
+        }
+    }
+}
+
+/// /admin/alerts/broadcast-geo
+pub async fn broadcast_geo_alert(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<GeoEmergencyAlertBody>,
+) -> FallibleJsonResponse<EmergencyAlert> {
+    let area = match geofence::Area::from_geojson(&body.area) {
+        Ok(area) => area,
+        Err(error_message) => {
+            return FallibleJsonResponse::Err(StatusCode::UNPROCESSABLE_ENTITY, error_message)
+        }
+    };
+
+    let node_ids = positions::known_positions(&state)
+        .await
+        .into_iter()
+        .filter(|(_, position)| area.contains(position.latitude, position.longitude))
+        .map(|(node_id, _)| node_id)
+        .collect::<BTreeSet<_>>();
+
+    if node_ids.is_empty() {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "None of the nodes with a known position are in the area".to_owned(),
+        );
+    }
+
+    let alert = EmergencyAlertBody {
+        severity: body.severity,
+        text: body.text,
+        language: body.language,
+        translations: body.translations,
+        expires_in_seconds: body.expires_in_seconds,
+    };
+
+    let area = AlertArea {
+        geojson: body.area,
+        node_ids,
+    };
+
+    let drill = drill::is_on(&state).await;
+
+    match broadcast(&state, &user.name, alert, Some(area), drill).await {
+        Ok(alert) => FallibleJsonResponse::Ok(alert),
+        Err((status_code, error_message)) => {
+            FallibleJsonResponse::Err(status_code, error_message).log()
+        }
+    }
+}
+
+/// Publishes an emergency alert to every node (or just the ones in `area`) and starts
+/// re-publishing it until it expires. `expires_in_seconds` defaults to
+/// `EMERGENCY_ALERT_DEFAULT_DURATION_SECONDS`. `drill` is whether to mark it as a drill, which
+/// alerts from admins are while drill mode is on but automatic ones (like EEW) never are.
+pub async fn broadcast(
+    state: &AppState,
+    issued_by: &str,
+    body: EmergencyAlertBody,
+    area: Option<AlertArea>,
+    drill: bool,
+) -> Result<EmergencyAlert, (StatusCode, String)> {
+    let EmergencyAlertBody {
+        severity,
+        text,
+        language,
+        translations,
+        expires_in_seconds,
+    } = body;
+
+    messages::validate_text(&text)
+        .map_err(|error_message| (StatusCode::UNPROCESSABLE_ENTITY, error_message))?;
+
+    for (translation_language, translation) in &translations {
+        if translation_language.trim().is_empty() || language.as_ref() == Some(translation_language)
+        {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "{:?} isn't a language code, or is the language of text",
+                    translation_language
+                ),
+            ));
+        }
+
+        messages::validate_text(translation).map_err(|error_message| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("{} translation: {}", translation_language, error_message),
+            )
+        })?;
+    }
+
+    let expires_in_seconds =
+        expires_in_seconds.unwrap_or(CONFIG.emergency_alert_default_duration_seconds);
+
+    if expires_in_seconds == 0 {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "expires_in_seconds must be more than 0".to_owned(),
+        ));
+    }
+
+    let id = rand::random::<EmergencyAlertId>();
+    let now = unix_time_seconds();
+
+    let Some(expires_at) = now.checked_add(expires_in_seconds) else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "expires_in_seconds is too large".to_owned(),
+        ));
+    };
+
+    let targets = match &area {
+        Some(area) => area
+            .node_ids
+            .iter()
+            .map(|node_id| (*node_id, None))
+            .collect(),
+        None => messages::broadcast_targets(state).await,
+    };
+
+    let alert = EmergencyAlert {
+        id,
+        severity,
+        text,
+        language,
+        translations,
+        area,
+        issued_by: issued_by.to_owned(),
+        issued_at: now,
+        expires_at,
+        state: EmergencyAlertState::Active,
+        drill,
+        publish_count: 1,
+        last_published_at: now,
+        cancelled_by: None,
+        ended_at: None,
+        targets: targets.keys().copied().collect(),
+        acks: targets
+            .keys()
+            .map(|node_id| (*node_id, NodeAck::default()))
+            .collect(),
+    };
+
+    info!(
+        "{} broadcast {:?} {} {} until {}: {:?}",
+        issued_by,
+        alert.severity,
+        if alert.drill {
+            "drill emergency alert"
+        } else {
+            "emergency alert"
+        },
+        id,
+        alert.expires_at,
+        alert.text
+    );
+
+    let message = TrackedMessage {
+        id,
+        text: alert.text.clone(),
+        sent_by: issued_by.to_owned(),
+        sent_at: now,
+        queued: false,
+        targets,
+    };
+
+    let mut copies = alert_messages(state, &alert).await.into_iter();
+
+    let Some(first_copy) = copies.next() else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "There were no copies of the alert to send".to_owned(),
+        ));
+    };
+
+    // stored before it's published so that acks from nodes which respond straight away aren't
+    // lost, and along with its repeat task so that a cancellation can always find the task
+    {
+        let mut emergency_alerts = state.emergency_alerts.lock().await;
+
+        emergency_alerts.alerts.insert(id, alert.clone());
+        emergency_alerts
+            .repeat_tasks
+            .insert(id, repeat_task(state.clone(), id, alert.expires_at));
+    }
+
+    if let Err(error_message) = messages::track_and_send(state, message, first_copy).await {
+        let mut emergency_alerts = state.emergency_alerts.lock().await;
+
+        emergency_alerts.alerts.remove(&id);
+
+        if let Some(repeat_task) = emergency_alerts.repeat_tasks.remove(&id) {
+            repeat_task.abort();
+        }
+
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, error_message));
+    }
+
+    // the alert is out by now, so it's kept even if some of the other languages couldn't be sent
+    if let Err(error_message) = publish_messages(state, copies).await {
+        error!(
+            "Failed to publish every copy of emergency alert {}: {}",
+            id, error_message
+        );
+    }
+
+    alert_history::record_emergency_alert(state, &alert).await;
+
+    Ok(alert)
+}
+
+/// /admin/alerts/{id}/cancel
+pub async fn cancel_alert(
+    State(state): State<AppState>,
+    Path(id): Path<EmergencyAlertId>,
+    user: AuthedUser,
+) -> Response {
+    if CONFIG.dual_control {
+        // checked now so that nobody is asked to approve something which can't happen
+        if let Err((status_code, error_message)) =
+            state.emergency_alerts.lock().await.check_active(id)
+        {
+            return FallibleJsonResponse::<()>::Err(status_code, error_message).into_response();
+        }
+
+        return pending_actions::hold_emergency_alert_cancellation(&state, &user, id).await;
+    }
+
+    match cancel(&state, id, &user.name).await {
+        Ok(alert) => FallibleJsonResponse::Ok(alert).into_response(),
+        Err((status_code, error_message)) => {
+            FallibleJsonResponse::<()>::Err(status_code, error_message)
+                .log()
+                .into_response()
+        }
+    }
+}
+
+/// Cancels an active emergency alert, telling nodes to stop showing it
+pub async fn cancel(
+    state: &AppState,
+    id: EmergencyAlertId,
+    cancelled_by: &str,
+) -> Result<EmergencyAlert, (StatusCode, String)> {
+    let alert = {
+        let mut emergency_alerts = state.emergency_alerts.lock().await;
+
+        emergency_alerts.check_active(id)?;
+        emergency_alerts.end(
+            id,
+            EmergencyAlertState::Cancelled,
+            Some(cancelled_by.to_owned()),
+            unix_time_seconds(),
+        );
+
+        if let Some(repeat_task) = emergency_alerts.repeat_tasks.remove(&id) {
+            repeat_task.abort();
+        }
+
+        emergency_alerts.alerts[&id].clone()
+    };
+
+    info!("{} cancelled emergency alert {}", cancelled_by, id);
+
+    alert_history::record_emergency_stage(
+        state,
+        id,
+        AlertStage::Cancelled,
+        Some(cancelled_by.to_owned()),
+    )
+    .await;
+    delivery_reports::generate(state, id).await;
+
+    // so that nodes stop showing it straight away rather than when it would have expired
+    send_command_protobuf(alert_message(&alert, true), &state.mesh_interface)
+        .await
+        .map_err(|error_message| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "Emergency alert {} was cancelled, but nodes couldn't be told: {}",
+                    id, error_message
+                ),
+            )
+        })?;
+
+    Ok(alert)
+}
+
+/// /alerts/broadcasts
+pub async fn get_emergency_alerts(State(state): State<AppState>) -> Json<Vec<EmergencyAlert>> {
+    let mut alerts = state
+        .emergency_alerts
+        .lock()
+        .await
+        .alerts
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // newest first
+    alerts.sort_by_key(|alert| std::cmp::Reverse(alert.issued_at));
+
+    Json(alerts)
+}
+
+#[derive(Serialize)]
+pub struct NodeAckStatus {
+    state: AckState,
+    #[serde(flatten)]
+    ack: NodeAck,
+}
+
+#[derive(Serialize)]
+pub struct AlertAcks {
+    alert_id: EmergencyAlertId,
+    acknowledged: usize,
+    /// received but not acknowledged
+    received: usize,
+    pending: usize,
+    nodes: BTreeMap<NodeId, NodeAckStatus>,
+}
+
+/// /alerts/{id}/acks
+pub async fn get_alert_acks(
+    State(state): State<AppState>,
+    Path(id): Path<EmergencyAlertId>,
+) -> FallibleJsonResponse<AlertAcks> {
+    let emergency_alerts = state.emergency_alerts.lock().await;
+
+    let Some(alert) = emergency_alerts.alerts.get(&id) else {
+        return FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No emergency alert with ID {}", id),
+        );
+    };
+
+    let nodes = alert
+        .acks
+        .iter()
+        .map(|(node_id, ack)| {
+            (
+                *node_id,
+                NodeAckStatus {
+                    state: ack.state(),
+                    ack: *ack,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let count = |state| nodes.values().filter(|node| node.state == state).count();
+
+    FallibleJsonResponse::Ok(AlertAcks {
+        alert_id: id,
+        acknowledged: count(AckState::Acknowledged),
+        received: count(AckState::Received),
+        pending: count(AckState::Pending),
+        nodes,
+    })
+}
@@ -0,0 +1,52 @@
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use bytes::Bytes;
+use prost::Message;
+
+use crate::{config::CONFIG, proto::meshtastic::EncryptedCrisislabMessage};
+
+/// Encrypts a CrisislabMessage being published to the mesh, wrapping it in an
+/// `EncryptedCrisislabMessage`
+pub fn encrypt(payload: &[u8], key: &[u8]) -> Result<Bytes, String> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| "Invalid encryption key")?;
+    // random nonces are fine with how few messages the server sends
+    let nonce = rand::random::<[u8; 12]>();
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| "Failed to encrypt message")?;
+
+    Ok(EncryptedCrisislabMessage {
+        key_id: CONFIG.mesh_encryption_key_id,
+        nonce: nonce.to_vec(),
+        ciphertext,
+    }
+    .encode_to_vec()
+    .into())
+}
+
+/// Decrypts an `EncryptedCrisislabMessage` from the mesh, returning the CrisislabMessage inside it
+pub fn decrypt(payload: &[u8], key: &[u8]) -> Result<Bytes, String> {
+    let encrypted_message = EncryptedCrisislabMessage::decode(payload)
+        .map_err(|error| format!("Failed to decode encrypted message: {:?}", error))?;
+
+    if encrypted_message.key_id != CONFIG.mesh_encryption_key_id {
+        return Err(format!(
+            "Message was encrypted with unknown key {}",
+            encrypted_message.key_id
+        ));
+    }
+
+    if encrypted_message.nonce.len() != 12 {
+        return Err("Message has an invalid nonce".to_owned());
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| "Invalid encryption key")?;
+
+    cipher
+        .decrypt(
+            Nonce::from_slice(&encrypted_message.nonce),
+            encrypted_message.ciphertext.as_slice(),
+        )
+        .map(Bytes::from)
+        .map_err(|_| "Failed to decrypt message (wrong key or tampered with)".to_owned())
+}
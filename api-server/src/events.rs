@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    alerts::AlertEvent,
+    anomaly::Anomaly,
+    battery::NodeWarning,
+    emergency_alerts::AlertAckEvent,
+    expected_nodes::MembershipAlert,
+    firmware::FirmwareUpdateStatus,
+    mesh_status::MeshStatus,
+    pathfinding::{AdjacencyMap, NodeId},
+    presence::PresenceEvent,
+    proto::meshtastic::{
+        crisislab_message::{self, MeshSettings, SignalData, Telemetry},
+        CrisislabMessage,
+    },
+    seismic_events::ShakeEvent,
+    seismic_triggers::SeismicTrigger,
+    AppSettings,
+};
+
+/// Telemetry pushed to live websocket clients, either fresh from the mesh or replayed from storage
+#[derive(Clone, Serialize, Debug)]
+pub struct TelemetryEvent {
+    #[serde(flatten)]
+    pub telemetry: Telemetry,
+    /// only included for replayed telemetry so that clients can tell the difference
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub replay: bool,
+    /// the node's name from the registry, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+}
+
+/// Sent after a route update completes so that clients can redraw the mesh
+#[derive(Clone, Serialize, Debug)]
+pub struct TopologyEvent {
+    /// edge weights used for pathfinding, `adjacency_map[to][from]`
+    pub adjacency_map: AdjacencyMap<NodeId>,
+    pub gateway_ids: Vec<NodeId>,
+    pub next_hops: HashMap<NodeId, Vec<NodeId>>,
+    /// the registry's names for whichever of the nodes have one
+    pub node_names: HashMap<NodeId, String>,
+}
+
+/// Sent when settings are changed through the API so that every dashboard can refresh its
+/// settings view
+#[derive(Clone, Serialize, Debug)]
+#[serde(tag = "scope", content = "settings", rename_all = "snake_case")]
+pub enum SettingsChangedEvent {
+    /// every server setting, after the change
+    Server(AppSettings),
+    /// only the mesh settings which were changed, since the server doesn't keep track of them
+    Mesh(MeshSettings),
+}
+
+/// Events pushed to live websocket clients. Telemetry goes through here (rather than each client
+/// decoding mesh messages itself) so that it's only forwarded once it's been deduplicated.
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerEvent {
+    Alert(AlertEvent),
+    NodeWarning(NodeWarning),
+    NodePresence(PresenceEvent),
+    Anomaly(Anomaly),
+    Telemetry(Box<TelemetryEvent>),
+    /// link readings from a node, forwarded as they arrive rather than only during route updates
+    SignalData(SignalData),
+    Topology(TopologyEvent),
+    MeshStatus(MeshStatus),
+    SettingsChanged(SettingsChangedEvent),
+    FirmwareUpdate(FirmwareUpdateStatus),
+    MembershipAlert(MembershipAlert),
+    AlertAck(AlertAckEvent),
+    /// sent when a shake event starts, whenever it changes, and when it ends
+    ShakeEvent(ShakeEvent),
+    /// sent when a node's STA/LTA detector triggers, and again when the trigger ends
+    SeismicTrigger(SeismicTrigger),
+    Error(String),
+}
+
+/// The kinds of events websocket clients can subscribe to, named after the packets' keys
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Alert,
+    NodeWarning,
+    NodePresence,
+    Anomaly,
+    Telemetry,
+    SignalData,
+    Topology,
+    MeshStatus,
+    SettingsChanged,
+    FirmwareUpdate,
+    MembershipAlert,
+    AlertAck,
+    ShakeEvent,
+    SeismicTrigger,
+    Error,
+}
+
+/// Names clients can use for several kinds at once
+const EVENT_KIND_GROUPS: [(&str, &[EventKind]); 1] = [(
+    "alerts",
+    &[
+        EventKind::Alert,
+        EventKind::MembershipAlert,
+        EventKind::AlertAck,
+    ],
+)];
+
+impl EventKind {
+    pub const ALL: [EventKind; 15] = [
+        EventKind::Alert,
+        EventKind::NodeWarning,
+        EventKind::NodePresence,
+        EventKind::Anomaly,
+        EventKind::Telemetry,
+        EventKind::SignalData,
+        EventKind::Topology,
+        EventKind::MeshStatus,
+        EventKind::SettingsChanged,
+        EventKind::FirmwareUpdate,
+        EventKind::MembershipAlert,
+        EventKind::AlertAck,
+        EventKind::ShakeEvent,
+        EventKind::SeismicTrigger,
+        EventKind::Error,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Alert => "alert",
+            EventKind::NodeWarning => "node_warning",
+            EventKind::NodePresence => "node_presence",
+            EventKind::Anomaly => "anomaly",
+            EventKind::Telemetry => "telemetry",
+            EventKind::SignalData => "signal_data",
+            EventKind::Topology => "topology",
+            EventKind::MeshStatus => "mesh_status",
+            EventKind::SettingsChanged => "settings_changed",
+            EventKind::FirmwareUpdate => "firmware_update",
+            EventKind::MembershipAlert => "membership_alert",
+            EventKind::AlertAck => "alert_ack",
+            EventKind::ShakeEvent => "shake_event",
+            EventKind::SeismicTrigger => "seismic_trigger",
+            EventKind::Error => "error",
+        }
+    }
+
+    /// Parses a kind's name, or the name of a group of kinds (e.g. `alerts`)
+    pub fn parse(name: &str) -> Result<Vec<EventKind>, String> {
+        if let Some((_, kinds)) = EVENT_KIND_GROUPS
+            .iter()
+            .find(|(group_name, _)| *group_name == name)
+        {
+            return Ok(kinds.to_vec());
+        }
+
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .map(|kind| vec![kind])
+            .ok_or_else(|| {
+                format!(
+                    "unknown kind `{}`, expected one of {} or alerts",
+                    name,
+                    EventKind::ALL.map(EventKind::name).join(", ")
+                )
+            })
+    }
+}
+
+/// Deserialises a list of kinds and groups of kinds, as clients give them
+pub fn deserialize_kinds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashSet<EventKind>, D::Error> {
+    let mut kinds = HashSet::new();
+
+    for name in Vec::<String>::deserialize(deserializer)? {
+        kinds.extend(EventKind::parse(&name).map_err(D::Error::custom)?);
+    }
+
+    Ok(kinds)
+}
+
+/// Like `deserialize_kinds`, but allows `null`
+pub fn deserialize_optional_kinds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HashSet<EventKind>>, D::Error> {
+    #[derive(Deserialize)]
+    struct Kinds(#[serde(deserialize_with = "deserialize_kinds")] HashSet<EventKind>);
+
+    Ok(Option::<Kinds>::deserialize(deserializer)?.map(|Kinds(kinds)| kinds))
+}
+
+impl ServerEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ServerEvent::Alert(_) => EventKind::Alert,
+            ServerEvent::NodeWarning(_) => EventKind::NodeWarning,
+            ServerEvent::NodePresence(_) => EventKind::NodePresence,
+            ServerEvent::Anomaly(_) => EventKind::Anomaly,
+            ServerEvent::Telemetry(_) => EventKind::Telemetry,
+            ServerEvent::SignalData(_) => EventKind::SignalData,
+            ServerEvent::Topology(_) => EventKind::Topology,
+            ServerEvent::MeshStatus(_) => EventKind::MeshStatus,
+            ServerEvent::SettingsChanged(_) => EventKind::SettingsChanged,
+            ServerEvent::FirmwareUpdate(_) => EventKind::FirmwareUpdate,
+            ServerEvent::MembershipAlert(_) => EventKind::MembershipAlert,
+            ServerEvent::AlertAck(_) => EventKind::AlertAck,
+            ServerEvent::ShakeEvent(_) => EventKind::ShakeEvent,
+            ServerEvent::SeismicTrigger(_) => EventKind::SeismicTrigger,
+            ServerEvent::Error(_) => EventKind::Error,
+        }
+    }
+
+    /// The event as a protobuf, for clients which want them, if it can be represented as one
+    pub fn to_protobuf(&self) -> Option<CrisislabMessage> {
+        match self {
+            ServerEvent::Telemetry(telemetry) => Some(CrisislabMessage {
+                message: Some(crisislab_message::Message::Telemetry(
+                    telemetry.telemetry.clone(),
+                )),
+                destination: None,
+            }),
+            ServerEvent::SignalData(signal_data) => Some(CrisislabMessage {
+                message: Some(crisislab_message::Message::SignalData(signal_data.clone())),
+                destination: None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The node the event is about, if it's about a particular node
+    pub fn node_id(&self) -> Option<NodeId> {
+        match self {
+            ServerEvent::Alert(alert) => Some(alert.node_id),
+            ServerEvent::NodeWarning(warning) => Some(warning.node_id),
+            ServerEvent::NodePresence(presence) => Some(presence.node_id),
+            ServerEvent::Anomaly(anomaly) => Some(anomaly.node_id),
+            ServerEvent::Telemetry(telemetry) => Some(telemetry.telemetry.node_num),
+            ServerEvent::SignalData(signal_data) => Some(signal_data.to),
+            ServerEvent::FirmwareUpdate(status) => Some(status.node_id),
+            ServerEvent::MembershipAlert(alert) => Some(alert.node_id),
+            ServerEvent::AlertAck(ack) => Some(ack.node_id),
+            ServerEvent::SeismicTrigger(trigger) => Some(trigger.node_id),
+            ServerEvent::Topology(_)
+            | ServerEvent::MeshStatus(_)
+            | ServerEvent::SettingsChanged(_)
+            | ServerEvent::ShakeEvent(_)
+            | ServerEvent::Error(_) => None,
+        }
+    }
+}
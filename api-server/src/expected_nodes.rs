@@ -0,0 +1,262 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{
+    alerts::{self, AlertState},
+    auth::AuthedUser,
+    config::CONFIG,
+    events::ServerEvent,
+    maintenance_windows,
+    pathfinding::NodeId,
+    persistence,
+    presence::{NodePresence, PresenceState},
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipAlertKind {
+    /// an expected node isn't online
+    Missing,
+    /// a node which isn't expected is online, which could be a misconfigured or foreign node
+    Unexpected,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct MembershipAlert {
+    pub node_id: NodeId,
+    pub kind: MembershipAlertKind,
+    pub state: AlertState,
+    /// `None` if the node has never been heard from
+    pub last_seen: Option<u64>,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+}
+
+/// The nodes operators have said should be on the mesh, which are saved to `expected-nodes.json` in
+/// the data directory whenever they change. Nothing is checked until they've been declared.
+#[derive(Default)]
+pub struct ExpectedNodes {
+    node_ids: Option<BTreeSet<NodeId>>,
+    /// nodes which currently have a membership alert, so each one only fires once
+    active: BTreeMap<NodeId, MembershipAlertKind>,
+}
+
+impl ExpectedNodes {
+    pub fn restore(&mut self, node_ids: Option<BTreeSet<NodeId>>) {
+        self.node_ids = node_ids;
+    }
+
+    pub fn node_ids(&self) -> Option<&BTreeSet<NodeId>> {
+        self.node_ids.as_ref()
+    }
+
+    /// Compares the expected nodes with the ones which are online, returning alerts for any nodes
+    /// which have gone missing or turned up unexpectedly, and for any which are back to normal
+    fn check(
+        &mut self,
+        presence: &HashMap<NodeId, NodePresence>,
+        now: u64,
+    ) -> Vec<MembershipAlert> {
+        let is_online = |node_id: &NodeId| {
+            presence
+                .get(node_id)
+                .is_some_and(|presence| presence.state == PresenceState::Online)
+        };
+
+        let current = match &self.node_ids {
+            Some(node_ids) => node_ids
+                .iter()
+                .filter(|node_id| !is_online(node_id))
+                .map(|node_id| (*node_id, MembershipAlertKind::Missing))
+                .chain(
+                    presence
+                        .keys()
+                        .filter(|node_id| !node_ids.contains(node_id) && is_online(node_id))
+                        .map(|node_id| (*node_id, MembershipAlertKind::Unexpected)),
+                )
+                .collect::<BTreeMap<_, _>>(),
+            None => BTreeMap::new(),
+        };
+
+        let alert =
+            |node_id: NodeId, kind: MembershipAlertKind, state: AlertState| MembershipAlert {
+                node_id,
+                kind,
+                state,
+                last_seen: presence.get(&node_id).map(|presence| presence.last_seen),
+                timestamp: now,
+            };
+
+        let mut alerts = self
+            .active
+            .iter()
+            .filter(|(node_id, kind)| current.get(node_id) != Some(kind))
+            .map(|(node_id, kind)| alert(*node_id, *kind, AlertState::Resolved))
+            .collect::<Vec<_>>();
+
+        alerts.extend(
+            current
+                .iter()
+                .filter(|(node_id, kind)| self.active.get(node_id) != Some(kind))
+                .map(|(node_id, kind)| alert(*node_id, *kind, AlertState::Fired)),
+        );
+
+        self.active = current;
+
+        alerts
+    }
+}
+
+/// Pushes membership alerts to live websocket clients and any configured webhooks
+pub async fn dispatch(state: &AppState, alerts: Vec<MembershipAlert>) {
+    for alert in alerts {
+        match (alert.kind, alert.state) {
+            (MembershipAlertKind::Missing, AlertState::Fired) => {
+                warn!("Expected node {} is missing", alert.node_id)
+            }
+            (MembershipAlertKind::Unexpected, AlertState::Fired) => {
+                warn!("Unexpected node {} is online", alert.node_id)
+            }
+            (_, AlertState::Resolved) => {
+                info!("{:?} node {} alert resolved", alert.kind, alert.node_id)
+            }
+        }
+
+        let event = ServerEvent::MembershipAlert(alert.clone());
+
+        if maintenance_windows::mute(state, &event).await {
+            continue;
+        }
+
+        alerts::send_to_webhooks(state, &alert);
+
+        let _ = state.server_events.send(event);
+    }
+}
+
+/// Spawns the task which periodically compares the expected nodes with the ones which are online
+pub fn check_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting expected nodes check task");
+
+        // nodes aren't online straight after the server starts, so give them a chance to be heard
+        // from before any are reported missing
+        tokio::time::sleep(Duration::from_secs(CONFIG.node_offline_after_seconds)).await;
+
+        let check_interval = Duration::from_secs(CONFIG.expected_nodes_check_seconds.max(1));
+
+        loop {
+            let alerts = {
+                let presence = state.presence.lock().await;
+
+                state
+                    .expected_nodes
+                    .lock()
+                    .await
+                    .check(presence.nodes(), unix_time_seconds())
+            };
+
+            dispatch(&state, alerts).await;
+
+            tokio::time::sleep(check_interval).await;
+        }
+    })
+}
+
+#[derive(Serialize)]
+pub struct ExpectedNodesStatus {
+    /// `None` if the expected nodes haven't been declared
+    node_ids: Option<BTreeSet<NodeId>>,
+    /// as of the last check
+    missing: Vec<NodeId>,
+    unexpected: Vec<NodeId>,
+}
+
+fn status(expected_nodes: &ExpectedNodes) -> ExpectedNodesStatus {
+    let with_kind = |kind| {
+        expected_nodes
+            .active
+            .iter()
+            .filter(|(_, active_kind)| **active_kind == kind)
+            .map(|(node_id, _)| *node_id)
+            .collect()
+    };
+
+    ExpectedNodesStatus {
+        node_ids: expected_nodes.node_ids.clone(),
+        missing: with_kind(MembershipAlertKind::Missing),
+        unexpected: with_kind(MembershipAlertKind::Unexpected),
+    }
+}
+
+/// /admin/expected-nodes (GET)
+pub async fn get_expected_nodes(State(state): State<AppState>) -> Json<ExpectedNodesStatus> {
+    Json(status(&*state.expected_nodes.lock().await))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedNodesBody {
+    /// seeded from every node in the registry which isn't pending if this isn't given
+    node_ids: Option<BTreeSet<NodeId>>,
+}
+
+/// /admin/expected-nodes (PUT)
+pub async fn set_expected_nodes(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<ExpectedNodesBody>,
+) -> FallibleJsonResponse<ExpectedNodesStatus> {
+    let node_ids = match body.node_ids {
+        Some(node_ids) => node_ids,
+        None => state
+            .node_registry
+            .lock()
+            .await
+            .nodes()
+            .iter()
+            .filter(|(_, info)| !info.pending)
+            .map(|(node_id, _)| *node_id)
+            .collect(),
+    };
+
+    info!("{} set the expected nodes to {:?}", user, node_ids);
+
+    state.expected_nodes.lock().await.node_ids = Some(node_ids);
+
+    if let Err(error_message) = persistence::save_expected_nodes(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(status(&*state.expected_nodes.lock().await))
+}
+
+/// /admin/expected-nodes (DELETE)
+pub async fn clear_expected_nodes(
+    State(state): State<AppState>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state.expected_nodes.lock().await.node_ids.take().is_none() {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            "The expected nodes haven't been declared".to_owned(),
+        );
+    }
+
+    info!("{} stopped checking for expected nodes", user);
+
+    if let Err(error_message) = persistence::save_expected_nodes(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
@@ -0,0 +1,402 @@
+use std::{iter::Peekable, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    alerts::{telemetry_values, AlertField, Comparison},
+    pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::Telemetry,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Identifier(String),
+    /// kept as text so that node IDs can be parsed as integers, since not every one fits in an f32
+    Number(String),
+    Comparison(Comparison),
+    Or,
+    And,
+    Not,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+}
+
+fn tokenise(string: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = string.chars().peekable();
+
+    while let Some(&char) = chars.peek() {
+        if char.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if char.is_ascii_alphabetic() || char == '_' {
+            let mut identifier = String::new();
+
+            while let Some(&char) = chars.peek() {
+                if !(char.is_ascii_alphanumeric() || char == '_') {
+                    break;
+                }
+
+                identifier.push(char);
+                chars.next();
+            }
+
+            tokens.push(Token::Identifier(identifier));
+            continue;
+        }
+
+        if char.is_ascii_digit() || char == '-' || char == '.' {
+            let mut number = String::new();
+
+            while let Some(&char) = chars.peek() {
+                if !(char.is_ascii_digit() || char == '-' || char == '.') {
+                    break;
+                }
+
+                number.push(char);
+                chars.next();
+            }
+
+            if number.parse::<f32>().is_err() {
+                return Err(format!("Invalid number: {}", number));
+            }
+
+            tokens.push(Token::Number(number));
+            continue;
+        }
+
+        chars.next();
+
+        let next_is_equals = chars.next_if_eq(&'=').is_some();
+
+        let token = match (char, next_is_equals) {
+            ('<', false) => Token::Comparison(Comparison::LessThan),
+            ('<', true) => Token::Comparison(Comparison::LessThanOrEqual),
+            ('>', false) => Token::Comparison(Comparison::GreaterThan),
+            ('>', true) => Token::Comparison(Comparison::GreaterThanOrEqual),
+            ('|', false) if chars.next_if_eq(&'|').is_some() => Token::Or,
+            ('&', false) if chars.next_if_eq(&'&').is_some() => Token::And,
+            ('!', false) => Token::Not,
+            ('(', false) => Token::OpenParen,
+            (')', false) => Token::CloseParen,
+            ('[', false) => Token::OpenBracket,
+            (']', false) => Token::CloseBracket,
+            (',', false) => Token::Comma,
+            _ => return Err(format!("Unexpected character: {}", char)),
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// A parsed filter expression such as `battery < 30 || node_id in [5, 7]`, which websocket
+/// clients can use to only be sent some telemetry
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TelemetryFilter {
+    Or(Box<TelemetryFilter>, Box<TelemetryFilter>),
+    And(Box<TelemetryFilter>, Box<TelemetryFilter>),
+    Not(Box<TelemetryFilter>),
+    NodeIdIn(Vec<NodeId>),
+    NodeId(Comparison, NodeId),
+    /// false if the packet doesn't have the field
+    Field(AlertField, Comparison, f32),
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+/// How deeply parentheses and `!`s can be nested. The parser (and matching) recurse once per level,
+/// so without a limit a filter full of `(`s could overflow the stack.
+const MAX_DEPTH: usize = 32;
+
+/// Longer filters are rejected before parsing, which also limits how long chains of `&&` and `||`
+/// can be, since they're nested just as deeply once parsed
+const MAX_TOKENS: usize = 256;
+
+fn expect(tokens: &mut Tokens, expected: Token) -> Result<(), String> {
+    match tokens.next() {
+        Some(token) if token == expected => Ok(()),
+        Some(token) => Err(format!("Expected {:?}, got {:?}", expected, token)),
+        None => Err(format!(
+            "Expected {:?}, got the end of the filter",
+            expected
+        )),
+    }
+}
+
+fn parse_number(tokens: &mut Tokens) -> Result<f32, String> {
+    match tokens.next() {
+        Some(Token::Number(number)) => number
+            .parse()
+            .map_err(|_| format!("Invalid number: {}", number)),
+        Some(token) => Err(format!("Expected a number, got {:?}", token)),
+        None => Err("Expected a number, got the end of the filter".to_owned()),
+    }
+}
+
+fn parse_node_id(tokens: &mut Tokens) -> Result<NodeId, String> {
+    match tokens.next() {
+        Some(Token::Number(number)) => number
+            .parse()
+            .map_err(|_| format!("Invalid node ID: {}", number)),
+        Some(token) => Err(format!("Expected a node ID, got {:?}", token)),
+        None => Err("Expected a node ID, got the end of the filter".to_owned()),
+    }
+}
+
+fn parse_or(tokens: &mut Tokens, depth: usize) -> Result<TelemetryFilter, String> {
+    let mut filter = parse_and(tokens, depth)?;
+
+    while tokens.next_if_eq(&Token::Or).is_some() {
+        filter = TelemetryFilter::Or(Box::new(filter), Box::new(parse_and(tokens, depth)?));
+    }
+
+    Ok(filter)
+}
+
+fn parse_and(tokens: &mut Tokens, depth: usize) -> Result<TelemetryFilter, String> {
+    let mut filter = parse_term(tokens, depth)?;
+
+    while tokens.next_if_eq(&Token::And).is_some() {
+        filter = TelemetryFilter::And(Box::new(filter), Box::new(parse_term(tokens, depth)?));
+    }
+
+    Ok(filter)
+}
+
+fn parse_term(tokens: &mut Tokens, depth: usize) -> Result<TelemetryFilter, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "Filters can't be nested more than {} levels deep",
+            MAX_DEPTH
+        ));
+    }
+
+    match tokens.next() {
+        Some(Token::Not) => Ok(TelemetryFilter::Not(Box::new(parse_term(
+            tokens,
+            depth + 1,
+        )?))),
+        Some(Token::OpenParen) => {
+            let filter = parse_or(tokens, depth + 1)?;
+            expect(tokens, Token::CloseParen)?;
+            Ok(filter)
+        }
+        Some(Token::Identifier(name)) if name == "node_id" => match tokens.next() {
+            Some(Token::Identifier(keyword)) if keyword == "in" => {
+                expect(tokens, Token::OpenBracket)?;
+
+                let mut node_ids = Vec::new();
+
+                if tokens.next_if_eq(&Token::CloseBracket).is_none() {
+                    loop {
+                        node_ids.push(parse_node_id(tokens)?);
+
+                        if tokens.next_if_eq(&Token::Comma).is_none() {
+                            expect(tokens, Token::CloseBracket)?;
+                            break;
+                        }
+                    }
+                }
+
+                Ok(TelemetryFilter::NodeIdIn(node_ids))
+            }
+            Some(Token::Comparison(comparison)) => {
+                Ok(TelemetryFilter::NodeId(comparison, parse_node_id(tokens)?))
+            }
+            _ => Err("Expected a comparison or \"in\" after node_id".to_owned()),
+        },
+        Some(Token::Identifier(name)) => {
+            let field = name.parse()?;
+
+            match tokens.next() {
+                Some(Token::Comparison(comparison)) => Ok(TelemetryFilter::Field(
+                    field,
+                    comparison,
+                    parse_number(tokens)?,
+                )),
+                _ => Err(format!("Expected a comparison after {}", name)),
+            }
+        }
+        Some(token) => Err(format!("Unexpected {:?}", token)),
+        None => Err("Unexpected end of filter".to_owned()),
+    }
+}
+
+impl FromStr for TelemetryFilter {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenise(string)?;
+
+        if tokens.len() > MAX_TOKENS {
+            return Err(format!(
+                "Filters can't be longer than {} tokens",
+                MAX_TOKENS
+            ));
+        }
+
+        let mut tokens = tokens.into_iter().peekable();
+        let filter = parse_or(&mut tokens, 0)?;
+
+        match tokens.next() {
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Ok(filter),
+        }
+    }
+}
+
+impl TryFrom<String> for TelemetryFilter {
+    type Error = String;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl TelemetryFilter {
+    pub fn matches(&self, telemetry: &Telemetry) -> bool {
+        match self {
+            TelemetryFilter::Or(left, right) => left.matches(telemetry) || right.matches(telemetry),
+            TelemetryFilter::And(left, right) => {
+                left.matches(telemetry) && right.matches(telemetry)
+            }
+            TelemetryFilter::Not(filter) => !filter.matches(telemetry),
+            TelemetryFilter::NodeIdIn(node_ids) => node_ids.contains(&telemetry.node_num),
+            TelemetryFilter::NodeId(comparison, value) => {
+                comparison.holds(telemetry.node_num, *value)
+            }
+            TelemetryFilter::Field(field, comparison, threshold) => telemetry_values(telemetry)
+                .into_iter()
+                .any(|(value_field, value)| {
+                    value_field == *field && comparison.holds(value, *threshold)
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::meshtastic::DeviceMetrics;
+
+    fn telemetry(node_num: NodeId, battery_level: u32) -> Telemetry {
+        Telemetry {
+            node_num,
+            device_metrics: Some(DeviceMetrics {
+                battery_level: Some(battery_level),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn nested(depth: usize) -> String {
+        format!("{}battery < 20{}", "(".repeat(depth), ")".repeat(depth))
+    }
+
+    fn chain(clauses: usize) -> String {
+        vec!["battery < 20"; clauses].join(" || ")
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let filter = "battery < 20 || battery > 50 && node_id in [1]"
+            .parse::<TelemetryFilter>()
+            .unwrap();
+
+        let TelemetryFilter::Or(_, right) = &filter else {
+            panic!("Expected an Or, got {:?}", filter);
+        };
+        assert!(matches!(**right, TelemetryFilter::And(..)));
+
+        assert!(filter.matches(&telemetry(5, 10)));
+        assert!(!filter.matches(&telemetry(5, 60)));
+        assert!(filter.matches(&telemetry(1, 60)));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let filter = "!battery < 20 && node_id in [5]"
+            .parse::<TelemetryFilter>()
+            .unwrap();
+
+        assert!(matches!(filter, TelemetryFilter::And(..)));
+        assert!(filter.matches(&telemetry(5, 60)));
+        assert!(!filter.matches(&telemetry(5, 10)));
+        assert!(!filter.matches(&telemetry(6, 60)));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let filter = "(battery < 20 || battery > 50) && node_id in [1]"
+            .parse::<TelemetryFilter>()
+            .unwrap();
+
+        assert!(!filter.matches(&telemetry(5, 10)));
+        assert!(filter.matches(&telemetry(1, 10)));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        assert!(nested(MAX_DEPTH).parse::<TelemetryFilter>().is_ok());
+        assert!(nested(MAX_DEPTH + 1).parse::<TelemetryFilter>().is_err());
+
+        let nots = format!("{}battery < 20", "!".repeat(MAX_DEPTH + 1));
+        assert!(nots.parse::<TelemetryFilter>().is_err());
+    }
+
+    #[test]
+    fn length_is_limited() {
+        // each clause is 3 tokens, with an || between each of them
+        let max_clauses = (MAX_TOKENS + 1) / 4;
+
+        assert!(chain(max_clauses).parse::<TelemetryFilter>().is_ok());
+        assert!(chain(max_clauses + 1).parse::<TelemetryFilter>().is_err());
+    }
+
+    #[test]
+    fn node_ids_are_compared_exactly() {
+        // 2^24 + 1, which an f32 would round down to 2^24
+        let node_id = 16_777_217;
+
+        let filter = "node_id in [16777217]".parse::<TelemetryFilter>().unwrap();
+        assert!(filter.matches(&telemetry(node_id, 10)));
+        assert!(!filter.matches(&telemetry(node_id - 1, 10)));
+
+        let filter = "node_id > 16777216".parse::<TelemetryFilter>().unwrap();
+        assert!(filter.matches(&telemetry(node_id, 10)));
+        assert!(!filter.matches(&telemetry(node_id - 1, 10)));
+
+        let filter = "node_id <= 4294967295".parse::<TelemetryFilter>().unwrap();
+        assert!(filter.matches(&telemetry(NodeId::MAX, 10)));
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for filter in [
+            "",
+            "battery",
+            "battery < ",
+            "(battery < 20",
+            "battery < 20)",
+            "node_id in [1,",
+            "node_id in [1.5]",
+            "unknown < 1",
+            "battery = 20",
+        ] {
+            assert!(
+                filter.parse::<TelemetryFilter>().is_err(),
+                "{:?} should be rejected",
+                filter
+            );
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    command_history,
+    events::ServerEvent,
+    pathfinding::NodeId,
+    persistence,
+    proto::meshtastic::{
+        crisislab_message::{self, FirmwareUpdateProgress, Telemetry},
+        CrisislabMessage,
+    },
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody},
+    AppState,
+};
+
+/// The firmware a node says it's running, kept in the node registry
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct FirmwareInfo {
+    /// e.g. "2.5.6"
+    pub version: String,
+    pub build: Option<String>,
+    /// seconds since unix epoch that the node was first heard running this firmware
+    pub since: u64,
+}
+
+/// Records the firmware in a telemetry packet in the node registry, if the node sent it
+pub async fn record_firmware(state: &AppState, telemetry: &Telemetry) {
+    let Some(version) = telemetry.firmware_version.clone() else {
+        return;
+    };
+
+    if !state.node_registry.lock().await.record_firmware(
+        telemetry.node_num,
+        version.clone(),
+        telemetry.firmware_build.clone(),
+        unix_time_seconds(),
+    ) {
+        return;
+    }
+
+    info!(
+        "Node {} is running firmware {}",
+        telemetry.node_num, version
+    );
+
+    if let Err(error_message) = persistence::save_node_registry(state).await {
+        error!("{}", error_message);
+    }
+}
+
+#[derive(Serialize)]
+pub struct FirmwareVersionSummary {
+    version: String,
+    build: Option<String>,
+    node_ids: Vec<NodeId>,
+}
+
+#[derive(Serialize)]
+pub struct FleetFirmware {
+    /// most common first
+    versions: Vec<FirmwareVersionSummary>,
+    /// nodes in the registry which haven't reported their firmware
+    unknown: Vec<NodeId>,
+}
+
+/// /info/firmware-versions
+pub async fn get_firmware_versions(State(state): State<AppState>) -> Json<FleetFirmware> {
+    let mut versions = BTreeMap::<(String, Option<String>), Vec<NodeId>>::new();
+    let mut unknown = Vec::new();
+
+    for (node_id, info) in state.node_registry.lock().await.nodes() {
+        match &info.firmware {
+            Some(firmware) => versions
+                .entry((firmware.version.clone(), firmware.build.clone()))
+                .or_default()
+                .push(*node_id),
+            None => unknown.push(*node_id),
+        }
+    }
+
+    let mut versions = versions
+        .into_iter()
+        .map(|((version, build), node_ids)| FirmwareVersionSummary {
+            version,
+            build,
+            node_ids,
+        })
+        .collect::<Vec<_>>();
+
+    versions.sort_by_key(|summary| std::cmp::Reverse(summary.node_ids.len()));
+
+    Json(FleetFirmware { versions, unknown })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FirmwareUpdateState {
+    /// sent to the mesh, but the node hasn't reported any progress yet
+    Requested,
+    InProgress,
+    Done,
+    Failed,
+}
+
+/// How an update the server asked a node to do is going. Sent to live websocket clients whenever
+/// the node reports progress.
+#[derive(Clone, Serialize, Debug)]
+pub struct FirmwareUpdateStatus {
+    pub node_id: NodeId,
+    pub version: String,
+    /// the gateway which was asked to relay the update
+    pub via_gateway: NodeId,
+    pub requested_by: String,
+    /// seconds since unix epoch
+    pub requested_at: u64,
+    pub state: FirmwareUpdateState,
+    pub percent: u32,
+    pub error: Option<String>,
+    /// seconds since unix epoch of the last progress report
+    pub updated_at: u64,
+}
+
+/// The most recent update requested for each node. These are only kept in memory, so progress
+/// reports for updates requested before the server restarted are ignored.
+#[derive(Default)]
+pub struct FirmwareUpdateStore {
+    updates: HashMap<NodeId, FirmwareUpdateStatus>,
+}
+
+impl FirmwareUpdateStore {
+    fn record_progress(
+        &mut self,
+        progress: &FirmwareUpdateProgress,
+        now: u64,
+    ) -> Option<FirmwareUpdateStatus> {
+        let status = self.updates.get_mut(&progress.node_num)?;
+
+        status.state = if progress.error.is_some() {
+            FirmwareUpdateState::Failed
+        } else if progress.done {
+            FirmwareUpdateState::Done
+        } else {
+            FirmwareUpdateState::InProgress
+        };
+        status.percent = progress.percent.min(100);
+        status.error = progress.error.clone();
+        status.updated_at = now;
+
+        Some(status.clone())
+    }
+}
+
+/// Updates the status of a node's firmware update from a progress report it sent
+pub async fn record_progress(state: &AppState, progress: &FirmwareUpdateProgress) {
+    let Some(status) = state
+        .firmware_updates
+        .lock()
+        .await
+        .record_progress(progress, unix_time_seconds())
+    else {
+        warn!(
+            "Ignoring firmware update progress from node {}, which wasn't asked to update",
+            progress.node_num
+        );
+        return;
+    };
+
+    match status.state {
+        FirmwareUpdateState::Done => info!(
+            "Node {} finished updating to firmware {}",
+            status.node_id, status.version
+        ),
+        FirmwareUpdateState::Failed => warn!(
+            "Node {} failed to update to firmware {}: {}",
+            status.node_id,
+            status.version,
+            status.error.as_deref().unwrap_or_default()
+        ),
+        _ => {}
+    }
+
+    let _ = state
+        .server_events
+        .send(ServerEvent::FirmwareUpdate(status));
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestUpdateBody {
+    version: String,
+    /// where the node should download the firmware from, if it isn't the default
+    url: Option<String>,
+}
+
+/// /admin/nodes/{id}/request-update
+pub async fn request_update(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<RequestUpdateBody>,
+) -> FallibleJsonResponse<FirmwareUpdateStatus> {
+    let Some(via_gateway) = state.topology.lock().await.nearest_gateway(node_id) else {
+        return FallibleJsonResponse::Err(
+            StatusCode::CONFLICT,
+            format!(
+                "No route from node {} to a gateway is known, try updating the routes first",
+                node_id
+            ),
+        );
+    };
+
+    info!(
+        "{} asked node {} to update to firmware {} via gateway {}",
+        user, node_id, body.version, via_gateway
+    );
+
+    let crisislab_message = CrisislabMessage {
+        message: Some(crisislab_message::Message::FirmwareUpdate(
+            crisislab_message::FirmwareUpdate {
+                version: body.version.clone(),
+                url: body.url,
+                via_gateway,
+            },
+        )),
+        destination: Some(node_id),
+    };
+
+    if let Err(error_message) =
+        command_history::send_to_node(&state, node_id, crisislab_message, &user.name).await
+    {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let now = unix_time_seconds();
+
+    let status = FirmwareUpdateStatus {
+        node_id,
+        version: body.version,
+        via_gateway,
+        requested_by: user.name.clone(),
+        requested_at: now,
+        state: FirmwareUpdateState::Requested,
+        percent: 0,
+        error: None,
+        updated_at: now,
+    };
+
+    state
+        .firmware_updates
+        .lock()
+        .await
+        .updates
+        .insert(node_id, status.clone());
+
+    FallibleJsonResponse::Ok(status)
+}
+
+/// /admin/firmware-updates
+pub async fn get_firmware_updates(
+    State(state): State<AppState>,
+) -> Json<Vec<FirmwareUpdateStatus>> {
+    let mut updates = state
+        .firmware_updates
+        .lock()
+        .await
+        .updates
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    updates.sort_by_key(|status| status.requested_at);
+
+    Json(updates)
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    pathfinding::NodeId,
+    persistence,
+    presence::PresenceState,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Gateway {
+    pub node_id: NodeId,
+    pub registered_by: String,
+    /// seconds since unix epoch
+    pub registered_at: u64,
+}
+
+/// The nodes operators have said are gateways, which are saved to `gateways.json` in the data
+/// directory whenever they change. If there are any, route updates use these instead of whichever
+/// nodes say they're gateways in their signal data.
+#[derive(Default)]
+pub struct GatewayRegistry {
+    gateways: BTreeMap<NodeId, Gateway>,
+}
+
+impl GatewayRegistry {
+    pub fn restore(&mut self, gateways: Vec<Gateway>) {
+        self.gateways = gateways
+            .into_iter()
+            .map(|gateway| (gateway.node_id, gateway))
+            .collect();
+    }
+
+    pub fn gateways(&self) -> Vec<&Gateway> {
+        self.gateways.values().collect()
+    }
+
+    pub fn ids(&self) -> Vec<NodeId> {
+        self.gateways.keys().copied().collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GatewayBody {
+    node_id: NodeId,
+}
+
+/// /admin/gateways (POST)
+pub async fn register_gateway(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<GatewayBody>,
+) -> FallibleJsonResponse<Gateway> {
+    let gateway = Gateway {
+        node_id: body.node_id,
+        registered_by: user.name.clone(),
+        registered_at: unix_time_seconds(),
+    };
+
+    {
+        let mut gateway_registry = state.gateway_registry.lock().await;
+
+        if let Some(existing) = gateway_registry.gateways.get(&body.node_id) {
+            return FallibleJsonResponse::Ok(existing.clone());
+        }
+
+        gateway_registry
+            .gateways
+            .insert(gateway.node_id, gateway.clone());
+    }
+
+    info!("{} registered node {} as a gateway", user, gateway.node_id);
+
+    if let Err(error_message) = persistence::save_gateway_registry(&state).await {
+        error!("{}", error_message);
+    }
+
+    FallibleJsonResponse::Ok(gateway)
+}
+
+#[derive(Serialize)]
+pub struct GatewayStatus {
+    #[serde(flatten)]
+    gateway: Gateway,
+    /// from the node registry
+    name: Option<String>,
+    /// `None` if the gateway has never been heard from
+    state: Option<PresenceState>,
+    /// seconds since unix epoch
+    last_seen: Option<u64>,
+}
+
+/// /admin/gateways (GET)
+pub async fn get_gateways(State(state): State<AppState>) -> Json<Vec<GatewayStatus>> {
+    let gateway_registry = state.gateway_registry.lock().await;
+    let presence = state.presence.lock().await;
+    let node_registry = state.node_registry.lock().await;
+
+    Json(
+        gateway_registry
+            .gateways
+            .values()
+            .map(|gateway| {
+                let node_presence = presence.nodes().get(&gateway.node_id);
+
+                GatewayStatus {
+                    gateway: gateway.clone(),
+                    name: node_registry.name(gateway.node_id),
+                    state: node_presence.map(|presence| presence.state),
+                    last_seen: node_presence.map(|presence| presence.last_seen),
+                }
+            })
+            .collect(),
+    )
+}
+
+/// /admin/gateways/{id}
+pub async fn unregister_gateway(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state
+        .gateway_registry
+        .lock()
+        .await
+        .gateways
+        .remove(&node_id)
+        .is_none()
+    {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("Node {} isn't a registered gateway", node_id),
+        );
+    }
+
+    info!("{} unregistered gateway {}", user, node_id);
+
+    if let Err(error_message) = persistence::save_gateway_registry(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
@@ -0,0 +1,202 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A GeoJSON position, which is longitude first and may have an altitude
+type Position = Vec<f64>;
+
+/// An outer ring followed by any holes, each closed (the first and last positions are the same)
+type Polygon = Vec<Vec<Position>>;
+
+/// The GeoJSON objects an area can be given as. Other geometries (points, lines) don't enclose
+/// anything, so aren't accepted.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum GeoJson {
+    Polygon { coordinates: Polygon },
+    MultiPolygon { coordinates: Vec<Polygon> },
+    Feature { geometry: Box<GeoJson> },
+}
+
+/// An area on the map, made of one or more polygons which may have holes in them
+#[derive(Debug)]
+pub struct Area {
+    polygons: Vec<Polygon>,
+}
+
+impl Area {
+    /// Reads a GeoJSON Polygon or MultiPolygon, or a Feature with one as its geometry
+    pub fn from_geojson(geojson: &Value) -> Result<Self, String> {
+        let geojson = GeoJson::deserialize(geojson)
+            .map_err(|error| format!("The area isn't a GeoJSON polygon: {}", error))?;
+
+        let polygons = flatten(geojson);
+
+        for ring in polygons.iter().flatten() {
+            if ring.len() < 4 || ring.first() != ring.last() {
+                return Err(
+                    "Each of the area's rings must have at least 4 positions, with the last the \
+                     same as the first"
+                        .to_owned(),
+                );
+            }
+
+            for position in ring {
+                let [longitude, latitude, ..] = position[..] else {
+                    return Err("Each position must have a longitude and a latitude".to_owned());
+                };
+
+                if !(-180.0..=180.0).contains(&longitude) || !(-90.0..=90.0).contains(&latitude) {
+                    return Err(format!(
+                        "[{}, {}] isn't a valid longitude and latitude",
+                        longitude, latitude
+                    ));
+                }
+            }
+        }
+
+        if polygons.is_empty() {
+            return Err("The area doesn't have any polygons".to_owned());
+        }
+
+        Ok(Area { polygons })
+    }
+
+    /// Whether the point is inside one of the polygons, and not in one of its holes
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        self.polygons.iter().any(|polygon| {
+            let mut rings = polygon.iter();
+
+            rings
+                .next()
+                .is_some_and(|outer| ring_contains(outer, latitude, longitude))
+                && !rings.any(|hole| ring_contains(hole, latitude, longitude))
+        })
+    }
+}
+
+fn flatten(geojson: GeoJson) -> Vec<Polygon> {
+    match geojson {
+        GeoJson::Polygon { coordinates } => vec![coordinates],
+        GeoJson::MultiPolygon { coordinates } => coordinates,
+        GeoJson::Feature { geometry } => flatten(*geometry),
+    }
+}
+
+/// Counts how many of the ring's edges a line going east from the point crosses, which is odd
+/// when the point is inside. Areas are small enough that treating coordinates as flat is fine.
+fn ring_contains(ring: &[Position], latitude: f64, longitude: f64) -> bool {
+    let mut inside = false;
+
+    for edge in ring.windows(2) {
+        let (x1, y1) = (edge[0][0], edge[0][1]);
+        let (x2, y2) = (edge[1][0], edge[1][1]);
+
+        if (y1 > latitude) != (y2 > latitude)
+            && longitude < x1 + (latitude - y1) * (x2 - x1) / (y2 - y1)
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// [longitude, latitude] corners, closed
+    fn square(min: f64, max: f64) -> Value {
+        json!([[min, min], [max, min], [max, max], [min, max], [min, min]])
+    }
+
+    fn square_with_hole() -> Area {
+        Area::from_geojson(&json!({
+            "type": "Polygon",
+            "coordinates": [square(0.0, 10.0), square(4.0, 6.0)],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn points_in_a_hole_are_outside() {
+        let area = square_with_hole();
+
+        assert!(area.contains(2.0, 2.0));
+        assert!(area.contains(8.0, 5.0));
+        assert!(area.contains(5.0, 3.9));
+        assert!(!area.contains(5.0, 5.0));
+        assert!(!area.contains(4.5, 5.5));
+        assert!(!area.contains(-1.0, 5.0));
+        assert!(!area.contains(5.0, 11.0));
+    }
+
+    #[test]
+    fn latitude_and_longitude_are_not_swapped() {
+        let area = Area::from_geojson(&json!({
+            "type": "Polygon",
+            // a strip 10 degrees of longitude wide but only 1 degree of latitude tall
+            "coordinates": [[[170.0, -41.0], [180.0, -41.0], [180.0, -40.0], [170.0, -40.0], [170.0, -41.0]]],
+        }))
+        .unwrap();
+
+        assert!(area.contains(-40.5, 175.0));
+        assert!(!area.contains(175.0, -40.5));
+    }
+
+    #[test]
+    fn concave_rings() {
+        // a U shape, open at the top between longitudes 4 and 6
+        let area = Area::from_geojson(&json!({
+            "type": "Polygon",
+            "coordinates": [[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [6.0, 10.0], [6.0, 4.0],
+                             [4.0, 4.0], [4.0, 10.0], [0.0, 10.0], [0.0, 0.0]]],
+        }))
+        .unwrap();
+
+        assert!(area.contains(8.0, 2.0));
+        assert!(area.contains(2.0, 5.0));
+        assert!(area.contains(8.0, 8.0));
+        assert!(!area.contains(8.0, 5.0));
+    }
+
+    #[test]
+    fn multi_polygons_and_features() {
+        let area = Area::from_geojson(&json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "MultiPolygon",
+                "coordinates": [[square(0.0, 1.0)], [square(5.0, 6.0)]],
+            },
+        }))
+        .unwrap();
+
+        assert!(area.contains(0.5, 0.5));
+        assert!(area.contains(5.5, 5.5));
+        assert!(!area.contains(3.0, 3.0));
+    }
+
+    #[test]
+    fn invalid_areas_are_rejected() {
+        for geojson in [
+            json!({"type": "Point", "coordinates": [0.0, 0.0]}),
+            json!({"type": "MultiPolygon", "coordinates": []}),
+            // not closed
+            json!({"type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]]}),
+            // too few positions
+            json!({"type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 1.0], [0.0, 0.0]]]}),
+            // latitude out of range
+            json!({"type": "Polygon", "coordinates": [square(0.0, 100.0)]}),
+            json!({"type": "Polygon", "coordinates": [[[0.0], [1.0], [2.0], [0.0]]]}),
+        ] {
+            assert!(
+                Area::from_geojson(&geojson).is_err(),
+                "{} should be rejected",
+                geojson
+            );
+        }
+    }
+}
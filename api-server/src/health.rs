@@ -0,0 +1,228 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::Duration,
+};
+
+use log::debug;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::CONFIG,
+    node_metrics::uptime_percent,
+    pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::Telemetry,
+    telemetry::{latest_by_node, timestamps_by_node},
+    utils::unix_time_seconds,
+    AppState,
+};
+
+/// How much each part counts towards the score. Parts which can't be worked out for a node (e.g.
+/// it has no battery readings) are left out and the rest are scaled up to make 100.
+const BATTERY_WEIGHT: f32 = 30.0;
+const REGULARITY_WEIGHT: f32 = 30.0;
+const LINK_WEIGHT: f32 = 25.0;
+const REBOOTS_WEIGHT: f32 = 15.0;
+
+/// LoRa can decode down to about -20 dB SNR, and links above 10 dB are as good as they get
+const WORST_SNR: f32 = -20.0;
+const BEST_SNR: f32 = 10.0;
+
+/// this many reboots within the window counts as the worst possible
+const WORST_REBOOT_COUNT: usize = 3;
+
+/// 101 means the node is on external power
+const EXTERNAL_POWER_BATTERY_LEVEL: u32 = 101;
+
+/// Keeps track of when each node rebooted, and the health score of each node from the last time
+/// they were calculated
+#[derive(Default)]
+pub struct HealthTracker {
+    /// the most recent uptime each node reported, and the timestamp it came with
+    last_uptime: HashMap<NodeId, (u64, u32)>,
+    reboots: HashMap<NodeId, VecDeque<u64>>,
+    scores: HashMap<NodeId, u8>,
+}
+
+impl HealthTracker {
+    pub fn score(&self, node_id: NodeId) -> Option<u8> {
+        self.scores.get(&node_id).copied()
+    }
+
+    pub fn scores(&self) -> &HashMap<NodeId, u8> {
+        &self.scores
+    }
+
+    /// Records a reboot if the node's uptime has gone down since its previous packet
+    pub fn record(&mut self, telemetry: &Telemetry) {
+        let Some(uptime) = telemetry
+            .device_metrics
+            .and_then(|metrics| metrics.uptime_seconds)
+        else {
+            return;
+        };
+
+        let previous = self
+            .last_uptime
+            .insert(telemetry.node_num, (telemetry.timestamp, uptime));
+
+        // packets can arrive out of order, which would otherwise look like a reboot
+        if previous.is_some_and(|(previous_timestamp, previous_uptime)| {
+            telemetry.timestamp > previous_timestamp && uptime < previous_uptime
+        }) {
+            debug!("Node {} rebooted", telemetry.node_num);
+
+            self.reboots
+                .entry(telemetry.node_num)
+                .or_default()
+                .push_back(telemetry.timestamp);
+        }
+    }
+
+    fn reboot_count(&mut self, node_id: NodeId, window_start: u64) -> usize {
+        let Some(reboots) = self.reboots.get_mut(&node_id) else {
+            return 0;
+        };
+
+        while reboots.front().is_some_and(|reboot| *reboot < window_start) {
+            reboots.pop_front();
+        }
+
+        reboots.len()
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.last_uptime.remove(&node_id);
+        self.reboots.remove(&node_id);
+        self.scores.remove(&node_id);
+    }
+}
+
+/// Combines the parts (each from 0 to 1) into a score out of 100
+fn combine(parts: &[(Option<f32>, f32)]) -> Option<u8> {
+    let (total, total_weight) = parts
+        .iter()
+        .filter_map(|(part, weight)| Some((part.as_ref()?.clamp(0.0, 1.0) * weight, *weight)))
+        .fold(
+            (0.0_f32, 0.0_f32),
+            |(total, total_weight), (value, weight)| (total + value, total_weight + weight),
+        );
+
+    (total_weight > 0.0).then(|| (total / total_weight * 100.0).round() as u8)
+}
+
+/// Works out the health score of every node which has sent telemetry or been heard by another node
+pub async fn recalculate(state: &AppState) {
+    let now = unix_time_seconds();
+    let window_seconds = CONFIG.health_window_hours * 60 * 60;
+
+    let (battery_levels, timestamps) = {
+        let telemetry_cache = state.telemetry_cache.lock().await;
+        let telemetry_archive = state.telemetry_archive.lock().await;
+
+        let battery_levels = latest_by_node(&telemetry_cache)
+            .into_iter()
+            .filter_map(|(node_id, telemetry)| {
+                Some((node_id, telemetry.device_metrics?.battery_level?))
+            })
+            .collect::<HashMap<_, _>>();
+
+        (
+            battery_levels,
+            timestamps_by_node(&telemetry_cache, &telemetry_archive),
+        )
+    };
+
+    let best_snrs = state
+        .topology
+        .lock()
+        .await
+        .links
+        .iter()
+        .filter_map(|(node_id, links)| {
+            Some((
+                *node_id,
+                links.values().map(|reading| reading.snr).reduce(f32::max)?,
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+
+    // the cache doesn't necessarily go back as far as the window, so time before the oldest
+    // reading isn't counted against nodes
+    let window_start = timestamps
+        .values()
+        .filter_map(|timestamps| timestamps.first())
+        .min()
+        .copied()
+        .unwrap_or(now)
+        .max(now.saturating_sub(window_seconds));
+
+    let node_ids = timestamps
+        .keys()
+        .chain(best_snrs.keys())
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    let mut health_tracker = state.health.lock().await;
+
+    let scores = node_ids
+        .into_iter()
+        .filter_map(|node_id| {
+            let battery = battery_levels.get(&node_id).map(|battery_level| {
+                if *battery_level >= EXTERNAL_POWER_BATTERY_LEVEL {
+                    1.0
+                } else {
+                    *battery_level as f32 / 100.0
+                }
+            });
+
+            let regularity = timestamps.get(&node_id).and_then(|timestamps| {
+                uptime_percent(
+                    timestamps,
+                    window_start,
+                    now,
+                    CONFIG.expected_report_interval_seconds,
+                )
+                .map(|percent| percent / 100.0)
+            });
+
+            let link_quality = best_snrs
+                .get(&node_id)
+                .map(|snr| (snr - WORST_SNR) / (BEST_SNR - WORST_SNR));
+
+            // nodes which don't report their uptime can't be checked for reboots
+            let reboots = health_tracker.last_uptime.contains_key(&node_id).then(|| {
+                let reboot_count =
+                    health_tracker.reboot_count(node_id, now.saturating_sub(window_seconds));
+
+                1.0 - reboot_count.min(WORST_REBOOT_COUNT) as f32 / WORST_REBOOT_COUNT as f32
+            });
+
+            let score = combine(&[
+                (battery, BATTERY_WEIGHT),
+                (regularity, REGULARITY_WEIGHT),
+                (link_quality, LINK_WEIGHT),
+                (reboots, REBOOTS_WEIGHT),
+            ])?;
+
+            Some((node_id, score))
+        })
+        .collect();
+
+    health_tracker.scores = scores;
+}
+
+/// Spawns the task which recalculates every node's health score every
+/// `HEALTH_RECALCULATION_SECONDS`
+pub fn recalculation_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting node health task");
+
+        let interval = Duration::from_secs(CONFIG.health_recalculation_seconds.max(1));
+
+        loop {
+            recalculate(&state).await;
+
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
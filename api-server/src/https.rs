@@ -0,0 +1,94 @@
+use axum::{
+    extract::Request,
+    http::{
+        header::{
+            CONTENT_SECURITY_POLICY, HOST, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+            X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+        },
+        uri::Authority,
+        HeaderMap, HeaderValue, Uri,
+    },
+    middleware::Next,
+    response::{Redirect, Response},
+    Router,
+};
+use log::{error, info};
+
+use crate::config::CONFIG;
+
+/// Sends plain HTTP requests to the same path on the HTTPS server
+async fn redirect_to_https(headers: HeaderMap, uri: Uri) -> Redirect {
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .map(|authority| authority.host().to_owned())
+        .unwrap_or_else(|| "localhost".to_owned());
+
+    let path_and_query = uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+
+    let https_url = if CONFIG.server_port == 443 {
+        format!("https://{}{}", host, path_and_query)
+    } else {
+        format!("https://{}:{}{}", host, CONFIG.server_port, path_and_query)
+    };
+
+    Redirect::permanent(&https_url)
+}
+
+/// Middleware which adds the standard security headers to every response, unless the handler set
+/// them itself. The API only serves JSON, so the content security policy doesn't allow anything to
+/// be loaded or embedded. HSTS is only sent when the server is serving HTTPS itself.
+pub async fn add_security_headers(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    let mut security_headers = vec![
+        (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        (
+            CONTENT_SECURITY_POLICY,
+            "default-src 'none'; frame-ancestors 'none'",
+        ),
+        (X_FRAME_OPTIONS, "DENY"),
+        (REFERRER_POLICY, "no-referrer"),
+    ];
+
+    if CONFIG.tls_cert_path.is_some() && CONFIG.tls_key_path.is_some() {
+        // a year, which is what preload lists expect
+        security_headers.push((
+            STRICT_TRANSPORT_SECURITY,
+            "max-age=31536000; includeSubDomains",
+        ));
+    }
+
+    for (name, value) in security_headers {
+        headers
+            .entry(name)
+            .or_insert(HeaderValue::from_static(value));
+    }
+
+    response
+}
+
+/// Serves redirects to the HTTPS server on `HTTP_REDIRECT_PORT`, so that people typing the
+/// server's address into a browser don't just get a connection error
+pub async fn serve_redirects(port: u16) {
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Failed to listen for HTTP on port {}: {}", port, error);
+            return;
+        }
+    };
+
+    info!("Redirecting HTTP requests on port {} to HTTPS", port);
+
+    let app = Router::new().fallback(redirect_to_https);
+
+    if let Err(error) = axum::serve(listener, app).await {
+        error!("HTTP redirect server stopped: {}", error);
+    }
+}
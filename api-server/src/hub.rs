@@ -0,0 +1,819 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Write,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, Utf8Bytes},
+        Path, State,
+    },
+    http::StatusCode,
+    Json,
+};
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use log::{debug, error, info, warn};
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    sync::{broadcast::error::RecvError, Notify},
+    task::JoinHandle,
+};
+
+use crate::{
+    auth::AuthedUser,
+    config::CONFIG,
+    drill,
+    events::{self, EventKind, ServerEvent},
+    filter::TelemetryFilter,
+    pathfinding::NodeId,
+    utils::{unix_time_seconds, StringOrEmptyResponse},
+    AppState,
+};
+
+pub type ClientId = u64;
+
+/// How a client wants packets sent to it, chosen with the `compression` query parameter
+#[derive(Clone, Copy, Default, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WebSocketCompression {
+    /// JSON text frames
+    #[default]
+    None,
+    /// gzipped JSON in binary frames, for clients on slow links
+    Gzip,
+}
+
+/// Which encoding a client wants packets in, chosen with the `format` query parameter
+#[derive(Clone, Copy, PartialEq, Eq, Default, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WebSocketFormat {
+    #[default]
+    Json,
+    /// `CrisislabMessage` protobufs in binary frames, for clients where parsing JSON is too slow.
+    /// Only telemetry and signal data can be sent this way.
+    Protobuf,
+}
+
+/// A packet ready to be sent to a client
+#[derive(Clone)]
+pub enum Frame {
+    Json(Utf8Bytes),
+    /// JSON which the hub has already gzipped, so that it's only compressed once however many
+    /// clients want it compressed. The JSON is kept for when it's batched.
+    GzippedJson {
+        json: Utf8Bytes,
+        gzipped: Bytes,
+    },
+    Protobuf(Bytes),
+}
+
+impl Frame {
+    /// Size of the frame before compression
+    fn len(&self) -> usize {
+        match self {
+            Frame::Json(text) | Frame::GzippedJson { json: text, .. } => text.len(),
+            Frame::Protobuf(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn into_message(self, compression: WebSocketCompression) -> Message {
+        match self {
+            Frame::Json(text) => compression.encode(text),
+            Frame::GzippedJson { json, gzipped } => match compression {
+                WebSocketCompression::None => Message::Text(json),
+                WebSocketCompression::Gzip => Message::Binary(gzipped),
+            },
+            Frame::Protobuf(bytes) => Message::Binary(bytes),
+        }
+    }
+}
+
+fn gzip(data: &[u8]) -> Bytes {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map(Bytes::from)
+        .expect("Failed to gzip WS message")
+}
+
+impl WebSocketCompression {
+    pub fn encode(self, text: Utf8Bytes) -> Message {
+        match self {
+            WebSocketCompression::None => Message::Text(text),
+            WebSocketCompression::Gzip => Message::Binary(gzip(text.as_bytes())),
+        }
+    }
+}
+
+/// Which packets a client wants. Anything left out means everything.
+#[derive(Default, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    nodes: Option<HashSet<NodeId>>,
+    #[serde(default, deserialize_with = "events::deserialize_optional_kinds")]
+    kinds: Option<HashSet<EventKind>>,
+    /// only applies to telemetry
+    filter: Option<TelemetryFilter>,
+}
+
+impl Subscription {
+    /// Only the given kinds of packets, from any node
+    pub fn channels(kinds: HashSet<EventKind>) -> Self {
+        Self {
+            nodes: None,
+            kinds: Some(kinds),
+            filter: None,
+        }
+    }
+
+    pub fn includes(&self, kind: EventKind) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&kind))
+    }
+
+    fn matches(&self, event: &ServerEvent) -> bool {
+        let node_matches = match (&self.nodes, event.node_id()) {
+            (Some(nodes), Some(node_id)) => nodes.contains(&node_id),
+            // events which aren't about a particular node (e.g. errors) go to everyone
+            _ => true,
+        };
+
+        let filter_matches = match (&self.filter, event) {
+            (Some(filter), ServerEvent::Telemetry(telemetry)) => {
+                filter.matches(&telemetry.telemetry)
+            }
+            _ => true,
+        };
+
+        node_matches && filter_matches && self.includes(event.kind())
+    }
+
+    fn join(&mut self, channels: HashSet<EventKind>) {
+        // already receiving everything otherwise
+        if let Some(kinds) = &mut self.kinds {
+            kinds.extend(channels);
+        }
+    }
+
+    fn leave(&mut self, channels: HashSet<EventKind>) {
+        self.kinds
+            .get_or_insert_with(|| EventKind::ALL.into_iter().collect())
+            .retain(|kind| !channels.contains(kind));
+    }
+}
+
+/// Control frames clients can send on the live websocket
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClientMessage {
+    /// replaces the client's current subscription
+    Subscribe(Subscription),
+    /// starts receiving the given kinds of packets, in addition to the current ones
+    Join(#[serde(deserialize_with = "events::deserialize_kinds")] HashSet<EventKind>),
+    /// stops receiving the given kinds of packets
+    Leave(#[serde(deserialize_with = "events::deserialize_kinds")] HashSet<EventKind>),
+    /// replaces the client's telemetry filter, or removes it if `null`
+    Filter(Option<TelemetryFilter>),
+}
+
+/// Frames of these kinds can be dropped when a client falls behind, since the client can catch up
+/// by re-fetching the cache (or, for signal data, by waiting for the next reading). Everything
+/// else (e.g. alerts) is always delivered.
+const DROPPABLE_KINDS: [EventKind; 2] = [EventKind::Telemetry, EventKind::SignalData];
+
+struct QueuedMessage {
+    kind: EventKind,
+    frame: Frame,
+    queued_at: Instant,
+}
+
+impl QueuedMessage {
+    /// Only JSON telemetry is batched since there's no protobuf message for a batch
+    fn is_batchable(&self) -> bool {
+        self.kind == EventKind::Telemetry
+            && matches!(self.frame, Frame::Json(_) | Frame::GzippedJson { .. })
+    }
+}
+
+/// Totals for one kind of packet across every client
+#[derive(Default, Clone, Copy)]
+pub struct StreamCounters {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub dropped_messages: u64,
+    /// summed across every message sent, measured from when the hub queued it to when it was
+    /// handed to the client's connection
+    pub total_latency: Duration,
+}
+
+impl StreamCounters {
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.messages_sent > 0).then(|| {
+            Duration::from_secs_f64(self.total_latency.as_secs_f64() / self.messages_sent as f64)
+        })
+    }
+}
+
+type SharedStreamCounters = Arc<std::sync::Mutex<HashMap<EventKind, StreamCounters>>>;
+
+#[derive(Default)]
+struct ClientQueueState {
+    messages: VecDeque<QueuedMessage>,
+    /// dropped since the client was last told about it
+    unreported_drop_count: u64,
+    sent_count: u64,
+    dropped_count: u64,
+    is_closed: bool,
+    /// telemetry is batched while it's arriving faster than `WEBSOCKET_BATCH_RATE_PER_SECOND`
+    is_batching: bool,
+    telemetry_window_start: Option<Instant>,
+    telemetry_in_window: u32,
+    last_flush: Option<Instant>,
+}
+
+impl ClientQueueState {
+    /// Works out whether telemetry should be batched, based on how quickly it's been arriving
+    fn record_telemetry(&mut self) {
+        let now = Instant::now();
+        let window_start = *self.telemetry_window_start.get_or_insert(now);
+        let elapsed = now - window_start;
+
+        self.telemetry_in_window += 1;
+
+        if elapsed >= Duration::from_secs(1) {
+            let rate = self.telemetry_in_window as f64 / elapsed.as_secs_f64();
+
+            self.is_batching = CONFIG.websocket_batch_rate_per_second > 0
+                && rate > CONFIG.websocket_batch_rate_per_second as f64;
+            self.telemetry_window_start = Some(now);
+            self.telemetry_in_window = 0;
+        }
+    }
+
+    fn is_batch_next(&self) -> bool {
+        self.is_batching
+            && self
+                .messages
+                .front()
+                .is_some_and(QueuedMessage::is_batchable)
+    }
+}
+
+/// A client's outgoing messages. When it's full, the oldest droppable message is thrown away to
+/// make room so that a slow client never holds up the hub.
+pub struct ClientQueue {
+    state: std::sync::Mutex<ClientQueueState>,
+    notify: Notify,
+    stream_counters: SharedStreamCounters,
+}
+
+impl ClientQueue {
+    fn new(stream_counters: SharedStreamCounters) -> Self {
+        Self {
+            state: Default::default(),
+            notify: Notify::new(),
+            stream_counters,
+        }
+    }
+
+    fn record_drop(&self, state: &mut ClientQueueState, kind: EventKind) {
+        state.dropped_count += 1;
+        state.unreported_drop_count += 1;
+
+        self.stream_counters
+            .lock()
+            .expect("Stream counters lock poisoned")
+            .entry(kind)
+            .or_default()
+            .dropped_messages += 1;
+    }
+
+    /// Adds a message to the queue, returning `false` if a message had to be dropped
+    fn push(&self, kind: EventKind, frame: Frame) -> bool {
+        let mut state = self.state.lock().expect("Client queue lock poisoned");
+        let mut has_dropped = false;
+
+        if state.messages.len() >= CONFIG.websocket_queue_capacity {
+            let oldest_droppable = state
+                .messages
+                .iter()
+                .position(|message| DROPPABLE_KINDS.contains(&message.kind));
+
+            if let Some(dropped) = oldest_droppable.and_then(|index| state.messages.remove(index)) {
+                self.record_drop(&mut state, dropped.kind);
+                has_dropped = true;
+            } else if DROPPABLE_KINDS.contains(&kind) {
+                // nothing older can go, so this one does instead
+                self.record_drop(&mut state, kind);
+                return false;
+            }
+            // otherwise nothing can be dropped, so the queue goes over capacity
+        }
+
+        if kind == EventKind::Telemetry {
+            state.record_telemetry();
+        }
+
+        state.messages.push_back(QueuedMessage {
+            kind,
+            frame,
+            queued_at: Instant::now(),
+        });
+
+        drop(state);
+        self.notify.notify_one();
+
+        !has_dropped
+    }
+
+    fn close(&self) {
+        self.state
+            .lock()
+            .expect("Client queue lock poisoned")
+            .is_closed = true;
+        self.notify.notify_one();
+    }
+
+    fn record_sent(&self, state: &mut ClientQueueState, message: &QueuedMessage) {
+        state.sent_count += 1;
+
+        let mut stream_counters = self
+            .stream_counters
+            .lock()
+            .expect("Stream counters lock poisoned");
+        let counters = stream_counters.entry(message.kind).or_default();
+
+        counters.messages_sent += 1;
+        counters.bytes_sent += message.frame.len() as u64;
+        counters.total_latency += message.queued_at.elapsed();
+    }
+
+    /// Combines the telemetry at the front of the queue into one `telemetry_batch` frame. The
+    /// packets are already serialised, so they're just joined together.
+    fn pop_batch(&self, state: &mut ClientQueueState) -> Frame {
+        let mut batch = String::from(r#"{"telemetry_batch":["#);
+
+        while state
+            .messages
+            .front()
+            .is_some_and(QueuedMessage::is_batchable)
+        {
+            let message = state
+                .messages
+                .pop_front()
+                .expect("Queue was just checked to have a message");
+
+            self.record_sent(state, &message);
+
+            if let Frame::Json(text) | Frame::GzippedJson { json: text, .. } = message.frame {
+                if !batch.ends_with('[') {
+                    batch.push(',');
+                }
+
+                batch.push_str(text.as_str());
+            }
+        }
+
+        batch.push_str("]}");
+
+        Frame::Json(batch.into())
+    }
+
+    /// Waits for the next message to send. If any messages have been dropped since the last one,
+    /// a notice saying how many is sent first. While telemetry is being batched, it's sent at
+    /// most once per `WEBSOCKET_BATCH_INTERVAL_MS`. Returns `None` once the client has been
+    /// removed from the hub.
+    pub async fn recv(&self) -> Option<Frame> {
+        let flush_interval = Duration::from_millis(CONFIG.websocket_batch_interval_ms);
+
+        loop {
+            let next_flush = {
+                let mut state = self.state.lock().expect("Client queue lock poisoned");
+
+                if state.is_closed {
+                    return None;
+                }
+
+                if state.unreported_drop_count > 0 {
+                    let notice = json!({ "dropped_messages": state.unreported_drop_count });
+                    state.unreported_drop_count = 0;
+
+                    return Some(Frame::Json(notice.to_string().into()));
+                }
+
+                if state.is_batch_next() {
+                    let next_flush = state
+                        .last_flush
+                        .map(|last_flush| last_flush + flush_interval)
+                        .filter(|next_flush| *next_flush > Instant::now());
+
+                    if next_flush.is_none() {
+                        state.last_flush = Some(Instant::now());
+
+                        return Some(self.pop_batch(&mut state));
+                    }
+
+                    next_flush
+                } else if let Some(message) = state.messages.pop_front() {
+                    self.record_sent(&mut state, &message);
+
+                    return Some(message.frame);
+                } else {
+                    None
+                }
+            };
+
+            // the batch is left in the queue while waiting, so nothing's lost if this is cancelled
+            match next_flush {
+                Some(next_flush) => tokio::time::sleep_until(next_flush.into()).await,
+                None => self.notify.notified().await,
+            }
+        }
+    }
+}
+
+struct ClientHandle {
+    queue: Arc<ClientQueue>,
+    subscription: Subscription,
+    format: WebSocketFormat,
+    compression: WebSocketCompression,
+    /// only sent telemetry whatever it subscribes to, for telemetry-only API tokens
+    telemetry_only: bool,
+    remote_address: SocketAddr,
+    /// seconds since unix epoch
+    connected_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct ClientInfo {
+    id: ClientId,
+    remote_address: SocketAddr,
+    connected_at: u64,
+    messages_sent: u64,
+    /// messages waiting to be sent, which grows when the client can't keep up
+    queued_messages: usize,
+    dropped_messages: u64,
+}
+
+#[derive(Serialize)]
+pub struct StreamStats {
+    messages_sent: u64,
+    /// before compression
+    bytes_sent: u64,
+    dropped_messages: u64,
+    /// how long messages wait in clients' queues on average, `None` if none have been sent
+    average_latency_ms: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct WsStats {
+    connected_clients: usize,
+    /// since the server started
+    total_connections: u64,
+    streams: HashMap<EventKind, StreamStats>,
+}
+
+/// Keeps track of every connected live websocket client so that each packet only has to be
+/// serialised (and compressed) once, no matter how many clients there are
+pub struct WebSocketHub {
+    clients: HashMap<ClientId, ClientHandle>,
+    /// also the number of clients which have ever connected
+    next_client_id: ClientId,
+    stream_counters: SharedStreamCounters,
+    next_sequence_number: u64,
+    /// recently sent packets, so that clients which reconnect can pick up where they left off
+    history: VecDeque<HistoryEntry>,
+}
+
+/// A packet encoded in every format, so that it can be sent to any client
+struct HistoryEntry {
+    seq: u64,
+    /// kept so that clients' subscriptions can be checked against it when they resume
+    event: ServerEvent,
+    json: Utf8Bytes,
+    /// the JSON gzipped, once a client which wants it compressed has been sent it
+    gzipped_json: Option<Bytes>,
+    /// `None` for events which can't be sent as protobufs
+    protobuf: Option<Bytes>,
+}
+
+impl HistoryEntry {
+    fn frame(
+        &mut self,
+        format: WebSocketFormat,
+        compression: WebSocketCompression,
+    ) -> Option<Frame> {
+        match (format, compression) {
+            (WebSocketFormat::Json, WebSocketCompression::None) => {
+                Some(Frame::Json(self.json.clone()))
+            }
+            (WebSocketFormat::Json, WebSocketCompression::Gzip) => Some(Frame::GzippedJson {
+                json: self.json.clone(),
+                gzipped: self
+                    .gzipped_json
+                    .get_or_insert_with(|| gzip(self.json.as_bytes()))
+                    .clone(),
+            }),
+            (WebSocketFormat::Protobuf, _) => self.protobuf.clone().map(Frame::Protobuf),
+        }
+    }
+}
+
+/// Every packet sent by the hub is numbered so that clients can tell what they've missed
+#[derive(Serialize)]
+struct SequencedEvent<'a> {
+    #[serde(flatten)]
+    event: &'a ServerEvent,
+    seq: u64,
+    /// only included while drill mode is on
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    drill: bool,
+}
+
+impl WebSocketHub {
+    pub fn new() -> Self {
+        Self {
+            clients: HashMap::new(),
+            next_client_id: 0,
+            stream_counters: Default::default(),
+            // start from the current time so that sequence numbers from before a restart are
+            // always older than the history, and clients resuming with one get the whole cache
+            next_sequence_number: unix_time_seconds() * 1000,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Adds a client, returning its ID and its queue of outgoing messages. If `resume_from` is
+    /// given and everything after it is still in the history, the packets it's subscribed to are
+    /// queued and the last value is `true`.
+    pub fn register(
+        &mut self,
+        remote_address: SocketAddr,
+        format: WebSocketFormat,
+        compression: WebSocketCompression,
+        subscription: Subscription,
+        resume_from: Option<u64>,
+        telemetry_only: bool,
+    ) -> (ClientId, Arc<ClientQueue>, bool) {
+        let queue = Arc::new(ClientQueue::new(self.stream_counters.clone()));
+
+        let oldest_sequence_number = self
+            .history
+            .front()
+            .map_or(self.next_sequence_number, |entry| entry.seq);
+
+        // the client can't have seen anything newer than what's been sent (which would happen if
+        // the server restarted), and if it's been gone too long there'll be a gap
+        let resume_from = resume_from.filter(|resume_from| {
+            *resume_from < self.next_sequence_number && resume_from + 1 >= oldest_sequence_number
+        });
+
+        if let Some(resume_from) = resume_from {
+            for entry in &mut self.history {
+                if entry.seq <= resume_from
+                    || !subscription.matches(&entry.event)
+                    || (telemetry_only && entry.event.kind() != EventKind::Telemetry)
+                {
+                    continue;
+                }
+
+                if let Some(frame) = entry.frame(format, compression) {
+                    queue.push(entry.event.kind(), frame);
+                }
+            }
+        }
+
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+
+        self.clients.insert(
+            client_id,
+            ClientHandle {
+                queue: queue.clone(),
+                subscription,
+                format,
+                compression,
+                telemetry_only,
+                remote_address,
+                connected_at: unix_time_seconds(),
+            },
+        );
+
+        (client_id, queue, resume_from.is_some())
+    }
+
+    /// Removes the client, which also closes its connection if it's still open. Returns whether
+    /// the client was registered.
+    pub fn unregister(&mut self, client_id: ClientId) -> bool {
+        match self.clients.remove(&client_id) {
+            Some(client) => {
+                client.queue.close();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let mut clients = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| {
+                let queue = client
+                    .queue
+                    .state
+                    .lock()
+                    .expect("Client queue lock poisoned");
+
+                ClientInfo {
+                    id: *client_id,
+                    remote_address: client.remote_address,
+                    connected_at: client.connected_at,
+                    messages_sent: queue.sent_count,
+                    queued_messages: queue.messages.len(),
+                    dropped_messages: queue.dropped_count,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        clients.sort_by_key(|client| client.id);
+
+        clients
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn total_connections(&self) -> u64 {
+        self.next_client_id
+    }
+
+    pub fn stream_counters(&self) -> HashMap<EventKind, StreamCounters> {
+        self.stream_counters
+            .lock()
+            .expect("Stream counters lock poisoned")
+            .clone()
+    }
+
+    /// The client's telemetry filter, which also needs to be applied to the cache
+    pub fn telemetry_filter(&self, client_id: ClientId) -> Option<TelemetryFilter> {
+        self.clients
+            .get(&client_id)
+            .and_then(|client| client.subscription.filter.clone())
+    }
+
+    /// Applies a control message to the client's subscription, returning whether it has just
+    /// started receiving telemetry (and so needs to be sent the cache)
+    pub fn update_subscription(&mut self, client_id: ClientId, message: ClientMessage) -> bool {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return false;
+        };
+
+        let was_receiving_telemetry = client.subscription.includes(EventKind::Telemetry);
+
+        match message {
+            ClientMessage::Subscribe(subscription) => client.subscription = subscription,
+            ClientMessage::Join(channels) => client.subscription.join(channels),
+            ClientMessage::Leave(channels) => client.subscription.leave(channels),
+            ClientMessage::Filter(filter) => client.subscription.filter = filter,
+        }
+
+        !was_receiving_telemetry && client.subscription.includes(EventKind::Telemetry)
+    }
+
+    /// Serialises the event and queues it for every client that's subscribed to it. Events sent
+    /// while drill mode is on are marked as such.
+    pub fn broadcast(&mut self, event: ServerEvent, drill: bool) {
+        let seq = self.next_sequence_number;
+        self.next_sequence_number += 1;
+
+        let mut entry = HistoryEntry {
+            seq,
+            json: serde_json::to_string(&SequencedEvent {
+                event: &event,
+                seq,
+                drill,
+            })
+            .expect("Failed to serialize server event for WS message")
+            .into(),
+            gzipped_json: None,
+            protobuf: event
+                .to_protobuf()
+                .map(|message| message.encode_to_vec().into()),
+            event,
+        };
+
+        for (client_id, client) in &self.clients {
+            if !client.subscription.matches(&entry.event)
+                || (client.telemetry_only && entry.event.kind() != EventKind::Telemetry)
+            {
+                continue;
+            }
+
+            let Some(frame) = entry.frame(client.format, client.compression) else {
+                continue;
+            };
+
+            if !client.queue.push(entry.event.kind(), frame) {
+                warn!(
+                    "WS client {} isn't keeping up, dropped a message",
+                    client_id
+                );
+            }
+        }
+
+        if self.history.len() >= CONFIG.websocket_resume_capacity {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(entry);
+    }
+}
+
+/// Spawns the task which serialises server events and hands them to every websocket client
+pub fn hub_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting websocket hub task");
+
+        let mut server_events_receiver = state.server_events.subscribe();
+
+        loop {
+            match server_events_receiver.recv().await {
+                Ok(event) => {
+                    let drill = drill::is_on(&state).await;
+
+                    state.websocket_hub.lock().await.broadcast(event, drill);
+                }
+                Err(RecvError::Lagged(count)) => {
+                    error!(
+                        "WS hub lagged behind server events, skipped {} events",
+                        count
+                    );
+                }
+                Err(RecvError::Closed) => {
+                    error!("Server events channel closed, stopping WS hub task");
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// /info/ws-stats
+pub async fn get_ws_stats(State(state): State<AppState>) -> Json<WsStats> {
+    let hub = state.websocket_hub.lock().await;
+
+    Json(WsStats {
+        connected_clients: hub.connected_clients(),
+        total_connections: hub.total_connections(),
+        streams: hub
+            .stream_counters()
+            .into_iter()
+            .map(|(kind, counters)| {
+                (
+                    kind,
+                    StreamStats {
+                        messages_sent: counters.messages_sent,
+                        bytes_sent: counters.bytes_sent,
+                        dropped_messages: counters.dropped_messages,
+                        average_latency_ms: counters
+                            .average_latency()
+                            .map(|latency| latency.as_secs_f64() * 1000.0),
+                    },
+                )
+            })
+            .collect(),
+    })
+}
+
+/// /admin/ws-clients
+pub async fn get_ws_clients(State(state): State<AppState>) -> Json<Vec<ClientInfo>> {
+    Json(state.websocket_hub.lock().await.clients())
+}
+
+/// /admin/ws-clients/{id}/disconnect
+pub async fn disconnect_ws_client(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state.websocket_hub.lock().await.unregister(client_id) {
+        info!("{} disconnected WS client {}", user, client_id);
+        StringOrEmptyResponse::Ok
+    } else {
+        StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No WS client with ID {}", client_id),
+        )
+    }
+}
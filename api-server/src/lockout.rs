@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, response::Response, Json};
+use log::warn;
+use serde::Serialize;
+
+use crate::{
+    config::CONFIG,
+    rate_limit::too_many_requests,
+    utils::{unix_time_seconds, RingBuffer},
+    AppState,
+};
+
+#[derive(Clone, Copy, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventKind {
+    /// a wrong username or password was sent to /auth/login
+    FailedLogin,
+    /// a request had an API key or token which was wrong
+    InvalidCredentials,
+    /// a client was locked out after too many failures
+    LockedOut,
+    /// a request was rejected because the client was locked out
+    RejectedWhileLockedOut,
+}
+
+/// Something which happened while authenticating a request, kept for auditing
+#[derive(Clone, Serialize, Debug)]
+pub struct AuthEvent {
+    /// seconds since unix epoch
+    pub timestamp: u64,
+    pub kind: AuthEventKind,
+    pub ip: Option<IpAddr>,
+    /// the username which was tried, for logins
+    pub username: Option<String>,
+    pub path: String,
+    /// how long the client is locked out for, for `locked_out` events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lockout_seconds: Option<u64>,
+}
+
+struct Failures {
+    count: u32,
+    last_failed_at: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Failed attempts to authenticate, counted separately for each IP address and username. Once a
+/// client has failed more than `LOCKOUT_FREE_ATTEMPTS` times, it's locked out for
+/// `LOCKOUT_BASE_SECONDS`, which doubles with every further failure up to `LOCKOUT_MAX_SECONDS`.
+/// Failures are forgotten once there haven't been any for `LOCKOUT_MAX_SECONDS`.
+pub struct AuthLockout {
+    /// `ip <address>` or `user <username>` -> failures
+    failures: HashMap<String, Failures>,
+    events: RingBuffer<AuthEvent>,
+}
+
+impl AuthLockout {
+    pub fn new(event_history_capacity: usize) -> Self {
+        Self {
+            failures: HashMap::new(),
+            events: RingBuffer::new(event_history_capacity),
+        }
+    }
+
+    /// How long the longest lockout of any of the clients has left, if any of them are locked out
+    fn remaining_lockout(&self, keys: &[String], now: Instant) -> Option<Duration> {
+        keys.iter()
+            .filter_map(|key| self.failures.get(key)?.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max()
+    }
+
+    /// Records a failure for each of the clients, returning how long the longest new lockout is if
+    /// any of them are now locked out
+    fn record_failure(&mut self, keys: &[String], now: Instant) -> Option<Duration> {
+        let forget_after = Duration::from_secs(CONFIG.lockout_max_seconds);
+
+        self.failures.retain(|_, failures| {
+            now.duration_since(failures.last_failed_at) < forget_after
+                || failures
+                    .locked_until
+                    .is_some_and(|locked_until| locked_until > now)
+        });
+
+        keys.iter()
+            .filter_map(|key| {
+                let failures = self.failures.entry(key.clone()).or_insert(Failures {
+                    count: 0,
+                    last_failed_at: now,
+                    locked_until: None,
+                });
+
+                failures.count += 1;
+                failures.last_failed_at = now;
+
+                let extra_failures = failures
+                    .count
+                    .checked_sub(CONFIG.lockout_free_attempts + 1)?;
+                let lockout_seconds = CONFIG
+                    .lockout_base_seconds
+                    .saturating_mul(2_u64.saturating_pow(extra_failures))
+                    .min(CONFIG.lockout_max_seconds);
+                let lockout = Duration::from_secs(lockout_seconds);
+
+                failures.locked_until = Some(now + lockout);
+
+                Some(lockout)
+            })
+            .max()
+    }
+
+    fn record_event(&mut self, event: AuthEvent) {
+        warn!(
+            target: "audit",
+            "{:?} from {} for {} on {}",
+            event.kind,
+            event
+                .ip
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| "unknown address".to_owned()),
+            event.username.as_deref().unwrap_or("unknown user"),
+            event.path
+        );
+
+        self.events.write(event);
+    }
+
+    pub fn events(&self) -> &RingBuffer<AuthEvent> {
+        &self.events
+    }
+}
+
+fn keys(ip: Option<IpAddr>, username: Option<&str>) -> Vec<String> {
+    ip.map(|ip| format!("ip {}", ip.to_canonical()))
+        .into_iter()
+        .chain(username.map(|username| format!("user {}", username)))
+        .collect()
+}
+
+fn event(kind: AuthEventKind, ip: Option<IpAddr>, username: Option<&str>, path: &str) -> AuthEvent {
+    AuthEvent {
+        timestamp: unix_time_seconds(),
+        kind,
+        ip,
+        username: username.map(str::to_owned),
+        path: path.to_owned(),
+        lockout_seconds: None,
+    }
+}
+
+/// Rejects the request with 429 Too Many Requests if the IP address or username is locked out
+pub async fn check(
+    state: &AppState,
+    ip: Option<IpAddr>,
+    username: Option<&str>,
+    path: &str,
+) -> Result<(), Response> {
+    let mut lockout = state.auth_lockout.lock().await;
+
+    let Some(remaining) = lockout.remaining_lockout(&keys(ip, username), Instant::now()) else {
+        return Ok(());
+    };
+
+    lockout.record_event(event(
+        AuthEventKind::RejectedWhileLockedOut,
+        ip,
+        username,
+        path,
+    ));
+
+    Err(too_many_requests(
+        "Too many failed attempts to authenticate, try again later",
+        remaining,
+    ))
+}
+
+/// Records a failed attempt to authenticate, locking out the IP address and username if they've
+/// failed too many times
+pub async fn record_failure(
+    state: &AppState,
+    kind: AuthEventKind,
+    ip: Option<IpAddr>,
+    username: Option<&str>,
+    path: &str,
+) {
+    let mut lockout = state.auth_lockout.lock().await;
+
+    lockout.record_event(event(kind, ip, username, path));
+
+    if let Some(duration) = lockout.record_failure(&keys(ip, username), Instant::now()) {
+        lockout.record_event(AuthEvent {
+            lockout_seconds: Some(duration.as_secs()),
+            ..event(AuthEventKind::LockedOut, ip, username, path)
+        });
+    }
+}
+
+/// Forgets about a user's failed logins once they've logged in. Their IP address's failures are
+/// kept, since someone trying lots of usernames might guess one of them right.
+pub async fn record_success(state: &AppState, username: &str) {
+    state
+        .auth_lockout
+        .lock()
+        .await
+        .failures
+        .remove(&format!("user {}", username));
+}
+
+/// /admin/auth-events
+pub async fn get_auth_events(State(state): State<AppState>) -> Json<Vec<AuthEvent>> {
+    Json(
+        state
+            .auth_lockout
+            .lock()
+            .await
+            .events()
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
@@ -1,28 +1,135 @@
+mod actuators;
+mod alert_history;
+mod alerts;
+mod anomaly;
+mod api_tokens;
+mod archive;
+mod auth;
+mod battery;
+mod broker;
+mod capabilities;
+mod cli;
+mod command_history;
 mod config;
+mod delivery_reports;
+mod diagnostics;
+mod discovery;
+mod drill;
+mod eew;
+mod emergency_alerts;
+mod encryption;
+mod events;
+mod expected_nodes;
+mod filter;
+mod firmware;
+mod gateways;
+mod geofence;
+mod health;
+mod https;
+mod hub;
+mod lockout;
+mod maintenance;
+mod maintenance_windows;
+mod mesh_status;
+mod message_templates;
+mod messages;
+mod metrics;
 mod mqtt;
+mod node_commands;
+mod node_metrics;
+mod nodes;
+mod oidc;
+mod outbox;
 mod pathfinding;
+mod pending_actions;
+mod persistence;
+mod positions;
+mod presence;
 mod proto;
+mod raspberry_shake;
+mod rate_limit;
+mod reload;
+mod replay;
 mod routes;
+mod seismic;
+mod seismic_events;
+mod seismic_triggers;
+mod status;
+mod telemetry;
+mod topology;
+mod users;
 mod utils;
+mod vault;
+mod webhooks;
 
+use alert_history::AlertHistory;
+use alerts::AlertStore;
+use anomaly::AnomalyDetector;
+use api_tokens::ApiTokenStore;
+use archive::TelemetryArchive;
+use auth::{SessionStore, WsTokenStore};
 use axum::{
-    extract::FromRef,
+    extract::{DefaultBodyLimit, FromRef},
     http::{
         header::{AUTHORIZATION, CONTENT_TYPE},
         HeaderValue, Method,
     },
-    routing::{any, get, post},
+    middleware,
+    routing::{any, delete, get, post, put},
     Router,
 };
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use battery::BatteryTracker;
 use bytes::Bytes;
+use clap::Parser;
+use cli::{Cli, Command};
+use command_history::CommandHistory;
 use config::CONFIG;
+use delivery_reports::DeliveryReportStore;
+use drill::DrillMode;
+use eew::EewStore;
+use emergency_alerts::EmergencyAlertStore;
+use events::ServerEvent;
+use expected_nodes::ExpectedNodes;
+use firmware::FirmwareUpdateStore;
+use gateways::GatewayRegistry;
+use health::HealthTracker;
+use hub::WebSocketHub;
+use lockout::AuthLockout;
+use log::{error, info, warn};
+use maintenance::MaintenanceLog;
+use maintenance_windows::MaintenanceWindowStore;
+use mesh_status::MeshStatus;
+use message_templates::MessageTemplateStore;
+use messages::MessageStore;
+use nodes::NodeRegistry;
+use oidc::OidcProvider;
+use outbox::Outbox;
 use pathfinding::EdgeWeight;
+use pending_actions::PendingActionStore;
+use positions::PositionStore;
+use presence::PresenceTracker;
 use proto::meshtastic::crisislab_message::Telemetry;
+use rate_limit::RateLimiter;
+use routes::LiveTelemetryAutoStop;
+use seismic::SeismicStore;
+use seismic_events::SeismicEventStore;
+use seismic_triggers::SeismicTriggerStore;
 use serde::Serialize;
-use std::sync::{atomic::AtomicBool, Arc};
-use tokio::sync::{broadcast, mpsc, Mutex};
-use tower_http::cors::CorsLayer;
-use utils::RingBuffer;
+use std::{
+    net::SocketAddr,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, watch, Mutex, Semaphore},
+    task::JoinHandle,
+};
+use topology::Topology;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use users::UserStore;
+use utils::{CommandCounter, RingBuffer};
+use webhooks::WebhookSourceStore;
 
 /// Outer state struct to be passed to Axum handlers
 #[derive(Clone)]
@@ -30,8 +137,52 @@ pub struct AppState {
     mesh_interface: MeshInterface,
     app_settings: Arc<Mutex<AppSettings>>,
     updating_routes_lock: Arc<Mutex<()>>,
+    discovering_lock: Arc<Mutex<()>>,
     telemetry_cache: Arc<Mutex<RingBuffer<Telemetry>>>,
+    telemetry_archive: Arc<Mutex<TelemetryArchive>>,
     live_telemetry_is_enabled: Arc<AtomicBool>,
+    topology: Arc<Mutex<Topology>>,
+    alerts: Arc<Mutex<AlertStore>>,
+    alert_history: Arc<Mutex<AlertHistory>>,
+    server_events: broadcast::Sender<ServerEvent>,
+    http_client: reqwest::Client,
+    /// limits how many webhook requests are in flight at once
+    webhook_deliveries: Arc<Semaphore>,
+    battery_tracker: Arc<Mutex<BatteryTracker>>,
+    presence: Arc<Mutex<PresenceTracker>>,
+    live_telemetry_auto_stop: Arc<Mutex<Option<LiveTelemetryAutoStop>>>,
+    positions: Arc<Mutex<PositionStore>>,
+    seismic: Arc<Mutex<SeismicStore>>,
+    seismic_events: Arc<Mutex<SeismicEventStore>>,
+    seismic_triggers: Arc<Mutex<SeismicTriggerStore>>,
+    anomaly_detector: Arc<Mutex<AnomalyDetector>>,
+    replay_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    websocket_hub: Arc<Mutex<WebSocketHub>>,
+    ws_tokens: Arc<Mutex<WsTokenStore>>,
+    users: Arc<Mutex<UserStore>>,
+    sessions: Arc<Mutex<SessionStore>>,
+    oidc: Arc<Mutex<OidcProvider>>,
+    api_tokens: Arc<Mutex<ApiTokenStore>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    auth_lockout: Arc<Mutex<AuthLockout>>,
+    webhook_sources: Arc<Mutex<WebhookSourceStore>>,
+    pending_actions: Arc<Mutex<PendingActionStore>>,
+    node_registry: Arc<Mutex<NodeRegistry>>,
+    gateway_registry: Arc<Mutex<GatewayRegistry>>,
+    maintenance_log: Arc<Mutex<MaintenanceLog>>,
+    maintenance_windows: Arc<Mutex<MaintenanceWindowStore>>,
+    command_history: Arc<Mutex<CommandHistory>>,
+    messages: Arc<Mutex<MessageStore>>,
+    outbox: Arc<Mutex<Outbox>>,
+    message_templates: Arc<Mutex<MessageTemplateStore>>,
+    emergency_alerts: Arc<Mutex<EmergencyAlertStore>>,
+    delivery_reports: Arc<Mutex<DeliveryReportStore>>,
+    drill_mode: Arc<Mutex<Option<DrillMode>>>,
+    eew: Arc<Mutex<EewStore>>,
+    expected_nodes: Arc<Mutex<ExpectedNodes>>,
+    firmware_updates: Arc<Mutex<FirmwareUpdateStore>>,
+    health: Arc<Mutex<HealthTracker>>,
+    mesh_status: Arc<Mutex<MeshStatus>>,
 }
 
 /// Struct containing the two Tokio channels required for communication with the mesh
@@ -39,6 +190,9 @@ pub struct AppState {
 pub struct MeshInterface {
     sender_to_publisher: mpsc::Sender<Bytes>,
     sender_to_subscribers: broadcast::Sender<Bytes>,
+    sender_to_seismic_subscribers: broadcast::Sender<Bytes>,
+    mqtt_connected: watch::Receiver<bool>,
+    command_counter: Arc<Mutex<CommandCounter>>,
 }
 
 impl MeshInterface {
@@ -49,6 +203,23 @@ impl MeshInterface {
     pub fn subscribe(&self) -> broadcast::Receiver<Bytes> {
         self.sender_to_subscribers.subscribe()
     }
+
+    /// For seismic data from somewhere other than MQTT, to go through the same processing
+    pub fn clone_sender_to_seismic_subscribers(&self) -> broadcast::Sender<Bytes> {
+        self.sender_to_seismic_subscribers.clone()
+    }
+
+    pub fn subscribe_seismic(&self) -> broadcast::Receiver<Bytes> {
+        self.sender_to_seismic_subscribers.subscribe()
+    }
+
+    pub fn watch_mqtt_connected(&self) -> watch::Receiver<bool> {
+        self.mqtt_connected.clone()
+    }
+
+    pub fn command_counter(&self) -> &Mutex<CommandCounter> {
+        &self.command_counter
+    }
 }
 
 // These FromRef impls allow the outer AppState struct to be derferenced to inner components
@@ -59,13 +230,32 @@ impl FromRef<AppState> for MeshInterface {
 }
 
 /// Settings relating to the server not the mesh
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Debug)]
 pub struct AppSettings {
     get_settings_timeout_seconds: u64,
     signal_data_timeout_seconds: u64,
     route_cost_weight: EdgeWeight,
     route_hops_weight: EdgeWeight,
+    sensor_relay_penalty: EdgeWeight,
     ad_hoc_telemetry_timeout_seconds: u64,
+    command_ack_timeout_seconds: u64,
+    discovery_timeout_seconds: u64,
+}
+
+impl AppSettings {
+    /// The settings' starting values, from the config
+    pub fn from_config() -> Self {
+        AppSettings {
+            get_settings_timeout_seconds: CONFIG.default_get_settings_timeout_seconds,
+            signal_data_timeout_seconds: CONFIG.default_signal_data_timeout_seconds,
+            route_cost_weight: CONFIG.default_route_cost_weight,
+            route_hops_weight: CONFIG.default_route_hops_weight,
+            sensor_relay_penalty: CONFIG.default_sensor_relay_penalty,
+            ad_hoc_telemetry_timeout_seconds: CONFIG.default_ad_hoc_telemetry_timeout_seconds,
+            command_ack_timeout_seconds: CONFIG.default_command_ack_timeout_seconds,
+            discovery_timeout_seconds: CONFIG.default_discovery_timeout_seconds,
+        }
+    }
 }
 
 impl FromRef<AppState> for Arc<Mutex<AppSettings>> {
@@ -75,11 +265,14 @@ impl FromRef<AppState> for Arc<Mutex<AppSettings>> {
 }
 
 pub fn init_app(state: AppState) -> Router {
-    // temporary cors fix for testing on Migada's laptop
-    let allowlist = [
-        HeaderValue::from_static("http://localhost:8000"),
-        HeaderValue::from_static("http://127.0.0.1:8000"),
-    ];
+    // checked on each request rather than listed up front so that reloading the config changes it.
+    // `Any` can't be used alongside credentials, so "*" echoes back whatever origin was sent.
+    let allowlist = AllowOrigin::predicate(|origin: &HeaderValue, _| {
+        CONFIG
+            .cors_allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || origin.as_bytes() == allowed.as_bytes())
+    });
 
     let cors = CorsLayer::new()
         .allow_origin(allowlist)
@@ -93,50 +286,545 @@ pub fn init_app(state: AppState) -> Router {
         .allow_headers([CONTENT_TYPE, AUTHORIZATION])
         .allow_credentials(true);
 
-    Router::new()
+    // routes which send requests out over the mesh
+    let rate_limit_layer =
+        middleware::from_fn_with_state(state.clone(), rate_limit::limit_expensive_requests);
+
+    let admin_routes = Router::new()
         .route("/admin/set-mesh-settings", post(routes::set_mesh_settings))
         .route(
             "/admin/set-server-settings",
             post(routes::set_server_settings),
         )
-        .route("/get-mesh-settings", get(routes::get_mesh_settings))
-        .route("/get-server-settings", get(routes::get_server_settings))
-        .route("/admin/update-routes", get(routes::update_routes))
-        .route("/telemetry/socket", any(routes::live_telemetry))
+        .route(
+            "/admin/update-routes",
+            get(routes::update_routes).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/admin/discover",
+            post(discovery::discover).route_layer(rate_limit_layer.clone()),
+        )
+        .route("/admin/ws-clients", get(hub::get_ws_clients))
+        .route("/admin/reload-config", post(reload::reload_config))
+        .route("/admin/auth-events", get(lockout::get_auth_events))
+        .route(
+            "/admin/ws-clients/{id}/disconnect",
+            post(hub::disconnect_ws_client),
+        )
+        .route(
+            "/admin/api-tokens",
+            get(api_tokens::get_api_tokens).post(api_tokens::create_api_token),
+        )
+        .route(
+            "/admin/api-tokens/{id}",
+            delete(api_tokens::revoke_api_token),
+        )
+        .route(
+            "/admin/nodes/{id}",
+            put(nodes::set_node).delete(nodes::delete_node),
+        )
+        .route("/admin/nodes/export", get(nodes::export_nodes))
+        .route("/admin/nodes/import", post(nodes::import_nodes))
+        .route(
+            "/admin/gateways",
+            get(gateways::get_gateways).post(gateways::register_gateway),
+        )
+        .route("/admin/gateways/{id}", delete(gateways::unregister_gateway))
+        .route(
+            "/admin/expected-nodes",
+            get(expected_nodes::get_expected_nodes)
+                .put(expected_nodes::set_expected_nodes)
+                .delete(expected_nodes::clear_expected_nodes),
+        )
+        .route(
+            "/admin/nodes/{id}/reboot",
+            post(node_commands::reboot_node).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/admin/nodes/{id}/shutdown",
+            post(node_commands::shutdown_node).route_layer(rate_limit_layer.clone()),
+        )
+        .route("/admin/nodes/{id}/position", put(nodes::set_node_position))
+        .route("/admin/nodes/{id}/events", post(maintenance::log_event))
+        .route(
+            "/admin/nodes/{id}/settings",
+            put(nodes::set_node_settings).delete(nodes::clear_node_settings),
+        )
+        .route("/admin/nodes/{id}/role", put(nodes::set_node_role))
+        .route("/admin/nodes/{id}/actuate", post(actuators::actuate_node))
+        .route("/admin/actuate", post(actuators::actuate_tagged_nodes))
+        .route("/admin/drill-mode", post(drill::set_drill_mode))
+        .route("/admin/eew-mappings", get(eew::get_eew_mappings))
+        .route(
+            "/admin/eew-mappings/{source}",
+            put(eew::set_eew_mapping).delete(eew::remove_eew_mapping),
+        )
+        .route("/admin/eew-decisions", get(eew::get_eew_decisions))
+        .route(
+            "/admin/maintenance-windows",
+            post(maintenance_windows::create_maintenance_window),
+        )
+        .route(
+            "/admin/maintenance-windows/{id}",
+            delete(maintenance_windows::remove_maintenance_window),
+        )
+        .route(
+            "/admin/nodes/{id}/query-capabilities",
+            post(capabilities::query_capabilities).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/admin/nodes/{id}/traceroute",
+            post(diagnostics::traceroute).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/admin/messages/broadcast",
+            post(messages::broadcast).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/admin/messages/send",
+            post(messages::send_message).route_layer(rate_limit_layer.clone()),
+        )
+        .route("/admin/outbox/{id}", delete(outbox::cancel_queued_command))
+        .route(
+            "/admin/message-templates/{name}",
+            put(message_templates::set_message_template)
+                .delete(message_templates::remove_message_template),
+        )
+        .route(
+            "/admin/message-templates/{name}/send",
+            post(message_templates::send_message_template).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/admin/nodes/{id}/request-update",
+            post(firmware::request_update),
+        )
+        .route(
+            "/admin/firmware-updates",
+            get(firmware::get_firmware_updates),
+        )
+        .route(
+            "/admin/pending-actions",
+            get(pending_actions::get_pending_actions),
+        )
+        .route(
+            "/admin/pending-actions/{id}",
+            delete(pending_actions::cancel_pending_action),
+        )
+        .route(
+            "/admin/pending-actions/{id}/approve",
+            post(pending_actions::approve_pending_action),
+        )
+        .route(
+            "/admin/webhook-sources",
+            get(webhooks::get_webhook_sources).post(webhooks::set_webhook_source),
+        )
+        .route(
+            "/admin/webhook-sources/{name}",
+            delete(webhooks::remove_webhook_source),
+        )
+        .route("/admin/alerts/rules", post(alerts::add_alert_rule))
+        .route(
+            "/admin/alerts/history/{id}/acknowledge",
+            post(alert_history::acknowledge_alert),
+        )
+        .route(
+            "/admin/alerts/history/{id}/escalate",
+            post(alert_history::escalate_alert),
+        )
+        // deliberately not rate limited, so that nothing can hold up an emergency alert
+        .route(
+            "/admin/alerts/broadcast",
+            post(emergency_alerts::broadcast_alert),
+        )
+        .route(
+            "/admin/alerts/broadcast-geo",
+            post(emergency_alerts::broadcast_geo_alert),
+        )
+        .route(
+            "/admin/alerts/{id}/cancel",
+            post(emergency_alerts::cancel_alert),
+        )
+        .route(
+            "/admin/alerts/rules/{id}",
+            delete(alerts::delete_alert_rule),
+        )
+        .route("/debug/replay-telemetry", post(replay::replay_telemetry))
         .route("/telemetry/start-live", any(routes::start_live_telemetry))
         .route("/telemetry/stop-live", any(routes::stop_live_telemetry))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_admin,
+        ))
+        // layers added later run first, so the network is checked before any credentials
+        .route_layer(middleware::from_fn(auth::require_allowed_network));
+
+    let viewer_routes = Router::new()
         .route("/telemetry/live-status", get(routes::get_live_status))
-        .route("/telemetry/ad-hoc", get(routes::get_ad_hoc_telemetry))
+        .route(
+            "/telemetry/ad-hoc",
+            get(routes::get_ad_hoc_telemetry).route_layer(rate_limit_layer.clone()),
+        )
+        .route("/telemetry/latest", get(routes::get_latest_telemetry))
+        .route("/telemetry/gaps", get(routes::get_telemetry_gaps))
+        .route("/telemetry/stats", get(routes::get_telemetry_stats))
+        .route("/telemetry/storage-stats", get(archive::get_storage_stats))
+        .route("/alerts/rules", get(alerts::get_alert_rules))
+        .route("/alerts/history", get(alert_history::get_alert_history))
+        .route("/alerts/report", get(alert_history::get_alert_report))
+        .route(
+            "/alerts/broadcasts",
+            get(emergency_alerts::get_emergency_alerts),
+        )
+        .route("/alerts/{id}/acks", get(emergency_alerts::get_alert_acks))
+        .route(
+            "/alerts/{id}/report",
+            get(delivery_reports::get_delivery_report),
+        )
+        .route("/info/node-warnings", get(battery::get_node_warnings))
+        .route("/info/node-presence", get(presence::get_node_presence))
+        .route("/info/mesh-status", get(mesh_status::get_mesh_status))
+        .route("/info/drill-mode", get(drill::get_drill_mode))
+        .route("/info/ws-stats", get(hub::get_ws_stats))
+        .route("/info/command-counter", get(utils::get_command_counter))
+        .route("/info/node-status", get(status::get_node_status))
+        .route("/info/node-metrics", get(node_metrics::get_node_metrics))
+        .route(
+            "/info/positions.geojson",
+            get(positions::get_positions_geojson),
+        )
+        .route(
+            "/info/positions/history",
+            get(positions::get_position_history),
+        )
+        .route("/info/anomalies", get(anomaly::get_anomalies))
+        .route(
+            "/info/firmware-versions",
+            get(firmware::get_firmware_versions),
+        )
+        .route("/nodes", get(nodes::get_nodes))
+        .route("/nodes/{id}", get(nodes::get_node))
+        .route("/nodes/{id}/events", get(maintenance::get_events))
+        .route(
+            "/nodes/{id}/commands",
+            get(command_history::get_node_commands),
+        )
+        .route("/nodes/{id}/neighbors", get(topology::get_neighbours))
+        .route("/messages/{id}/status", get(messages::get_message_status))
+        .route("/outbox", get(outbox::get_outbox))
+        .route(
+            "/maintenance-windows",
+            get(maintenance_windows::get_maintenance_windows),
+        )
+        .route(
+            "/maintenance-windows/muted",
+            get(maintenance_windows::get_muted_events),
+        )
+        .route(
+            "/message-templates",
+            get(message_templates::get_message_templates),
+        )
+        .route(
+            "/nodes/{id}/ping",
+            post(diagnostics::ping).route_layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/get-mesh-settings",
+            get(routes::get_mesh_settings).route_layer(rate_limit_layer),
+        )
+        .route("/get-server-settings", get(routes::get_server_settings))
+        .route("/seismic/waveform", get(seismic::get_waveform))
+        .route("/seismic/events", get(seismic_events::get_seismic_events))
+        .route(
+            "/seismic/triggers",
+            get(seismic_triggers::get_seismic_triggers),
+        )
+        .route("/metrics", get(metrics::get_metrics))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_viewer,
+        ));
+
+    // routes which external systems post to
+    let integration_routes = Router::new()
+        .route("/integrations/ping", post(webhooks::ping))
+        .route("/integrations/eew", post(eew::receive_warning))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            webhooks::verify_webhook_signature,
+        ));
+
+    Router::new()
+        .merge(admin_routes)
+        .merge(viewer_routes)
+        .merge(integration_routes)
+        .route("/auth/ws-token", post(auth::issue_ws_token))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/refresh", post(auth::refresh))
+        .route("/auth/logout", post(auth::logout))
+        .route("/auth/oidc/login", get(oidc::start_login))
+        .route("/auth/oidc/callback", get(oidc::callback))
+        // browsers can't set headers on websocket requests, so these check for a viewer themselves
+        .route("/ws", any(routes::multiplexed_websocket))
+        .route("/telemetry/socket", any(routes::live_telemetry))
+        .layer(DefaultBodyLimit::max(CONFIG.max_request_body_bytes))
+        .layer(middleware::from_fn(utils::time_out_requests))
+        .layer(middleware::from_fn(https::add_security_headers))
         .layer(cors)
         .with_state(state)
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
+    let cli = Cli::parse();
+
+    // these set environment variables, which is only safe before the runtime has started any
+    // other threads
     dotenvy::dotenv().ok();
+    cli.apply_to_env();
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the tokio runtime")
+        .block_on(run(cli));
+}
+
+async fn run(cli: Cli) {
     env_logger::init();
 
+    if let Some(Command::HashPassword) = cli.command {
+        let mut password = String::new();
+        std::io::stdin()
+            .read_line(&mut password)
+            .expect("Failed to read password");
+
+        match users::hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(password_hash) => println!("{}", password_hash),
+            Err(error_message) => error!("{}", error_message),
+        }
+
+        return;
+    }
+
+    // secrets from Vault are needed to load the config, which is all checked up front so that
+    // every problem with it can be reported at once
+    let loaded = match vault::fetch_secrets().await {
+        Ok(()) => config::load(),
+        Err(error_message) => Err(error_message),
+    };
+
+    if let Err(error_message) = loaded {
+        eprintln!("{}", error_message);
+        std::process::exit(1);
+    }
+
+    let users = match &CONFIG.users_file {
+        Some(path) => UserStore::load(path).unwrap_or_else(|error_message| {
+            eprintln!("{}", error_message);
+            std::process::exit(1);
+        }),
+        None => UserStore::default(),
+    };
+
+    if let Some(Command::CheckConfig) = cli.command {
+        println!("Config is valid");
+        return;
+    }
+
+    if CONFIG.admin_api_keys.is_empty() && CONFIG.jwt_secret.is_none() && !oidc::is_enabled() {
+        warn!("None of ADMIN_API_KEYS, JWT_SECRET and OIDC are set, so admin routes are open to anyone");
+    }
+
+    if CONFIG.dual_control && auth::is_open_to_anyone(auth::Role::Admin) {
+        warn!("DUAL_CONTROL is on but admin routes are open to anyone, so pending actions can't be approved (everyone is the same anonymous user)");
+    }
+
+    if CONFIG
+        .cors_allowed_origins
+        .iter()
+        .any(|origin| origin == "*")
+    {
+        warn!("CORS_ALLOWED_ORIGINS contains *, so browsers can call the API from any origin");
+    }
+
+    // OIDC logins without it are a config problem
+    if CONFIG.users_file.is_some() && CONFIG.jwt_secret.is_none() {
+        warn!("JWT_SECRET isn't set, so nobody can log in with a password to get a token");
+    }
+
+    if CONFIG.embedded_mqtt_broker {
+        broker::start();
+    }
+
     let mesh_interface = mqtt::init_client().await;
 
     let app_state = AppState {
         mesh_interface,
-        app_settings: Arc::new(Mutex::new(AppSettings {
-            get_settings_timeout_seconds: CONFIG.default_get_settings_timeout_seconds,
-            signal_data_timeout_seconds: CONFIG.default_signal_data_timeout_seconds,
-            route_cost_weight: CONFIG.default_route_cost_weight,
-            route_hops_weight: CONFIG.default_route_hops_weight,
-            ad_hoc_telemetry_timeout_seconds: CONFIG.default_ad_hoc_telemetry_timeout_seconds,
-        })),
+        app_settings: Arc::new(Mutex::new(AppSettings::from_config())),
         updating_routes_lock: Arc::new(Mutex::new(())),
+        discovering_lock: Arc::new(Mutex::new(())),
         telemetry_cache: Arc::new(Mutex::new(RingBuffer::new(CONFIG.telemetry_cache_capacity))),
+        telemetry_archive: Arc::new(Mutex::new(TelemetryArchive::new(
+            CONFIG.telemetry_archive_capacity,
+        ))),
         live_telemetry_is_enabled: Arc::new(AtomicBool::new(false)),
+        topology: Arc::new(Mutex::new(Topology::default())),
+        alerts: Arc::new(Mutex::new(AlertStore::default())),
+        alert_history: Arc::new(Mutex::new(AlertHistory::default())),
+        server_events: broadcast::channel(CONFIG.channel_capacity).0,
+        http_client: reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(CONFIG.http_connect_timeout_seconds))
+            .timeout(Duration::from_secs(CONFIG.http_request_timeout_seconds))
+            .build()
+            .expect("Failed to build HTTP client"),
+        webhook_deliveries: Arc::new(Semaphore::new(CONFIG.webhook_max_concurrent_deliveries)),
+        battery_tracker: Arc::new(Mutex::new(BatteryTracker::default())),
+        presence: Arc::new(Mutex::new(PresenceTracker::default())),
+        live_telemetry_auto_stop: Arc::new(Mutex::new(None)),
+        positions: Arc::new(Mutex::new(PositionStore::default())),
+        seismic: Arc::new(Mutex::new(SeismicStore::default())),
+        seismic_events: Arc::new(Mutex::new(SeismicEventStore::default())),
+        seismic_triggers: Arc::new(Mutex::new(SeismicTriggerStore::default())),
+        anomaly_detector: Arc::new(Mutex::new(AnomalyDetector::new(
+            CONFIG.anomaly_history_capacity,
+        ))),
+        replay_task: Arc::new(Mutex::new(None)),
+        websocket_hub: Arc::new(Mutex::new(WebSocketHub::new())),
+        ws_tokens: Arc::new(Mutex::new(WsTokenStore::default())),
+        users: Arc::new(Mutex::new(users)),
+        sessions: Arc::new(Mutex::new(SessionStore::default())),
+        oidc: Arc::new(Mutex::new(OidcProvider::default())),
+        api_tokens: Arc::new(Mutex::new(ApiTokenStore::default())),
+        rate_limiter: Arc::new(Mutex::new(RateLimiter::default())),
+        auth_lockout: Arc::new(Mutex::new(AuthLockout::new(
+            CONFIG.auth_event_history_capacity,
+        ))),
+        webhook_sources: Arc::new(Mutex::new(WebhookSourceStore::default())),
+        pending_actions: Arc::new(Mutex::new(PendingActionStore::default())),
+        node_registry: Arc::new(Mutex::new(NodeRegistry::default())),
+        gateway_registry: Arc::new(Mutex::new(GatewayRegistry::default())),
+        maintenance_log: Arc::new(Mutex::new(MaintenanceLog::default())),
+        maintenance_windows: Arc::new(Mutex::new(MaintenanceWindowStore::default())),
+        command_history: Arc::new(Mutex::new(CommandHistory::default())),
+        messages: Arc::new(Mutex::new(MessageStore::default())),
+        outbox: Arc::new(Mutex::new(Outbox::default())),
+        message_templates: Arc::new(Mutex::new(MessageTemplateStore::default())),
+        emergency_alerts: Arc::new(Mutex::new(EmergencyAlertStore::default())),
+        delivery_reports: Arc::new(Mutex::new(DeliveryReportStore::default())),
+        drill_mode: Arc::new(Mutex::new(None)),
+        eew: Arc::new(Mutex::new(EewStore::default())),
+        expected_nodes: Arc::new(Mutex::new(ExpectedNodes::default())),
+        firmware_updates: Arc::new(Mutex::new(FirmwareUpdateStore::default())),
+        health: Arc::new(Mutex::new(HealthTracker::default())),
+        mesh_status: Arc::new(Mutex::new(MeshStatus::default())),
     };
 
-    let app = init_app(app_state);
+    if let Err(error_message) = persistence::load(&app_state).await {
+        eprintln!("{}", error_message);
+        std::process::exit(1);
+    }
 
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", CONFIG.server_port))
-        .await
-        .unwrap();
+    telemetry::ingest_task(app_state.clone());
+    archive::archive_task(app_state.clone());
+    presence::offline_check_task(app_state.clone());
+    seismic::ingest_task(app_state.clone());
+    seismic_events::end_task(app_state.clone());
+
+    // the config can't have an address without a node ID
+    if let (Some(address), Some(node_id)) = (
+        &CONFIG.raspberry_shake_udp_address,
+        CONFIG.raspberry_shake_node_id,
+    ) {
+        raspberry_shake::ingest_task(app_state.clone(), address.clone(), node_id);
+    }
+
+    hub::hub_task(app_state.clone());
+    mesh_status::status_task(app_state.clone());
+    health::recalculation_task(app_state.clone());
+    expected_nodes::check_task(app_state.clone());
+    outbox::expiry_task(app_state.clone());
+    reload::sighup_task(app_state.clone());
+
+    let app = init_app(app_state.clone());
+
+    match (&CONFIG.tls_cert_path, &CONFIG.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let tls_config = RustlsConfig::from_pem_file(cert_path, key_path)
+                .await
+                .expect("Failed to load TLS_CERT_PATH and TLS_KEY_PATH");
+
+            if let Some(port) = CONFIG.http_redirect_port {
+                tokio::spawn(https::serve_redirects(port));
+            }
+
+            let handle = Handle::new();
+
+            tokio::spawn({
+                let handle = handle.clone();
+
+                async move {
+                    shutdown_signal().await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+
+            info!("Serving HTTPS on port {}", CONFIG.server_port);
+
+            axum_server::bind_rustls(
+                SocketAddr::from(([0, 0, 0, 0], CONFIG.server_port)),
+                tls_config,
+            )
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+        }
+        // the config can't have only one of them
+        _ => {
+            let listener = tokio::net::TcpListener::bind(("0.0.0.0", CONFIG.server_port))
+                .await
+                .unwrap();
+
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .unwrap();
+        }
+    }
+
+    if let Err(error_message) = persistence::save(&app_state).await {
+        error!(
+            "Failed to save state before shutting down: {}",
+            error_message
+        );
+    }
+}
+
+/// Resolves when the process is asked to stop with Ctrl+C or SIGTERM (e.g. by systemd or Docker)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 
-    axum::serve(listener, app).await.unwrap();
+    info!("Shutting down");
 }
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    pathfinding::NodeId,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody},
+    AppState,
+};
+
+/// How many events are included in `/nodes/{id}`
+pub const RECENT_EVENT_COUNT: usize = 5;
+
+pub type MaintenanceEventId = u64;
+
+/// Something done to a node on site, e.g. "battery replaced"
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MaintenanceEvent {
+    pub id: MaintenanceEventId,
+    pub node_id: NodeId,
+    /// e.g. "battery_replaced", "antenna_adjusted" or "inspection"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub description: String,
+    /// who did the work, which isn't necessarily who logged it
+    pub technician: Option<String>,
+    /// seconds since unix epoch that the work was done
+    pub occurred_at: u64,
+    pub logged_by: String,
+    /// seconds since unix epoch
+    pub logged_at: u64,
+}
+
+/// Every node's maintenance events, oldest first, which are saved to `maintenance.json` in the data
+/// directory whenever they change
+#[derive(Default)]
+pub struct MaintenanceLog {
+    events: BTreeMap<NodeId, Vec<MaintenanceEvent>>,
+    next_id: MaintenanceEventId,
+}
+
+impl MaintenanceLog {
+    pub fn restore(&mut self, events: Vec<MaintenanceEvent>) {
+        self.next_id = events.iter().map(|event| event.id + 1).max().unwrap_or(0);
+
+        self.events.clear();
+        for event in events {
+            self.insert(event);
+        }
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &MaintenanceEvent> {
+        self.events.values().flatten()
+    }
+
+    /// The node's most recent events, newest first
+    pub fn recent(&self, node_id: NodeId, count: usize) -> Vec<MaintenanceEvent> {
+        self.events
+            .get(&node_id)
+            .map(|events| events.iter().rev().take(count).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn latest(&self, node_id: NodeId) -> Option<&MaintenanceEvent> {
+        self.events.get(&node_id)?.last()
+    }
+
+    /// Keeps each node's events in the order the work was done, since events can be logged after
+    /// the fact
+    fn insert(&mut self, event: MaintenanceEvent) {
+        let events = self.events.entry(event.node_id).or_default();
+        let index = events.partition_point(|existing| existing.occurred_at <= event.occurred_at);
+
+        events.insert(index, event);
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.events.remove(&node_id);
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceEventBody {
+    #[serde(rename = "type")]
+    kind: String,
+    description: String,
+    technician: Option<String>,
+    /// defaults to now
+    occurred_at: Option<u64>,
+}
+
+/// /admin/nodes/{id}/events (POST)
+pub async fn log_event(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<MaintenanceEventBody>,
+) -> FallibleJsonResponse<MaintenanceEvent> {
+    let kind = body.kind.trim().to_owned();
+
+    if kind.is_empty() {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "type can't be empty".to_owned(),
+        );
+    }
+
+    let now = unix_time_seconds();
+
+    if body
+        .occurred_at
+        .is_some_and(|occurred_at| occurred_at > now)
+    {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "occurred_at can't be in the future".to_owned(),
+        );
+    }
+
+    info!(
+        "{} logged maintenance on node {}: {:?}",
+        user, node_id, body
+    );
+
+    let event = {
+        let mut maintenance_log = state.maintenance_log.lock().await;
+
+        let event = MaintenanceEvent {
+            id: maintenance_log.next_id,
+            node_id,
+            kind,
+            description: body.description,
+            technician: body.technician,
+            occurred_at: body.occurred_at.unwrap_or(now),
+            logged_by: user.name.clone(),
+            logged_at: now,
+        };
+
+        maintenance_log.next_id += 1;
+        maintenance_log.insert(event.clone());
+
+        event
+    };
+
+    if let Err(error_message) = persistence::save_maintenance_log(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(event)
+}
+
+/// /nodes/{id}/events
+pub async fn get_events(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+) -> Json<Vec<MaintenanceEvent>> {
+    Json(
+        state
+            .maintenance_log
+            .lock()
+            .await
+            .recent(node_id, usize::MAX),
+    )
+}
@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    config::CONFIG,
+    events::ServerEvent,
+    pathfinding::NodeId,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+pub type MaintenanceWindowId = u32;
+
+/// A time range during which offline, battery, anomaly and alert rule notifications for some nodes
+/// are muted, so that planned site work doesn't flood the alert channel
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MaintenanceWindow {
+    pub id: MaintenanceWindowId,
+    /// seconds since unix epoch
+    pub starts_at: u64,
+    /// seconds since unix epoch
+    pub ends_at: u64,
+    /// the nodes it applies to, as well as any with one of `tags`. If both are empty, it applies
+    /// to every node.
+    pub node_ids: BTreeSet<NodeId>,
+    pub tags: BTreeSet<String>,
+    pub reason: String,
+    pub created_by: String,
+    /// seconds since unix epoch
+    pub created_at: u64,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+
+    fn applies_to(&self, node_id: NodeId, node_tags: &BTreeSet<String>) -> bool {
+        (self.node_ids.is_empty() && self.tags.is_empty())
+            || self.node_ids.contains(&node_id)
+            || !self.tags.is_disjoint(node_tags)
+    }
+}
+
+/// A notification which wasn't sent because its node was in a maintenance window
+#[derive(Clone, Serialize, Debug)]
+pub struct MutedEvent {
+    pub window_id: MaintenanceWindowId,
+    /// seconds since unix epoch
+    pub muted_at: u64,
+    pub event: ServerEvent,
+}
+
+/// The maintenance windows, which are saved to `maintenance-windows.json` in the data directory
+/// whenever they change, and the most recent `ALERT_HISTORY_CAPACITY` muted notifications, oldest
+/// first
+#[derive(Default)]
+pub struct MaintenanceWindowStore {
+    windows: BTreeMap<MaintenanceWindowId, MaintenanceWindow>,
+    muted: VecDeque<MutedEvent>,
+}
+
+impl MaintenanceWindowStore {
+    pub fn restore(&mut self, windows: Vec<MaintenanceWindow>) {
+        self.windows = windows
+            .into_iter()
+            .map(|window| (window.id, window))
+            .collect();
+    }
+
+    pub fn windows(&self) -> impl Iterator<Item = &MaintenanceWindow> {
+        self.windows.values()
+    }
+
+    fn next_id(&self) -> MaintenanceWindowId {
+        self.windows.keys().next_back().map_or(1, |id| id + 1)
+    }
+
+    fn any_active(&self, now: u64) -> bool {
+        self.windows.values().any(|window| window.is_active(now))
+    }
+
+    fn window_for(
+        &self,
+        node_id: NodeId,
+        node_tags: &BTreeSet<String>,
+        now: u64,
+    ) -> Option<MaintenanceWindowId> {
+        self.windows
+            .values()
+            .find(|window| window.is_active(now) && window.applies_to(node_id, node_tags))
+            .map(|window| window.id)
+    }
+
+    fn record_muted(&mut self, muted: MutedEvent) {
+        self.muted.push_back(muted);
+
+        while self.muted.len() > CONFIG.alert_history_capacity {
+            self.muted.pop_front();
+        }
+    }
+}
+
+/// Whether the event's node is in a maintenance window, in which case it's recorded as muted and
+/// shouldn't be sent on
+pub async fn mute(state: &AppState, event: &ServerEvent) -> bool {
+    let Some(node_id) = event.node_id() else {
+        return false;
+    };
+
+    let now = unix_time_seconds();
+
+    // there usually aren't any windows, so don't bother looking up the node's tags
+    if !state.maintenance_windows.lock().await.any_active(now) {
+        return false;
+    }
+
+    let node_tags = state.node_registry.lock().await.tags(node_id);
+
+    let mut maintenance_windows = state.maintenance_windows.lock().await;
+
+    let Some(window_id) = maintenance_windows.window_for(node_id, &node_tags, now) else {
+        return false;
+    };
+
+    info!(
+        "Muted {} event for node {} during maintenance window {}",
+        event.kind().name(),
+        node_id,
+        window_id
+    );
+
+    maintenance_windows.record_muted(MutedEvent {
+        window_id,
+        muted_at: now,
+        event: event.clone(),
+    });
+
+    true
+}
+
+/// /maintenance-windows
+pub async fn get_maintenance_windows(
+    State(state): State<AppState>,
+) -> Json<Vec<MaintenanceWindow>> {
+    Json(
+        state
+            .maintenance_windows
+            .lock()
+            .await
+            .windows()
+            .cloned()
+            .collect(),
+    )
+}
+
+/// /maintenance-windows/muted
+pub async fn get_muted_events(State(state): State<AppState>) -> Json<Vec<MutedEvent>> {
+    Json(
+        state
+            .maintenance_windows
+            .lock()
+            .await
+            .muted
+            .iter()
+            .rev()
+            .cloned()
+            .collect(),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindowBody {
+    /// now if not given
+    starts_at: Option<u64>,
+    ends_at: u64,
+    #[serde(default)]
+    node_ids: BTreeSet<NodeId>,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    reason: String,
+}
+
+/// /admin/maintenance-windows
+pub async fn create_maintenance_window(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<MaintenanceWindowBody>,
+) -> FallibleJsonResponse<MaintenanceWindow> {
+    let now = unix_time_seconds();
+    let starts_at = body.starts_at.unwrap_or(now);
+
+    if body.ends_at <= starts_at || body.ends_at <= now {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "ends_at must be after starts_at and in the future".to_owned(),
+        );
+    }
+
+    if body.reason.trim().is_empty() {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A reason is required".to_owned(),
+        );
+    }
+
+    let window = {
+        let mut maintenance_windows = state.maintenance_windows.lock().await;
+
+        let window = MaintenanceWindow {
+            id: maintenance_windows.next_id(),
+            starts_at,
+            ends_at: body.ends_at,
+            node_ids: body.node_ids,
+            tags: body.tags,
+            reason: body.reason,
+            created_by: user.name.clone(),
+            created_at: now,
+        };
+
+        maintenance_windows
+            .windows
+            .insert(window.id, window.clone());
+
+        window
+    };
+
+    warn!(
+        target: "audit",
+        "{} created maintenance window {} from {} to {} for nodes {:?} and tags {:?}: {}",
+        user,
+        window.id,
+        window.starts_at,
+        window.ends_at,
+        window.node_ids,
+        window.tags,
+        window.reason
+    );
+
+    if let Err(error_message) = persistence::save_maintenance_windows(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(window)
+}
+
+/// /admin/maintenance-windows/{id} (DELETE)
+pub async fn remove_maintenance_window(
+    State(state): State<AppState>,
+    Path(window_id): Path<MaintenanceWindowId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state
+        .maintenance_windows
+        .lock()
+        .await
+        .windows
+        .remove(&window_id)
+        .is_none()
+    {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No maintenance window with id {}", window_id),
+        );
+    }
+
+    warn!(target: "audit", "{} removed maintenance window {}", user, window_id);
+
+    if let Err(error_message) = persistence::save_maintenance_windows(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use axum::{extract::State, Json};
+use log::{debug, info, warn};
+use serde::Serialize;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+use crate::{config::CONFIG, events::ServerEvent, utils::unix_time_seconds, AppState};
+
+/// Whether the server can currently hear the mesh, so that clients can tell a quiet mesh apart
+/// from the server having lost its uplink
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+pub struct MeshStatus {
+    pub mqtt_connected: bool,
+    /// whether any gateway has been heard from in the last `GATEWAY_SILENCE_SECONDS`
+    pub gateways_heard: bool,
+    /// seconds since unix epoch that the last message from the mesh arrived, `None` if nothing
+    /// has arrived since the server started
+    pub last_heard: Option<u64>,
+    /// whether alerts and actuations are currently drills, kept in sync by `drill::set`
+    pub drill_mode: bool,
+}
+
+impl Default for MeshStatus {
+    fn default() -> Self {
+        Self {
+            mqtt_connected: false,
+            // give the gateways a chance to report in before complaining about them
+            gateways_heard: true,
+            last_heard: None,
+            drill_mode: false,
+        }
+    }
+}
+
+/// Updates the stored status, sending an event to clients if the connection or gateways have
+/// changed state
+async fn update(state: &AppState, update: impl FnOnce(&mut MeshStatus)) {
+    let mut mesh_status = state.mesh_status.lock().await;
+    let previous_status = *mesh_status;
+
+    update(&mut mesh_status);
+
+    if mesh_status.mqtt_connected == previous_status.mqtt_connected
+        && mesh_status.gateways_heard == previous_status.gateways_heard
+    {
+        return;
+    }
+
+    if mesh_status.mqtt_connected && mesh_status.gateways_heard {
+        info!("Mesh status is now {:?}", *mesh_status);
+    } else {
+        warn!("Mesh status is now {:?}", *mesh_status);
+    }
+
+    let _ = state
+        .server_events
+        .send(ServerEvent::MeshStatus(*mesh_status));
+}
+
+/// Spawns the task which watches the MQTT connection and how recently the gateways were heard
+/// from
+pub fn status_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting mesh status task");
+
+        let mut mqtt_connected = state.mesh_interface.watch_mqtt_connected();
+        let mut mesh_receiver = state.mesh_interface.subscribe();
+        let mut seismic_receiver = state.mesh_interface.subscribe_seismic();
+
+        let started_at = unix_time_seconds();
+        let mut check_interval = tokio::time::interval(Duration::from_secs(
+            (CONFIG.gateway_silence_seconds / 10).max(1),
+        ));
+
+        loop {
+            // everything that arrives over MQTT has been uplinked by a gateway
+            let heard_from_mesh = tokio::select! {
+                result = mqtt_connected.changed() => {
+                    if result.is_err() {
+                        return;
+                    }
+
+                    let is_connected = *mqtt_connected.borrow_and_update();
+                    update(&state, |status| status.mqtt_connected = is_connected).await;
+
+                    false
+                }
+                result = mesh_receiver.recv() => !matches!(result, Err(RecvError::Closed)),
+                result = seismic_receiver.recv() => !matches!(result, Err(RecvError::Closed)),
+                _ = check_interval.tick() => false,
+            };
+
+            let now = unix_time_seconds();
+
+            update(&state, |status| {
+                if heard_from_mesh {
+                    status.last_heard = Some(now);
+                }
+
+                let quiet_since = status.last_heard.unwrap_or(started_at);
+                status.gateways_heard =
+                    now.saturating_sub(quiet_since) <= CONFIG.gateway_silence_seconds;
+            })
+            .await;
+        }
+    })
+}
+
+/// /info/mesh-status
+pub async fn get_mesh_status(State(state): State<AppState>) -> Json<MeshStatus> {
+    Json(*state.mesh_status.lock().await)
+}
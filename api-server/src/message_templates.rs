@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    messages::{self, SentMessage},
+    pathfinding::NodeId,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+/// A standard message which can be sent without typing it out, e.g. "Evacuate to {{place}} now"
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MessageTemplate {
+    pub name: String,
+    /// may contain `{{variable}}`s, which are filled in when it's sent
+    pub text: String,
+    pub updated_by: String,
+    /// seconds since unix epoch
+    pub updated_at: u64,
+}
+
+/// The message templates, keyed by name, which are saved to `message-templates.json` in the data
+/// directory whenever they change
+#[derive(Default)]
+pub struct MessageTemplateStore {
+    templates: BTreeMap<String, MessageTemplate>,
+}
+
+impl MessageTemplateStore {
+    pub fn restore(&mut self, templates: Vec<MessageTemplate>) {
+        self.templates = templates
+            .into_iter()
+            .map(|template| (template.name.clone(), template))
+            .collect();
+    }
+
+    pub fn templates(&self) -> impl Iterator<Item = &MessageTemplate> {
+        self.templates.values()
+    }
+}
+
+/// Replaces every `{{variable}}` in the text, returning the names of any variables which weren't
+/// given if there are some
+pub fn render(
+    text: &str,
+    variables: &BTreeMap<String, String>,
+) -> Result<String, BTreeSet<String>> {
+    let mut rendered = String::with_capacity(text.len());
+    let mut missing = BTreeSet::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start + 2..].find("}}") else {
+            break;
+        };
+
+        let name = rest[start + 2..start + 2 + length].trim();
+
+        rendered.push_str(&rest[..start]);
+
+        match variables.get(name) {
+            Some(value) => rendered.push_str(value),
+            None => {
+                missing.insert(name.to_owned());
+            }
+        }
+
+        rest = &rest[start + 2 + length + 2..];
+    }
+
+    rendered.push_str(rest);
+
+    if missing.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(missing)
+    }
+}
+
+/// e.g. "14:05 UTC"
+fn format_time(unix_time_seconds: u64) -> String {
+    let seconds_today = unix_time_seconds % (24 * 60 * 60);
+
+    format!(
+        "{:02}:{:02} UTC",
+        seconds_today / (60 * 60),
+        seconds_today / 60 % 60
+    )
+}
+
+/// /message-templates
+pub async fn get_message_templates(State(state): State<AppState>) -> Json<Vec<MessageTemplate>> {
+    Json(
+        state
+            .message_templates
+            .lock()
+            .await
+            .templates()
+            .cloned()
+            .collect(),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MessageTemplateBody {
+    text: String,
+}
+
+/// /admin/message-templates/{name} (PUT)
+pub async fn set_message_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<MessageTemplateBody>,
+) -> FallibleJsonResponse<MessageTemplate> {
+    if body.text.trim().is_empty() {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "text can't be empty".to_owned(),
+        );
+    }
+
+    let template = MessageTemplate {
+        name: name.clone(),
+        text: body.text,
+        updated_by: user.name.clone(),
+        updated_at: unix_time_seconds(),
+    };
+
+    let replaced = state
+        .message_templates
+        .lock()
+        .await
+        .templates
+        .insert(name.clone(), template.clone())
+        .is_some();
+
+    info!(
+        "{} {} message template {}: {:?}",
+        user,
+        if replaced { "updated" } else { "added" },
+        name,
+        template.text
+    );
+
+    if let Err(error_message) = persistence::save_message_templates(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(template)
+}
+
+/// /admin/message-templates/{name} (DELETE)
+pub async fn remove_message_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state
+        .message_templates
+        .lock()
+        .await
+        .templates
+        .remove(&name)
+        .is_none()
+    {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No message template named {}", name),
+        );
+    }
+
+    info!("{} removed message template {}", user, name);
+
+    if let Err(error_message) = persistence::save_message_templates(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SendTemplateBody {
+    /// the message is broadcast to every node if this isn't given
+    node_id: Option<NodeId>,
+    /// only used when sending to a single node, as with /admin/messages/send
+    #[serde(default)]
+    require_ack: bool,
+    /// values for the template's own variables, which can also override the built in ones
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+}
+
+/// /admin/message-templates/{name}/send
+pub async fn send_message_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<SendTemplateBody>,
+) -> FallibleJsonResponse<SentMessage> {
+    let Some(template) = state
+        .message_templates
+        .lock()
+        .await
+        .templates
+        .get(&name)
+        .cloned()
+    else {
+        return FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No message template named {}", name),
+        );
+    };
+
+    let mut variables = BTreeMap::from([("time".to_owned(), format_time(unix_time_seconds()))]);
+
+    if let Some(node_id) = body.node_id {
+        let node_name = state
+            .node_registry
+            .lock()
+            .await
+            .name(node_id)
+            .unwrap_or_else(|| node_id.to_string());
+
+        variables.insert("node_name".to_owned(), node_name);
+    }
+
+    variables.extend(body.variables);
+
+    let text = match render(&template.text, &variables) {
+        Ok(text) => text,
+        Err(missing) => {
+            return FallibleJsonResponse::Err(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "No values were given for the template's variables: {}",
+                    missing.into_iter().collect::<Vec<_>>().join(", ")
+                ),
+            );
+        }
+    };
+
+    info!("{} is sending message template {}", user, name);
+
+    match body.node_id {
+        Some(node_id) => messages::send_text(&state, &user, node_id, text, body.require_ack).await,
+        None => messages::broadcast_text(&state, &user, text).await,
+    }
+}
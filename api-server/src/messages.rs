@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    command_history::{self, CommandOutcome},
+    config::CONFIG,
+    outbox,
+    pathfinding::NodeId,
+    presence,
+    proto::meshtastic::{crisislab_message, CrisislabMessage},
+    utils::{
+        await_mesh_response, send_command_protobuf, unix_time_seconds, FallibleJsonResponse,
+        JsonBody,
+    },
+    AppState,
+};
+
+pub type MessageId = u32;
+
+/// A message sent to the mesh whose delivery is being tracked
+#[derive(Clone, Debug)]
+pub struct TrackedMessage {
+    pub id: MessageId,
+    pub text: String,
+    pub sent_by: String,
+    /// seconds since unix epoch, which is when it was published if it was queued
+    pub sent_at: u64,
+    /// waiting in the outbox for its node to come back online
+    pub queued: bool,
+    /// when each node the message was meant for acknowledged it, if it has
+    pub targets: BTreeMap<NodeId, Option<u64>>,
+}
+
+/// The most recent `MESSAGE_HISTORY_CAPACITY` messages sent to the mesh, oldest first, along with
+/// which of their targets have acknowledged them
+#[derive(Default)]
+pub struct MessageStore {
+    messages: VecDeque<TrackedMessage>,
+}
+
+impl MessageStore {
+    pub fn track(&mut self, message: TrackedMessage) {
+        self.messages.push_back(message);
+
+        while self.messages.len() > CONFIG.message_history_capacity {
+            self.messages.pop_front();
+        }
+    }
+
+    pub fn get(&self, id: MessageId) -> Option<&TrackedMessage> {
+        self.messages.iter().find(|message| message.id == id)
+    }
+
+    /// Records that a queued message has been published
+    pub fn mark_published(&mut self, id: MessageId, now: u64) {
+        if let Some(message) = self.messages.iter_mut().find(|message| message.id == id) {
+            message.sent_at = now;
+            message.queued = false;
+        }
+    }
+
+    /// Records that a queued message was dropped from the outbox without being published
+    pub fn mark_dropped(&mut self, id: MessageId) {
+        if let Some(message) = self.messages.iter_mut().find(|message| message.id == id) {
+            message.queued = false;
+        }
+    }
+
+    /// Forgets a message which couldn't be published
+    fn remove(&mut self, id: MessageId) {
+        self.messages.retain(|message| message.id != id);
+    }
+
+    /// Records the nodes a gateway says have acknowledged a message. Nodes the message wasn't meant
+    /// for are ignored.
+    fn record_delivery(&mut self, id: MessageId, node_ids: &[NodeId], now: u64) {
+        let Some(message) = self.messages.iter_mut().find(|message| message.id == id) else {
+            return;
+        };
+
+        for node_id in node_ids {
+            if let Some(delivered_at) = message.targets.get_mut(node_id) {
+                delivered_at.get_or_insert(now);
+            }
+        }
+    }
+}
+
+/// Records a delivery report from a gateway
+pub async fn record_delivery_report(state: &AppState, report: crisislab_message::DeliveryReport) {
+    debug!("Delivery report: {:?}", report);
+
+    for node_id in &report.node_nums {
+        presence::mark_seen(state, *node_id).await;
+    }
+
+    state.messages.lock().await.record_delivery(
+        report.message_id,
+        &report.node_nums,
+        unix_time_seconds(),
+    );
+}
+
+/// Tracks a message and publishes it, so that delivery reports which arrive straight away aren't
+/// missed
+pub async fn track_and_send(
+    state: &AppState,
+    message: TrackedMessage,
+    crisislab_message: CrisislabMessage,
+) -> Result<(), String> {
+    let id = message.id;
+
+    state.messages.lock().await.track(message);
+
+    let result = send_command_protobuf(crisislab_message, &state.mesh_interface).await;
+
+    if result.is_err() {
+        state.messages.lock().await.remove(id);
+    }
+
+    result
+}
+
+/// Checks that a text message isn't empty and fits in a single mesh packet
+pub fn validate_text(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("text can't be empty".to_owned());
+    }
+
+    if text.len() > CONFIG.text_message_max_bytes {
+        return Err(format!(
+            "text can't be longer than {} bytes",
+            CONFIG.text_message_max_bytes
+        ));
+    }
+
+    Ok(())
+}
+
+fn text_message(text: String, id: MessageId, destination: Option<NodeId>) -> CrisislabMessage {
+    CrisislabMessage {
+        message: Some(crisislab_message::Message::TextMessage(
+            crisislab_message::TextMessage { text, id },
+        )),
+        destination,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BroadcastBody {
+    text: String,
+}
+
+/// /admin/messages/broadcast
+pub async fn broadcast(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<BroadcastBody>,
+) -> FallibleJsonResponse<SentMessage> {
+    broadcast_text(&state, &user, body.text).await
+}
+
+/// The nodes which are expected to acknowledge a message sent to the whole mesh, which is every
+/// node which has been accepted into the registry
+pub async fn broadcast_targets(state: &AppState) -> BTreeMap<NodeId, Option<u64>> {
+    state
+        .node_registry
+        .lock()
+        .await
+        .nodes()
+        .iter()
+        .filter(|(_, info)| !info.pending)
+        .map(|(node_id, _)| (*node_id, None))
+        .collect()
+}
+
+/// Publishes a text message to every node and tracks its delivery
+pub async fn broadcast_text(
+    state: &AppState,
+    user: &AuthedUser,
+    text: String,
+) -> FallibleJsonResponse<SentMessage> {
+    if let Err(error_message) = validate_text(&text) {
+        return FallibleJsonResponse::Err(StatusCode::UNPROCESSABLE_ENTITY, error_message);
+    }
+
+    let id = rand::random::<MessageId>();
+
+    info!("{} broadcast message {} to the mesh: {:?}", user, id, text);
+
+    let targets = broadcast_targets(state).await;
+
+    let message = TrackedMessage {
+        id,
+        text: text.clone(),
+        sent_by: user.name.clone(),
+        sent_at: unix_time_seconds(),
+        queued: false,
+        targets,
+    };
+
+    match track_and_send(state, message, text_message(text, id, None)).await {
+        Ok(()) => FallibleJsonResponse::Ok(SentMessage {
+            id,
+            delivered_at: None,
+            queued: false,
+        }),
+        Err(error_message) => {
+            FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SendMessageBody {
+    node_id: NodeId,
+    text: String,
+    /// wait for the node to acknowledge the message before responding
+    #[serde(default)]
+    require_ack: bool,
+}
+
+#[derive(Serialize)]
+pub struct SentMessage {
+    id: MessageId,
+    /// seconds since unix epoch that the node's acknowledgement arrived, only set if it was
+    /// waited for
+    delivered_at: Option<u64>,
+    /// the node is offline, so the message will be published once it's heard from
+    queued: bool,
+}
+
+/// /admin/messages/send
+pub async fn send_message(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<SendMessageBody>,
+) -> FallibleJsonResponse<SentMessage> {
+    send_text(&state, &user, body.node_id, body.text, body.require_ack).await
+}
+
+/// Sends a text message to a single node and tracks its delivery, waiting for the node to
+/// acknowledge it if `require_ack` is set
+pub async fn send_text(
+    state: &AppState,
+    user: &AuthedUser,
+    node_id: NodeId,
+    text: String,
+    require_ack: bool,
+) -> FallibleJsonResponse<SentMessage> {
+    if let Err(error_message) = validate_text(&text) {
+        return FallibleJsonResponse::Err(StatusCode::UNPROCESSABLE_ENTITY, error_message);
+    }
+
+    let id = rand::random::<MessageId>();
+
+    info!(
+        "{} sent message {} to node {}: {:?}",
+        user, id, node_id, text
+    );
+
+    let node_offline = outbox::is_offline(state, node_id).await;
+
+    if node_offline && require_ack {
+        return FallibleJsonResponse::Err(
+            StatusCode::CONFLICT,
+            format!(
+                "Node {} is offline, so its acknowledgement can't be waited for. Send the message \
+                without require_ack to queue it until the node is heard from.",
+                node_id
+            ),
+        );
+    }
+
+    let sent_at = unix_time_seconds();
+    let crisislab_message = text_message(text.clone(), id, Some(node_id));
+
+    let message = TrackedMessage {
+        id,
+        text,
+        sent_by: user.name.clone(),
+        sent_at,
+        queued: node_offline,
+        targets: BTreeMap::from([(node_id, None)]),
+    };
+
+    if !require_ack {
+        state.messages.lock().await.track(message);
+
+        return match command_history::send_to_node(state, node_id, crisislab_message, &user.name)
+            .await
+        {
+            Ok(outcome) => FallibleJsonResponse::Ok(SentMessage {
+                id,
+                delivered_at: None,
+                queued: outcome == CommandOutcome::Queued,
+            }),
+            Err(error_message) => {
+                state.messages.lock().await.remove(id);
+
+                FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log()
+            }
+        };
+    }
+
+    // subscribe before sending the message so that a quick acknowledgement can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+
+    if let Err(error_message) = track_and_send(state, message, crisislab_message.clone()).await {
+        command_history::record(
+            state,
+            node_id,
+            &crisislab_message,
+            &user.name,
+            sent_at,
+            CommandOutcome::Failed,
+            Some(error_message.clone()),
+        )
+        .await;
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let timeout_duration =
+        Duration::from_secs(state.app_settings.lock().await.command_ack_timeout_seconds);
+
+    let delivered =
+        await_mesh_response(
+            &mut mesh_receiver,
+            timeout_duration,
+            |message| match message.message {
+                Some(crisislab_message::Message::DeliveryReport(report))
+                    if report.message_id == id && report.node_nums.contains(&node_id) =>
+                {
+                    Some(())
+                }
+                _ => None,
+            },
+        )
+        .await;
+
+    let (outcome, error) = match &delivered {
+        Ok(()) => (CommandOutcome::Responded, None),
+        Err(error_message) => (CommandOutcome::TimedOut, Some(error_message.clone())),
+    };
+
+    command_history::record(
+        state,
+        node_id,
+        &crisislab_message,
+        &user.name,
+        sent_at,
+        outcome,
+        error,
+    )
+    .await;
+
+    match delivered {
+        Ok(()) => FallibleJsonResponse::Ok(SentMessage {
+            id,
+            delivered_at: Some(unix_time_seconds()),
+            queued: false,
+        }),
+        Err(error_message) => FallibleJsonResponse::Err(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!(
+                "Node {} didn't acknowledge message {}: {}",
+                node_id, id, error_message
+            ),
+        )
+        .log(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    Delivered,
+    /// not acknowledged yet
+    Pending,
+    /// not acknowledged within `MESSAGE_DELIVERY_TIMEOUT_SECONDS`
+    Failed,
+}
+
+#[derive(Serialize)]
+pub struct TargetStatus {
+    state: DeliveryState,
+    delivered_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct MessageStatus {
+    id: MessageId,
+    text: String,
+    sent_by: String,
+    sent_at: u64,
+    queued: bool,
+    delivered: usize,
+    pending: usize,
+    failed: usize,
+    targets: BTreeMap<NodeId, TargetStatus>,
+}
+
+/// How a message's delivery is going as of `now`. Targets which haven't acknowledged it in time
+/// are failed, though they're still marked as delivered if a report turns up later. Queued
+/// messages can't fail until they've been published.
+pub fn status(message: &TrackedMessage, now: u64) -> MessageStatus {
+    let timed_out =
+        !message.queued && now >= message.sent_at + CONFIG.message_delivery_timeout_seconds;
+
+    let targets = message
+        .targets
+        .iter()
+        .map(|(node_id, delivered_at)| {
+            let state = match delivered_at {
+                Some(_) => DeliveryState::Delivered,
+                None if timed_out => DeliveryState::Failed,
+                None => DeliveryState::Pending,
+            };
+
+            (
+                *node_id,
+                TargetStatus {
+                    state,
+                    delivered_at: *delivered_at,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let count = |state| {
+        targets
+            .values()
+            .filter(|target| target.state == state)
+            .count()
+    };
+
+    MessageStatus {
+        id: message.id,
+        text: message.text.clone(),
+        sent_by: message.sent_by.clone(),
+        sent_at: message.sent_at,
+        queued: message.queued,
+        delivered: count(DeliveryState::Delivered),
+        pending: count(DeliveryState::Pending),
+        failed: count(DeliveryState::Failed),
+        targets,
+    }
+}
+
+/// /messages/{id}/status
+pub async fn get_message_status(
+    State(state): State<AppState>,
+    Path(id): Path<MessageId>,
+) -> FallibleJsonResponse<MessageStatus> {
+    match state.messages.lock().await.get(id) {
+        Some(message) => FallibleJsonResponse::Ok(status(message, unix_time_seconds())),
+        None => FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("Message {} isn't being tracked", id),
+        ),
+    }
+}
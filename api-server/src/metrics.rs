@@ -0,0 +1,295 @@
+use std::fmt::{Display, Write};
+
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+
+use crate::{telemetry::latest_by_node, utils::unix_time_seconds, AppState};
+
+/// Small helper for building a response in the Prometheus text exposition format
+struct MetricsWriter {
+    output: String,
+}
+
+impl MetricsWriter {
+    fn new() -> Self {
+        Self {
+            output: String::new(),
+        }
+    }
+
+    /// Writes the HELP and TYPE lines which must precede the samples of a gauge
+    fn gauge(&mut self, name: &str, help: &str) {
+        self.header(name, help, "gauge");
+    }
+
+    fn counter(&mut self, name: &str, help: &str) {
+        self.header(name, help, "counter");
+    }
+
+    fn header(&mut self, name: &str, help: &str, metric_type: &str) {
+        // writing to a String can't fail
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, String)], value: impl Display) {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let _ = writeln!(self.output, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+/// /metrics
+pub async fn get_metrics(State(state): State<AppState>) -> Response {
+    let mut writer = MetricsWriter::new();
+    let now = unix_time_seconds();
+
+    {
+        let telemetry_cache = state.telemetry_cache.lock().await;
+        let latest = latest_by_node(&telemetry_cache);
+
+        writer.gauge(
+            "node_last_seen_seconds",
+            "Seconds since the most recent telemetry from the node",
+        );
+        for (node_id, telemetry) in &latest {
+            writer.sample(
+                "node_last_seen_seconds",
+                &[("node_id", node_id.to_string())],
+                now.saturating_sub(telemetry.timestamp),
+            );
+        }
+
+        writer.gauge(
+            "node_battery_percent",
+            "Battery level reported by the node (101 means externally powered)",
+        );
+        for (node_id, telemetry) in &latest {
+            if let Some(battery_level) = telemetry.device_metrics.and_then(|m| m.battery_level) {
+                writer.sample(
+                    "node_battery_percent",
+                    &[("node_id", node_id.to_string())],
+                    battery_level,
+                );
+            }
+        }
+
+        writer.gauge("node_voltage_volts", "Battery voltage reported by the node");
+        for (node_id, telemetry) in &latest {
+            if let Some(voltage) = telemetry.device_metrics.and_then(|m| m.voltage) {
+                writer.sample(
+                    "node_voltage_volts",
+                    &[("node_id", node_id.to_string())],
+                    voltage,
+                );
+            }
+        }
+
+        writer.gauge(
+            "node_channel_utilization_percent",
+            "Channel utilization seen by the node",
+        );
+        for (node_id, telemetry) in &latest {
+            if let Some(utilization) = telemetry.device_metrics.and_then(|m| m.channel_utilization)
+            {
+                writer.sample(
+                    "node_channel_utilization_percent",
+                    &[("node_id", node_id.to_string())],
+                    utilization,
+                );
+            }
+        }
+
+        writer.gauge(
+            "node_temperature_celsius",
+            "Temperature measured by the node's environmental sensor",
+        );
+        for (node_id, telemetry) in &latest {
+            if let Some(temperature) = telemetry.environment_metrics.and_then(|m| m.temperature) {
+                writer.sample(
+                    "node_temperature_celsius",
+                    &[("node_id", node_id.to_string())],
+                    temperature,
+                );
+            }
+        }
+
+        writer.gauge(
+            "node_relative_humidity_percent",
+            "Relative humidity measured by the node's environmental sensor",
+        );
+        for (node_id, telemetry) in &latest {
+            if let Some(humidity) = telemetry
+                .environment_metrics
+                .and_then(|m| m.relative_humidity)
+            {
+                writer.sample(
+                    "node_relative_humidity_percent",
+                    &[("node_id", node_id.to_string())],
+                    humidity,
+                );
+            }
+        }
+
+        writer.gauge(
+            "node_barometric_pressure_hpa",
+            "Barometric pressure measured by the node's environmental sensor",
+        );
+        for (node_id, telemetry) in &latest {
+            if let Some(pressure) = telemetry
+                .environment_metrics
+                .and_then(|m| m.barometric_pressure)
+            {
+                writer.sample(
+                    "node_barometric_pressure_hpa",
+                    &[("node_id", node_id.to_string())],
+                    pressure,
+                );
+            }
+        }
+
+        writer.gauge("node_uptime_seconds", "Uptime reported by the node");
+        for (node_id, telemetry) in &latest {
+            if let Some(uptime) = telemetry.device_metrics.and_then(|m| m.uptime_seconds) {
+                writer.sample(
+                    "node_uptime_seconds",
+                    &[("node_id", node_id.to_string())],
+                    uptime,
+                );
+            }
+        }
+    }
+
+    {
+        let topology = state.topology.lock().await;
+
+        writer.gauge(
+            "node_is_gateway",
+            "Whether the node is a gateway (1) or not (0)",
+        );
+        for node_id in topology.links.keys() {
+            writer.sample(
+                "node_is_gateway",
+                &[("node_id", node_id.to_string())],
+                topology.gateway_ids.contains(node_id) as u8,
+            );
+        }
+
+        writer.gauge("link_snr", "Most recent SNR of the link between two nodes");
+        for (to, links) in &topology.links {
+            for (from, reading) in links {
+                writer.sample(
+                    "link_snr",
+                    &[("from", from.to_string()), ("to", to.to_string())],
+                    reading.snr,
+                );
+            }
+        }
+
+        writer.gauge(
+            "link_rssi",
+            "Most recent RSSI of the link between two nodes",
+        );
+        for (to, links) in &topology.links {
+            for (from, reading) in links {
+                writer.sample(
+                    "link_rssi",
+                    &[("from", from.to_string()), ("to", to.to_string())],
+                    reading.rssi,
+                );
+            }
+        }
+    }
+
+    {
+        let health = state.health.lock().await;
+
+        writer.gauge(
+            "node_health_score",
+            "Health of the node from 0 to 100, combining battery, telemetry regularity, link quality and reboots",
+        );
+        for (node_id, score) in health.scores() {
+            writer.sample(
+                "node_health_score",
+                &[("node_id", node_id.to_string())],
+                score,
+            );
+        }
+    }
+
+    {
+        let hub = state.websocket_hub.lock().await;
+        let stream_counters = hub.stream_counters();
+
+        writer.gauge(
+            "ws_connected_clients",
+            "Websocket clients currently connected",
+        );
+        writer.sample("ws_connected_clients", &[], hub.connected_clients());
+
+        writer.counter(
+            "ws_connections_total",
+            "Websocket clients which have connected since the server started",
+        );
+        writer.sample("ws_connections_total", &[], hub.total_connections());
+
+        writer.counter(
+            "ws_messages_sent_total",
+            "Messages sent to websocket clients, by stream",
+        );
+        for (kind, counters) in &stream_counters {
+            writer.sample(
+                "ws_messages_sent_total",
+                &[("stream", kind.name().to_owned())],
+                counters.messages_sent,
+            );
+        }
+
+        writer.counter(
+            "ws_bytes_sent_total",
+            "Bytes (before compression) sent to websocket clients, by stream",
+        );
+        for (kind, counters) in &stream_counters {
+            writer.sample(
+                "ws_bytes_sent_total",
+                &[("stream", kind.name().to_owned())],
+                counters.bytes_sent,
+            );
+        }
+
+        writer.counter(
+            "ws_dropped_messages_total",
+            "Messages dropped because a websocket client couldn't keep up, by stream",
+        );
+        for (kind, counters) in &stream_counters {
+            writer.sample(
+                "ws_dropped_messages_total",
+                &[("stream", kind.name().to_owned())],
+                counters.dropped_messages,
+            );
+        }
+
+        writer.gauge(
+            "ws_average_latency_seconds",
+            "Average time messages wait in websocket clients' queues, by stream",
+        );
+        for (kind, counters) in &stream_counters {
+            if let Some(latency) = counters.average_latency() {
+                writer.sample(
+                    "ws_average_latency_seconds",
+                    &[("stream", kind.name().to_owned())],
+                    latency.as_secs_f64(),
+                );
+            }
+        }
+    }
+
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], writer.output).into_response()
+}
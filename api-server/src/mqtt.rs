@@ -1,10 +1,10 @@
-use crate::{config::CONFIG, MeshInterface};
+use crate::{config::CONFIG, encryption, utils::CommandCounter, MeshInterface};
 use bytes::Bytes;
-use log::{debug, error};
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet};
-use std::time::Duration;
+use log::{debug, error, info, warn};
+use rumqttc::{mqttbytes::matches, AsyncClient, Event, EventLoop, MqttOptions, Packet};
+use std::{sync::Arc, time::Duration};
 use tokio::{
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, watch, Mutex},
     task::JoinHandle,
 };
 
@@ -14,6 +14,17 @@ fn publisher_task(client: AsyncClient, mut rx: mpsc::Receiver<Bytes>) -> JoinHan
 
         // when we have a message on the mpsc channel, publish it to the MQTT broker
         while let Some(bytes) = rx.recv().await {
+            let bytes = match &CONFIG.mesh_encryption_key {
+                Some(key) => match encryption::encrypt(&bytes, key) {
+                    Ok(encrypted_bytes) => encrypted_bytes,
+                    Err(error_message) => {
+                        error!("Not publishing MQTT message: {}", error_message);
+                        continue;
+                    }
+                },
+                None => bytes,
+            };
+
             client
                 .publish(
                     CONFIG.mqtt_outgoing_topic.clone(),
@@ -30,15 +41,46 @@ fn publisher_task(client: AsyncClient, mut rx: mpsc::Receiver<Bytes>) -> JoinHan
 }
 
 #[allow(unused_variables)]
-fn handle_mqtt_message(topic: String, payload: Bytes, tx_to_handlers: broadcast::Sender<Bytes>) {
+fn handle_mqtt_message(
+    topic: String,
+    payload: Bytes,
+    tx_to_handlers: broadcast::Sender<Bytes>,
+    tx_to_seismic: broadcast::Sender<Bytes>,
+) {
     debug!(
         "Got message from MQTT on \"{}\" topic ({} bytes)",
         topic,
         payload.len()
     );
 
-    // this logic might become more complex in the future
-    if let Err(error) = tx_to_handlers.send(payload) {
+    // seismic data is high rate and isn't a CrisislabMessage, so it gets its own channel
+    let is_seismic = CONFIG
+        .mqtt_seismic_topic
+        .as_ref()
+        .is_some_and(|filter| matches(&topic, filter));
+
+    let sender = if is_seismic {
+        tx_to_seismic
+    } else {
+        tx_to_handlers
+    };
+
+    // seismic chunks aren't CrisislabMessages, so they're left as they are
+    let payload = match &CONFIG.mesh_encryption_key {
+        Some(key) if !is_seismic => match encryption::decrypt(&payload, key) {
+            Ok(decrypted_payload) => decrypted_payload,
+            Err(error_message) => {
+                warn!(
+                    "Ignoring message from \"{}\" topic: {}",
+                    topic, error_message
+                );
+                return;
+            }
+        },
+        _ => payload,
+    };
+
+    if let Err(error) = sender.send(payload) {
         error!("Failed to send message to channel receivers. (No receivers?)");
     }
 }
@@ -46,20 +88,33 @@ fn handle_mqtt_message(topic: String, payload: Bytes, tx_to_handlers: broadcast:
 fn subscriber_task(
     mut event_loop: EventLoop,
     tx_to_handlers: broadcast::Sender<Bytes>,
+    tx_to_seismic: broadcast::Sender<Bytes>,
+    tx_connected: watch::Sender<bool>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         debug!("Starting MQTT subscriber task");
 
         loop {
             match event_loop.poll().await {
-                Ok(event) => {
+                Ok(event) => match event {
                     // for every message being received from the broker
-                    if let Event::Incoming(Packet::Publish(packet)) = event {
-                        handle_mqtt_message(packet.topic, packet.payload, tx_to_handlers.clone());
+                    Event::Incoming(Packet::Publish(packet)) => {
+                        handle_mqtt_message(
+                            packet.topic,
+                            packet.payload,
+                            tx_to_handlers.clone(),
+                            tx_to_seismic.clone(),
+                        );
                     }
-                }
+                    Event::Incoming(Packet::ConnAck(_)) => {
+                        info!("Connected to MQTT broker");
+                        tx_connected.send_replace(true);
+                    }
+                    _ => {}
+                },
                 Err(error) => {
                     error!("Error polling MQTT event loop: {:?}", error);
+                    tx_connected.send_replace(false);
                     tokio::time::sleep(Duration::from_secs(3)).await;
                 }
             }
@@ -68,11 +123,14 @@ fn subscriber_task(
 }
 
 pub async fn init_client() -> MeshInterface {
-    let mut options = MqttOptions::new(
-        "crisislab-api-server",
-        CONFIG.mqtt_host.as_str(),
-        CONFIG.mqtt_port,
-    );
+    // the embedded broker is reachable on localhost unless it's only listening on one other address
+    let host = match CONFIG.embedded_mqtt_broker {
+        true if CONFIG.embedded_mqtt_broker_address.is_unspecified() => "localhost".to_owned(),
+        true => CONFIG.embedded_mqtt_broker_address.to_string(),
+        false => CONFIG.mqtt_host.clone(),
+    };
+
+    let mut options = MqttOptions::new("crisislab-api-server", host, CONFIG.mqtt_port);
 
     options.set_keep_alive(Duration::from_secs(30));
     options.set_credentials(CONFIG.mqtt_username.as_str(), CONFIG.mqtt_password.as_str());
@@ -87,6 +145,13 @@ pub async fn init_client() -> MeshInterface {
             CONFIG.mqtt_incoming_topic
         ));
 
+    if let Some(seismic_topic) = &CONFIG.mqtt_seismic_topic {
+        client
+            .subscribe(seismic_topic.clone(), CONFIG.mqtt_qos)
+            .await
+            .unwrap_or_else(|_| panic!("Failed to subscribe to {} channel", seismic_topic));
+    }
+
     // channel for sending message from the mqtt subscriber task to all the endpoint handlers
     let (sender_to_publisher, outgoing_msg_receiver) =
         mpsc::channel::<Bytes>(CONFIG.channel_capacity);
@@ -98,10 +163,26 @@ pub async fn init_client() -> MeshInterface {
 
     // we need to clone the broadcast transmitter because it's being returned
     // so that .subscribe() can be called on it to create a receiver
-    subscriber_task(event_loop, sender_to_subscribers.clone());
+    // channel for the subscriber task to send seismic data to whatever is processing it
+    let (sender_to_seismic_subscribers, _) =
+        broadcast::channel::<Bytes>(CONFIG.seismic_channel_capacity);
+
+    // lets the server tell when the connection to the broker drops
+    let (sender_of_connected, connected_receiver) = watch::channel(false);
+
+    subscriber_task(
+        event_loop,
+        sender_to_subscribers.clone(),
+        sender_to_seismic_subscribers.clone(),
+        sender_of_connected,
+    );
 
     MeshInterface {
         sender_to_publisher,
         sender_to_subscribers,
+        sender_to_seismic_subscribers,
+        mqtt_connected: connected_receiver,
+        // restored from the data directory once the server's state has been set up
+        command_counter: Arc::new(Mutex::new(CommandCounter::default())),
     }
 }
@@ -0,0 +1,190 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use log::{debug, info};
+
+use crate::{
+    auth::AuthedUser,
+    command_history::{self, CommandOutcome},
+    config::CONFIG,
+    pathfinding::NodeId,
+    pending_actions,
+    proto::meshtastic::{crisislab_message, CrisislabMessage},
+    utils::{await_mesh_response, send_command_protobuf, unix_time_seconds, StringOrEmptyResponse},
+    AppState,
+};
+
+/// The `CrisislabMessage.message` tags which nodes send back in a `CommandAck`
+const REBOOT_TAG: u32 = 13;
+const SHUTDOWN_TAG: u32 = 14;
+
+/// Publishes a command addressed to a single node and waits (up to `command_ack_timeout_seconds`)
+/// for the node to acknowledge it
+async fn send_and_await_ack(
+    state: &AppState,
+    user: &AuthedUser,
+    node_id: NodeId,
+    message: crisislab_message::Message,
+    message_tag: u32,
+) -> StringOrEmptyResponse {
+    // subscribe before sending the command so that a quick acknowledgement can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+
+    let crisislab_message = CrisislabMessage {
+        message: Some(message),
+        destination: Some(node_id),
+    };
+
+    let sent_at = unix_time_seconds();
+
+    if let Err(error_message) =
+        send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await
+    {
+        command_history::record(
+            state,
+            node_id,
+            &crisislab_message,
+            &user.name,
+            sent_at,
+            CommandOutcome::Failed,
+            Some(error_message.clone()),
+        )
+        .await;
+
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let timeout_duration =
+        Duration::from_secs(state.app_settings.lock().await.command_ack_timeout_seconds);
+
+    match await_mesh_response(
+        &mut mesh_receiver,
+        timeout_duration,
+        |message| match message.message {
+            Some(crisislab_message::Message::CommandAck(ack))
+                if ack.node_num == node_id && ack.message_tag == message_tag =>
+            {
+                Some(ack)
+            }
+            _ => None,
+        },
+    )
+    .await
+    {
+        Ok(_) => {
+            debug!("Node {} acknowledged command {}", node_id, message_tag);
+
+            command_history::record(
+                state,
+                node_id,
+                &crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::Responded,
+                None,
+            )
+            .await;
+
+            StringOrEmptyResponse::Ok
+        }
+        Err(error_message) => {
+            command_history::record(
+                state,
+                node_id,
+                &crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::TimedOut,
+                Some(error_message.clone()),
+            )
+            .await;
+
+            StringOrEmptyResponse::Err(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "Node {} didn't acknowledge the command: {}",
+                    node_id, error_message
+                ),
+            )
+            .log()
+        }
+    }
+}
+
+/// /admin/nodes/{id}/reboot
+pub async fn reboot_node(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> Response {
+    info!("{} is rebooting node {}", user, node_id);
+
+    // a node which doesn't come back up after a reboot has to be fixed on site
+    if CONFIG.dual_control {
+        return pending_actions::send_or_hold(
+            &state,
+            &user,
+            format!("Reboot node {}", node_id),
+            vec![CrisislabMessage {
+                message: Some(crisislab_message::Message::Reboot(
+                    crisislab_message::Empty {},
+                )),
+                destination: Some(node_id),
+            }],
+            true,
+            None,
+        )
+        .await;
+    }
+
+    send_and_await_ack(
+        &state,
+        &user,
+        node_id,
+        crisislab_message::Message::Reboot(crisislab_message::Empty {}),
+        REBOOT_TAG,
+    )
+    .await
+    .into_response()
+}
+
+/// /admin/nodes/{id}/shutdown
+pub async fn shutdown_node(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> Response {
+    info!("{} is shutting down node {}", user, node_id);
+
+    // a node which has been shut down can only be brought back by someone on site
+    if CONFIG.dual_control {
+        return pending_actions::send_or_hold(
+            &state,
+            &user,
+            format!("Shut down node {}", node_id),
+            vec![CrisislabMessage {
+                message: Some(crisislab_message::Message::Shutdown(
+                    crisislab_message::Empty {},
+                )),
+                destination: Some(node_id),
+            }],
+            true,
+            None,
+        )
+        .await;
+    }
+
+    send_and_await_ack(
+        &state,
+        &user,
+        node_id,
+        crisislab_message::Message::Shutdown(crisislab_message::Empty {}),
+        SHUTDOWN_TAG,
+    )
+    .await
+    .into_response()
+}
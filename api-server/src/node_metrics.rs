@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashSet};
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::{
+    config::CONFIG, pathfinding::NodeId, telemetry::timestamps_by_node, utils::unix_time_seconds,
+    AppState,
+};
+
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+
+/// Percentage of report intervals between `window_start` and `now` in which at least one of the
+/// (sorted) timestamps falls. Returns `None` if the window is empty.
+pub fn uptime_percent(
+    timestamps: &[u64],
+    window_start: u64,
+    now: u64,
+    report_interval_seconds: u64,
+) -> Option<f32> {
+    if now <= window_start || report_interval_seconds == 0 {
+        return None;
+    }
+
+    let interval_count = (now - window_start).div_ceil(report_interval_seconds);
+
+    let intervals_with_reports = timestamps
+        .iter()
+        .filter(|timestamp| (window_start..now).contains(*timestamp))
+        .map(|timestamp| (timestamp - window_start) / report_interval_seconds)
+        .collect::<HashSet<_>>()
+        .len();
+
+    Some(intervals_with_reports as f32 / interval_count as f32 * 100.0)
+}
+
+/// Figures that go into reports about how the network is performing
+#[derive(Serialize)]
+pub struct NodeMetrics {
+    uptime_percent_24h: Option<f32>,
+    uptime_percent_7d: Option<f32>,
+    /// positive means draining, negative means charging
+    battery_drain_percent_per_hour: Option<f32>,
+}
+
+/// /info/node-metrics
+pub async fn get_node_metrics(
+    State(state): State<AppState>,
+) -> Json<BTreeMap<NodeId, NodeMetrics>> {
+    let battery_tracker = state.battery_tracker.lock().await;
+    let timestamps = timestamps_by_node(
+        &*state.telemetry_cache.lock().await,
+        &*state.telemetry_archive.lock().await,
+    );
+
+    let now = unix_time_seconds();
+
+    // the cache doesn't necessarily go back a whole week, so don't count time before the oldest
+    // reading as downtime
+    let oldest_timestamp = timestamps
+        .values()
+        .filter_map(|timestamps| timestamps.first())
+        .min()
+        .copied()
+        .unwrap_or(now);
+
+    let uptime = |timestamps: &[u64], window_seconds: u64| {
+        uptime_percent(
+            timestamps,
+            now.saturating_sub(window_seconds).max(oldest_timestamp),
+            now,
+            CONFIG.expected_report_interval_seconds,
+        )
+    };
+
+    Json(
+        timestamps
+            .iter()
+            .map(|(&node_id, timestamps)| {
+                (
+                    node_id,
+                    NodeMetrics {
+                        uptime_percent_24h: uptime(timestamps, DAY_SECONDS),
+                        uptime_percent_7d: uptime(timestamps, 7 * DAY_SECONDS),
+                        battery_drain_percent_per_hour: battery_tracker.drain_rate(node_id),
+                    },
+                )
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,832 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthedUser,
+    capabilities::NodeCapabilities,
+    command_history,
+    firmware::FirmwareInfo,
+    maintenance::{MaintenanceEvent, RECENT_EVENT_COUNT},
+    pathfinding::NodeId,
+    persistence,
+    proto::meshtastic::{crisislab_message, position::LocSource, CrisislabMessage, Position},
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+/// Meshtastic positions are integers in units of 1e-7 degrees
+const UNITS_PER_DEGREE: f64 = 1e7;
+
+/// Where a node without GPS is installed, set by operators
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+pub struct FixedPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// metres above sea level
+    pub altitude: Option<i32>,
+    /// seconds since unix epoch
+    pub set_at: u64,
+}
+
+/// Settings a node keeps when the rest of the mesh's settings are changed, e.g. a shorter broadcast
+/// interval for a node that's being diagnosed
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize, Debug)]
+pub struct SettingsOverrides {
+    pub broadcast_interval_seconds: Option<u32>,
+    pub ping_timeout_seconds: Option<u32>,
+}
+
+impl SettingsOverrides {
+    /// Whether applying the mesh settings to the node would undo any of the overrides
+    pub fn conflicts_with(&self, mesh_settings: &crisislab_message::MeshSettings) -> bool {
+        (self.broadcast_interval_seconds.is_some()
+            && mesh_settings.broadcast_interval_seconds.is_some())
+            || (self.ping_timeout_seconds.is_some() && mesh_settings.ping_timeout_seconds.is_some())
+    }
+
+    /// The command which applies the overrides to the node, which gateways only forward to it
+    pub fn command(&self, node_id: NodeId) -> CrisislabMessage {
+        CrisislabMessage {
+            message: Some(crisislab_message::Message::MeshSettings(
+                crisislab_message::MeshSettings {
+                    broadcast_interval_seconds: self.broadcast_interval_seconds,
+                    channel_name: None,
+                    ping_timeout_seconds: self.ping_timeout_seconds,
+                },
+            )),
+            destination: Some(node_id),
+        }
+    }
+}
+
+/// What a node is for, which affects how routes are worked out
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeRole {
+    /// often on batteries, so it's avoided as a relay
+    Sensor,
+    Repeater,
+    Gateway,
+    /// e.g. a siren
+    Actuator,
+}
+
+/// What operators know about a node, which the node doesn't report itself
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct NodeInfo {
+    /// friendly name shown instead of the node's ID
+    pub name: Option<String>,
+    /// e.g. "RAK WisBlock 4631"
+    pub hardware_model: Option<String>,
+    /// where the node is installed, e.g. "Kelburn school roof"
+    pub site: Option<String>,
+    /// e.g. "2025-03-14"
+    pub installed_on: Option<String>,
+    /// who looks after the node
+    pub owner: Option<String>,
+    /// anything else operators want to remember, e.g. how to get to it
+    pub notes: Option<String>,
+    /// the language code emergency alerts should be shown in, e.g. "mi" for te reo Māori
+    #[serde(default)]
+    pub language: Option<String>,
+    /// e.g. "ridge-line", "school-site" or "solar", which commands can be sent to as a group
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// seconds since unix epoch that the server first heard from the node
+    pub first_heard: Option<u64>,
+    /// found by a discovery sweep and not yet looked at by an operator, which setting the node's
+    /// details clears
+    #[serde(default)]
+    pub pending: bool,
+    /// the firmware the node last said it was running, which operators can't set
+    #[serde(default)]
+    pub firmware: Option<FirmwareInfo>,
+    /// set with `/admin/nodes/{id}/position` rather than with the rest of the details
+    #[serde(default)]
+    pub position: Option<FixedPosition>,
+    /// set with `/admin/nodes/{id}/settings` rather than with the rest of the details
+    #[serde(default)]
+    pub settings_overrides: Option<SettingsOverrides>,
+    /// set with `/admin/nodes/{id}/role` rather than with the rest of the details
+    #[serde(default)]
+    pub role: Option<NodeRole>,
+    /// what the node last said it has, which operators can't set
+    #[serde(default)]
+    pub capabilities: Option<NodeCapabilities>,
+}
+
+/// Every node the server has heard from (or been told about), which is saved to `nodes.json` in
+/// the data directory whenever it changes
+#[derive(Default)]
+pub struct NodeRegistry {
+    nodes: BTreeMap<NodeId, NodeInfo>,
+}
+
+impl NodeRegistry {
+    pub fn restore(&mut self, nodes: BTreeMap<NodeId, NodeInfo>) {
+        self.nodes = nodes;
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<NodeId, NodeInfo> {
+        &self.nodes
+    }
+
+    pub fn name(&self, node_id: NodeId) -> Option<String> {
+        self.nodes.get(&node_id)?.name.clone()
+    }
+
+    /// The names of whichever of the nodes have one
+    pub fn names(&self, node_ids: impl IntoIterator<Item = NodeId>) -> HashMap<NodeId, String> {
+        node_ids
+            .into_iter()
+            .filter_map(|node_id| Some((node_id, self.name(node_id)?)))
+            .collect()
+    }
+
+    pub fn tags(&self, node_id: NodeId) -> BTreeSet<String> {
+        self.nodes
+            .get(&node_id)
+            .map(|info| info.tags.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn with_tag(&self, tag: &str) -> Vec<NodeId> {
+        self.nodes
+            .iter()
+            .filter(|(_, info)| info.tags.contains(tag))
+            .map(|(node_id, _)| *node_id)
+            .collect()
+    }
+
+    pub fn with_role(&self, role: NodeRole) -> Vec<NodeId> {
+        self.nodes
+            .iter()
+            .filter(|(_, info)| info.role == Some(role))
+            .map(|(node_id, _)| *node_id)
+            .collect()
+    }
+
+    pub fn settings_overrides(&self) -> impl Iterator<Item = (NodeId, SettingsOverrides)> + '_ {
+        self.nodes.iter().filter_map(|(node_id, info)| {
+            info.settings_overrides
+                .map(|settings_overrides| (*node_id, settings_overrides))
+        })
+    }
+
+    /// Records the firmware the node says it's running, returning whether it's changed
+    pub fn record_firmware(
+        &mut self,
+        node_id: NodeId,
+        version: String,
+        build: Option<String>,
+        now: u64,
+    ) -> bool {
+        let Some(info) = self.nodes.get_mut(&node_id) else {
+            return false;
+        };
+
+        if info
+            .firmware
+            .as_ref()
+            .is_some_and(|firmware| firmware.version == version && firmware.build == build)
+        {
+            return false;
+        }
+
+        info.firmware = Some(FirmwareInfo {
+            version,
+            build,
+            since: now,
+        });
+
+        true
+    }
+
+    /// Records the sensors and modules the node says it has, returning whether they've changed
+    pub fn record_capabilities(
+        &mut self,
+        node_id: NodeId,
+        sensors: Vec<String>,
+        modules: Vec<String>,
+        now: u64,
+    ) -> bool {
+        let Some(info) = self.nodes.get_mut(&node_id) else {
+            return false;
+        };
+
+        if info.capabilities.as_ref().is_some_and(|capabilities| {
+            capabilities.sensors == sensors && capabilities.modules == modules
+        }) {
+            return false;
+        }
+
+        info.capabilities = Some(NodeCapabilities {
+            sensors,
+            modules,
+            since: now,
+        });
+
+        true
+    }
+
+    /// Marks a node found by a discovery sweep as pending. It may already have an empty entry from
+    /// being heard while the sweep was running.
+    pub fn add_pending(&mut self, node_id: NodeId, hardware_model: Option<String>, now: u64) {
+        let info = self.nodes.entry(node_id).or_insert_with(|| NodeInfo {
+            first_heard: Some(now),
+            ..NodeInfo::default()
+        });
+
+        info.pending = true;
+
+        if info.hardware_model.is_none() {
+            info.hardware_model = hardware_model;
+        }
+    }
+
+    /// Adds an empty entry for the node if it isn't in the registry, returning whether it was added
+    fn add_stub(&mut self, node_id: NodeId, now: u64) -> bool {
+        if self.nodes.contains_key(&node_id) {
+            return false;
+        }
+
+        self.nodes.insert(
+            node_id,
+            NodeInfo {
+                first_heard: Some(now),
+                ..NodeInfo::default()
+            },
+        );
+
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeEntry {
+    id: NodeId,
+    #[serde(flatten)]
+    info: NodeInfo,
+}
+
+#[derive(Serialize)]
+pub struct NodeDetail {
+    #[serde(flatten)]
+    entry: NodeEntry,
+    /// newest first
+    recent_events: Vec<MaintenanceEvent>,
+}
+
+/// The nodes a command with a `tag` selector should be sent to, which is an error if there aren't
+/// any so that a typo doesn't look like it worked
+pub async fn nodes_with_tag(state: &AppState, tag: &str) -> Result<Vec<NodeId>, String> {
+    let node_ids = state.node_registry.lock().await.with_tag(tag);
+
+    if node_ids.is_empty() {
+        Err(format!("No nodes are tagged {}", tag))
+    } else {
+        Ok(node_ids)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NodesQuery {
+    /// only list nodes with this tag
+    tag: Option<String>,
+}
+
+/// /nodes
+pub async fn get_nodes(
+    State(state): State<AppState>,
+    Query(query): Query<NodesQuery>,
+) -> Json<Vec<NodeEntry>> {
+    Json(
+        state
+            .node_registry
+            .lock()
+            .await
+            .nodes
+            .iter()
+            .filter(|(_, info)| query.tag.as_ref().is_none_or(|tag| info.tags.contains(tag)))
+            .map(|(node_id, info)| NodeEntry {
+                id: *node_id,
+                info: info.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// /nodes/{id}
+pub async fn get_node(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+) -> FallibleJsonResponse<NodeDetail> {
+    let Some(info) = state
+        .node_registry
+        .lock()
+        .await
+        .nodes
+        .get(&node_id)
+        .cloned()
+    else {
+        return FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No node {} in the registry", node_id),
+        );
+    };
+
+    FallibleJsonResponse::Ok(NodeDetail {
+        entry: NodeEntry { id: node_id, info },
+        recent_events: state
+            .maintenance_log
+            .lock()
+            .await
+            .recent(node_id, RECENT_EVENT_COUNT),
+    })
+}
+
+/// The details operators can set, which replace whatever the node had before
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NodeInfoBody {
+    name: Option<String>,
+    hardware_model: Option<String>,
+    site: Option<String>,
+    installed_on: Option<String>,
+    owner: Option<String>,
+    notes: Option<String>,
+    language: Option<String>,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+}
+
+/// /admin/nodes/{id} (PUT)
+pub async fn set_node(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<NodeInfoBody>,
+) -> FallibleJsonResponse<NodeEntry> {
+    info!("{} is setting node {}'s details: {:?}", user, node_id, body);
+
+    let info = {
+        let mut node_registry = state.node_registry.lock().await;
+        // nodes can be added before they've been heard from, e.g. while they're being installed
+        let info = node_registry.nodes.entry(node_id).or_default();
+
+        *info = NodeInfo {
+            name: body.name,
+            hardware_model: body.hardware_model,
+            site: body.site,
+            installed_on: body.installed_on,
+            owner: body.owner,
+            notes: body.notes,
+            language: body
+                .language
+                .map(|language| language.trim().to_owned())
+                .filter(|language| !language.is_empty()),
+            tags: body
+                .tags
+                .into_iter()
+                .map(|tag| tag.trim().to_owned())
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+            first_heard: info.first_heard,
+            pending: false,
+            firmware: info.firmware.take(),
+            position: info.position,
+            settings_overrides: info.settings_overrides,
+            role: info.role,
+            capabilities: info.capabilities.take(),
+        };
+
+        info.clone()
+    };
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(NodeEntry { id: node_id, info })
+}
+
+#[derive(Deserialize)]
+pub struct DeleteNodeQuery {
+    /// whether to also forget the node's telemetry, links and routes
+    #[serde(default)]
+    purge: bool,
+}
+
+/// /admin/nodes/{id} (DELETE)
+pub async fn delete_node(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    Query(query): Query<DeleteNodeQuery>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state
+        .node_registry
+        .lock()
+        .await
+        .nodes
+        .remove(&node_id)
+        .is_none()
+    {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No node {} in the registry", node_id),
+        );
+    }
+
+    if query.purge {
+        state
+            .telemetry_cache
+            .lock()
+            .await
+            .retain(|telemetry| telemetry.node_num != node_id);
+        state.telemetry_archive.lock().await.remove_node(node_id);
+        state.topology.lock().await.remove_node(node_id);
+        state.presence.lock().await.remove_node(node_id);
+        state.positions.lock().await.remove_node(node_id);
+        state.health.lock().await.remove_node(node_id);
+        state.maintenance_log.lock().await.remove_node(node_id);
+        state.command_history.lock().await.remove_node(node_id);
+        state.outbox.lock().await.remove_node(node_id);
+    }
+
+    info!(
+        "{} deleted node {} from the registry{}",
+        user,
+        node_id,
+        if query.purge {
+            " and purged its telemetry, routes, maintenance log and command history"
+        } else {
+            ""
+        }
+    );
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    if query.purge {
+        if let Err(error_message) = persistence::save_maintenance_log(&state).await {
+            return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message)
+                .log();
+        }
+
+        if let Err(error_message) = persistence::save_command_history(&state).await {
+            return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message)
+                .log();
+        }
+    }
+
+    StringOrEmptyResponse::Ok
+}
+
+fn valid_coordinates(latitude: f64, longitude: f64) -> bool {
+    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+}
+
+/// The whole registry, as returned by `/admin/nodes/export` and accepted by `/admin/nodes/import`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryDump {
+    /// seconds since unix epoch, which is ignored when importing
+    #[serde(default)]
+    exported_at: u64,
+    nodes: Vec<NodeEntry>,
+}
+
+/// /admin/nodes/export
+pub async fn export_nodes(State(state): State<AppState>) -> Json<RegistryDump> {
+    Json(RegistryDump {
+        exported_at: unix_time_seconds(),
+        nodes: state
+            .node_registry
+            .lock()
+            .await
+            .nodes
+            .iter()
+            .map(|(node_id, info)| NodeEntry {
+                id: *node_id,
+                info: info.clone(),
+            })
+            .collect(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ImportNodesQuery {
+    /// whether to remove nodes which aren't in the import
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Serialize)]
+pub struct ImportSummary {
+    added: Vec<NodeId>,
+    updated: Vec<NodeId>,
+    removed: Vec<NodeId>,
+}
+
+/// /admin/nodes/import
+pub async fn import_nodes(
+    State(state): State<AppState>,
+    Query(query): Query<ImportNodesQuery>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<RegistryDump>,
+) -> FallibleJsonResponse<ImportSummary> {
+    let mut imported = BTreeMap::new();
+
+    for entry in body.nodes {
+        if let Some(position) = &entry.info.position {
+            if !valid_coordinates(position.latitude, position.longitude) {
+                return FallibleJsonResponse::Err(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Node {} has a position out of range", entry.id),
+                );
+            }
+        }
+
+        if imported.insert(entry.id, entry.info).is_some() {
+            return FallibleJsonResponse::Err(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Node {} is in the import more than once", entry.id),
+            );
+        }
+    }
+
+    let summary = {
+        let mut node_registry = state.node_registry.lock().await;
+
+        let removed = if query.replace {
+            node_registry
+                .nodes
+                .keys()
+                .filter(|node_id| !imported.contains_key(node_id))
+                .copied()
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        for node_id in &removed {
+            node_registry.nodes.remove(node_id);
+        }
+
+        let (updated, added) = imported
+            .keys()
+            .partition(|node_id| node_registry.nodes.contains_key(node_id));
+
+        node_registry.nodes.extend(imported);
+
+        ImportSummary {
+            added,
+            updated,
+            removed,
+        }
+    };
+
+    info!(
+        "{} imported the node registry: {} added, {} updated and {} removed",
+        user,
+        summary.added.len(),
+        summary.updated.len(),
+        summary.removed.len()
+    );
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(summary)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FixedPositionBody {
+    latitude: f64,
+    longitude: f64,
+    altitude: Option<i32>,
+}
+
+/// /admin/nodes/{id}/position
+pub async fn set_node_position(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<FixedPositionBody>,
+) -> FallibleJsonResponse<NodeEntry> {
+    if !valid_coordinates(body.latitude, body.longitude) {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "latitude must be between -90 and 90, and longitude between -180 and 180".to_owned(),
+        );
+    }
+
+    info!(
+        "{} is setting node {}'s position: {:?}",
+        user, node_id, body
+    );
+
+    // the node is told too so that it broadcasts the position like a node with GPS would
+    let crisislab_message = CrisislabMessage {
+        message: Some(crisislab_message::Message::SetPosition(Position {
+            latitude_i: Some((body.latitude * UNITS_PER_DEGREE).round() as i32),
+            longitude_i: Some((body.longitude * UNITS_PER_DEGREE).round() as i32),
+            altitude: body.altitude,
+            location_source: LocSource::LocManual as i32,
+            ..Default::default()
+        })),
+        destination: Some(node_id),
+    };
+
+    if let Err(error_message) =
+        command_history::send_to_node(&state, node_id, crisislab_message, &user.name).await
+    {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let info = {
+        let mut node_registry = state.node_registry.lock().await;
+        let info = node_registry.nodes.entry(node_id).or_default();
+
+        info.position = Some(FixedPosition {
+            latitude: body.latitude,
+            longitude: body.longitude,
+            altitude: body.altitude,
+            set_at: unix_time_seconds(),
+        });
+
+        info.clone()
+    };
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(NodeEntry { id: node_id, info })
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NodeRoleBody {
+    /// `null` clears the role
+    role: Option<NodeRole>,
+}
+
+/// /admin/nodes/{id}/role
+pub async fn set_node_role(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<NodeRoleBody>,
+) -> FallibleJsonResponse<NodeEntry> {
+    info!(
+        "{} is setting node {}'s role to {:?}",
+        user, node_id, body.role
+    );
+
+    let info = {
+        let mut node_registry = state.node_registry.lock().await;
+        let info = node_registry.nodes.entry(node_id).or_default();
+
+        info.role = body.role;
+
+        info.clone()
+    };
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(NodeEntry { id: node_id, info })
+}
+
+/// Only the fields that are given are changed
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsOverridesBody {
+    broadcast_interval_seconds: Option<u32>,
+    ping_timeout_seconds: Option<u32>,
+}
+
+/// /admin/nodes/{id}/settings (PUT)
+pub async fn set_node_settings(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<SettingsOverridesBody>,
+) -> FallibleJsonResponse<NodeEntry> {
+    if body.broadcast_interval_seconds.is_none() && body.ping_timeout_seconds.is_none() {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "At least one setting must be given".to_owned(),
+        );
+    }
+
+    info!(
+        "{} is overriding node {}'s settings: {:?}",
+        user, node_id, body
+    );
+
+    let mut settings_overrides = state
+        .node_registry
+        .lock()
+        .await
+        .nodes
+        .get(&node_id)
+        .and_then(|info| info.settings_overrides)
+        .unwrap_or_default();
+
+    if let Some(broadcast_interval_seconds) = body.broadcast_interval_seconds {
+        settings_overrides.broadcast_interval_seconds = Some(broadcast_interval_seconds);
+    }
+
+    if let Some(ping_timeout_seconds) = body.ping_timeout_seconds {
+        settings_overrides.ping_timeout_seconds = Some(ping_timeout_seconds);
+    }
+
+    if let Err(error_message) = command_history::send_to_node(
+        &state,
+        node_id,
+        settings_overrides.command(node_id),
+        &user.name,
+    )
+    .await
+    {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    let info = {
+        let mut node_registry = state.node_registry.lock().await;
+        let info = node_registry.nodes.entry(node_id).or_default();
+
+        info.settings_overrides = Some(settings_overrides);
+
+        info.clone()
+    };
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(NodeEntry { id: node_id, info })
+}
+
+/// /admin/nodes/{id}/settings (DELETE)
+pub async fn clear_node_settings(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state
+        .node_registry
+        .lock()
+        .await
+        .nodes
+        .get_mut(&node_id)
+        .and_then(|info| info.settings_overrides.take())
+        .is_none()
+    {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("Node {} doesn't have any settings overrides", node_id),
+        );
+    }
+
+    info!("{} cleared node {}'s settings overrides", user, node_id);
+
+    if let Err(error_message) = persistence::save_node_registry(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}
+
+/// Adds a node to the registry the first time it's heard from, so that operators can fill in its
+/// details later
+pub async fn record_heard(state: &AppState, node_id: NodeId) {
+    if !state
+        .node_registry
+        .lock()
+        .await
+        .add_stub(node_id, unix_time_seconds())
+    {
+        return;
+    }
+
+    info!("Added node {} to the registry", node_id);
+
+    if let Err(error_message) = persistence::save_node_registry(state).await {
+        error!("{}", error_message);
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    auth::{self, AuthedUser, Role},
+    config::CONFIG,
+    utils::{unix_time_seconds, FallibleJsonResponse},
+    AppState,
+};
+
+/// how long a user has to finish logging in with the provider
+const LOGIN_TTL_SECONDS: u64 = 600;
+/// the provider's keys are fetched again when a token is signed with one the server doesn't know
+/// about, but no more often than this so that made up key IDs can't be used to spam the provider
+const KEYS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The parts of the provider's discovery document the server uses
+#[derive(Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct PendingLogin {
+    nonce: String,
+    expires_at: u64,
+}
+
+/// What the server knows about the OpenID Connect provider (e.g. Azure AD), which is fetched the
+/// first time it's needed, and logins which have been started with it
+#[derive(Default)]
+pub struct OidcProvider {
+    metadata: Option<ProviderMetadata>,
+    keys: Option<JwkSet>,
+    keys_fetched_at: Option<Instant>,
+    /// state parameter -> login
+    pending_logins: HashMap<String, PendingLogin>,
+}
+
+/// Whether `OIDC_ISSUER_URL` and `OIDC_CLIENT_ID` are set
+pub fn is_enabled() -> bool {
+    CONFIG.oidc_issuer_url.is_some() && CONFIG.oidc_client_id.is_some()
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(state: &AppState, url: &str) -> Result<T, String> {
+    state
+        .http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|error| format!("Failed to fetch {}: {}", url, error))?
+        .json()
+        .await
+        .map_err(|error| format!("Failed to parse {}: {}", url, error))
+}
+
+async fn metadata(state: &AppState) -> Result<ProviderMetadata, String> {
+    if let Some(metadata) = &state.oidc.lock().await.metadata {
+        return Ok(metadata.clone());
+    }
+
+    let Some(issuer_url) = &CONFIG.oidc_issuer_url else {
+        return Err("OIDC isn't enabled".to_owned());
+    };
+
+    let discovery_url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer_url.trim_end_matches('/')
+    );
+
+    let metadata = get_json::<ProviderMetadata>(state, &discovery_url).await?;
+
+    info!("Fetched OIDC provider metadata for {}", metadata.issuer);
+
+    state.oidc.lock().await.metadata = Some(metadata.clone());
+
+    Ok(metadata)
+}
+
+/// The provider's key with the ID, fetching the provider's keys again if it's a new one
+async fn decoding_key(state: &AppState, key_id: &str) -> Result<DecodingKey, String> {
+    {
+        let oidc = state.oidc.lock().await;
+
+        if let Some(jwk) = oidc.keys.as_ref().and_then(|keys| keys.find(key_id)) {
+            return DecodingKey::from_jwk(jwk)
+                .map_err(|error| format!("Unusable signing key {}: {}", key_id, error));
+        }
+
+        if oidc
+            .keys_fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() < KEYS_REFETCH_INTERVAL)
+        {
+            return Err(format!("Unknown signing key {}", key_id));
+        }
+    }
+
+    let keys = get_json::<JwkSet>(state, &metadata(state).await?.jwks_uri).await?;
+
+    let mut oidc = state.oidc.lock().await;
+    oidc.keys_fetched_at = Some(Instant::now());
+
+    let key = match keys.find(key_id) {
+        Some(jwk) => DecodingKey::from_jwk(jwk)
+            .map_err(|error| format!("Unusable signing key {}: {}", key_id, error)),
+        None => Err(format!("Unknown signing key {}", key_id)),
+    };
+
+    oidc.keys = Some(keys);
+
+    key
+}
+
+/// The role a user gets from their groups, or `None` if they aren't in any of the configured ones
+fn role_for_groups(claims: &Map<String, Value>) -> Option<Role> {
+    let groups = claims
+        .get(&CONFIG.oidc_groups_claim)
+        .and_then(Value::as_array)
+        .map(|groups| groups.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let is_in_any = |configured_groups: &[String]| {
+        configured_groups
+            .iter()
+            .any(|group| groups.contains(&group.as_str()))
+    };
+
+    if is_in_any(&CONFIG.oidc_commander_groups) {
+        Some(Role::Commander)
+    } else if is_in_any(&CONFIG.oidc_admin_groups) {
+        Some(Role::Admin)
+    } else if is_in_any(&CONFIG.oidc_viewer_groups) {
+        Some(Role::Viewer)
+    } else {
+        None
+    }
+}
+
+/// Validates an ID or access token issued by the provider, returning who it belongs to
+pub async fn authenticate(
+    state: &AppState,
+    token: &str,
+    expected_nonce: Option<&str>,
+) -> Result<AuthedUser, String> {
+    let header = jsonwebtoken::decode_header(token).map_err(|error| error.to_string())?;
+
+    if header.alg != Algorithm::RS256 {
+        return Err(format!("Unsupported algorithm {:?}", header.alg));
+    }
+
+    let Some(key_id) = header.kid else {
+        return Err("Token doesn't say which key signed it".to_owned());
+    };
+
+    let key = decoding_key(state, &key_id).await?;
+    let metadata = metadata(state).await?;
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_issuer(&[&metadata.issuer]);
+    // ID tokens are for the client, whereas access tokens for the API might be for a separate
+    // audience
+    let audiences = [&CONFIG.oidc_client_id, &CONFIG.oidc_audience]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    validation.set_audience(&audiences);
+
+    let claims = jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation)
+        .map_err(|error| error.to_string())?
+        .claims;
+
+    if let Some(expected_nonce) = expected_nonce {
+        if claims.get("nonce").and_then(Value::as_str) != Some(expected_nonce) {
+            return Err("Token has the wrong nonce".to_owned());
+        }
+    }
+
+    let name = ["preferred_username", "email", "sub"]
+        .iter()
+        .find_map(|claim| claims.get(*claim).and_then(Value::as_str))
+        .unwrap_or("unknown")
+        .to_owned();
+
+    let Some(role) = role_for_groups(&claims) else {
+        return Err(format!("{} isn't in any of the OIDC groups", name));
+    };
+
+    Ok(AuthedUser {
+        id: auth::user_id(&name),
+        name,
+        role,
+        telemetry_only: false,
+    })
+}
+
+/// /auth/oidc/login
+pub async fn start_login(State(state): State<AppState>) -> Response {
+    let (Some(client_id), Some(redirect_url)) = (&CONFIG.oidc_client_id, &CONFIG.oidc_redirect_url)
+    else {
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::NOT_FOUND,
+            "OIDC isn't enabled".to_owned(),
+        )
+        .into_response();
+    };
+
+    let metadata = match metadata(&state).await {
+        Ok(metadata) => metadata,
+        Err(error_message) => {
+            return FallibleJsonResponse::<()>::Err(StatusCode::BAD_GATEWAY, error_message)
+                .log()
+                .into_response();
+        }
+    };
+
+    let login_state = hex::encode(rand::random::<[u8; 16]>());
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let now = unix_time_seconds();
+
+    {
+        let mut oidc = state.oidc.lock().await;
+
+        // forget about logins which were never finished
+        oidc.pending_logins
+            .retain(|_, login| login.expires_at > now);
+
+        oidc.pending_logins.insert(
+            login_state.clone(),
+            PendingLogin {
+                nonce: nonce.clone(),
+                expires_at: now + LOGIN_TTL_SECONDS,
+            },
+        );
+    }
+
+    let authorization_url = match reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        [
+            ("response_type", "code"),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_url),
+            ("scope", "openid profile email"),
+            ("state", &login_state),
+            ("nonce", &nonce),
+        ],
+    ) {
+        Ok(authorization_url) => authorization_url,
+        Err(error) => {
+            return FallibleJsonResponse::<()>::Err(
+                StatusCode::BAD_GATEWAY,
+                format!("Invalid authorization endpoint: {}", error),
+            )
+            .log()
+            .into_response();
+        }
+    };
+
+    Redirect::to(authorization_url.as_str()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    state: String,
+    code: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Swaps the code from the provider for an ID token and checks it
+async fn finish_login(
+    state: &AppState,
+    query: CallbackQuery,
+    nonce: &str,
+) -> Result<AuthedUser, String> {
+    if let Some(error) = query.error {
+        return Err(format!(
+            "Provider refused the login: {} {}",
+            error,
+            query.error_description.unwrap_or_default()
+        ));
+    }
+
+    let Some(code) = query.code else {
+        return Err("Provider didn't send a code".to_owned());
+    };
+
+    let metadata = metadata(state).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+    ];
+
+    for (name, value) in [
+        ("redirect_uri", &CONFIG.oidc_redirect_url),
+        ("client_id", &CONFIG.oidc_client_id),
+        ("client_secret", &CONFIG.oidc_client_secret),
+    ] {
+        if let Some(value) = value {
+            form.push((name, value.as_str()));
+        }
+    }
+
+    let token_response = state
+        .http_client
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|error| format!("Failed to redeem code: {}", error))?
+        .json::<TokenResponse>()
+        .await
+        .map_err(|error| format!("Failed to parse token response: {}", error))?;
+
+    authenticate(state, &token_response.id_token, Some(nonce)).await
+}
+
+/// /auth/oidc/callback
+pub async fn callback(
+    State(state): State<AppState>,
+    Query(query): Query<CallbackQuery>,
+) -> Response {
+    let pending_login = state
+        .oidc
+        .lock()
+        .await
+        .pending_logins
+        .remove(&query.state)
+        .filter(|login| login.expires_at > unix_time_seconds());
+
+    let Some(pending_login) = pending_login else {
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::BAD_REQUEST,
+            "Unknown or expired login, try logging in again".to_owned(),
+        )
+        .into_response();
+    };
+
+    let user = match finish_login(&state, query, &pending_login.nonce).await {
+        Ok(user) => user,
+        Err(error_message) => {
+            warn!("Failed OIDC login: {}", error_message);
+
+            return FallibleJsonResponse::<()>::Err(StatusCode::UNAUTHORIZED, error_message)
+                .into_response();
+        }
+    };
+
+    info!("{} logged in with OIDC", user);
+
+    let session = match auth::start_session(&state, user, false).await {
+        FallibleJsonResponse::Ok(session) => session,
+        error_response => return error_response.into_response(),
+    };
+
+    match &CONFIG.oidc_dashboard_url {
+        // the fragment isn't sent to the dashboard's server or included in Referer headers
+        Some(dashboard_url) => Redirect::to(&format!(
+            "{}#access_token={}&expires_at={}&refresh_token={}&role={}",
+            dashboard_url,
+            session.access_token,
+            session.expires_at,
+            session.refresh_token,
+            session.role.name()
+        ))
+        .into_response(),
+        None => Json(session).into_response(),
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use log::{debug, info};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::{
+    auth::AuthedUser,
+    command_history::{self, CommandOutcome},
+    config::CONFIG,
+    pathfinding::NodeId,
+    presence::PresenceState,
+    proto::meshtastic::{crisislab_message::Message, CrisislabMessage},
+    utils::{send_command_protobuf, unix_time_seconds, StringOrEmptyResponse},
+    AppState,
+};
+
+pub type QueuedCommandId = u64;
+
+/// A command for a node which was offline when it was sent, waiting to be published once the node
+/// is heard from again
+#[derive(Clone, Debug)]
+pub struct QueuedCommand {
+    pub id: QueuedCommandId,
+    pub node_id: NodeId,
+    pub crisislab_message: CrisislabMessage,
+    pub queued_by: String,
+    /// seconds since unix epoch
+    pub queued_at: u64,
+    pub expires_at: u64,
+}
+
+/// Commands queued for offline nodes, oldest first. They're only kept in memory, so they're lost
+/// if the server restarts.
+#[derive(Default)]
+pub struct Outbox {
+    queued: BTreeMap<NodeId, Vec<QueuedCommand>>,
+    next_id: QueuedCommandId,
+}
+
+impl Outbox {
+    fn queue(
+        &mut self,
+        node_id: NodeId,
+        crisislab_message: CrisislabMessage,
+        queued_by: &str,
+        now: u64,
+    ) -> Result<QueuedCommandId, String> {
+        let commands = self.queued.entry(node_id).or_default();
+
+        if commands.len() >= CONFIG.outbox_max_commands_per_node {
+            return Err(format!(
+                "Node {} is offline and already has {} commands queued",
+                node_id,
+                commands.len()
+            ));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        commands.push(QueuedCommand {
+            id,
+            node_id,
+            crisislab_message,
+            queued_by: queued_by.to_owned(),
+            queued_at: now,
+            expires_at: now.saturating_add(CONFIG.outbox_ttl_seconds),
+        });
+
+        Ok(id)
+    }
+
+    fn take(&mut self, node_id: NodeId) -> Vec<QueuedCommand> {
+        self.queued.remove(&node_id).unwrap_or_default()
+    }
+
+    fn take_expired(&mut self, now: u64) -> Vec<QueuedCommand> {
+        let mut expired = Vec::new();
+
+        for commands in self.queued.values_mut() {
+            let (kept, removed) = commands
+                .drain(..)
+                .partition(|command| command.expires_at > now);
+
+            *commands = kept;
+            expired.extend(removed);
+        }
+
+        self.queued.retain(|_, commands| !commands.is_empty());
+
+        expired
+    }
+
+    fn remove(&mut self, id: QueuedCommandId) -> Option<QueuedCommand> {
+        let (node_id, index) = self.queued.iter().find_map(|(node_id, commands)| {
+            let index = commands.iter().position(|command| command.id == id)?;
+            Some((*node_id, index))
+        })?;
+
+        let commands = self.queued.get_mut(&node_id)?;
+        let command = commands.remove(index);
+
+        if commands.is_empty() {
+            self.queued.remove(&node_id);
+        }
+
+        Some(command)
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.queued.remove(&node_id);
+    }
+}
+
+/// Whether commands for the node should be queued rather than published. Nodes which have never
+/// been heard from aren't counted as offline.
+pub async fn is_offline(state: &AppState, node_id: NodeId) -> bool {
+    state
+        .presence
+        .lock()
+        .await
+        .nodes()
+        .get(&node_id)
+        .is_some_and(|presence| presence.state == PresenceState::Offline)
+}
+
+/// Queues a command for an offline node and records it, unless the node already has
+/// `OUTBOX_MAX_COMMANDS_PER_NODE` commands queued
+pub async fn queue(
+    state: &AppState,
+    node_id: NodeId,
+    crisislab_message: CrisislabMessage,
+    queued_by: &str,
+) -> Result<(), String> {
+    let now = unix_time_seconds();
+
+    let result =
+        state
+            .outbox
+            .lock()
+            .await
+            .queue(node_id, crisislab_message.clone(), queued_by, now);
+
+    let id = match result {
+        Ok(id) => id,
+        Err(error_message) => {
+            command_history::record(
+                state,
+                node_id,
+                &crisislab_message,
+                queued_by,
+                now,
+                CommandOutcome::Failed,
+                Some(error_message.clone()),
+            )
+            .await;
+
+            return Err(error_message);
+        }
+    };
+
+    info!(
+        "Node {} is offline, so command {} has been queued until it's heard from",
+        node_id, id
+    );
+
+    command_history::record(
+        state,
+        node_id,
+        &crisislab_message,
+        queued_by,
+        now,
+        CommandOutcome::Queued,
+        None,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Publishes every command queued for a node which has just come back online
+pub async fn flush(state: &AppState, node_id: NodeId) {
+    let commands = state.outbox.lock().await.take(node_id);
+
+    if commands.is_empty() {
+        return;
+    }
+
+    info!(
+        "Node {} is back online, publishing {} queued commands",
+        node_id,
+        commands.len()
+    );
+
+    for command in commands {
+        let now = unix_time_seconds();
+
+        if command.expires_at <= now {
+            record_expired(state, &command).await;
+            continue;
+        }
+
+        let result =
+            send_command_protobuf(command.crisislab_message.clone(), &state.mesh_interface).await;
+
+        let (outcome, error) = match result {
+            Ok(()) => {
+                if let Some(Message::TextMessage(text_message)) = &command.crisislab_message.message
+                {
+                    state
+                        .messages
+                        .lock()
+                        .await
+                        .mark_published(text_message.id, now);
+                }
+
+                (CommandOutcome::Sent, None)
+            }
+            Err(error_message) => {
+                // it isn't requeued, so it'd otherwise be shown as queued forever
+                drop_message(state, &command).await;
+
+                (CommandOutcome::Failed, Some(error_message))
+            }
+        };
+
+        command_history::record(
+            state,
+            node_id,
+            &command.crisislab_message,
+            &command.queued_by,
+            now,
+            outcome,
+            error,
+        )
+        .await;
+    }
+}
+
+/// Lets the message store know that a queued text message is never going to be published, so that
+/// it can be counted as failed
+async fn drop_message(state: &AppState, command: &QueuedCommand) {
+    if let Some(Message::TextMessage(text_message)) = &command.crisislab_message.message {
+        state.messages.lock().await.mark_dropped(text_message.id);
+    }
+}
+
+async fn record_expired(state: &AppState, command: &QueuedCommand) {
+    info!(
+        "Queued command {} for node {} expired before the node was heard from",
+        command.id, command.node_id
+    );
+
+    drop_message(state, command).await;
+
+    command_history::record(
+        state,
+        command.node_id,
+        &command.crisislab_message,
+        &command.queued_by,
+        command.queued_at,
+        CommandOutcome::Expired,
+        None,
+    )
+    .await;
+}
+
+/// Spawns the task which periodically drops queued commands which have expired
+pub fn expiry_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting outbox expiry task");
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            let expired = state.outbox.lock().await.take_expired(unix_time_seconds());
+
+            for command in &expired {
+                record_expired(&state, command).await;
+            }
+        }
+    })
+}
+
+#[derive(Serialize)]
+pub struct QueuedCommandInfo {
+    id: QueuedCommandId,
+    node_id: NodeId,
+    /// the CrisislabMessage's `message`, as in the command history
+    command: Option<&'static str>,
+    details: Option<serde_json::Value>,
+    queued_by: String,
+    queued_at: u64,
+    expires_at: u64,
+}
+
+/// /outbox
+pub async fn get_outbox(State(state): State<AppState>) -> Json<Vec<QueuedCommandInfo>> {
+    Json(
+        state
+            .outbox
+            .lock()
+            .await
+            .queued
+            .values()
+            .flatten()
+            .map(|command| {
+                let message = command.crisislab_message.message.as_ref();
+
+                QueuedCommandInfo {
+                    id: command.id,
+                    node_id: command.node_id,
+                    command: message.map(command_history::command_name),
+                    details: message.and_then(command_history::command_details),
+                    queued_by: command.queued_by.clone(),
+                    queued_at: command.queued_at,
+                    expires_at: command.expires_at,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// /admin/outbox/{id}
+pub async fn cancel_queued_command(
+    State(state): State<AppState>,
+    Path(id): Path<QueuedCommandId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    let Some(command) = state.outbox.lock().await.remove(id) else {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("Command {} isn't queued", id),
+        );
+    };
+
+    info!(
+        "{} cancelled queued command {} for node {}",
+        user, id, command.node_id
+    );
+
+    drop_message(&state, &command).await;
+
+    command_history::record(
+        &state,
+        command.node_id,
+        &command.crisislab_message,
+        &command.queued_by,
+        command.queued_at,
+        CommandOutcome::Cancelled,
+        Some(format!("Cancelled by {}", user.name)),
+    )
+    .await;
+
+    StringOrEmptyResponse::Ok
+}
@@ -81,10 +81,13 @@ pub struct DijkstraEntry<V: Clone> {
 
 type DijkstraResult<V> = HashMap<V, DijkstraEntry<V>>;
 
+/// `relay_penalties` are added to the weight of every edge out of a node, for nodes which should
+/// only relay for others when there isn't a much better route
 pub async fn dijkstra<V>(
     app_settings: Arc<Mutex<AppSettings>>,
     adjacency_map: &AdjacencyMap<V>,
     gateway_ids: &Vec<V>,
+    relay_penalties: &HashMap<V, EdgeWeight>,
     start: &V,
 ) -> DijkstraResult<V>
 where
@@ -143,11 +146,20 @@ where
 
         let current_entry = result.get(current).unwrap().clone();
 
+        // the start is the gateway, which isn't relaying for anyone
+        let relay_penalty = if current == start {
+            0.0 as EdgeWeight
+        } else {
+            relay_penalties.get(current).copied().unwrap_or(0.0)
+        };
+
         for (neighbour, weight) in adjacency_map.get(current).unwrap() {
             if !unvisited.contains(neighbour) {
                 continue;
             }
 
+            let weight = weight + relay_penalty;
+
             let old_cost = result.get(neighbour).unwrap().total_cost;
 
             let new_cost = get_route_cost(
@@ -188,6 +200,7 @@ pub async fn compute_next_hops_map<V>(
     app_settings: Arc<Mutex<AppSettings>>,
     adjacency_map: AdjacencyMap<V>,
     gateway_ids: Vec<V>,
+    relay_penalties: HashMap<V, EdgeWeight>,
 ) -> HashMap<V, Vec<V>>
 where
     V: Hash + Eq + Ord + Clone + Display + Debug,
@@ -208,6 +221,7 @@ where
             app_settings.clone(),
             &adjacency_map,
             &gateway_ids,
+            &relay_penalties,
             gateway_id,
         )
         .await;
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    auth::AuthedUser,
+    command_history::{self, CommandOutcome},
+    config::CONFIG,
+    emergency_alerts::{self, EmergencyAlertId},
+    events::ServerEvent,
+    proto::meshtastic::CrisislabMessage,
+    utils::{
+        send_command_protobuf, unix_time_seconds, FallibleJsonResponse, StringOrEmptyResponse,
+    },
+    AppState,
+};
+
+pub type PendingActionId = u32;
+
+/// A high-impact command which is waiting for a second admin to approve it before it's published
+#[derive(Clone, Serialize, Debug)]
+pub struct PendingAction {
+    pub id: PendingActionId,
+    /// e.g. "Change the channel to crisislab-2"
+    pub description: String,
+    /// what will be published to the mesh once it's approved, more than one if it's being sent to
+    /// each of a group of nodes
+    pub commands: Vec<CrisislabMessage>,
+    /// the emergency alert which will be cancelled once it's approved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancels_emergency_alert: Option<EmergencyAlertId>,
+    pub requested_by: String,
+    /// `AuthedUser::id` of whoever requested it, which can't be the one to approve it
+    #[serde(skip)]
+    requested_by_id: String,
+    /// seconds since unix epoch
+    pub requested_at: u64,
+    pub expires_at: u64,
+    /// sent to websocket clients once the command has been published
+    #[serde(skip)]
+    on_sent: Option<ServerEvent>,
+}
+
+/// Commands waiting for approval while `DUAL_CONTROL` is on. They're only kept in memory, so
+/// they're forgotten if the server restarts.
+#[derive(Default)]
+pub struct PendingActionStore {
+    actions: HashMap<PendingActionId, PendingAction>,
+    next_id: PendingActionId,
+}
+
+impl PendingActionStore {
+    fn add(
+        &mut self,
+        description: String,
+        commands: Vec<CrisislabMessage>,
+        cancels_emergency_alert: Option<EmergencyAlertId>,
+        requested_by: &AuthedUser,
+        on_sent: Option<ServerEvent>,
+        now: u64,
+    ) -> PendingAction {
+        // forget about actions nobody approved in time
+        self.actions.retain(|_, action| action.expires_at > now);
+
+        let action = PendingAction {
+            id: self.next_id,
+            description,
+            commands,
+            cancels_emergency_alert,
+            requested_by: requested_by.name.clone(),
+            requested_by_id: requested_by.id.clone(),
+            requested_at: now,
+            expires_at: now.saturating_add(CONFIG.pending_action_ttl_seconds),
+            on_sent,
+        };
+
+        self.next_id += 1;
+        self.actions.insert(action.id, action.clone());
+
+        action
+    }
+
+    fn take(&mut self, id: PendingActionId, now: u64) -> Option<PendingAction> {
+        self.actions
+            .remove(&id)
+            .filter(|action| action.expires_at > now)
+    }
+}
+
+/// Publishes the commands in order, stopping at the first which fails. The error comes with the
+/// commands which weren't sent, starting with the one which failed.
+async fn send(
+    state: &AppState,
+    commands: Vec<CrisislabMessage>,
+    on_sent: Option<ServerEvent>,
+    sent_by: &str,
+) -> Result<(), (String, Vec<CrisislabMessage>)> {
+    let mut commands = commands.into_iter();
+
+    while let Some(command) = commands.next() {
+        let result = match command.destination {
+            Some(node_id) => {
+                command_history::send_to_node(state, node_id, command.clone(), sent_by)
+                    .await
+                    .map(|_| ())
+            }
+            None => send_command_protobuf(command.clone(), &state.mesh_interface).await,
+        };
+
+        if let Err(error_message) = result {
+            return Err((
+                error_message,
+                std::iter::once(command).chain(commands).collect(),
+            ));
+        }
+    }
+
+    if let Some(event) = on_sent {
+        let _ = state.server_events.send(event);
+    }
+
+    Ok(())
+}
+
+/// Publishes the commands, unless `DUAL_CONTROL` is on and it's high-impact, in which case it's held
+/// until a second admin approves it. Responds with 202 Accepted and the pending action if it's
+/// held.
+pub async fn send_or_hold(
+    state: &AppState,
+    user: &AuthedUser,
+    description: String,
+    commands: Vec<CrisislabMessage>,
+    is_high_impact: bool,
+    on_sent: Option<ServerEvent>,
+) -> Response {
+    if !(CONFIG.dual_control && is_high_impact) {
+        return match send(state, commands, on_sent, &user.name).await {
+            Ok(()) => StringOrEmptyResponse::Ok.into_response(),
+            Err((error_message, _)) => {
+                StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message)
+                    .log()
+                    .into_response()
+            }
+        };
+    }
+
+    hold(state, user, description, commands, None, on_sent).await
+}
+
+/// Holds cancelling an emergency alert until a second admin approves it, responding with 202
+/// Accepted and the pending action
+pub async fn hold_emergency_alert_cancellation(
+    state: &AppState,
+    user: &AuthedUser,
+    id: EmergencyAlertId,
+) -> Response {
+    hold(
+        state,
+        user,
+        format!("Cancel emergency alert {}", id),
+        Vec::new(),
+        Some(id),
+        None,
+    )
+    .await
+}
+
+async fn hold(
+    state: &AppState,
+    user: &AuthedUser,
+    description: String,
+    commands: Vec<CrisislabMessage>,
+    cancels_emergency_alert: Option<EmergencyAlertId>,
+    on_sent: Option<ServerEvent>,
+) -> Response {
+    let action = state.pending_actions.lock().await.add(
+        description,
+        commands,
+        cancels_emergency_alert,
+        user,
+        on_sent,
+        unix_time_seconds(),
+    );
+
+    info!(
+        "{} requested pending action {} ({}), which needs another admin to approve it",
+        user, action.id, action.description
+    );
+
+    for command in &action.commands {
+        if let Some(node_id) = command.destination {
+            command_history::record(
+                state,
+                node_id,
+                command,
+                &user.name,
+                action.requested_at,
+                CommandOutcome::HeldForApproval,
+                None,
+            )
+            .await;
+        }
+    }
+
+    (StatusCode::ACCEPTED, Json(action)).into_response()
+}
+
+/// /admin/pending-actions
+pub async fn get_pending_actions(State(state): State<AppState>) -> Json<Vec<PendingAction>> {
+    let now = unix_time_seconds();
+
+    let mut actions = state
+        .pending_actions
+        .lock()
+        .await
+        .actions
+        .values()
+        .filter(|action| action.expires_at > now)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    actions.sort_by_key(|action| action.id);
+
+    Json(actions)
+}
+
+/// /admin/pending-actions/{id}/approve
+pub async fn approve_pending_action(
+    State(state): State<AppState>,
+    Path(id): Path<PendingActionId>,
+    user: AuthedUser,
+) -> FallibleJsonResponse<PendingAction> {
+    // taken out of the store while it's being sent, so that nobody else can approve it meanwhile
+    // and the store isn't locked while commands are published
+    let action = {
+        let mut pending_actions = state.pending_actions.lock().await;
+
+        let Some(action) = pending_actions.take(id, unix_time_seconds()) else {
+            return FallibleJsonResponse::Err(
+                StatusCode::NOT_FOUND,
+                format!("No pending action with ID {} (it may have expired)", id),
+            );
+        };
+
+        if action.requested_by_id == user.id {
+            pending_actions.actions.insert(action.id, action);
+
+            return FallibleJsonResponse::Err(
+                StatusCode::FORBIDDEN,
+                "Pending actions must be approved by a different admin".to_owned(),
+            );
+        }
+
+        action
+    };
+
+    let sent_by = format!("{} (approved by {})", action.requested_by, user.name);
+
+    if let Some(alert_id) = action.cancels_emergency_alert {
+        // the alert can't be cancelled twice, so it isn't put back if this fails
+        if let Err((status_code, error_message)) =
+            emergency_alerts::cancel(&state, alert_id, &sent_by).await
+        {
+            return FallibleJsonResponse::Err(status_code, error_message).log();
+        }
+
+        info!(
+            "{} approved pending action {} ({}) requested by {}",
+            user, action.id, action.description, action.requested_by
+        );
+
+        return FallibleJsonResponse::Ok(action);
+    }
+
+    if let Err((error_message, unsent_commands)) = send(
+        &state,
+        action.commands.clone(),
+        action.on_sent.clone(),
+        &sent_by,
+    )
+    .await
+    {
+        // put back with just the commands which weren't sent, so that it can be approved again
+        // once the mesh is reachable without sending the rest to their nodes twice
+        state.pending_actions.lock().await.actions.insert(
+            action.id,
+            PendingAction {
+                commands: unsent_commands,
+                ..action
+            },
+        );
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    info!(
+        "{} approved pending action {} ({}) requested by {}",
+        user, action.id, action.description, action.requested_by
+    );
+
+    FallibleJsonResponse::Ok(action)
+}
+
+/// /admin/pending-actions/{id}
+pub async fn cancel_pending_action(
+    State(state): State<AppState>,
+    Path(id): Path<PendingActionId>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    let Some(action) = state
+        .pending_actions
+        .lock()
+        .await
+        .take(id, unix_time_seconds())
+    else {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No pending action with ID {}", id),
+        );
+    };
+
+    info!(
+        "{} cancelled pending action {} ({}) requested by {}",
+        user, action.id, action.description, action.requested_by
+    );
+
+    StringOrEmptyResponse::Ok
+}
@@ -0,0 +1,630 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use bytes::{Buf, BytesMut};
+use log::{error, info};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    alert_history::AlertRecord,
+    api_tokens::StoredApiToken,
+    command_history::CommandRecord,
+    config::CONFIG,
+    delivery_reports::DeliveryReport,
+    drill::{self, DrillMode},
+    eew::{BroadcastEvent, EewDecision, EewMapping},
+    gateways::Gateway,
+    maintenance::MaintenanceEvent,
+    maintenance_windows::MaintenanceWindow,
+    message_templates::MessageTemplate,
+    nodes::NodeInfo,
+    pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::Telemetry,
+    seismic_events::ShakeEvent,
+    utils::{unix_time_seconds, CommandCounter},
+    webhooks::StoredWebhookSource,
+    AppState,
+};
+
+const TELEMETRY_CACHE_FILE_NAME: &str = "telemetry-cache.pb";
+const LAST_SEEN_FILE_NAME: &str = "last-seen.json";
+const API_TOKENS_FILE_NAME: &str = "api-tokens.json";
+const COMMAND_COUNTER_FILE_NAME: &str = "command-counter.json";
+const WEBHOOK_SOURCES_FILE_NAME: &str = "webhook-sources.json";
+const NODE_REGISTRY_FILE_NAME: &str = "nodes.json";
+const GATEWAY_REGISTRY_FILE_NAME: &str = "gateways.json";
+const MAINTENANCE_LOG_FILE_NAME: &str = "maintenance.json";
+const EXPECTED_NODES_FILE_NAME: &str = "expected-nodes.json";
+const COMMAND_HISTORY_FILE_NAME: &str = "command-history.json";
+const MESSAGE_TEMPLATES_FILE_NAME: &str = "message-templates.json";
+const DRILL_MODE_FILE_NAME: &str = "drill-mode.json";
+const EEW_MAPPINGS_FILE_NAME: &str = "eew-mappings.json";
+const EEW_DECISIONS_FILE_NAME: &str = "eew-decisions.json";
+const EEW_BROADCAST_EVENTS_FILE_NAME: &str = "eew-broadcast-events.json";
+const MAINTENANCE_WINDOWS_FILE_NAME: &str = "maintenance-windows.json";
+const ALERT_HISTORY_FILE_NAME: &str = "alert-history.json";
+const SEISMIC_EVENTS_FILE_NAME: &str = "seismic-events.json";
+const DELIVERY_REPORTS_FILE_NAME: &str = "delivery-reports.json";
+
+fn data_path(file_name: &str) -> PathBuf {
+    PathBuf::from(&CONFIG.data_directory).join(file_name)
+}
+
+/// Only readable by the server's own user, for files with secrets in them
+const PRIVATE_FILE_MODE: u32 = 0o600;
+
+/// Writes `contents` to a temporary file which then replaces `file_name` in the data directory, so
+/// that a crash part way through can't leave a truncated file behind. `what` is used in errors.
+/// The file is created with `mode` if it's given, rather than the default from the umask.
+async fn write_atomically(
+    file_name: &str,
+    contents: &[u8],
+    what: &str,
+    mode: Option<u32>,
+) -> Result<(), String> {
+    tokio::fs::create_dir_all(&CONFIG.data_directory)
+        .await
+        .map_err(|error| format!("Failed to create data directory: {:?}", error))?;
+
+    let path = data_path(file_name);
+    let temporary_path = data_path(&format!("{}.tmp", file_name));
+
+    let mut file = tokio::fs::File::create(&temporary_path)
+        .await
+        .map_err(|error| format!("Failed to create {} file: {:?}", what, error))?;
+
+    // set before anything is written, and on the file rather than when opening it so that a
+    // temporary file left behind by a crash gets it too
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(std::fs::Permissions::from_mode(mode))
+            .await
+            .map_err(|error| format!("Failed to restrict {} file: {:?}", what, error))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    file.write_all(contents)
+        .await
+        .map_err(|error| format!("Failed to write {}: {:?}", what, error))?;
+
+    file.sync_all()
+        .await
+        .map_err(|error| format!("Failed to write {}: {:?}", what, error))?;
+
+    tokio::fs::rename(&temporary_path, &path)
+        .await
+        .map_err(|error| format!("Failed to replace {} file: {:?}", what, error))
+}
+
+/// Serialises `value` and writes it with `write_atomically`
+async fn write_json_atomically<T: Serialize + ?Sized>(
+    file_name: &str,
+    value: &T,
+    what: &str,
+) -> Result<(), String> {
+    let json = serde_json::to_vec(value)
+        .map_err(|error| format!("Failed to serialise {}: {:?}", what, error))?;
+
+    write_atomically(file_name, &json, what, None).await
+}
+
+/// `write_json_atomically`, for files which only the server's own user should be able to read
+async fn write_private_json_atomically<T: Serialize + ?Sized>(
+    file_name: &str,
+    value: &T,
+    what: &str,
+) -> Result<(), String> {
+    let json = serde_json::to_vec(value)
+        .map_err(|error| format!("Failed to serialise {}: {:?}", what, error))?;
+
+    write_atomically(file_name, &json, what, Some(PRIVATE_FILE_MODE)).await
+}
+
+/// Reads and parses a file written by `write_json_atomically`, or `None` if there isn't one yet
+async fn read_json<T: DeserializeOwned>(file_name: &str, what: &str) -> Result<Option<T>, String> {
+    let path = data_path(file_name);
+
+    let contents = match tokio::fs::read(&path).await {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(format!(
+                "Failed to read saved {} in {}: {:?}",
+                what,
+                path.display(),
+                error
+            ))
+        }
+    };
+
+    serde_json::from_slice(&contents)
+        .map(Some)
+        .map_err(|error| {
+            format!(
+                "Failed to parse saved {} in {}: {}",
+                what,
+                path.display(),
+                error
+            )
+        })
+}
+
+/// `read_json`, logging and skipping anything which can't be restored
+async fn read_json_or_skip<T: DeserializeOwned>(file_name: &str, what: &str) -> Option<T> {
+    read_json(file_name, what)
+        .await
+        .unwrap_or_else(|error_message| {
+            error!("{}", error_message);
+            None
+        })
+}
+
+/// Writes the telemetry cache (as length-delimited protobufs, oldest first) and each node's last
+/// seen time to the data directory
+pub async fn save(state: &AppState) -> Result<(), String> {
+    let mut buffer = BytesMut::new();
+
+    let entry_count = {
+        // the cache is locked first, as everywhere else, since the archive task is still running
+        let telemetry_cache = state.telemetry_cache.lock().await;
+        let telemetry_archive = state.telemetry_archive.lock().await;
+        let mut entry_count = 0;
+
+        // archived telemetry goes first so that when it's loaded it overflows the cache and ends
+        // up back in the archive
+        for telemetry in telemetry_archive
+            .telemetry()
+            .chain(telemetry_cache.into_iter().cloned())
+        {
+            telemetry
+                .encode_length_delimited(&mut buffer)
+                .map_err(|error| format!("Failed to encode cached telemetry: {:?}", error))?;
+            entry_count += 1;
+        }
+
+        entry_count
+    };
+
+    write_atomically(TELEMETRY_CACHE_FILE_NAME, &buffer, "telemetry cache", None).await?;
+
+    let last_seen = state
+        .presence
+        .lock()
+        .await
+        .nodes()
+        .iter()
+        .map(|(node_id, presence)| (*node_id, presence.last_seen))
+        .collect::<HashMap<NodeId, u64>>();
+
+    write_json_atomically(LAST_SEEN_FILE_NAME, &last_seen, "last seen times").await?;
+
+    info!(
+        "Saved {} cached telemetry packets and {} last seen times to {}",
+        entry_count,
+        last_seen.len(),
+        CONFIG.data_directory
+    );
+
+    Ok(())
+}
+
+// Each of these holds its lock until the file has been replaced, so that two saves of the same
+// file can't write over each other's temporary file or finish out of order.
+
+/// Writes the API tokens (or rather their hashes) to the data directory. Unlike everything else,
+/// this happens whenever they change, so that revoked tokens stay revoked if the server crashes.
+pub async fn save_api_tokens(state: &AppState) -> Result<(), String> {
+    let api_tokens = state.api_tokens.lock().await;
+
+    write_json_atomically(
+        API_TOKENS_FILE_NAME,
+        api_tokens.stored_tokens(),
+        "API tokens",
+    )
+    .await
+}
+
+/// Writes the counter for signed commands to the data directory, which happens before every
+/// signed command is sent
+pub async fn save_command_counter(command_counter: &CommandCounter) -> Result<(), String> {
+    write_json_atomically(
+        COMMAND_COUNTER_FILE_NAME,
+        command_counter,
+        "command counter",
+    )
+    .await
+}
+
+/// Writes the webhook sources and their secrets to the data directory whenever they change
+pub async fn save_webhook_sources(state: &AppState) -> Result<(), String> {
+    let webhook_sources = state.webhook_sources.lock().await;
+
+    write_private_json_atomically(
+        WEBHOOK_SOURCES_FILE_NAME,
+        &webhook_sources.stored_sources(),
+        "webhook sources",
+    )
+    .await
+}
+
+/// Writes the node registry to the data directory whenever it changes
+pub async fn save_node_registry(state: &AppState) -> Result<(), String> {
+    let node_registry = state.node_registry.lock().await;
+
+    // pretty so that it can be edited by hand while the server isn't running
+    let node_registry_json = serde_json::to_vec_pretty(node_registry.nodes())
+        .map_err(|error| format!("Failed to serialise node registry: {:?}", error))?;
+
+    write_atomically(
+        NODE_REGISTRY_FILE_NAME,
+        &node_registry_json,
+        "node registry",
+        None,
+    )
+    .await
+}
+
+/// Writes the registered gateways to the data directory whenever they change
+pub async fn save_gateway_registry(state: &AppState) -> Result<(), String> {
+    let gateway_registry = state.gateway_registry.lock().await;
+
+    write_json_atomically(
+        GATEWAY_REGISTRY_FILE_NAME,
+        &gateway_registry.gateways(),
+        "gateway registry",
+    )
+    .await
+}
+
+/// Writes every node's maintenance events to the data directory whenever one is logged
+pub async fn save_maintenance_log(state: &AppState) -> Result<(), String> {
+    let maintenance_log = state.maintenance_log.lock().await;
+
+    write_json_atomically(
+        MAINTENANCE_LOG_FILE_NAME,
+        &maintenance_log.events().collect::<Vec<_>>(),
+        "maintenance log",
+    )
+    .await
+}
+
+/// Writes the message templates to the data directory whenever they change
+pub async fn save_message_templates(state: &AppState) -> Result<(), String> {
+    let message_templates = state.message_templates.lock().await;
+
+    write_json_atomically(
+        MESSAGE_TEMPLATES_FILE_NAME,
+        &message_templates.templates().collect::<Vec<_>>(),
+        "message templates",
+    )
+    .await
+}
+
+/// Writes the commands sent to each node to the data directory whenever one is recorded
+pub async fn save_command_history(state: &AppState) -> Result<(), String> {
+    let command_history = state.command_history.lock().await;
+
+    write_json_atomically(
+        COMMAND_HISTORY_FILE_NAME,
+        &command_history.records().collect::<Vec<_>>(),
+        "command history",
+    )
+    .await
+}
+
+/// Writes the expected nodes to the data directory whenever they change, as `null` if they've been
+/// cleared
+pub async fn save_expected_nodes(state: &AppState) -> Result<(), String> {
+    let expected_nodes = state.expected_nodes.lock().await;
+
+    write_json_atomically(
+        EXPECTED_NODES_FILE_NAME,
+        &expected_nodes.node_ids(),
+        "expected nodes",
+    )
+    .await
+}
+
+pub async fn save_eew_mappings(state: &AppState) -> Result<(), String> {
+    let eew = state.eew.lock().await;
+
+    write_json_atomically(
+        EEW_MAPPINGS_FILE_NAME,
+        &eew.mappings().collect::<Vec<_>>(),
+        "EEW mappings",
+    )
+    .await
+}
+
+pub async fn save_eew_decisions(state: &AppState) -> Result<(), String> {
+    let eew = state.eew.lock().await;
+
+    write_json_atomically(
+        EEW_DECISIONS_FILE_NAME,
+        &eew.decisions().collect::<Vec<_>>(),
+        "EEW decisions",
+    )
+    .await
+}
+
+pub async fn save_eew_broadcast_events(state: &AppState) -> Result<(), String> {
+    let eew = state.eew.lock().await;
+
+    write_json_atomically(
+        EEW_BROADCAST_EVENTS_FILE_NAME,
+        &eew.broadcast_events().collect::<Vec<_>>(),
+        "EEW broadcast events",
+    )
+    .await
+}
+
+pub async fn save_maintenance_windows(state: &AppState) -> Result<(), String> {
+    let maintenance_windows = state.maintenance_windows.lock().await;
+
+    write_json_atomically(
+        MAINTENANCE_WINDOWS_FILE_NAME,
+        &maintenance_windows.windows().collect::<Vec<_>>(),
+        "maintenance windows",
+    )
+    .await
+}
+
+pub async fn save_alert_history(state: &AppState) -> Result<(), String> {
+    let alert_history = state.alert_history.lock().await;
+
+    write_json_atomically(
+        ALERT_HISTORY_FILE_NAME,
+        &alert_history.records().collect::<Vec<_>>(),
+        "alert history",
+    )
+    .await
+}
+
+pub async fn save_delivery_reports(state: &AppState) -> Result<(), String> {
+    let delivery_reports = state.delivery_reports.lock().await;
+
+    write_json_atomically(
+        DELIVERY_REPORTS_FILE_NAME,
+        &delivery_reports.reports().collect::<Vec<_>>(),
+        "delivery reports",
+    )
+    .await
+}
+
+pub async fn save_seismic_events(state: &AppState) -> Result<(), String> {
+    let seismic_events = state.seismic_events.lock().await;
+
+    write_json_atomically(
+        SEISMIC_EVENTS_FILE_NAME,
+        &seismic_events.ended().collect::<Vec<_>>(),
+        "seismic events",
+    )
+    .await
+}
+
+pub async fn save_drill_mode(state: &AppState) -> Result<(), String> {
+    let drill_mode = state.drill_mode.lock().await;
+
+    write_json_atomically(DRILL_MODE_FILE_NAME, &*drill_mode, "drill mode").await
+}
+
+/// Restores whatever was written by `save` and the other `save_*` functions. Missing files aren't
+/// an error since there won't be any the first time the server runs. Anything else which can't be
+/// restored is logged and skipped, apart from the command counter, since starting it again from 0
+/// would get every signed command rejected by the gateways.
+pub async fn load(state: &AppState) -> Result<(), String> {
+    match tokio::fs::read(data_path(TELEMETRY_CACHE_FILE_NAME)).await {
+        Ok(contents) => {
+            let mut buffer = contents.as_slice();
+            let mut telemetry_cache = state.telemetry_cache.lock().await;
+            let mut telemetry_archive = state.telemetry_archive.lock().await;
+            let mut entry_count = 0;
+
+            while buffer.has_remaining() {
+                match Telemetry::decode_length_delimited(&mut buffer) {
+                    Ok(telemetry) => {
+                        if let Some(evicted) = telemetry_cache.write(telemetry) {
+                            telemetry_archive.record(&evicted);
+                        }
+                        entry_count += 1;
+                    }
+                    Err(error) => {
+                        error!(
+                            "Stopped restoring telemetry cache, failed to decode: {:?}",
+                            error
+                        );
+                        break;
+                    }
+                }
+            }
+
+            info!("Restored {} cached telemetry packets", entry_count);
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => error!("Failed to read saved telemetry cache: {:?}", error),
+    }
+
+    if let Some(last_seen) =
+        read_json_or_skip::<HashMap<NodeId, u64>>(LAST_SEEN_FILE_NAME, "last seen times").await
+    {
+        info!("Restored last seen times for {} nodes", last_seen.len());
+
+        state.presence.lock().await.restore(
+            last_seen,
+            unix_time_seconds(),
+            CONFIG.node_offline_after_seconds,
+        );
+    }
+
+    if let Some(api_tokens) =
+        read_json_or_skip::<Vec<StoredApiToken>>(API_TOKENS_FILE_NAME, "API tokens").await
+    {
+        info!("Restored {} API tokens", api_tokens.len());
+
+        state.api_tokens.lock().await.restore(api_tokens);
+    }
+
+    if let Some(command_counter) =
+        read_json::<CommandCounter>(COMMAND_COUNTER_FILE_NAME, "command counter")
+            .await
+            .map_err(|error_message| {
+                format!(
+                    "{}. Fix it, or replace it with {{\"last_counter\": <a counter higher than \
+                     any sent before>}}",
+                    error_message
+                )
+            })?
+    {
+        info!("Restored command counter {}", command_counter.last_counter);
+
+        *state.mesh_interface.command_counter().lock().await = command_counter;
+    }
+
+    if let Some(webhook_sources) =
+        read_json_or_skip::<Vec<StoredWebhookSource>>(WEBHOOK_SOURCES_FILE_NAME, "webhook sources")
+            .await
+    {
+        info!("Restored {} webhook sources", webhook_sources.len());
+
+        state.webhook_sources.lock().await.restore(webhook_sources);
+    }
+
+    if let Some(nodes) =
+        read_json_or_skip::<BTreeMap<NodeId, NodeInfo>>(NODE_REGISTRY_FILE_NAME, "node registry")
+            .await
+    {
+        info!("Restored {} nodes in the registry", nodes.len());
+
+        state.node_registry.lock().await.restore(nodes);
+    }
+
+    if let Some(gateways) =
+        read_json_or_skip::<Vec<Gateway>>(GATEWAY_REGISTRY_FILE_NAME, "gateway registry").await
+    {
+        info!("Restored {} registered gateways", gateways.len());
+
+        state.gateway_registry.lock().await.restore(gateways);
+    }
+
+    if let Some(events) =
+        read_json_or_skip::<Vec<MaintenanceEvent>>(MAINTENANCE_LOG_FILE_NAME, "maintenance log")
+            .await
+    {
+        info!("Restored {} maintenance events", events.len());
+
+        state.maintenance_log.lock().await.restore(events);
+    }
+
+    if let Some(records) =
+        read_json_or_skip::<Vec<CommandRecord>>(COMMAND_HISTORY_FILE_NAME, "command history").await
+    {
+        info!("Restored {} command history records", records.len());
+
+        state.command_history.lock().await.restore(records);
+    }
+
+    if let Some(templates) =
+        read_json_or_skip::<Vec<MessageTemplate>>(MESSAGE_TEMPLATES_FILE_NAME, "message templates")
+            .await
+    {
+        info!("Restored {} message templates", templates.len());
+
+        state.message_templates.lock().await.restore(templates);
+    }
+
+    let eew_mappings =
+        match read_json_or_skip::<Vec<EewMapping>>(EEW_MAPPINGS_FILE_NAME, "EEW mappings").await {
+            Some(mappings) => {
+                info!("Restored {} EEW mappings", mappings.len());
+                mappings
+            }
+            None => Vec::new(),
+        };
+
+    let eew_decisions =
+        read_json_or_skip::<Vec<EewDecision>>(EEW_DECISIONS_FILE_NAME, "EEW decisions")
+            .await
+            .unwrap_or_default();
+
+    let eew_broadcast_events = read_json_or_skip::<Vec<BroadcastEvent>>(
+        EEW_BROADCAST_EVENTS_FILE_NAME,
+        "EEW broadcast events",
+    )
+    .await
+    .unwrap_or_default();
+
+    state
+        .eew
+        .lock()
+        .await
+        .restore(eew_mappings, eew_decisions, eew_broadcast_events);
+
+    if let Some(windows) = read_json_or_skip::<Vec<MaintenanceWindow>>(
+        MAINTENANCE_WINDOWS_FILE_NAME,
+        "maintenance windows",
+    )
+    .await
+    {
+        info!("Restored {} maintenance windows", windows.len());
+
+        state.maintenance_windows.lock().await.restore(windows);
+    }
+
+    if let Some(records) =
+        read_json_or_skip::<Vec<AlertRecord>>(ALERT_HISTORY_FILE_NAME, "alert history").await
+    {
+        info!("Restored {} alerts from the alert history", records.len());
+
+        state.alert_history.lock().await.restore(records);
+    }
+
+    if let Some(reports) =
+        read_json_or_skip::<Vec<DeliveryReport>>(DELIVERY_REPORTS_FILE_NAME, "delivery reports")
+            .await
+    {
+        info!("Restored {} delivery reports", reports.len());
+
+        state.delivery_reports.lock().await.restore(reports);
+    }
+
+    if let Some(events) =
+        read_json_or_skip::<Vec<ShakeEvent>>(SEISMIC_EVENTS_FILE_NAME, "seismic events").await
+    {
+        info!("Restored {} seismic events", events.len());
+
+        state.seismic_events.lock().await.restore(events);
+    }
+
+    if let Some(drill_mode) =
+        read_json_or_skip::<Option<DrillMode>>(DRILL_MODE_FILE_NAME, "drill mode").await
+    {
+        if let Some(drill_mode) = &drill_mode {
+            info!(
+                "Drill mode is still on, since {} turned it on",
+                drill_mode.enabled_by
+            );
+        }
+
+        drill::set(state, drill_mode).await;
+    }
+
+    if let Some(node_ids) =
+        read_json_or_skip::<Option<BTreeSet<NodeId>>>(EXPECTED_NODES_FILE_NAME, "expected nodes")
+            .await
+    {
+        if let Some(node_ids) = &node_ids {
+            info!("Restored {} expected nodes", node_ids.len());
+        }
+
+        state.expected_nodes.lock().await.restore(node_ids);
+    }
+
+    Ok(())
+}
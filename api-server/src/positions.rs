@@ -0,0 +1,205 @@
+use std::collections::{HashMap, VecDeque};
+
+use axum::{
+    extract::{Query, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    config::CONFIG, pathfinding::NodeId, presence::PresenceState,
+    proto::meshtastic::crisislab_message::Telemetry, utils::FallibleJsonResponse, AppState,
+};
+
+/// Meshtastic positions are integers in units of 1e-7 degrees
+const DEGREES_PER_UNIT: f64 = 1e-7;
+
+#[derive(Clone, Copy, Serialize, Debug)]
+pub struct NodePosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// metres above sea level
+    pub altitude: Option<i32>,
+    /// seconds since unix epoch, from the telemetry the position came from
+    pub timestamp: u64,
+}
+
+impl NodePosition {
+    /// Extracts the position from a telemetry packet if it has a usable one. Nodes without a GPS
+    /// fix report 0, 0, so that's treated as no position.
+    pub fn from_telemetry(telemetry: &Telemetry) -> Option<Self> {
+        let position = telemetry.position.as_ref()?;
+        let latitude_i = position.latitude_i?;
+        let longitude_i = position.longitude_i?;
+
+        if latitude_i == 0 && longitude_i == 0 {
+            return None;
+        }
+
+        Some(NodePosition {
+            latitude: latitude_i as f64 * DEGREES_PER_UNIT,
+            longitude: longitude_i as f64 * DEGREES_PER_UNIT,
+            altitude: position.altitude,
+            timestamp: telemetry.timestamp,
+        })
+    }
+}
+
+/// The most recent known position of each node, plus a bounded history of where it's been
+#[derive(Default)]
+pub struct PositionStore {
+    latest: HashMap<NodeId, NodePosition>,
+    history: HashMap<NodeId, VecDeque<NodePosition>>,
+}
+
+impl PositionStore {
+    pub fn latest(&self) -> &HashMap<NodeId, NodePosition> {
+        &self.latest
+    }
+
+    pub fn history(&self, node_id: NodeId) -> Option<&VecDeque<NodePosition>> {
+        self.history.get(&node_id)
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.latest.remove(&node_id);
+        self.history.remove(&node_id);
+    }
+
+    pub fn record(&mut self, telemetry: &Telemetry) {
+        let Some(position) = NodePosition::from_telemetry(telemetry) else {
+            return;
+        };
+
+        self.latest.insert(telemetry.node_num, position);
+
+        let history = self.history.entry(telemetry.node_num).or_default();
+
+        // fixed nodes report the same position over and over, which would push anything
+        // interesting out of the history
+        let has_moved = history.back().is_none_or(|previous| {
+            previous.latitude != position.latitude
+                || previous.longitude != position.longitude
+                || previous.altitude != position.altitude
+        });
+
+        if has_moved {
+            if history.len() >= CONFIG.position_history_capacity {
+                history.pop_front();
+            }
+
+            history.push_back(position);
+        }
+    }
+}
+
+/// Where each node is, from its GPS or otherwise from the position operators set in the registry
+pub async fn known_positions(state: &AppState) -> HashMap<NodeId, NodePosition> {
+    let mut known_positions = state.positions.lock().await.latest().clone();
+
+    for (node_id, info) in state.node_registry.lock().await.nodes() {
+        if let Some(position) = info.position {
+            known_positions.entry(*node_id).or_insert(NodePosition {
+                latitude: position.latitude,
+                longitude: position.longitude,
+                altitude: position.altitude,
+                timestamp: position.set_at,
+            });
+        }
+    }
+
+    known_positions
+}
+
+/// /info/positions.geojson
+pub async fn get_positions_geojson(State(state): State<AppState>) -> Response {
+    let positions = state.positions.lock().await;
+    let presence = state.presence.lock().await;
+    let topology = state.topology.lock().await;
+    let node_registry = state.node_registry.lock().await;
+
+    // nodes without GPS are shown where operators said they are
+    let fixed_positions = node_registry
+        .nodes()
+        .iter()
+        .filter(|(node_id, _)| !positions.latest().contains_key(node_id))
+        .filter_map(|(node_id, info)| {
+            let position = info.position?;
+
+            Some((
+                node_id,
+                NodePosition {
+                    latitude: position.latitude,
+                    longitude: position.longitude,
+                    altitude: position.altitude,
+                    timestamp: position.set_at,
+                },
+                true,
+            ))
+        });
+
+    let features = positions
+        .latest()
+        .iter()
+        .map(|(node_id, position)| (node_id, *position, false))
+        .chain(fixed_positions)
+        .map(|(node_id, position, is_fixed)| {
+            // GeoJSON coordinates are longitude first
+            let mut coordinates = vec![json!(position.longitude), json!(position.latitude)];
+
+            if let Some(altitude) = position.altitude {
+                coordinates.push(json!(altitude));
+            }
+
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": coordinates,
+                },
+                "properties": {
+                    "id": node_id,
+                    "is_gateway": topology.gateway_ids.contains(node_id),
+                    "online": presence
+                        .nodes()
+                        .get(node_id)
+                        .is_some_and(|presence| presence.state == PresenceState::Online),
+                    "timestamp": position.timestamp,
+                    "fixed": is_fixed,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    (
+        [(CONTENT_TYPE, "application/geo+json")],
+        json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+        .to_string(),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PositionHistoryQuery {
+    node_id: NodeId,
+}
+
+/// /info/positions/history
+pub async fn get_position_history(
+    State(state): State<AppState>,
+    Query(query): Query<PositionHistoryQuery>,
+) -> FallibleJsonResponse<VecDeque<NodePosition>> {
+    match state.positions.lock().await.history(query.node_id) {
+        Some(history) => FallibleJsonResponse::Ok(history.clone()),
+        None => FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No position history for node {}", query.node_id),
+        ),
+    }
+}
@@ -0,0 +1,182 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{extract::State, Json};
+use log::{debug, info};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::CONFIG, events::ServerEvent, maintenance_windows, nodes, outbox, pathfinding::NodeId,
+    utils::unix_time_seconds, AppState,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceState {
+    Online,
+    Offline,
+}
+
+#[derive(Clone, Copy, Serialize, Debug)]
+pub struct NodePresence {
+    pub state: PresenceState,
+    /// seconds since unix epoch, measured by the server when it received the message
+    pub last_seen: u64,
+}
+
+/// Sent to live websocket clients when a node goes online or offline
+#[derive(Clone, Serialize, Debug)]
+pub struct PresenceEvent {
+    pub node_id: NodeId,
+    pub state: PresenceState,
+    pub last_seen: u64,
+}
+
+/// Tracks when each node was last heard from and whether it's considered online
+#[derive(Default)]
+pub struct PresenceTracker {
+    nodes: HashMap<NodeId, NodePresence>,
+}
+
+impl PresenceTracker {
+    pub fn nodes(&self) -> &HashMap<NodeId, NodePresence> {
+        &self.nodes
+    }
+
+    /// Replaces the tracked nodes with previously saved last seen times, working out whether each
+    /// one should still be considered online
+    pub fn restore(
+        &mut self,
+        last_seen: HashMap<NodeId, u64>,
+        now: u64,
+        offline_after_seconds: u64,
+    ) {
+        self.nodes = last_seen
+            .into_iter()
+            .map(|(node_id, last_seen)| {
+                let state = if now.saturating_sub(last_seen) > offline_after_seconds {
+                    PresenceState::Offline
+                } else {
+                    PresenceState::Online
+                };
+
+                (node_id, NodePresence { state, last_seen })
+            })
+            .collect();
+    }
+
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.nodes.remove(&node_id);
+    }
+
+    /// Records that a message was received from the node, returning an event if this brings it
+    /// (back) online
+    pub fn mark_seen(&mut self, node_id: NodeId, now: u64) -> Option<PresenceEvent> {
+        let previous_state = self.nodes.get(&node_id).map(|presence| presence.state);
+
+        self.nodes.insert(
+            node_id,
+            NodePresence {
+                state: PresenceState::Online,
+                last_seen: now,
+            },
+        );
+
+        if previous_state == Some(PresenceState::Online) {
+            None
+        } else {
+            Some(PresenceEvent {
+                node_id,
+                state: PresenceState::Online,
+                last_seen: now,
+            })
+        }
+    }
+
+    /// Marks every node that's been quiet for longer than `offline_after_seconds` as offline,
+    /// returning an event for each one that was previously online
+    pub fn mark_quiet_nodes_offline(
+        &mut self,
+        now: u64,
+        offline_after_seconds: u64,
+    ) -> Vec<PresenceEvent> {
+        self.nodes
+            .iter_mut()
+            .filter(|(_, presence)| {
+                presence.state == PresenceState::Online
+                    && now.saturating_sub(presence.last_seen) > offline_after_seconds
+            })
+            .map(|(node_id, presence)| {
+                presence.state = PresenceState::Offline;
+
+                PresenceEvent {
+                    node_id: *node_id,
+                    state: PresenceState::Offline,
+                    last_seen: presence.last_seen,
+                }
+            })
+            .collect()
+    }
+}
+
+pub async fn dispatch(state: &AppState, events: impl IntoIterator<Item = PresenceEvent>) {
+    for event in events {
+        info!("Node {} is now {:?}", event.node_id, event.state);
+
+        let event = ServerEvent::NodePresence(event);
+
+        if maintenance_windows::mute(state, &event).await {
+            continue;
+        }
+
+        let _ = state.server_events.send(event);
+    }
+}
+
+/// Records that a node has been heard from
+pub async fn mark_seen(state: &AppState, node_id: NodeId) {
+    let event = state
+        .presence
+        .lock()
+        .await
+        .mark_seen(node_id, unix_time_seconds());
+
+    let came_online = event.is_some();
+
+    dispatch(state, event).await;
+
+    nodes::record_heard(state, node_id).await;
+
+    if came_online {
+        outbox::flush(state, node_id).await;
+    }
+}
+
+/// Spawns the task which periodically checks for nodes that have gone quiet
+pub fn offline_check_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting node offline check task");
+
+        // check often enough that nodes are marked offline reasonably close to the threshold
+        let check_interval = Duration::from_secs((CONFIG.node_offline_after_seconds / 10).max(1));
+
+        loop {
+            tokio::time::sleep(check_interval).await;
+
+            let events = state
+                .presence
+                .lock()
+                .await
+                .mark_quiet_nodes_offline(unix_time_seconds(), CONFIG.node_offline_after_seconds);
+
+            dispatch(&state, events).await;
+        }
+    })
+}
+
+/// /info/node-presence
+pub async fn get_node_presence(
+    State(state): State<AppState>,
+) -> Json<HashMap<NodeId, NodePresence>> {
+    Json(state.presence.lock().await.nodes().clone())
+}
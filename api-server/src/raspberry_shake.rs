@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use log::{debug, error, info};
+use prost::Message;
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+use crate::{config::CONFIG, pathfinding::NodeId, proto::meshtastic::SeismicChunk, AppState};
+
+/// One packet from a Raspberry Shake's UDP datacast, which is one channel's samples for a fraction
+/// of a second
+struct DatacastPacket {
+    channel: String,
+    /// milliseconds since unix epoch of the first sample
+    timestamp_millis: u64,
+    /// raw sensor counts
+    counts: Vec<i32>,
+}
+
+/// Parses a packet, which looks like `{'ENZ', 1559312345.520, 123, -456, ...}`
+fn parse_packet(text: &str) -> Result<DatacastPacket, String> {
+    let mut fields = text
+        .trim()
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .split(',')
+        .map(str::trim);
+
+    let channel = fields
+        .next()
+        .filter(|channel| !channel.is_empty())
+        .ok_or("Missing channel")?
+        .trim_matches(['\'', '"'])
+        .to_owned();
+
+    let timestamp_seconds = fields
+        .next()
+        .ok_or("Missing timestamp")?
+        .parse::<f64>()
+        .map_err(|error| format!("Invalid timestamp: {}", error))?;
+
+    let counts = fields
+        .map(|count| {
+            count
+                .parse::<i32>()
+                .map_err(|error| format!("Invalid count {:?}: {}", count, error))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(DatacastPacket {
+        channel,
+        timestamp_millis: (timestamp_seconds * 1000.0).round() as u64,
+        counts,
+    })
+}
+
+/// The accelerometer's channels, which are sent separately and have to be matched up into chunks
+/// with all three axes. Any other channels (e.g. a geophone's `EHZ`) are ignored.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Axis {
+    East,
+    North,
+    Vertical,
+}
+
+impl Axis {
+    fn from_channel(channel: &str) -> Option<Axis> {
+        match channel {
+            "ENE" => Some(Axis::East),
+            "ENN" => Some(Axis::North),
+            "ENZ" => Some(Axis::Vertical),
+            _ => None,
+        }
+    }
+}
+
+/// Packets waiting for the other axes' packets from the same moment
+#[derive(Default)]
+struct ChunkAssembler {
+    pending: HashMap<Axis, DatacastPacket>,
+}
+
+impl ChunkAssembler {
+    /// Adds a packet, returning a chunk once all three axes have arrived for the same moment
+    fn add(&mut self, node_id: NodeId, axis: Axis, packet: DatacastPacket) -> Option<SeismicChunk> {
+        // packets from the same moment have the same timestamp, give or take rounding
+        let tolerance_millis = (500.0 / CONFIG.raspberry_shake_sample_rate_hz).ceil() as u64;
+
+        // anything older is from a moment whose other packets were lost
+        self.pending.retain(|_, pending| {
+            pending.timestamp_millis + tolerance_millis >= packet.timestamp_millis
+        });
+        self.pending.insert(axis, packet);
+
+        let east = self.pending.get(&Axis::East)?;
+        let north = self.pending.get(&Axis::North)?;
+        let vertical = self.pending.get(&Axis::Vertical)?;
+
+        if east.timestamp_millis.abs_diff(vertical.timestamp_millis) > tolerance_millis
+            || north.timestamp_millis.abs_diff(vertical.timestamp_millis) > tolerance_millis
+        {
+            return None;
+        }
+
+        let length = east
+            .counts
+            .len()
+            .min(north.counts.len())
+            .min(vertical.counts.len());
+        let to_acceleration = |counts: &[i32]| {
+            counts[..length]
+                .iter()
+                .map(|count| (*count as f64 / CONFIG.raspberry_shake_sensitivity) as f32)
+                .collect()
+        };
+
+        let chunk = SeismicChunk {
+            node_num: node_id,
+            start_timestamp_millis: vertical.timestamp_millis,
+            sample_rate_hz: CONFIG.raspberry_shake_sample_rate_hz as f32,
+            x: to_acceleration(&east.counts),
+            y: to_acceleration(&north.counts),
+            z: to_acceleration(&vertical.counts),
+        };
+
+        self.pending.clear();
+
+        Some(chunk)
+    }
+}
+
+/// Spawns the task which receives a Raspberry Shake's UDP datacast on
+/// `RASPBERRY_SHAKE_UDP_ADDRESS`, and passes its accelerometer data into the seismic pipeline as
+/// though it came from node `RASPBERRY_SHAKE_NODE_ID`
+pub fn ingest_task(state: AppState, address: String, node_id: NodeId) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let socket = match UdpSocket::bind(&address).await {
+            Ok(socket) => socket,
+            Err(error) => {
+                error!(
+                    "Failed to listen for Raspberry Shake data on {}: {:?}",
+                    address, error
+                );
+                return;
+            }
+        };
+
+        info!(
+            "Listening for Raspberry Shake data on {} as node {}",
+            address, node_id
+        );
+
+        let sender = state.mesh_interface.clone_sender_to_seismic_subscribers();
+        let mut assembler = ChunkAssembler::default();
+        let mut buffer = [0; 65536];
+
+        loop {
+            let length = match socket.recv(&mut buffer).await {
+                Ok(length) => length,
+                Err(error) => {
+                    error!("Failed to receive Raspberry Shake data: {:?}", error);
+                    continue;
+                }
+            };
+
+            let packet = match std::str::from_utf8(&buffer[..length])
+                .map_err(|error| error.to_string())
+                .and_then(parse_packet)
+            {
+                Ok(packet) => packet,
+                Err(error_message) => {
+                    error!("Invalid Raspberry Shake packet: {}", error_message);
+                    continue;
+                }
+            };
+
+            let Some(axis) = Axis::from_channel(&packet.channel) else {
+                debug!("Ignoring Raspberry Shake channel {}", packet.channel);
+                continue;
+            };
+
+            if let Some(chunk) = assembler.add(node_id, axis, packet) {
+                // an error here just means nothing is processing seismic data
+                let _ = sender.send(Bytes::from(chunk.encode_to_vec()));
+            }
+        }
+    })
+}
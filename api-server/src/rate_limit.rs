@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+
+use crate::{
+    auth::{self, AuthedUser},
+    config::CONFIG,
+    utils::FallibleJsonResponse,
+    AppState,
+};
+
+struct Window {
+    started_at: Instant,
+    requests: u32,
+}
+
+/// Counts requests to expensive routes in fixed windows, separately for each route and client.
+/// Clients are identified by who they authenticated as, and otherwise (e.g. when the routes are
+/// open to anyone) by their IP address.
+#[derive(Default)]
+pub struct RateLimiter {
+    windows: HashMap<(String, String), Window>,
+}
+
+impl RateLimiter {
+    /// Records a request, returning how long the client has to wait if it's over the limit
+    fn check(&mut self, route: String, client: String, now: Instant) -> Result<(), Duration> {
+        let window_length = Duration::from_secs(CONFIG.rate_limit_window_seconds);
+
+        // forget about clients which haven't made a request recently
+        self.windows
+            .retain(|_, window| now.duration_since(window.started_at) < window_length);
+
+        let window = self.windows.entry((route, client)).or_insert(Window {
+            started_at: now,
+            requests: 0,
+        });
+
+        if window.requests >= CONFIG.rate_limit_max_requests {
+            return Err(window_length.saturating_sub(now.duration_since(window.started_at)));
+        }
+
+        window.requests += 1;
+
+        Ok(())
+    }
+}
+
+/// 429 Too Many Requests, telling the client when to try again
+pub fn too_many_requests(message: &str, retry_after: Duration) -> Response {
+    let mut response =
+        FallibleJsonResponse::<()>::Err(StatusCode::TOO_MANY_REQUESTS, message.to_owned())
+            .into_response();
+
+    // round up so that clients don't retry slightly too early
+    let retry_after_seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, retry_after_seconds.into());
+
+    response
+}
+
+/// Middleware for routes which are expensive for the server or the mesh, which rejects clients
+/// making more than `RATE_LIMIT_MAX_REQUESTS` requests every `RATE_LIMIT_WINDOW_SECONDS` with 429
+/// Too Many Requests. It has to run after the routes' authentication middleware, so that clients
+/// can't get a fresh limit by sending made up keys.
+pub async fn limit_expensive_requests(
+    State(state): State<AppState>,
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // the route's template rather than its path, so that looping over node IDs doesn't get a
+    // fresh limit for each one
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(matched_path) => matched_path.as_str().to_owned(),
+        None => request.uri().path().to_owned(),
+    };
+    // everyone is "anonymous" on routes which are open to anyone
+    let client = match request.extensions().get::<AuthedUser>() {
+        Some(user) if !auth::is_open_to_anyone(user.role) => format!("user {}", user.name),
+        _ => format!("ip {}", remote_address.ip()),
+    };
+
+    let result = state
+        .rate_limiter
+        .lock()
+        .await
+        .check(route, client, Instant::now());
+
+    if let Err(retry_after) = result {
+        warn!(
+            "Rate limited request to {} from {}",
+            request.uri(),
+            remote_address
+        );
+
+        return too_many_requests("Too many requests, try again later", retry_after);
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,129 @@
+use axum::{extract::State, http::StatusCode};
+use log::{debug, error, warn};
+use tokio::task::JoinHandle;
+
+use crate::{
+    auth::AuthedUser,
+    config::{self, ConfigChanges},
+    events::{ServerEvent, SettingsChangedEvent},
+    utils::FallibleJsonResponse,
+    AppSettings, AppState,
+};
+
+/// Reloads the config, and applies any changed `DEFAULT_*` settings to the server settings, which
+/// replaces whatever they'd been changed to through the API
+async fn reload(state: &AppState, by: &str) -> Result<ConfigChanges, String> {
+    let changes = config::reload()?;
+
+    warn!(
+        target: "audit",
+        "{} reloaded the config: applied {:?}, requiring a restart {:?}",
+        by,
+        changes.applied,
+        changes.requires_restart
+    );
+
+    let changed = |name: &str| changes.applied.iter().any(|applied| applied == name);
+    let defaults = AppSettings::from_config();
+
+    let mut app_settings = state.app_settings.lock().await;
+
+    if changed("DEFAULT_GET_SETTINGS_TIMEOUT_SECONDS") {
+        app_settings.get_settings_timeout_seconds = defaults.get_settings_timeout_seconds;
+    }
+
+    if changed("DEFAULT_SIGNAL_DATA_TIMEOUT_SECONDS") {
+        app_settings.signal_data_timeout_seconds = defaults.signal_data_timeout_seconds;
+    }
+
+    if changed("DEFAULT_ROUTE_COST_WEIGHT") {
+        app_settings.route_cost_weight = defaults.route_cost_weight;
+    }
+
+    if changed("DEFAULT_ROUTE_HOPS_WEIGHT") {
+        app_settings.route_hops_weight = defaults.route_hops_weight;
+    }
+
+    if changed("DEFAULT_SENSOR_RELAY_PENALTY") {
+        app_settings.sensor_relay_penalty = defaults.sensor_relay_penalty;
+    }
+
+    if changed("DEFAULT_AD_HOC_TELEMETRY_TIMEOUT_SECONDS") {
+        app_settings.ad_hoc_telemetry_timeout_seconds = defaults.ad_hoc_telemetry_timeout_seconds;
+    }
+
+    if changed("DEFAULT_COMMAND_ACK_TIMEOUT_SECONDS") {
+        app_settings.command_ack_timeout_seconds = defaults.command_ack_timeout_seconds;
+    }
+
+    if changed("DEFAULT_DISCOVERY_TIMEOUT_SECONDS") {
+        app_settings.discovery_timeout_seconds = defaults.discovery_timeout_seconds;
+    }
+
+    // every setting starting with DEFAULT_ is one of the server settings
+    let app_settings_changed = changes
+        .applied
+        .iter()
+        .any(|name| name.starts_with("DEFAULT_"));
+
+    if app_settings_changed {
+        // an error here just means there aren't any websocket clients connected
+        let _ =
+            state
+                .server_events
+                .send(ServerEvent::SettingsChanged(SettingsChangedEvent::Server(
+                    app_settings.clone(),
+                )));
+    }
+
+    Ok(changes)
+}
+
+/// Spawns the task which reloads the config whenever the process gets SIGHUP
+pub fn sighup_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(error) => {
+                    error!("Failed to listen for SIGHUP: {:?}", error);
+                    return;
+                }
+            };
+
+            debug!("Starting SIGHUP config reload task");
+
+            while hangups.recv().await.is_some() {
+                if let Err(error_message) = reload(&state, "SIGHUP").await {
+                    error!(
+                        "Failed to reload the config, so it hasn't changed: {}",
+                        error_message
+                    );
+                }
+            }
+        }
+
+        #[cfg(not(unix))]
+        let _ = state;
+    })
+}
+
+/// /admin/reload-config
+pub async fn reload_config(
+    State(state): State<AppState>,
+    user: AuthedUser,
+) -> FallibleJsonResponse<ConfigChanges> {
+    match reload(&state, &user.to_string()).await {
+        Ok(changes) => FallibleJsonResponse::Ok(changes),
+        Err(error_message) => FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "The config is invalid, so it hasn't changed: {}",
+                error_message
+            ),
+        ),
+    }
+}
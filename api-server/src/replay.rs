@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::{ServerEvent, TelemetryEvent},
+    pathfinding::NodeId,
+    proto::meshtastic::crisislab_message::Telemetry,
+    utils::{FallibleJsonResponse, JsonBody},
+    AppState,
+};
+
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 1000.0;
+/// the longest the replay waits between two packets, so that a gap in the telemetry (e.g. while
+/// the mesh was down) doesn't stall it
+const MAX_GAP: Duration = Duration::from_secs(60);
+
+/// How long to wait between replaying packets `gap_seconds` apart
+fn replay_gap(gap_seconds: u64, speed: f64) -> Duration {
+    Duration::try_from_secs_f64(gap_seconds as f64 / speed)
+        .unwrap_or(MAX_GAP)
+        .min(MAX_GAP)
+}
+
+/// Structure that clients should send replay requests in as JSON body
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayTelemetryBody {
+    /// seconds since unix epoch, inclusive
+    from: Option<u64>,
+    /// seconds since unix epoch, inclusive
+    to: Option<u64>,
+    node_id: Option<NodeId>,
+    /// how many times faster than real time to replay, from `MIN_SPEED` to `MAX_SPEED` (defaults
+    /// to 1)
+    speed: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ReplayTelemetryResponse {
+    packet_count: usize,
+    duration_seconds: f64,
+}
+
+/// /debug/replay-telemetry
+pub async fn replay_telemetry(
+    State(state): State<AppState>,
+    JsonBody(body): JsonBody<ReplayTelemetryBody>,
+) -> FallibleJsonResponse<ReplayTelemetryResponse> {
+    info!("Replaying telemetry: {:?}", body);
+
+    let speed = body.speed.unwrap_or(1.0);
+
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return FallibleJsonResponse::Err(
+            StatusCode::BAD_REQUEST,
+            format!("speed must be from {} to {}", MIN_SPEED, MAX_SPEED),
+        );
+    }
+
+    let from = body.from.unwrap_or(0);
+    let to = body.to.unwrap_or(u64::MAX);
+
+    let is_requested = |telemetry: &Telemetry| {
+        (from..=to).contains(&telemetry.timestamp)
+            && body
+                .node_id
+                .is_none_or(|node_id| node_id == telemetry.node_num)
+    };
+
+    // archived telemetry is older than anything in the cache, so include it too
+    let mut packets = state
+        .telemetry_archive
+        .lock()
+        .await
+        .telemetry()
+        .filter(|telemetry| is_requested(telemetry))
+        .collect::<Vec<_>>();
+
+    packets.extend(
+        state
+            .telemetry_cache
+            .lock()
+            .await
+            .into_iter()
+            .filter(|telemetry| is_requested(telemetry))
+            .cloned(),
+    );
+
+    packets.sort_by_key(|telemetry| telemetry.timestamp);
+
+    let duration_seconds = packets
+        .windows(2)
+        .map(|pair| replay_gap(pair[1].timestamp - pair[0].timestamp, speed))
+        .sum::<Duration>()
+        .as_secs_f64();
+
+    let response = ReplayTelemetryResponse {
+        packet_count: packets.len(),
+        duration_seconds,
+    };
+
+    let replay_state = state.clone();
+
+    let replay_task = tokio::spawn(async move {
+        let mut previous_timestamp = None;
+
+        for telemetry in packets {
+            if let Some(previous_timestamp) = previous_timestamp {
+                tokio::time::sleep(replay_gap(telemetry.timestamp - previous_timestamp, speed))
+                    .await;
+            }
+
+            previous_timestamp = Some(telemetry.timestamp);
+
+            let node_name = replay_state
+                .node_registry
+                .lock()
+                .await
+                .name(telemetry.node_num);
+
+            let _ = replay_state
+                .server_events
+                .send(ServerEvent::Telemetry(Box::new(TelemetryEvent {
+                    telemetry,
+                    replay: true,
+                    node_name,
+                })));
+        }
+
+        debug!("Telemetry replay finished");
+    });
+
+    // only one replay runs at a time, so starting a new one stops the previous one
+    if let Some(previous_task) = state.replay_task.lock().await.replace(replay_task) {
+        previous_task.abort();
+    }
+
+    FallibleJsonResponse::Ok(response)
+}
@@ -1,32 +1,48 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
     sync::{atomic::Ordering, Arc},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    auth::{self, AuthedUser},
+    command_history::{self, CommandOutcome},
+    config::CONFIG,
+    events::EventKind,
+    events::{ServerEvent, SettingsChangedEvent, TopologyEvent},
+    hub::{
+        ClientId, ClientMessage, ClientQueue, Frame, Subscription, WebSocketCompression,
+        WebSocketFormat,
+    },
+    nodes::{self, NodeRole},
     pathfinding::{self, compute_edge_weight_proportionalised, AdjacencyMap, EdgeWeight, NodeId},
+    pending_actions,
     proto::meshtastic::{
         crisislab_message::{self, Telemetry},
         CrisislabMessage,
     },
+    telemetry::{self, NodeTelemetryStats, TelemetryGap},
     utils::{
-        self, await_mesh_response, send_command_protobuf, FallibleJsonResponse, RingBuffer,
+        self, await_mesh_response, send_command_protobuf, FallibleJsonResponse, JsonBody,
         SerializableIterator, StringOrEmptyResponse,
     },
-    AppSettings, AppState, MeshInterface,
+    AppSettings, AppState,
 };
 use axum::{
-    extract::{ws::WebSocket, State, WebSocketUpgrade},
-    http::StatusCode,
-    response::Response,
+    extract::{
+        ws::{close_code, CloseFrame, WebSocket},
+        ConnectInfo, Query, State, WebSocketUpgrade,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use bytes::Bytes;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use prost::Message;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use tokio::{sync::Mutex, task::JoinHandle};
 
 /// Structure that clients should send mesh settings in as JSON body
 #[derive(Deserialize, Debug)]
@@ -35,30 +51,80 @@ pub struct MeshSettingsBody {
     broadcast_interval_seconds: Option<u32>,
     channel_name: Option<String>,
     ping_timeout_seconds: Option<u32>,
+    /// only send the settings to nodes with this tag, rather than every node
+    tag: Option<String>,
 }
 
 /// /admin/set-mesh-settings
 pub async fn set_mesh_settings(
-    State(mesh_interface): State<MeshInterface>,
-    Json(body): Json<MeshSettingsBody>,
-) -> StringOrEmptyResponse {
-    info!("Setting mesh settings: {:?}", body);
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<MeshSettingsBody>,
+) -> Response {
+    info!("{} is setting mesh settings: {:?}", user, body);
 
-    let crisislab_message = CrisislabMessage {
-        message: Some(crisislab_message::Message::MeshSettings(
-            crisislab_message::MeshSettings {
-                broadcast_interval_seconds: body.broadcast_interval_seconds,
-                channel_name: body.channel_name,
-                ping_timeout_seconds: body.ping_timeout_seconds,
-            },
-        )),
+    // nodes which miss a channel change can't hear the rest of the mesh anymore
+    let changes_channel = body.channel_name.is_some();
+    let mut description = match &body.channel_name {
+        Some(channel_name) => format!("Change the mesh's channel to {}", channel_name),
+        None => "Change the mesh's settings".to_owned(),
     };
 
-    if let Err(error_message) = send_command_protobuf(crisislab_message, &mesh_interface).await {
-        StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log()
-    } else {
-        StringOrEmptyResponse::Ok
-    }
+    let destinations = match &body.tag {
+        Some(tag) => match nodes::nodes_with_tag(&state, tag).await {
+            Ok(node_ids) => {
+                description.push_str(&format!(" on nodes tagged {}", tag));
+                node_ids.into_iter().map(Some).collect()
+            }
+            Err(error_message) => {
+                return FallibleJsonResponse::<()>::Err(StatusCode::NOT_FOUND, error_message)
+                    .into_response()
+            }
+        },
+        None => vec![None],
+    };
+
+    let mesh_settings = crisislab_message::MeshSettings {
+        broadcast_interval_seconds: body.broadcast_interval_seconds,
+        channel_name: body.channel_name,
+        ping_timeout_seconds: body.ping_timeout_seconds,
+    };
+
+    // nodes with their own settings are sent them again afterwards so that they keep them
+    let overrides_commands = state
+        .node_registry
+        .lock()
+        .await
+        .settings_overrides()
+        .filter(|(node_id, settings_overrides)| {
+            (destinations.contains(&None) || destinations.contains(&Some(*node_id)))
+                && settings_overrides.conflicts_with(&mesh_settings)
+        })
+        .map(|(node_id, settings_overrides)| settings_overrides.command(node_id))
+        .collect::<Vec<_>>();
+
+    let crisislab_messages = destinations
+        .into_iter()
+        .map(|destination| CrisislabMessage {
+            message: Some(crisislab_message::Message::MeshSettings(
+                mesh_settings.clone(),
+            )),
+            destination,
+        })
+        .chain(overrides_commands)
+        .collect();
+
+    pending_actions::send_or_hold(
+        &state,
+        &user,
+        description,
+        crisislab_messages,
+        changes_channel,
+        Some(ServerEvent::SettingsChanged(SettingsChangedEvent::Mesh(
+            mesh_settings,
+        ))),
+    )
+    .await
 }
 
 /// Structure that clients should send server settings in as JSON body
@@ -69,14 +135,19 @@ pub struct ServerSettingsBody {
     signal_data_timeout_seconds: Option<u64>,
     route_cost_weight: Option<EdgeWeight>,
     route_hops_weight: Option<EdgeWeight>,
+    sensor_relay_penalty: Option<EdgeWeight>,
+    ad_hoc_telemetry_timeout_seconds: Option<u64>,
+    command_ack_timeout_seconds: Option<u64>,
+    discovery_timeout_seconds: Option<u64>,
 }
 
 /// /admin/set-server-settings
 pub async fn set_server_settings(
     State(state): State<AppState>,
-    Json(body): Json<ServerSettingsBody>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<ServerSettingsBody>,
 ) -> StatusCode {
-    info!("Setting server settings: {:?}", body);
+    info!("{} is setting server settings: {:?}", user, body);
 
     let mut app_settings = state.app_settings.lock().await;
 
@@ -96,6 +167,28 @@ pub async fn set_server_settings(
         app_settings.route_hops_weight = route_hops_weight;
     }
 
+    if let Some(sensor_relay_penalty) = body.sensor_relay_penalty {
+        app_settings.sensor_relay_penalty = sensor_relay_penalty;
+    }
+
+    if let Some(ad_hoc_telemetry_timeout_seconds) = body.ad_hoc_telemetry_timeout_seconds {
+        app_settings.ad_hoc_telemetry_timeout_seconds = ad_hoc_telemetry_timeout_seconds;
+    }
+
+    if let Some(command_ack_timeout_seconds) = body.command_ack_timeout_seconds {
+        app_settings.command_ack_timeout_seconds = command_ack_timeout_seconds;
+    }
+
+    if let Some(discovery_timeout_seconds) = body.discovery_timeout_seconds {
+        app_settings.discovery_timeout_seconds = discovery_timeout_seconds;
+    }
+
+    let _ = state
+        .server_events
+        .send(ServerEvent::SettingsChanged(SettingsChangedEvent::Server(
+            app_settings.clone(),
+        )));
+
     StatusCode::OK
 }
 
@@ -109,6 +202,7 @@ pub async fn get_mesh_settings(
         message: Some(crisislab_message::Message::GetMeshSettingsRequest(
             crisislab_message::Empty {},
         )),
+        destination: None,
     };
 
     // send request to the mesh to get the current mesh settings
@@ -179,6 +273,7 @@ pub async fn update_routes(
         message: Some(crisislab_message::Message::UpdateNextHopsRequest(
             crisislab_message::Empty {},
         )),
+        destination: None,
     };
 
     if let Err(error_message) =
@@ -190,7 +285,7 @@ pub async fn update_routes(
     debug!("Update routes handler sent request to mesh");
 
     let mut adjacency_map: AdjacencyMap<NodeId> = HashMap::new();
-    let mut gateway_ids = Vec::<NodeId>::new();
+    let mut reported_gateway_ids = Vec::<NodeId>::new();
 
     let timeout_duration =
         Duration::from_secs(state.app_settings.lock().await.signal_data_timeout_seconds);
@@ -208,7 +303,7 @@ pub async fn update_routes(
                 debug!("Signal data: {:?}", signal_data);
 
                 if signal_data.is_gateway {
-                    gateway_ids.push(signal_data.to);
+                    reported_gateway_ids.push(signal_data.to);
                 }
 
                 // get the map within the main ajacency map that we're going to fill
@@ -235,11 +330,77 @@ pub async fn update_routes(
 
     debug!("Timeout reached for signal data, proceeding with pathfinding");
 
-    let next_hops_map =
-        pathfinding::compute_next_hops_map(state.app_settings, adjacency_map, gateway_ids).await;
+    // gateways registered by operators are authoritative, and nodes saying they're gateways in
+    // their signal data are only relied on if none have been registered
+    let registered_gateway_ids = state.gateway_registry.lock().await.ids();
+
+    let gateway_ids = if registered_gateway_ids.is_empty() {
+        reported_gateway_ids
+    } else {
+        for node_id in &reported_gateway_ids {
+            if !registered_gateway_ids.contains(node_id) {
+                warn!(
+                    "Node {} says it's a gateway but isn't registered as one, so it's being ignored",
+                    node_id
+                );
+            }
+        }
+
+        // a registered gateway which didn't send signal data can't be routed to, and would stop
+        // any routes from being computed
+        registered_gateway_ids
+            .into_iter()
+            .filter(|node_id| {
+                let heard = adjacency_map.contains_key(node_id);
+
+                if !heard {
+                    warn!(
+                        "Gateway {} is registered but didn't send signal data, so it's being left out of the routes",
+                        node_id
+                    );
+                }
+
+                heard
+            })
+            .collect()
+    };
+
+    // sensors are often on batteries, so they're only used as relays when the route through them
+    // is better than any other by more than the penalty
+    let sensor_relay_penalty = state.app_settings.lock().await.sensor_relay_penalty;
+    let relay_penalties = state
+        .node_registry
+        .lock()
+        .await
+        .with_role(NodeRole::Sensor)
+        .into_iter()
+        .map(|node_id| (node_id, sensor_relay_penalty))
+        .collect();
+
+    let next_hops_map = pathfinding::compute_next_hops_map(
+        state.app_settings.clone(),
+        adjacency_map.clone(),
+        gateway_ids.clone(),
+        relay_penalties,
+    )
+    .await;
 
     debug!("Computed next hops map: {:?}", next_hops_map);
 
+    // publishing an empty map would wipe every node's routes
+    if next_hops_map.is_empty() {
+        return FallibleJsonResponse::Err(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No routes could be computed (no gateways, or nodes linked to them, sent signal data), so the existing routes have been kept".to_owned(),
+        );
+    }
+
+    state
+        .topology
+        .lock()
+        .await
+        .set_routes(gateway_ids.clone(), next_hops_map.clone());
+
     let next_hops_message = CrisislabMessage {
         message: Some(crisislab_message::Message::UpdatedNextHops(
             crisislab_message::NextHopsMap {
@@ -257,6 +418,7 @@ pub async fn update_routes(
                     .collect(),
             },
         )),
+        destination: None,
     };
 
     if let Err(error_message) =
@@ -265,51 +427,158 @@ pub async fn update_routes(
         return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
     }
 
+    let node_names = state.node_registry.lock().await.names(
+        adjacency_map
+            .iter()
+            .flat_map(|(to, links)| std::iter::once(to).chain(links.keys()))
+            .copied(),
+    );
+
+    let _ = state
+        .server_events
+        .send(ServerEvent::Topology(TopologyEvent {
+            adjacency_map,
+            gateway_ids,
+            next_hops: next_hops_map.clone(),
+            node_names,
+        }));
+
     debug!("Update routes handler completed (next hops have been sent to mesh), returning next hops to client now");
 
     FallibleJsonResponse::Ok(next_hops_map)
 }
 
-pub async fn start_live_telemetry(State(state): State<AppState>) -> StringOrEmptyResponse {
-    debug!("Received request to start live telemetry");
+/// The longest `duration_seconds` live telemetry can be started for (a week)
+const MAX_LIVE_TELEMETRY_DURATION_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// A pending automatic stop of live telemetry
+pub struct LiveTelemetryAutoStop {
+    /// seconds since unix epoch
+    deadline: u64,
+    timer: JoinHandle<()>,
+}
 
+/// Cancels any pending automatic stop of live telemetry
+async fn cancel_live_telemetry_auto_stop(state: &AppState) {
+    if let Some(auto_stop) = state.live_telemetry_auto_stop.lock().await.take() {
+        auto_stop.timer.abort();
+    }
+}
+
+/// Sends StopLiveTelemetry to the mesh and marks live telemetry as disabled if that succeeds
+async fn send_stop_live_telemetry(state: &AppState) -> Result<(), String> {
     let message = CrisislabMessage {
-        message: Some(crisislab_message::Message::StartLiveTelemetry(
+        message: Some(crisislab_message::Message::StopLiveTelemetry(
             crisislab_message::Empty {},
         )),
+        destination: None,
     };
 
-    if let Err(error_message) = send_command_protobuf(message, &state.mesh_interface).await {
-        StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log()
-    } else {
-        debug!("Sent StartLiveTelemetry message to mesh");
+    send_command_protobuf(message, &state.mesh_interface).await?;
 
-        state
-            .live_telemetry_is_enabled
-            .store(true, Ordering::Relaxed);
+    debug!("Sent StopLiveTelemetry message to mesh");
 
-        StringOrEmptyResponse::Ok
-    }
+    state
+        .live_telemetry_is_enabled
+        .store(false, Ordering::Relaxed);
+
+    Ok(())
 }
 
-pub async fn stop_live_telemetry(State(state): State<AppState>) -> StringOrEmptyResponse {
-    debug!("Received request to stop live telemetry");
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StartLiveTelemetryQuery {
+    /// if given, live telemetry is automatically stopped after this many seconds
+    duration_seconds: Option<u64>,
+}
+
+pub async fn start_live_telemetry(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    Query(query): Query<StartLiveTelemetryQuery>,
+) -> StringOrEmptyResponse {
+    if let Some(duration_seconds) = query.duration_seconds {
+        if !(1..=MAX_LIVE_TELEMETRY_DURATION_SECONDS).contains(&duration_seconds) {
+            return StringOrEmptyResponse::Err(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "duration_seconds must be between 1 and {}",
+                    MAX_LIVE_TELEMETRY_DURATION_SECONDS
+                ),
+            );
+        }
+    }
+
+    info!("{} is starting live telemetry", user);
 
     let message = CrisislabMessage {
-        message: Some(crisislab_message::Message::StopLiveTelemetry(
+        message: Some(crisislab_message::Message::StartLiveTelemetry(
             crisislab_message::Empty {},
         )),
+        destination: None,
     };
 
     if let Err(error_message) = send_command_protobuf(message, &state.mesh_interface).await {
-        StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log()
-    } else {
-        debug!("Sent StopLiveTelemetry message to mesh");
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
 
-        state
-            .live_telemetry_is_enabled
-            .store(false, Ordering::Relaxed);
+    debug!("Sent StartLiveTelemetry message to mesh");
+
+    state
+        .live_telemetry_is_enabled
+        .store(true, Ordering::Relaxed);
+
+    // held until the new timer is stored, so that a short timer can't finish before its deadline
+    // is recorded (which would leave a stale one behind)
+    let mut auto_stop = state.live_telemetry_auto_stop.lock().await;
+
+    // starting again replaces (or removes) whatever timer was running before
+    if let Some(previous) = auto_stop.take() {
+        previous.timer.abort();
+    }
+
+    if let Some(duration_seconds) = query.duration_seconds {
+        let timer_state = state.clone();
+
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(duration_seconds)).await;
+
+            info!(
+                "Live telemetry duration of {} seconds elapsed, stopping it",
+                duration_seconds
+            );
+
+            // clear the pending stop before sending so that a failure doesn't leave a stale deadline
+            timer_state.live_telemetry_auto_stop.lock().await.take();
+
+            if let Err(error_message) = send_stop_live_telemetry(&timer_state).await {
+                error!(
+                    "Failed to automatically stop live telemetry: {}",
+                    error_message
+                );
+            }
+        });
+
+        *auto_stop = Some(LiveTelemetryAutoStop {
+            deadline: utils::unix_time_seconds().saturating_add(duration_seconds),
+            timer,
+        });
+    }
 
+    StringOrEmptyResponse::Ok
+}
+
+pub async fn stop_live_telemetry(
+    State(state): State<AppState>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    info!("{} is stopping live telemetry", user);
+
+    cancel_live_telemetry_auto_stop(&state).await;
+
+    if let Err(error_message) = send_stop_live_telemetry(&state).await {
+        StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log()
+    } else {
         StringOrEmptyResponse::Ok
     }
 }
@@ -317,169 +586,773 @@ pub async fn stop_live_telemetry(State(state): State<AppState>) -> StringOrEmpty
 #[derive(Serialize)]
 pub struct LiveStatusResponse {
     is_enabled: bool,
+    /// seconds until live telemetry is automatically stopped, if a duration was given
+    remaining_seconds: Option<u64>,
 }
 
 pub async fn get_live_status(State(state): State<AppState>) -> Json<LiveStatusResponse> {
+    let remaining_seconds = state
+        .live_telemetry_auto_stop
+        .lock()
+        .await
+        .as_ref()
+        .map(|auto_stop| {
+            auto_stop
+                .deadline
+                .saturating_sub(utils::unix_time_seconds())
+        });
+
     Json(LiveStatusResponse {
         is_enabled: state.live_telemetry_is_enabled.load(Ordering::Relaxed),
+        remaining_seconds,
     })
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LiveTelemetryQuery {
+    #[serde(default)]
+    compression: WebSocketCompression,
+    #[serde(default)]
+    format: WebSocketFormat,
+    /// from /auth/ws-token, for clients which can't authenticate with a header
+    token: Option<String>,
+    /// sequence number of the last packet the client received before reconnecting
+    resume_from: Option<u64>,
+    /// comma separated kinds of packets to start out receiving
+    channels: Option<String>,
+}
+
+impl LiveTelemetryQuery {
+    fn subscription(&self) -> Result<Option<Subscription>, String> {
+        let Some(channels) = &self.channels else {
+            return Ok(None);
+        };
+
+        let mut kinds = HashSet::new();
+
+        for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+            kinds.extend(EventKind::parse(channel)?);
+        }
+
+        Ok(Some(Subscription::channels(kinds)))
+    }
+}
+
+/// /telemetry/socket, which sends everything unless told otherwise
 pub async fn live_telemetry(
     websocket_upgrade: WebSocketUpgrade,
     State(state): State<AppState>,
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<LiveTelemetryQuery>,
 ) -> Response {
-    websocket_upgrade.on_upgrade(|socket| handle_live_telemetry_websocket(socket, state))
+    upgrade_websocket(
+        websocket_upgrade,
+        state,
+        remote_address,
+        headers,
+        "/telemetry/socket",
+        query,
+        Subscription::default(),
+    )
+    .await
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "snake_case")]
-enum TelemetryWSPacket<'a> {
-    Telemetry(&'a Telemetry),
-    Cache(
-        SerializableIterator<'a, Telemetry, <&'a RingBuffer<Telemetry> as IntoIterator>::IntoIter>,
-    ),
-    Error(String),
-}
-
-async fn on_message_from_mesh(websocket: &mut WebSocket, state: &AppState, bytes: Bytes) {
-    match CrisislabMessage::decode(bytes) {
-        Ok(crisislab_message) => {
-            if let Some(crisislab_message::Message::Telemetry(live_data)) =
-                crisislab_message.message
-            {
-                // stringify data and send to client on websocket
-                if websocket
-                    .send(axum::extract::ws::Message::Text(
-                        serde_json::to_string(&TelemetryWSPacket::Telemetry(&live_data))
-                            .expect("Failed to serialize CrisislabMessage for WS message")
-                            .into(),
-                    ))
-                    .await
-                    .is_err()
-                {
-                    debug!("Client disconnected from websocket");
-                    return;
-                }
+/// /ws, where clients only receive the channels they join
+pub async fn multiplexed_websocket(
+    websocket_upgrade: WebSocketUpgrade,
+    State(state): State<AppState>,
+    ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<LiveTelemetryQuery>,
+) -> Response {
+    upgrade_websocket(
+        websocket_upgrade,
+        state,
+        remote_address,
+        headers,
+        "/ws",
+        query,
+        Subscription::channels(HashSet::new()),
+    )
+    .await
+}
 
-                state.telemetry_cache.lock().await.write(live_data);
-            }
-        }
-        Err(error) => {
-            error!("Failed to decode CrisislabMessage: {:?}", error);
+async fn upgrade_websocket(
+    websocket_upgrade: WebSocketUpgrade,
+    state: AppState,
+    remote_address: SocketAddr,
+    headers: HeaderMap,
+    path: &str,
+    query: LiveTelemetryQuery,
+    default_subscription: Subscription,
+) -> Response {
+    let subscription = match query.subscription() {
+        Ok(subscription) => subscription.unwrap_or(default_subscription),
+        Err(error_message) => return (StatusCode::BAD_REQUEST, error_message).into_response(),
+    };
 
-            // notify client of decoding error
+    let telemetry_only = match auth::authorise_websocket(
+        &state,
+        &headers,
+        remote_address.ip(),
+        path,
+        query.token.as_deref(),
+    )
+    .await
+    {
+        Ok(telemetry_only) => telemetry_only,
+        Err(response) => return response,
+    };
 
-            let packet =
-                TelemetryWSPacket::Error(format!("Failed to decode CrisislabMessage: {:?}", error));
+    // clients only send small control messages, so anything bigger is a bug (or abuse) and the
+    // connection is closed
+    websocket_upgrade
+        .max_message_size(CONFIG.websocket_max_inbound_message_bytes)
+        .max_frame_size(CONFIG.websocket_max_inbound_message_bytes)
+        .on_upgrade(move |socket| {
+            handle_live_telemetry_websocket(
+                socket,
+                state,
+                remote_address,
+                query,
+                subscription,
+                telemetry_only,
+            )
+        })
+}
 
-            if websocket
-                .send(axum::extract::ws::Message::Text(
-                    serde_json::to_string(&packet)
-                        .expect("Failed to serialize error packet to send to WS client")
-                        .into(),
-                ))
-                .await
-                .is_err()
-            {
-                error!("Failed to inform WS client of decoding error. Disconnecting.");
-                return;
-            }
-        }
-    }
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case", bound = "")]
+enum TelemetryWSPacket<'a, I: Iterator<Item = &'a Telemetry> + Clone> {
+    Cache(SerializableIterator<'a, Telemetry, I>),
 }
 
-async fn handle_live_telemetry_websocket(mut websocket: WebSocket, state: AppState) {
+async fn handle_live_telemetry_websocket(
+    mut websocket: WebSocket,
+    state: AppState,
+    remote_address: SocketAddr,
+    query: LiveTelemetryQuery,
+    subscription: Subscription,
+    telemetry_only: bool,
+) {
     info!("Client connected to live info websocket");
 
-    // get recent telemetry and send to client
+    let LiveTelemetryQuery {
+        compression,
+        format,
+        resume_from,
+        ..
+    } = query;
+
+    let is_receiving_telemetry = subscription.includes(EventKind::Telemetry);
+
+    // register before sending the cache so that nothing which arrives in the meantime is missed
+    let (client_id, hub_receiver, has_resumed) = state.websocket_hub.lock().await.register(
+        remote_address,
+        format,
+        compression,
+        subscription,
+        resume_from,
+        telemetry_only,
+    );
+
+    if has_resumed {
+        debug!("WS client {} resumed after {:?}", client_id, resume_from);
+    }
 
+    forward_to_websocket(
+        &mut websocket,
+        &state,
+        client_id,
+        &hub_receiver,
+        compression,
+        format,
+        is_receiving_telemetry && !has_resumed,
+    )
+    .await;
+
+    state.websocket_hub.lock().await.unregister(client_id);
+}
+
+/// Handles a control frame (e.g. a subscription) sent by a websocket client, returning whether
+/// the client needs to be sent the cache because it's just started receiving telemetry
+async fn on_message_from_client(
+    state: &AppState,
+    client_id: ClientId,
+    text: &str,
+) -> Result<bool, String> {
+    let message = serde_json::from_str::<ClientMessage>(text)
+        .map_err(|error| format!("Invalid control message: {}", error))?;
+
+    debug!("WS client {} sent {:?}", client_id, message);
+
+    Ok(state
+        .websocket_hub
+        .lock()
+        .await
+        .update_subscription(client_id, message))
+}
+
+/// Sends recent telemetry (which passes the client's filter) to a client, returning whether it
+/// was sent successfully
+async fn send_cache(
+    websocket: &mut WebSocket,
+    state: &AppState,
+    client_id: ClientId,
+    compression: WebSocketCompression,
+    format: WebSocketFormat,
+) -> bool {
+    let filter = state.websocket_hub.lock().await.telemetry_filter(client_id);
     let telemetry_cache = state.telemetry_cache.lock().await;
 
-    let serialised_cache = serde_json::to_string(&TelemetryWSPacket::Cache(SerializableIterator(
-        telemetry_cache.into_iter(),
-    )))
-    .expect("Failed to serialise telemetry cache");
+    let cached_telemetry = telemetry_cache.into_iter().filter(|telemetry| {
+        filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(telemetry))
+    });
+
+    let cache_frames = match format {
+        WebSocketFormat::Json => vec![Frame::Json(
+            serde_json::to_string(&TelemetryWSPacket::Cache(SerializableIterator(
+                cached_telemetry,
+            )))
+            .expect("Failed to serialise telemetry cache")
+            .into(),
+        )],
+        // protobuf clients get the cache as individual telemetry messages since there's no
+        // protobuf message for a batch of them
+        WebSocketFormat::Protobuf => cached_telemetry
+            .map(|telemetry| {
+                Frame::Protobuf(
+                    CrisislabMessage {
+                        message: Some(crisislab_message::Message::Telemetry(telemetry.clone())),
+                        destination: None,
+                    }
+                    .encode_to_vec()
+                    .into(),
+                )
+            })
+            .collect(),
+    };
 
     drop(telemetry_cache);
 
-    if websocket
-        .send(axum::extract::ws::Message::Text(serialised_cache.into()))
-        .await
-        .is_err()
-    {
+    for frame in cache_frames {
+        if websocket
+            .send(frame.into_message(compression))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn forward_to_websocket(
+    websocket: &mut WebSocket,
+    state: &AppState,
+    client_id: ClientId,
+    hub_receiver: &ClientQueue,
+    compression: WebSocketCompression,
+    format: WebSocketFormat,
+    should_send_cache: bool,
+) {
+    // get recent telemetry and send to client, unless it's reconnected and only needs what it
+    // missed
+
+    if should_send_cache && !send_cache(websocket, state, client_id, compression, format).await {
         error!("Failed to send recent telemetry to WS client. Disconnecting.");
         return;
     }
 
-    // main loop which alternates between forwarding telemetry from the mesh and checking for
-    // websocket disconnections
+    // main loop which alternates between forwarding messages from the hub, pinging the client,
+    // and checking for websocket disconnections
 
-    loop {
-        let mut mesh_receiver = state.mesh_interface.subscribe();
+    let mut ping_interval =
+        tokio::time::interval(Duration::from_secs(CONFIG.websocket_ping_interval_seconds));
+    let mut missed_pongs = 0;
+
+    // inbound messages are counted in one second windows so that a client stuck in a loop can't
+    // keep the hub busy
+    let mut inbound_window_start = Instant::now();
+    let mut inbound_messages_in_window = 0;
 
+    loop {
         // NOTE: splitting `websocket` and using two tasks here might be better but I'm not sure
         tokio::select! {
-            // handler message from mesh
-            Ok(bytes) = mesh_receiver.recv() => {
-                on_message_from_mesh(&mut websocket, &state, bytes).await;
+            // handle telemetry and events generated by the server (e.g. alerts), already
+            // serialised by the hub
+            message = hub_receiver.recv() => {
+                // the hub drops its end when an admin disconnects the client
+                let Some(message) = message else {
+                    info!("WS client {} was disconnected by the server", client_id);
+                    return;
+                };
+
+                if websocket
+                    .send(message.into_message(compression))
+                    .await
+                    .is_err()
+                {
+                    debug!("Client disconnected from websocket");
+                    return;
+                }
             }
-            // handle disconnections
-            websocket_message = websocket.recv() => {
-                if websocket_message.is_none() || websocket_message.unwrap().is_err() {
+            // disconnect clients which have stopped responding (e.g. a laptop which was closed)
+            // since they won't necessarily close the connection themselves
+            _ = ping_interval.tick() => {
+                if missed_pongs >= CONFIG.websocket_max_missed_pongs {
+                    info!("WS client missed {} pongs, disconnecting", missed_pongs);
+                    return;
+                }
+
+                if websocket
+                    .send(axum::extract::ws::Message::Ping(Bytes::new()))
+                    .await
+                    .is_err()
+                {
                     debug!("Client disconnected from websocket");
                     return;
                 }
+
+                missed_pongs += 1;
+            }
+            // handle control messages, pongs and disconnections
+            websocket_message = websocket.recv() => {
+                if !matches!(websocket_message, Some(Ok(axum::extract::ws::Message::Pong(_)))) {
+                    if inbound_window_start.elapsed() >= Duration::from_secs(1) {
+                        inbound_window_start = Instant::now();
+                        inbound_messages_in_window = 0;
+                    }
+
+                    inbound_messages_in_window += 1;
+
+                    if inbound_messages_in_window
+                        > CONFIG.websocket_max_inbound_messages_per_second
+                    {
+                        warn!(
+                            "WS client {} is sending too many messages, disconnecting",
+                            client_id
+                        );
+
+                        let _ = websocket
+                            .send(axum::extract::ws::Message::Close(Some(CloseFrame {
+                                code: close_code::POLICY,
+                                reason: "Too many messages".into(),
+                            })))
+                            .await;
+
+                        return;
+                    }
+                }
+
+                match websocket_message {
+                    Some(Ok(axum::extract::ws::Message::Text(text))) => {
+                        let is_sent = match on_message_from_client(state, client_id, &text).await {
+                            Ok(true) => {
+                                send_cache(websocket, state, client_id, compression, format).await
+                            }
+                            Ok(false) => true,
+                            Err(error_message) => {
+                                let packet =
+                                    serde_json::to_string(&ServerEvent::Error(error_message))
+                                        .expect(
+                                            "Failed to serialize error packet to send to WS client",
+                                        );
+
+                                websocket.send(compression.encode(packet.into())).await.is_ok()
+                            }
+                        };
+
+                        if !is_sent {
+                            debug!("Client disconnected from websocket");
+                            return;
+                        }
+                    }
+                    Some(Ok(axum::extract::ws::Message::Pong(_))) => missed_pongs = 0,
+                    Some(Ok(_)) => {}
+                    // e.g. a message over the size limit
+                    Some(Err(error)) => {
+                        info!(
+                            "Error receiving from WS client {}, disconnecting: {}",
+                            client_id, error
+                        );
+                        return;
+                    }
+                    None => {
+                        debug!("Client disconnected from websocket");
+                        return;
+                    }
+                }
             }
         }
     }
 }
 
+/// Either `node_id` or `tag` must be given
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct GetAdHocTelemetryBody {
-    node_id: u32,
+    node_id: Option<u32>,
+    /// request telemetry from every node with this tag
+    tag: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TaggedAdHocTelemetry {
+    telemetry: HashMap<NodeId, serde_json::Value>,
+    /// nodes which didn't respond before the timeout
+    missing: Vec<NodeId>,
 }
 
+/// /telemetry/ad-hoc
 pub async fn get_ad_hoc_telemetry(
     State(state): State<AppState>,
-    Json(body): Json<GetAdHocTelemetryBody>,
-) -> StringOrEmptyResponse {
-    info!("Requesting ad hoc telemetry from node {}", body.node_id);
+    user: AuthedUser,
+    Query(query): Query<TelemetryFieldsQuery>,
+    JsonBody(body): JsonBody<GetAdHocTelemetryBody>,
+) -> Response {
+    let fields = match query.parse() {
+        Ok(fields) => fields,
+        Err((status_code, error_message)) => {
+            return FallibleJsonResponse::<()>::Err(status_code, error_message).into_response()
+        }
+    };
+
+    match (body.node_id, body.tag) {
+        (Some(node_id), None) => {
+            get_node_ad_hoc_telemetry(&state, &user, node_id, fields.as_deref())
+                .await
+                .into_response()
+        }
+        (None, Some(tag)) => get_tagged_ad_hoc_telemetry(&state, &user, &tag, fields.as_deref())
+            .await
+            .into_response(),
+        _ => FallibleJsonResponse::<()>::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Exactly one of node_id and tag must be given".to_owned(),
+        )
+        .into_response(),
+    }
+}
+
+async fn get_tagged_ad_hoc_telemetry(
+    state: &AppState,
+    user: &AuthedUser,
+    tag: &str,
+    fields: Option<&[String]>,
+) -> FallibleJsonResponse<TaggedAdHocTelemetry> {
+    let node_ids = match nodes::nodes_with_tag(state, tag).await {
+        Ok(node_ids) => node_ids,
+        Err(error_message) => {
+            return FallibleJsonResponse::Err(StatusCode::NOT_FOUND, error_message)
+        }
+    };
+
+    info!(
+        "Requesting ad hoc telemetry from {} nodes tagged {}",
+        node_ids.len(),
+        tag
+    );
+
+    // subscribe before sending the requests so that a quick response can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
+    let sent_at = utils::unix_time_seconds();
+
+    let requests = node_ids
+        .iter()
+        .map(|node_id| {
+            (
+                *node_id,
+                CrisislabMessage {
+                    message: Some(crisislab_message::Message::GetAdHocTelemetry(*node_id)),
+                    destination: None,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (node_id, crisislab_message) in &requests {
+        if let Err(error_message) =
+            send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await
+        {
+            command_history::record(
+                state,
+                *node_id,
+                crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::Failed,
+                Some(error_message.clone()),
+            )
+            .await;
+
+            return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message)
+                .log();
+        }
+    }
+
+    let timeout_duration = Duration::from_secs(
+        state
+            .app_settings
+            .lock()
+            .await
+            .ad_hoc_telemetry_timeout_seconds,
+    );
+
+    let mut telemetry_by_node = HashMap::new();
+
+    // whichever nodes haven't responded by the timeout are reported as missing rather than
+    // failing the whole request
+    let _ = await_mesh_response(&mut mesh_receiver, timeout_duration, |message| {
+        if let Some(crisislab_message::Message::Telemetry(telemetry)) = message.message {
+            if node_ids.contains(&telemetry.node_num) {
+                telemetry_by_node.insert(telemetry.node_num, telemetry);
+            }
+        }
+
+        (telemetry_by_node.len() == node_ids.len()).then_some(())
+    })
+    .await;
+
+    for (node_id, crisislab_message) in &requests {
+        let outcome = if telemetry_by_node.contains_key(node_id) {
+            CommandOutcome::Responded
+        } else {
+            CommandOutcome::TimedOut
+        };
+
+        command_history::record(
+            state,
+            *node_id,
+            crisislab_message,
+            &user.name,
+            sent_at,
+            outcome,
+            None,
+        )
+        .await;
+    }
+
+    let missing = node_ids
+        .into_iter()
+        .filter(|node_id| !telemetry_by_node.contains_key(node_id))
+        .collect();
+
+    FallibleJsonResponse::Ok(TaggedAdHocTelemetry {
+        telemetry: telemetry_by_node
+            .iter()
+            .map(|(node_id, telemetry)| (*node_id, telemetry::to_json(telemetry, fields)))
+            .collect(),
+        missing,
+    })
+}
+
+async fn get_node_ad_hoc_telemetry(
+    state: &AppState,
+    user: &AuthedUser,
+    node_id: NodeId,
+    fields: Option<&[String]>,
+) -> FallibleJsonResponse<serde_json::Value> {
+    info!("Requesting ad hoc telemetry from node {}", node_id);
+
+    // subscribe before sending the request so that a quick response can't be missed
+    let mut mesh_receiver = state.mesh_interface.subscribe();
 
     let crisislab_message = CrisislabMessage {
-        message: Some(crisislab_message::Message::GetAdHocTelemetry(body.node_id)),
+        message: Some(crisislab_message::Message::GetAdHocTelemetry(node_id)),
+        destination: None,
     };
 
+    let sent_at = utils::unix_time_seconds();
+
     if let Err(error_message) =
-        send_command_protobuf(crisislab_message, &state.mesh_interface).await
+        send_command_protobuf(crisislab_message.clone(), &state.mesh_interface).await
     {
-        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+        command_history::record(
+            state,
+            node_id,
+            &crisislab_message,
+            &user.name,
+            sent_at,
+            CommandOutcome::Failed,
+            Some(error_message.clone()),
+        )
+        .await;
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
     }
 
-    let app_settings = state.app_settings.lock().await;
+    let timeout_duration = Duration::from_secs(
+        state
+            .app_settings
+            .lock()
+            .await
+            .ad_hoc_telemetry_timeout_seconds,
+    );
 
-    let telemetry_result: Result<(), String> = await_mesh_response(
-        &mut state.mesh_interface.subscribe(),
-        Duration::from_secs(app_settings.ad_hoc_telemetry_timeout_seconds),
-        |message| {
-            if let Some(crisislab_message::Message::Telemetry(_)) = message.message {
-                Some(())
-            } else {
-                None::<()>
+    // other nodes may be sending live telemetry at the same time, so only accept a packet from
+    // the node we asked
+    match await_mesh_response(
+        &mut mesh_receiver,
+        timeout_duration,
+        |message| match message.message {
+            Some(crisislab_message::Message::Telemetry(telemetry))
+                if telemetry.node_num == node_id =>
+            {
+                Some(telemetry)
             }
+            _ => None,
         },
     )
-    .await;
+    .await
+    {
+        Ok(telemetry) => {
+            debug!("Received ad hoc telemetry from node {}", node_id);
+
+            command_history::record(
+                state,
+                node_id,
+                &crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::Responded,
+                None,
+            )
+            .await;
+
+            FallibleJsonResponse::Ok(telemetry::to_json(&telemetry, fields))
+        }
+        Err(error_message) => {
+            command_history::record(
+                state,
+                node_id,
+                &crisislab_message,
+                &user.name,
+                sent_at,
+                CommandOutcome::TimedOut,
+                Some(error_message.clone()),
+            )
+            .await;
+
+            FallibleJsonResponse::Err(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "{}. Consider increasing ad_hoc_telemetry_timeout_seconds if mesh traffic is high.",
+                    error_message
+                ),
+            )
+            .log()
+        }
+    }
+}
 
-    if telemetry_result.is_ok() {
-        debug!("Detected telemetry packet in get_ad_hoc_telemetry");
-        StringOrEmptyResponse::Ok
-    } else {
-        StringOrEmptyResponse::Err(
-            StatusCode::GATEWAY_TIMEOUT,
-            format!("Timed out waiting for telemetry packet. Consider increasing ad_hoc_telemetry_timeout_seconds if mesh traffic is high.")
-        )
-        .log()
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryStatsQuery {
+    gap_threshold_seconds: Option<u64>,
+}
+
+/// /telemetry/stats
+pub async fn get_telemetry_stats(
+    State(state): State<AppState>,
+    Query(query): Query<TelemetryStatsQuery>,
+) -> Json<HashMap<NodeId, NodeTelemetryStats>> {
+    let gap_threshold_seconds = query
+        .gap_threshold_seconds
+        .unwrap_or(CONFIG.telemetry_gap_threshold_seconds);
+
+    Json(telemetry::compute_stats(
+        &*state.telemetry_cache.lock().await,
+        &*state.telemetry_archive.lock().await,
+        gap_threshold_seconds,
+        utils::unix_time_seconds(),
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryGapsQuery {
+    node_id: NodeId,
+    min_gap_seconds: Option<u64>,
+}
+
+/// /telemetry/gaps
+pub async fn get_telemetry_gaps(
+    State(state): State<AppState>,
+    Query(query): Query<TelemetryGapsQuery>,
+) -> FallibleJsonResponse<Vec<TelemetryGap>> {
+    let min_gap_seconds = query
+        .min_gap_seconds
+        .unwrap_or(CONFIG.telemetry_gap_threshold_seconds);
+
+    let timestamps = telemetry::timestamps_by_node(
+        &*state.telemetry_cache.lock().await,
+        &*state.telemetry_archive.lock().await,
+    );
+
+    match timestamps.get(&query.node_id) {
+        Some(timestamps) => {
+            FallibleJsonResponse::Ok(telemetry::find_gaps(timestamps, min_gap_seconds))
+        }
+        None => FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No telemetry from node {} in the cache", query.node_id),
+        ),
     }
 }
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryFieldsQuery {
+    /// comma-separated telemetry field names, e.g. `battery_level,voltage`
+    fields: Option<String>,
+}
+
+impl TelemetryFieldsQuery {
+    fn parse(&self) -> Result<Option<Vec<String>>, (StatusCode, String)> {
+        self.fields
+            .as_deref()
+            .map(telemetry::parse_fields)
+            .transpose()
+            .map_err(|error_message| (StatusCode::BAD_REQUEST, error_message))
+    }
+}
+
+/// /telemetry/latest
+pub async fn get_latest_telemetry(
+    State(state): State<AppState>,
+    Query(query): Query<TelemetryFieldsQuery>,
+) -> FallibleJsonResponse<HashMap<NodeId, serde_json::Value>> {
+    let fields = match query.parse() {
+        Ok(fields) => fields,
+        Err((status_code, error_message)) => {
+            return FallibleJsonResponse::Err(status_code, error_message)
+        }
+    };
+
+    // the cache is locked before the registry, in the same order as /info/node-status
+    let telemetry_cache = state.telemetry_cache.lock().await;
+    let node_registry = state.node_registry.lock().await;
+
+    FallibleJsonResponse::Ok(
+        telemetry::latest_by_node(&telemetry_cache)
+            .into_iter()
+            .map(|(node_id, telemetry)| {
+                let mut value = telemetry::to_json(telemetry, fields.as_deref());
+
+                if let (Some(object), Some(node_name)) =
+                    (value.as_object_mut(), node_registry.name(node_id))
+                {
+                    object.insert("node_name".to_owned(), node_name.into());
+                }
+
+                (node_id, value)
+            })
+            .collect(),
+    )
+}
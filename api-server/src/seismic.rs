@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+};
+use log::{debug, error};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+use crate::{
+    config::CONFIG,
+    pathfinding::NodeId,
+    presence,
+    proto::meshtastic::SeismicChunk,
+    seismic_triggers,
+    utils::{FallibleJsonResponse, RingBuffer},
+    AppState,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SeismicSample {
+    /// milliseconds since unix epoch
+    pub timestamp_millis: u64,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// Recent accelerometer samples for each node, in ring buffers sized by `SEISMIC_BUFFER_SAMPLES`
+#[derive(Default)]
+pub struct SeismicStore {
+    buffers: HashMap<NodeId, RingBuffer<SeismicSample>>,
+}
+
+impl SeismicStore {
+    pub fn buffer(&self, node_id: NodeId) -> Option<&RingBuffer<SeismicSample>> {
+        self.buffers.get(&node_id)
+    }
+
+    /// Splits a chunk into individual timestamped samples and stores them
+    pub fn record(&mut self, chunk: &SeismicChunk) -> Result<(), String> {
+        if chunk.x.len() != chunk.y.len() || chunk.x.len() != chunk.z.len() {
+            return Err(format!(
+                "Seismic chunk from node {} has mismatched axis lengths ({}, {}, {})",
+                chunk.node_num,
+                chunk.x.len(),
+                chunk.y.len(),
+                chunk.z.len()
+            ));
+        }
+
+        // written this way round so that NaN is rejected too
+        if !(chunk.sample_rate_hz.is_finite() && chunk.sample_rate_hz > 0.0) {
+            return Err(format!(
+                "Seismic chunk from node {} has invalid sample rate {}",
+                chunk.node_num, chunk.sample_rate_hz
+            ));
+        }
+
+        let buffer = self
+            .buffers
+            .entry(chunk.node_num)
+            .or_insert_with(|| RingBuffer::new(CONFIG.seismic_buffer_samples));
+
+        let millis_per_sample = 1000.0 / chunk.sample_rate_hz as f64;
+
+        for (index, ((x, y), z)) in chunk.x.iter().zip(&chunk.y).zip(&chunk.z).enumerate() {
+            buffer.write(SeismicSample {
+                timestamp_millis: chunk
+                    .start_timestamp_millis
+                    .saturating_add((index as f64 * millis_per_sample) as u64),
+                x: *x,
+                y: *y,
+                z: *z,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Spawns the task which decodes seismic chunks from their dedicated MQTT topic and stores them
+pub fn ingest_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting seismic ingest task");
+
+        let mut seismic_receiver = state.mesh_interface.subscribe_seismic();
+
+        loop {
+            match seismic_receiver.recv().await {
+                Ok(bytes) => match SeismicChunk::decode(bytes) {
+                    Ok(chunk) => {
+                        presence::mark_seen(&state, chunk.node_num).await;
+
+                        let result = state.seismic.lock().await.record(&chunk);
+
+                        match result {
+                            Ok(()) => seismic_triggers::detect(&state, &chunk).await,
+                            Err(error_message) => error!("{}", error_message),
+                        }
+                    }
+                    Err(error) => error!("Failed to decode SeismicChunk: {:?}", error),
+                },
+                Err(RecvError::Lagged(count)) => {
+                    error!(
+                        "Seismic ingest task lagged behind, skipped {} chunks",
+                        count
+                    );
+                }
+                Err(RecvError::Closed) => {
+                    error!("Seismic channel closed, stopping seismic ingest task");
+                    return;
+                }
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaveformQuery {
+    node_id: NodeId,
+    /// milliseconds since unix epoch, inclusive
+    from: Option<u64>,
+    /// milliseconds since unix epoch, inclusive
+    to: Option<u64>,
+}
+
+/// Samples in columns rather than as objects, since there can be a lot of them
+#[derive(Serialize, Default)]
+pub struct Waveform {
+    timestamps: Vec<u64>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+}
+
+/// /seismic/waveform
+pub async fn get_waveform(
+    State(state): State<AppState>,
+    Query(query): Query<WaveformQuery>,
+) -> FallibleJsonResponse<Waveform> {
+    let seismic = state.seismic.lock().await;
+
+    let Some(buffer) = seismic.buffer(query.node_id) else {
+        return FallibleJsonResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No seismic data for node {}", query.node_id),
+        );
+    };
+
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(u64::MAX);
+
+    let mut waveform = Waveform::default();
+
+    for sample in buffer
+        .into_iter()
+        .filter(|sample| (from..=to).contains(&sample.timestamp_millis))
+    {
+        waveform.timestamps.push(sample.timestamp_millis);
+        waveform.x.push(sample.x);
+        waveform.y.push(sample.y);
+        waveform.z.push(sample.z);
+    }
+
+    FallibleJsonResponse::Ok(waveform)
+}
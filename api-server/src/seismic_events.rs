@@ -0,0 +1,376 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use axum::{extract::State, Json};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::CONFIG, events::ServerEvent, pathfinding::NodeId, persistence, positions,
+    proto::meshtastic::SeismicChunk, utils::unix_time_seconds, AppState,
+};
+
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// The strongest shaking a node felt during an event
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct NodeShaking {
+    /// peak ground acceleration in m/s^2, with gravity taken out
+    pub pga: f64,
+    /// the PGA as a percentage of standard gravity, which is how it's usually quoted
+    pub pga_percent_g: f64,
+    /// a rough Modified Mercalli intensity worked out from the PGA, from 1 to 10
+    pub intensity: f64,
+    /// milliseconds since unix epoch, by the node's clock
+    pub peak_at_millis: u64,
+    /// where the node is, if it's known, so that the intensities can be mapped
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ShakeEventState {
+    /// nodes are still reporting strong motion
+    Ongoing,
+    /// none have for `SHAKE_WINDOW_SECONDS`
+    Ended,
+}
+
+/// Strong motion felt by several nodes at about the same time, which is most likely an earthquake
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ShakeEvent {
+    pub id: u32,
+    pub state: ShakeEventState,
+    /// seconds since unix epoch, by the server's clock
+    pub started_at: u64,
+    pub last_triggered_at: u64,
+    pub ended_at: Option<u64>,
+    pub max_pga: f64,
+    pub max_intensity: f64,
+    /// the average position of the nodes which felt it, weighted by their PGA, as a rough idea of
+    /// where the shaking was strongest
+    pub centroid: Option<Centroid>,
+    pub nodes: BTreeMap<NodeId, NodeShaking>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+pub struct Centroid {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl ShakeEvent {
+    /// Records a node's shaking, returning whether the event changed
+    fn record(&mut self, node_id: NodeId, shaking: NodeShaking, now: u64) -> bool {
+        self.last_triggered_at = now;
+
+        if self
+            .nodes
+            .get(&node_id)
+            .is_some_and(|previous| previous.pga >= shaking.pga)
+        {
+            return false;
+        }
+
+        self.nodes.insert(node_id, shaking);
+        self.summarise();
+
+        true
+    }
+
+    fn summarise(&mut self) {
+        self.max_pga = self
+            .nodes
+            .values()
+            .map(|shaking| shaking.pga)
+            .fold(0.0, f64::max);
+        self.max_intensity = self
+            .nodes
+            .values()
+            .map(|shaking| shaking.intensity)
+            .fold(0.0, f64::max);
+
+        let positioned = self
+            .nodes
+            .values()
+            .filter_map(|shaking| Some((shaking.latitude?, shaking.longitude?, shaking.pga)))
+            .collect::<Vec<_>>();
+        let total_pga = positioned.iter().map(|(_, _, pga)| pga).sum::<f64>();
+
+        self.centroid = (total_pga > 0.0).then(|| Centroid {
+            latitude: positioned
+                .iter()
+                .map(|(latitude, _, pga)| latitude * pga)
+                .sum::<f64>()
+                / total_pga,
+            longitude: positioned
+                .iter()
+                .map(|(_, longitude, pga)| longitude * pga)
+                .sum::<f64>()
+                / total_pga,
+        });
+    }
+}
+
+/// The peak ground acceleration in a chunk, in m/s^2. Each axis's mean is taken off first, which
+/// removes gravity (and any sensor offset) whichever way up the node is mounted.
+fn peak_ground_acceleration(chunk: &SeismicChunk) -> Option<(f64, usize)> {
+    let mean =
+        |axis: &[f32]| axis.iter().map(|value| *value as f64).sum::<f64>() / axis.len() as f64;
+
+    if chunk.x.is_empty() {
+        return None;
+    }
+
+    let (mean_x, mean_y, mean_z) = (mean(&chunk.x), mean(&chunk.y), mean(&chunk.z));
+
+    chunk
+        .x
+        .iter()
+        .zip(&chunk.y)
+        .zip(&chunk.z)
+        .map(|((x, y), z)| {
+            let (x, y, z) = (*x as f64 - mean_x, *y as f64 - mean_y, *z as f64 - mean_z);
+
+            (x * x + y * y + z * z).sqrt()
+        })
+        .enumerate()
+        .map(|(index, pga)| (pga, index))
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+}
+
+/// A rough Modified Mercalli intensity from PGA, using Wald et al. (1999)'s relationships for
+/// California, which is what ShakeMap uses. It's only meant to give a sense of scale.
+fn intensity(pga: f64) -> f64 {
+    // the relationship for weak shaking takes over below where the two cross, at about 66 cm/s^2
+    let log_pga = (pga * 100.0).log10();
+
+    (3.66 * log_pga - 1.66)
+        .max(2.20 * log_pga + 1.00)
+        .clamp(1.0, 10.0)
+}
+
+/// Strong motion from one node which hasn't been grouped into an event yet
+struct Trigger {
+    node_id: NodeId,
+    shaking: NodeShaking,
+    /// seconds since unix epoch that the server received it
+    received_at: u64,
+}
+
+/// Groups nodes' strong motion into events. Once `SHAKE_MIN_NODES` nodes have triggered with PGA of
+/// at least `SHAKE_TRIGGER_PGA` within `SHAKE_WINDOW_SECONDS` of each other, an event starts, and it
+/// lasts until none have for `SHAKE_WINDOW_SECONDS`. The most recent
+/// `SEISMIC_EVENT_HISTORY_CAPACITY` events are saved to `seismic-events.json` in the data
+/// directory whenever one ends.
+#[derive(Default)]
+pub struct SeismicEventStore {
+    triggers: VecDeque<Trigger>,
+    ongoing: Option<ShakeEvent>,
+    /// oldest first
+    ended: VecDeque<ShakeEvent>,
+}
+
+impl SeismicEventStore {
+    pub fn restore(&mut self, events: Vec<ShakeEvent>) {
+        self.ended = events.into();
+
+        while self.ended.len() > CONFIG.seismic_event_history_capacity {
+            self.ended.pop_front();
+        }
+    }
+
+    pub fn ended(&self) -> impl DoubleEndedIterator<Item = &ShakeEvent> {
+        self.ended.iter()
+    }
+
+    fn next_id(&self) -> u32 {
+        self.ended.back().map_or(1, |event| event.id + 1)
+    }
+
+    /// Records a node's strong motion, returning the event if it changed, and whether it has just
+    /// started
+    fn trigger(
+        &mut self,
+        node_id: NodeId,
+        shaking: NodeShaking,
+        now: u64,
+    ) -> Option<(ShakeEvent, bool)> {
+        if let Some(event) = &mut self.ongoing {
+            return event
+                .record(node_id, shaking, now)
+                .then(|| (event.clone(), false));
+        }
+
+        self.triggers
+            .retain(|trigger| trigger.received_at + CONFIG.shake_window_seconds >= now);
+        self.triggers.push_back(Trigger {
+            node_id,
+            shaking,
+            received_at: now,
+        });
+
+        let mut nodes = BTreeMap::<NodeId, NodeShaking>::new();
+
+        for trigger in &self.triggers {
+            if nodes
+                .get(&trigger.node_id)
+                .is_none_or(|previous| previous.pga < trigger.shaking.pga)
+            {
+                nodes.insert(trigger.node_id, trigger.shaking.clone());
+            }
+        }
+
+        if nodes.len() < CONFIG.shake_min_nodes {
+            return None;
+        }
+
+        let mut event = ShakeEvent {
+            id: self.next_id(),
+            state: ShakeEventState::Ongoing,
+            started_at: self
+                .triggers
+                .front()
+                .map_or(now, |trigger| trigger.received_at),
+            last_triggered_at: now,
+            ended_at: None,
+            max_pga: 0.0,
+            max_intensity: 0.0,
+            centroid: None,
+            nodes,
+        };
+
+        event.summarise();
+
+        self.triggers.clear();
+        self.ongoing = Some(event.clone());
+
+        Some((event, true))
+    }
+
+    /// Ends the ongoing event if nodes have stopped reporting strong motion, returning it
+    fn end_if_quiet(&mut self, now: u64) -> Option<ShakeEvent> {
+        if self
+            .ongoing
+            .as_ref()
+            .is_none_or(|event| event.last_triggered_at + CONFIG.shake_window_seconds > now)
+        {
+            return None;
+        }
+
+        let mut event = self.ongoing.take()?;
+
+        event.state = ShakeEventState::Ended;
+        event.ended_at = Some(now);
+
+        self.ended.push_back(event.clone());
+
+        while self.ended.len() > CONFIG.seismic_event_history_capacity {
+            self.ended.pop_front();
+        }
+
+        Some(event)
+    }
+}
+
+/// Checks a chunk from a triggered node for strong motion, and starts or updates an event if there's
+/// enough of it
+pub async fn record_chunk(state: &AppState, chunk: &SeismicChunk) {
+    let Some((pga, peak_index)) = peak_ground_acceleration(chunk) else {
+        return;
+    };
+
+    if pga < CONFIG.shake_trigger_pga {
+        return;
+    }
+
+    debug!("Node {} felt strong motion: {} m/s^2", chunk.node_num, pga);
+
+    let position = positions::known_positions(state)
+        .await
+        .remove(&chunk.node_num);
+
+    let shaking = NodeShaking {
+        pga,
+        pga_percent_g: pga / STANDARD_GRAVITY * 100.0,
+        intensity: intensity(pga),
+        peak_at_millis: chunk
+            .start_timestamp_millis
+            .saturating_add((peak_index as f64 * 1000.0 / chunk.sample_rate_hz as f64) as u64),
+        latitude: position.map(|position| position.latitude),
+        longitude: position.map(|position| position.longitude),
+    };
+
+    let Some((event, started)) =
+        state
+            .seismic_events
+            .lock()
+            .await
+            .trigger(chunk.node_num, shaking, unix_time_seconds())
+    else {
+        return;
+    };
+
+    if started {
+        warn!(
+            "Shake event {} started, felt by nodes {:?}",
+            event.id,
+            event.nodes.keys().collect::<Vec<_>>()
+        );
+    }
+
+    // an error here just means there aren't any websocket clients connected
+    let _ = state.server_events.send(ServerEvent::ShakeEvent(event));
+}
+
+/// Spawns the task which ends shake events once nodes stop reporting strong motion
+pub fn end_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting shake event end task");
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            let Some(event) = state
+                .seismic_events
+                .lock()
+                .await
+                .end_if_quiet(unix_time_seconds())
+            else {
+                continue;
+            };
+
+            info!(
+                "Shake event {} ended: felt by {} nodes, max PGA {:.3} m/s^2, max intensity {:.1}",
+                event.id,
+                event.nodes.len(),
+                event.max_pga,
+                event.max_intensity
+            );
+
+            let _ = state.server_events.send(ServerEvent::ShakeEvent(event));
+
+            if let Err(error_message) = persistence::save_seismic_events(&state).await {
+                error!("{}", error_message);
+            }
+        }
+    })
+}
+
+/// /seismic/events
+pub async fn get_seismic_events(State(state): State<AppState>) -> Json<Vec<ShakeEvent>> {
+    let seismic_events = state.seismic_events.lock().await;
+
+    Json(
+        seismic_events
+            .ongoing
+            .iter()
+            .chain(seismic_events.ended().rev())
+            .cloned()
+            .collect(),
+    )
+}
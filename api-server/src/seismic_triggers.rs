@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+
+use axum::{extract::State, Json};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG, events::ServerEvent, pathfinding::NodeId, proto::meshtastic::SeismicChunk,
+    seismic_events, AppState,
+};
+
+/// A node's STA/LTA detector triggering, which is likely the start of some shaking
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SeismicTrigger {
+    pub node_id: NodeId,
+    /// milliseconds since unix epoch, by the node's clock
+    pub triggered_at_millis: u64,
+    /// milliseconds since unix epoch, by the node's clock. None while still triggered.
+    pub ended_at_millis: Option<u64>,
+    /// the highest STA/LTA ratio while triggered
+    pub peak_ratio: f64,
+    /// the largest acceleration (in m/s^2, with gravity taken out) while triggered
+    pub peak_amplitude: f64,
+}
+
+/// A recursive short-term average/long-term average detector for one node's samples. Both averages
+/// are of the squared acceleration, after each axis's long-term mean is taken off to remove
+/// gravity.
+#[derive(Default)]
+struct Detector {
+    mean: [f64; 3],
+    short_term_average: f64,
+    long_term_average: f64,
+    /// seconds of samples seen, so that it doesn't trigger before the long-term average has settled
+    warmed_up_seconds: f64,
+    last_timestamp_millis: u64,
+    current: Option<SeismicTrigger>,
+}
+
+impl Detector {
+    /// Runs the chunk's samples through the detector, returning any triggers which started or
+    /// ended, and whether it was triggered at any point during the chunk
+    fn process(&mut self, chunk: &SeismicChunk) -> (Vec<SeismicTrigger>, bool) {
+        let mut changes = Vec::new();
+
+        // a long gap means the averages don't describe the current noise any more
+        if self.warmed_up_seconds > 0.0
+            && chunk.start_timestamp_millis
+                > self
+                    .last_timestamp_millis
+                    .saturating_add((CONFIG.sta_lta_long_seconds * 1000.0) as u64)
+        {
+            if let Some(mut trigger) = self.current.take() {
+                trigger.ended_at_millis = Some(self.last_timestamp_millis);
+                changes.push(trigger);
+            }
+
+            *self = Detector::default();
+        }
+
+        let seconds_per_sample = 1.0 / chunk.sample_rate_hz as f64;
+        let short_term_weight = (seconds_per_sample / CONFIG.sta_lta_short_seconds).min(1.0);
+        let long_term_weight = (seconds_per_sample / CONFIG.sta_lta_long_seconds).min(1.0);
+
+        let mut triggered = self.current.is_some();
+
+        for (index, ((x, y), z)) in chunk.x.iter().zip(&chunk.y).zip(&chunk.z).enumerate() {
+            let timestamp_millis = chunk
+                .start_timestamp_millis
+                .saturating_add((index as f64 * seconds_per_sample * 1000.0) as u64);
+            let sample = [*x as f64, *y as f64, *z as f64];
+
+            if self.warmed_up_seconds == 0.0 {
+                self.mean = sample;
+            }
+
+            let mut squared_amplitude = 0.0;
+
+            for (mean, value) in self.mean.iter_mut().zip(sample) {
+                *mean += (value - *mean) * long_term_weight;
+                squared_amplitude += (value - *mean).powi(2);
+            }
+
+            self.short_term_average +=
+                (squared_amplitude - self.short_term_average) * short_term_weight;
+
+            // the long-term average is held while triggered, so that the shaking doesn't raise it
+            // enough to end the trigger early
+            if self.current.is_none() {
+                self.long_term_average +=
+                    (squared_amplitude - self.long_term_average) * long_term_weight;
+            }
+
+            self.warmed_up_seconds += seconds_per_sample;
+            self.last_timestamp_millis = timestamp_millis;
+
+            if self.warmed_up_seconds < CONFIG.sta_lta_long_seconds || self.long_term_average <= 0.0
+            {
+                continue;
+            }
+
+            let ratio = self.short_term_average / self.long_term_average;
+            let amplitude = squared_amplitude.sqrt();
+
+            match &mut self.current {
+                None if ratio >= CONFIG.sta_lta_trigger_ratio => {
+                    let trigger = SeismicTrigger {
+                        node_id: chunk.node_num,
+                        triggered_at_millis: timestamp_millis,
+                        ended_at_millis: None,
+                        peak_ratio: ratio,
+                        peak_amplitude: amplitude,
+                    };
+
+                    changes.push(trigger.clone());
+                    self.current = Some(trigger);
+                    triggered = true;
+                }
+                None => {}
+                Some(trigger) => {
+                    trigger.peak_ratio = trigger.peak_ratio.max(ratio);
+                    trigger.peak_amplitude = trigger.peak_amplitude.max(amplitude);
+
+                    if ratio < CONFIG.sta_lta_detrigger_ratio {
+                        trigger.ended_at_millis = Some(timestamp_millis);
+                        changes.extend(self.current.take());
+                    }
+                }
+            }
+        }
+
+        (changes, triggered)
+    }
+}
+
+/// An STA/LTA detector for each node streaming seismic data, and the most recent
+/// `SEISMIC_TRIGGER_HISTORY_CAPACITY` triggers which have ended, oldest first
+#[derive(Default)]
+pub struct SeismicTriggerStore {
+    detectors: HashMap<NodeId, Detector>,
+    ended: VecDeque<SeismicTrigger>,
+}
+
+impl SeismicTriggerStore {
+    fn record_ended(&mut self, trigger: SeismicTrigger) {
+        self.ended.push_back(trigger);
+
+        while self.ended.len() > CONFIG.seismic_trigger_history_capacity {
+            self.ended.pop_front();
+        }
+    }
+}
+
+/// Runs a chunk through its node's detector, sends any triggers to websocket clients, and passes
+/// the chunk on to be grouped into a shake event if the node was triggered
+pub async fn detect(state: &AppState, chunk: &SeismicChunk) {
+    let (changes, triggered) = {
+        let mut seismic_triggers = state.seismic_triggers.lock().await;
+
+        let (changes, triggered) = seismic_triggers
+            .detectors
+            .entry(chunk.node_num)
+            .or_default()
+            .process(chunk);
+
+        for trigger in &changes {
+            if trigger.ended_at_millis.is_some() {
+                seismic_triggers.record_ended(trigger.clone());
+            }
+        }
+
+        (changes, triggered)
+    };
+
+    for trigger in changes {
+        match trigger.ended_at_millis {
+            None => info!(
+                "Node {} triggered: STA/LTA {:.1}, {:.3} m/s^2",
+                trigger.node_id, trigger.peak_ratio, trigger.peak_amplitude
+            ),
+            Some(_) => debug!(
+                "Node {} trigger ended: peak STA/LTA {:.1}, peak {:.3} m/s^2",
+                trigger.node_id, trigger.peak_ratio, trigger.peak_amplitude
+            ),
+        }
+
+        // an error here just means there aren't any websocket clients connected
+        let _ = state
+            .server_events
+            .send(ServerEvent::SeismicTrigger(trigger));
+    }
+
+    if triggered {
+        seismic_events::record_chunk(state, chunk).await;
+    }
+}
+
+/// /seismic/triggers
+pub async fn get_seismic_triggers(State(state): State<AppState>) -> Json<Vec<SeismicTrigger>> {
+    let seismic_triggers = state.seismic_triggers.lock().await;
+
+    Json(
+        seismic_triggers
+            .detectors
+            .values()
+            .filter_map(|detector| detector.current.clone())
+            .chain(seismic_triggers.ended.iter().rev().cloned())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    const SAMPLE_RATE_HZ: f32 = 100.0;
+    const START_MILLIS: u64 = 1_700_000_000_000;
+    /// m/s^2 either side of the mean, on one axis
+    const NOISE: f32 = 0.01;
+    const SHAKING: f32 = 1.0;
+
+    /// A second of samples starting `second` seconds in, alternating by `amplitude` around gravity
+    fn chunk(second: u64, amplitude: f32) -> SeismicChunk {
+        let samples = SAMPLE_RATE_HZ as usize;
+
+        SeismicChunk {
+            node_num: 1,
+            start_timestamp_millis: START_MILLIS + second * 1000,
+            sample_rate_hz: SAMPLE_RATE_HZ,
+            x: (0..samples)
+                .map(|i| if i % 2 == 0 { amplitude } else { -amplitude })
+                .collect(),
+            y: vec![0.0; samples],
+            z: vec![9.81; samples],
+        }
+    }
+
+    /// A detector which has seen a minute of background noise
+    fn warmed_up_detector() -> Detector {
+        let mut detector = Detector::default();
+
+        for second in 0..60 {
+            let (changes, triggered) = detector.process(&chunk(second, NOISE));
+
+            assert!(changes.is_empty(), "Triggered on noise at {}s", second);
+            assert!(!triggered);
+        }
+
+        detector
+    }
+
+    #[test]
+    fn triggers_and_detriggers_on_a_step() {
+        config::load_defaults_for_tests();
+
+        let mut detector = warmed_up_detector();
+
+        let (changes, triggered) = detector.process(&chunk(60, SHAKING));
+
+        assert!(triggered);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].triggered_at_millis, START_MILLIS + 60_000);
+        assert_eq!(changes[0].ended_at_millis, None);
+        assert!(changes[0].peak_ratio >= CONFIG.sta_lta_trigger_ratio);
+
+        let (changes, triggered) = detector.process(&chunk(61, SHAKING));
+
+        assert!(triggered);
+        assert!(changes.is_empty());
+
+        let mut ended = Vec::new();
+
+        for second in 62..100 {
+            ended.extend(detector.process(&chunk(second, NOISE)).0);
+        }
+
+        assert_eq!(ended.len(), 1);
+
+        let trigger = &ended[0];
+        let ended_at_millis = trigger.ended_at_millis.expect("It should have ended");
+
+        assert!(ended_at_millis > START_MILLIS + 62_000);
+        assert!(trigger.peak_ratio >= CONFIG.sta_lta_trigger_ratio);
+        assert!((trigger.peak_amplitude - SHAKING as f64).abs() < 0.01);
+        assert!(detector.current.is_none());
+
+        // and it can trigger again
+        let (changes, triggered) = detector.process(&chunk(100, SHAKING));
+
+        assert!(triggered);
+        assert_eq!(changes.len(), 1);
+    }
+
+    #[test]
+    fn does_not_trigger_while_warming_up() {
+        config::load_defaults_for_tests();
+
+        let mut detector = Detector::default();
+
+        for second in 0..5 {
+            detector.process(&chunk(second, NOISE));
+        }
+
+        let (changes, triggered) = detector.process(&chunk(5, SHAKING));
+
+        assert!(changes.is_empty());
+        assert!(!triggered);
+    }
+
+    #[test]
+    fn a_long_gap_ends_the_trigger_and_warms_up_again() {
+        config::load_defaults_for_tests();
+
+        let mut detector = warmed_up_detector();
+        detector.process(&chunk(60, SHAKING));
+
+        let last_sample_millis = START_MILLIS + 60_000 + 990;
+        let (changes, triggered) = detector.process(&chunk(600, SHAKING));
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].ended_at_millis, Some(last_sample_millis));
+        assert!(!triggered);
+        assert!(detector.warmed_up_seconds < CONFIG.sta_lta_long_seconds);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::{
+    maintenance::MaintenanceEvent, pathfinding::NodeId, presence::PresenceState,
+    telemetry::latest_by_node, AppState,
+};
+
+/// Everything the dashboard's main table needs to know about a node
+#[derive(Serialize)]
+pub struct NodeStatus {
+    /// from the node registry
+    name: Option<String>,
+    /// 0 to 100, from the node's battery, how regularly it reports, its best link and how often it
+    /// reboots
+    health_score: Option<u8>,
+    /// `None` if the node has never been heard from directly (e.g. it only appears as a neighbour
+    /// in another node's signal data)
+    state: Option<PresenceState>,
+    last_seen: Option<u64>,
+    is_gateway: bool,
+    next_hops: Vec<NodeId>,
+    battery_level: Option<u32>,
+    voltage: Option<f32>,
+    /// strongest link the node can currently hear
+    best_rssi: Option<i32>,
+    best_snr: Option<f32>,
+    /// the most recent work done on the node, e.g. a battery replacement
+    last_maintenance: Option<MaintenanceEvent>,
+}
+
+/// /info/node-status
+pub async fn get_node_status(State(state): State<AppState>) -> Json<BTreeMap<NodeId, NodeStatus>> {
+    let presence = state.presence.lock().await;
+    let topology = state.topology.lock().await;
+    let telemetry_cache = state.telemetry_cache.lock().await;
+    let node_registry = state.node_registry.lock().await;
+    let health = state.health.lock().await;
+    let maintenance_log = state.maintenance_log.lock().await;
+
+    let latest_telemetry = latest_by_node(&telemetry_cache);
+
+    let known_node_ids = presence
+        .nodes()
+        .keys()
+        .chain(latest_telemetry.keys())
+        .chain(topology.links.keys())
+        .chain(topology.links.values().flat_map(|links| links.keys()))
+        .chain(topology.gateway_ids.iter())
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    Json(
+        known_node_ids
+            .into_iter()
+            .map(|node_id| {
+                let node_presence = presence.nodes().get(&node_id);
+                let device_metrics = latest_telemetry
+                    .get(&node_id)
+                    .and_then(|telemetry| telemetry.device_metrics);
+                let links = topology.links.get(&node_id);
+
+                (
+                    node_id,
+                    NodeStatus {
+                        name: node_registry.name(node_id),
+                        health_score: health.score(node_id),
+                        state: node_presence.map(|presence| presence.state),
+                        last_seen: node_presence.map(|presence| presence.last_seen),
+                        is_gateway: topology.gateway_ids.contains(&node_id),
+                        next_hops: topology
+                            .next_hops
+                            .get(&node_id)
+                            .cloned()
+                            .unwrap_or_default(),
+                        battery_level: device_metrics.and_then(|metrics| metrics.battery_level),
+                        voltage: device_metrics.and_then(|metrics| metrics.voltage),
+                        best_rssi: links
+                            .and_then(|links| links.values().map(|reading| reading.rssi).max()),
+                        best_snr: links.and_then(|links| {
+                            links.values().map(|reading| reading.snr).reduce(f32::max)
+                        }),
+                        last_maintenance: maintenance_log.latest(node_id).cloned(),
+                    },
+                )
+            })
+            .collect(),
+    )
+}
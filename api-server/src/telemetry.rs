@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use log::{debug, error};
+use once_cell::sync::Lazy;
+use prost::Message;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+use crate::{
+    alerts, anomaly,
+    archive::TelemetryArchive,
+    battery, capabilities, emergency_alerts,
+    events::{ServerEvent, TelemetryEvent},
+    firmware, messages,
+    pathfinding::NodeId,
+    presence,
+    proto::meshtastic::{
+        crisislab_message::{self, Telemetry},
+        CrisislabMessage,
+    },
+    utils::RingBuffer,
+    AppState,
+};
+
+/// Returns the most recent telemetry packet from each node in the cache
+pub fn latest_by_node(cache: &RingBuffer<Telemetry>) -> HashMap<NodeId, &Telemetry> {
+    let mut result = HashMap::new();
+
+    // the cache iterates from oldest to newest so later entries overwrite earlier ones
+    for telemetry in cache {
+        result.insert(telemetry.node_num, telemetry);
+    }
+
+    result
+}
+
+/// Returns the timestamps of every cached and archived telemetry packet grouped by node, in
+/// ascending order
+pub fn timestamps_by_node(
+    cache: &RingBuffer<Telemetry>,
+    archive: &TelemetryArchive,
+) -> HashMap<NodeId, Vec<u64>> {
+    let mut result = HashMap::<NodeId, Vec<u64>>::new();
+
+    for node_id in archive.node_ids() {
+        result
+            .entry(node_id)
+            .or_default()
+            .extend(archive.timestamps(node_id));
+    }
+
+    for telemetry in cache {
+        result
+            .entry(telemetry.node_num)
+            .or_default()
+            .push(telemetry.timestamp);
+    }
+
+    // nodes' clocks aren't guaranteed to agree with the order packets arrive in
+    for timestamps in result.values_mut() {
+        timestamps.sort_unstable();
+    }
+
+    result
+}
+
+/// Shorter names which can be given as fields, matching the ones used in alert rules
+const FIELD_ALIASES: &[(&str, &str)] = &[
+    ("battery", "battery_level"),
+    ("humidity", "relative_humidity"),
+    ("pressure", "barometric_pressure"),
+];
+
+/// Every field name in JSON serialised telemetry, including the ones in nested objects
+static FIELD_NAMES: Lazy<HashSet<String>> = Lazy::new(|| {
+    fn collect_keys(value: &Value, keys: &mut HashSet<String>) {
+        if let Value::Object(object) = value {
+            for (key, value) in object {
+                keys.insert(key.clone());
+                collect_keys(value, keys);
+            }
+        }
+    }
+
+    let telemetry = Telemetry {
+        user: Some(Default::default()),
+        position: Some(Default::default()),
+        device_metrics: Some(Default::default()),
+        environment_metrics: Some(Default::default()),
+        ..Default::default()
+    };
+
+    let mut keys = HashSet::new();
+    collect_keys(
+        &serde_json::to_value(telemetry).expect("Failed to serialise telemetry"),
+        &mut keys,
+    );
+    keys
+});
+
+/// Parses a comma-separated `?fields=` list, resolving aliases. Returns an error naming the first
+/// field that telemetry doesn't have.
+pub fn parse_fields(fields: &str) -> Result<Vec<String>, String> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let field = FIELD_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == field)
+                .map_or(field, |(_, name)| name);
+
+            if FIELD_NAMES.contains(field) {
+                Ok(field.to_owned())
+            } else {
+                Err(format!("Telemetry doesn't have a field called {:?}", field))
+            }
+        })
+        .collect()
+}
+
+/// Keeps only the requested fields of a JSON object, searching nested objects (e.g.
+/// `device_metrics`) for fields which aren't at the top level
+fn retain_fields(object: Map<String, Value>, fields: &[String]) -> Map<String, Value> {
+    object
+        .into_iter()
+        .filter_map(|(key, value)| {
+            if fields.contains(&key) {
+                Some((key, value))
+            } else if let Value::Object(nested) = value {
+                let nested = retain_fields(nested, fields);
+                (!nested.is_empty()).then_some((key, Value::Object(nested)))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Serialises telemetry with only the given fields, plus the node number and timestamp so that
+/// the result still means something on its own
+pub fn project_fields(telemetry: &Telemetry, fields: &[String]) -> Value {
+    let mut projected = match serde_json::to_value(telemetry) {
+        Ok(Value::Object(object)) => retain_fields(object, fields),
+        _ => Map::new(),
+    };
+
+    projected.insert("node_num".to_owned(), telemetry.node_num.into());
+    projected.insert("timestamp".to_owned(), telemetry.timestamp.into());
+
+    Value::Object(projected)
+}
+
+/// Serialises telemetry, with only the given fields if there are any
+pub fn to_json(telemetry: &Telemetry, fields: Option<&[String]>) -> Value {
+    match fields {
+        Some(fields) => project_fields(telemetry, fields),
+        None => serde_json::to_value(telemetry).expect("Failed to serialise telemetry"),
+    }
+}
+
+/// A period during which a node didn't report any telemetry
+#[derive(Serialize, Debug)]
+pub struct TelemetryGap {
+    /// timestamp of the last reading before the gap
+    pub from: u64,
+    /// timestamp of the first reading after the gap
+    pub to: u64,
+    pub duration_seconds: u64,
+}
+
+/// Finds every gap between consecutive (sorted) timestamps that's at least `min_gap_seconds` long
+pub fn find_gaps(timestamps: &[u64], min_gap_seconds: u64) -> Vec<TelemetryGap> {
+    timestamps
+        .windows(2)
+        .filter_map(|pair| {
+            let duration_seconds = pair[1].saturating_sub(pair[0]);
+
+            if duration_seconds >= min_gap_seconds {
+                Some(TelemetryGap {
+                    from: pair[0],
+                    to: pair[1],
+                    duration_seconds,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct NodeTelemetryStats {
+    message_count: usize,
+    messages_per_minute: f64,
+    first_seen: u64,
+    last_seen: u64,
+    seconds_since_last_reading: u64,
+    gaps: Vec<TelemetryGap>,
+}
+
+/// Computes throughput and gap statistics for every node that has cached or archived telemetry
+pub fn compute_stats(
+    cache: &RingBuffer<Telemetry>,
+    archive: &TelemetryArchive,
+    gap_threshold_seconds: u64,
+    now: u64,
+) -> HashMap<NodeId, NodeTelemetryStats> {
+    timestamps_by_node(cache, archive)
+        .into_iter()
+        .filter_map(|(node_id, timestamps)| {
+            let first_seen = *timestamps.first()?;
+            let last_seen = *timestamps.last()?;
+
+            // a single reading doesn't give us a rate so treat the window as at least a minute
+            let window_minutes = ((last_seen - first_seen) as f64 / 60.0).max(1.0);
+
+            Some((
+                node_id,
+                NodeTelemetryStats {
+                    message_count: timestamps.len(),
+                    messages_per_minute: timestamps.len() as f64 / window_minutes,
+                    first_seen,
+                    last_seen,
+                    seconds_since_last_reading: now.saturating_sub(last_seen),
+                    gaps: find_gaps(&timestamps, gap_threshold_seconds),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// How many recent telemetry packets to remember for deduplication. Copies forwarded by different
+/// gateways arrive within seconds of each other, so this only needs to cover a short window.
+const RECENT_TELEMETRY_CAPACITY: usize = 1024;
+
+/// Remembers which telemetry packets have been seen recently, so that a packet forwarded by more
+/// than one gateway is only processed once
+#[derive(Default)]
+pub struct RecentTelemetry {
+    seen: HashSet<(NodeId, u64)>,
+    order: VecDeque<(NodeId, u64)>,
+}
+
+impl RecentTelemetry {
+    /// Records the packet, returning `false` if it has already been seen
+    pub fn insert(&mut self, telemetry: &Telemetry) -> bool {
+        let key = (telemetry.node_num, telemetry.timestamp);
+
+        if !self.seen.insert(key) {
+            return false;
+        }
+
+        if self.order.len() >= RECENT_TELEMETRY_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(key);
+
+        true
+    }
+}
+
+async fn on_message_from_mesh(
+    state: &AppState,
+    recent_telemetry: &mut RecentTelemetry,
+    crisislab_message: CrisislabMessage,
+) {
+    match crisislab_message.message {
+        Some(crisislab_message::Message::Telemetry(telemetry)) => {
+            if !recent_telemetry.insert(&telemetry) {
+                debug!(
+                    "Ignoring duplicate telemetry from node {} at {}",
+                    telemetry.node_num, telemetry.timestamp
+                );
+                return;
+            }
+
+            presence::mark_seen(state, telemetry.node_num).await;
+
+            let node_tags = state.node_registry.lock().await.tags(telemetry.node_num);
+            let alert_events = state
+                .alerts
+                .lock()
+                .await
+                .evaluate_telemetry(&telemetry, &node_tags);
+            alerts::dispatch(state, alert_events).await;
+
+            let anomalies = state
+                .anomaly_detector
+                .lock()
+                .await
+                .check_telemetry(&telemetry);
+            anomaly::dispatch(state, anomalies).await;
+
+            let warnings = state.battery_tracker.lock().await.record(&telemetry);
+            battery::dispatch(state, warnings).await;
+
+            state.positions.lock().await.record(&telemetry);
+
+            state.health.lock().await.record(&telemetry);
+
+            firmware::record_firmware(state, &telemetry).await;
+
+            let node_name = state.node_registry.lock().await.name(telemetry.node_num);
+
+            let _ = state
+                .server_events
+                .send(ServerEvent::Telemetry(Box::new(TelemetryEvent {
+                    telemetry: telemetry.clone(),
+                    replay: false,
+                    node_name,
+                })));
+
+            let evicted = state.telemetry_cache.lock().await.write(telemetry);
+
+            if let Some(evicted) = evicted {
+                state.telemetry_archive.lock().await.record(&evicted);
+            }
+        }
+        Some(crisislab_message::Message::SignalData(signal_data)) => {
+            presence::mark_seen(state, signal_data.to).await;
+
+            let node_tags = state.node_registry.lock().await.tags(signal_data.to);
+            let alert_events = state
+                .alerts
+                .lock()
+                .await
+                .evaluate_signal_data(&signal_data, &node_tags);
+            alerts::dispatch(state, alert_events).await;
+
+            let anomalies = state
+                .anomaly_detector
+                .lock()
+                .await
+                .check_signal_data(&signal_data);
+            anomaly::dispatch(state, anomalies).await;
+
+            state.topology.lock().await.record_signal_data(&signal_data);
+
+            let _ = state
+                .server_events
+                .send(ServerEvent::SignalData(signal_data));
+        }
+        Some(crisislab_message::Message::FirmwareUpdateProgress(progress)) => {
+            presence::mark_seen(state, progress.node_num).await;
+
+            firmware::record_progress(state, &progress).await;
+        }
+        Some(crisislab_message::Message::DiscoveryResponse(response)) => {
+            presence::mark_seen(state, response.node_num).await;
+        }
+        Some(crisislab_message::Message::Capabilities(capabilities)) => {
+            presence::mark_seen(state, capabilities.node_num).await;
+
+            capabilities::record_capabilities(state, capabilities).await;
+        }
+        Some(crisislab_message::Message::DeliveryReport(report)) => {
+            emergency_alerts::record_delivery_report(state, &report).await;
+            messages::record_delivery_report(state, report).await;
+        }
+        Some(crisislab_message::Message::AlertAck(ack)) => {
+            presence::mark_seen(state, ack.node_num).await;
+
+            emergency_alerts::record_ack(state, ack).await;
+        }
+        Some(crisislab_message::Message::CommandAck(ack)) => {
+            presence::mark_seen(state, ack.node_num).await;
+        }
+        Some(crisislab_message::Message::TracerouteResponse(response)) => {
+            presence::mark_seen(state, response.node_num).await;
+        }
+        Some(crisislab_message::Message::EchoReply(reply)) => {
+            presence::mark_seen(state, reply.node_num).await;
+        }
+        _ => {}
+    }
+}
+
+/// Spawns the task which decodes every message coming from the mesh and keeps the telemetry cache
+/// and topology up to date, regardless of whether any clients are connected. Deduplicated
+/// telemetry is forwarded to live websocket clients from here.
+pub fn ingest_task(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting telemetry ingest task");
+
+        let mut mesh_receiver = state.mesh_interface.subscribe();
+        let mut recent_telemetry = RecentTelemetry::default();
+
+        loop {
+            match mesh_receiver.recv().await {
+                Ok(bytes) => match CrisislabMessage::decode(bytes) {
+                    Ok(crisislab_message) => {
+                        on_message_from_mesh(&state, &mut recent_telemetry, crisislab_message).await
+                    }
+                    Err(error) => {
+                        error!("Ingest task failed to decode CrisislabMessage: {:?}", error);
+
+                        // notify clients of decoding error
+                        let _ = state.server_events.send(ServerEvent::Error(format!(
+                            "Failed to decode CrisislabMessage: {:?}",
+                            error
+                        )));
+                    }
+                },
+                Err(RecvError::Lagged(count)) => {
+                    error!(
+                        "Ingest task lagged behind the mesh, skipped {} messages",
+                        count
+                    );
+                }
+                Err(RecvError::Closed) => {
+                    error!("Mesh channel closed, stopping ingest task");
+                    return;
+                }
+            }
+        }
+    })
+}
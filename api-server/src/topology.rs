@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{
+    config::CONFIG,
+    pathfinding::{compute_edge_weight_proportionalised, EdgeWeight, NodeId},
+    proto::meshtastic::crisislab_message::SignalData,
+    utils::unix_time_seconds,
+    AppState,
+};
+
+/// The most recent signal reading for a link between two nodes
+#[derive(Clone, Copy, Serialize, Debug)]
+pub struct LinkReading {
+    pub rssi: i32,
+    pub snr: f32,
+    /// the link's edge weight, smoothed over every reading with `LINK_WEIGHT_SMOOTHING` so that one
+    /// noisy reading doesn't swing it
+    pub weight: EdgeWeight,
+    /// seconds since unix epoch
+    pub last_heard: u64,
+}
+
+/// What the server currently knows about the shape of the mesh. Links are recorded whenever signal
+/// data comes in from the mesh, and the gateway set and next hops are replaced after each route
+/// update.
+#[derive(Clone, Default, Serialize)]
+pub struct Topology {
+    /// Maps a receiving node to the nodes it can hear (i.e. `links[to][from]`)
+    pub links: HashMap<NodeId, HashMap<NodeId, LinkReading>>,
+    pub gateway_ids: BTreeSet<NodeId>,
+    pub next_hops: HashMap<NodeId, Vec<NodeId>>,
+}
+
+impl Topology {
+    pub fn record_signal_data(&mut self, signal_data: &SignalData) {
+        if signal_data.is_gateway {
+            self.gateway_ids.insert(signal_data.to);
+        }
+
+        let now = unix_time_seconds();
+        let links = self.links.entry(signal_data.to).or_default();
+
+        for edge in &signal_data.links {
+            let weight = compute_edge_weight_proportionalised(edge.rssi, edge.snr);
+
+            let weight = match links.get(&edge.from) {
+                Some(previous) => {
+                    previous.weight + CONFIG.link_weight_smoothing * (weight - previous.weight)
+                }
+                None => weight,
+            };
+
+            links.insert(
+                edge.from,
+                LinkReading {
+                    rssi: edge.rssi,
+                    snr: edge.snr,
+                    weight,
+                    last_heard: now,
+                },
+            );
+        }
+    }
+
+    /// Every node the node has a link with, in either direction
+    pub fn neighbours(&self, node_id: NodeId) -> Vec<Neighbour> {
+        let mut neighbours = BTreeMap::<NodeId, Neighbour>::new();
+
+        fn neighbour(
+            neighbours: &mut BTreeMap<NodeId, Neighbour>,
+            neighbour_id: NodeId,
+        ) -> &mut Neighbour {
+            neighbours.entry(neighbour_id).or_insert(Neighbour {
+                neighbour_id,
+                inbound: None,
+                outbound: None,
+            })
+        }
+
+        if let Some(links) = self.links.get(&node_id) {
+            for (from, reading) in links {
+                neighbour(&mut neighbours, *from).inbound = Some(*reading);
+            }
+        }
+
+        for (to, links) in &self.links {
+            if let Some(reading) = links.get(&node_id) {
+                neighbour(&mut neighbours, *to).outbound = Some(*reading);
+            }
+        }
+
+        neighbours.into_values().collect()
+    }
+
+    /// Forgets every link to or from the node, and any routes through it
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.links.remove(&node_id);
+
+        for links in self.links.values_mut() {
+            links.remove(&node_id);
+        }
+
+        self.gateway_ids.remove(&node_id);
+        self.next_hops.remove(&node_id);
+
+        for next_hops in self.next_hops.values_mut() {
+            next_hops.retain(|next_hop| *next_hop != node_id);
+        }
+    }
+
+    /// The gateway at the end of the node's best route, or the node itself if it's a gateway.
+    /// `None` if no route from the node is known (e.g. routes haven't been updated since it
+    /// joined).
+    pub fn nearest_gateway(&self, node_id: NodeId) -> Option<NodeId> {
+        self.best_route(node_id)?.last().copied()
+    }
+
+    /// Every node on the node's best route to a gateway, starting with the node and ending with the
+    /// gateway
+    pub fn best_route(&self, node_id: NodeId) -> Option<Vec<NodeId>> {
+        let mut route = vec![node_id];
+        let mut visited = BTreeSet::new();
+
+        while !self.gateway_ids.contains(route.last()?) {
+            let current = *route.last()?;
+
+            // next hops can briefly contain loops while routes are being replaced
+            if !visited.insert(current) {
+                return None;
+            }
+
+            route.push(*self.next_hops.get(&current)?.first()?);
+        }
+
+        Some(route)
+    }
+
+    pub fn set_routes(
+        &mut self,
+        gateway_ids: Vec<NodeId>,
+        next_hops: HashMap<NodeId, Vec<NodeId>>,
+    ) {
+        self.gateway_ids = gateway_ids.into_iter().collect();
+        self.next_hops = next_hops;
+    }
+}
+
+#[derive(Serialize)]
+pub struct Neighbour {
+    neighbour_id: NodeId,
+    /// how well the node hears the neighbour
+    inbound: Option<LinkReading>,
+    /// how well the neighbour hears the node
+    outbound: Option<LinkReading>,
+}
+
+/// /nodes/{id}/neighbors
+pub async fn get_neighbours(
+    State(state): State<AppState>,
+    Path(node_id): Path<NodeId>,
+) -> Json<Vec<Neighbour>> {
+    Json(state.topology.lock().await.neighbours(node_id))
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use log::info;
+use serde::Deserialize;
+
+use crate::auth::Role;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct User {
+    pub username: String,
+    /// argon2 hash in PHC string format, as printed by `api-server hash-password`
+    pub password_hash: String,
+    pub role: Role,
+}
+
+/// The users who can log in, loaded from `USERS_FILE`
+#[derive(Default)]
+pub struct UserStore {
+    users: HashMap<String, User>,
+}
+
+impl UserStore {
+    /// Reads a JSON array of users from the file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read(path)
+            .map_err(|error| format!("Failed to read users file {}: {:?}", path, error))?;
+
+        let users = serde_json::from_slice::<Vec<User>>(&json)
+            .map_err(|error| format!("Failed to parse users file {}: {}", path, error))?;
+
+        for user in &users {
+            PasswordHash::new(&user.password_hash).map_err(|error| {
+                format!("Invalid password hash for {}: {}", user.username, error)
+            })?;
+        }
+
+        info!("Loaded {} users from {}", users.len(), path);
+
+        Ok(Self {
+            users: users
+                .into_iter()
+                .map(|user| (user.username.clone(), user))
+                .collect(),
+        })
+    }
+
+    pub fn get(&self, username: &str) -> Option<&User> {
+        self.users.get(username)
+    }
+}
+
+/// Checks a password against an argon2 hash. This is deliberately slow, so it should be run with
+/// `spawn_blocking`.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|password_hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok()
+    })
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .map(|password_hash| password_hash.to_string())
+        .map_err(|error| format!("Failed to hash password: {}", error))
+}
@@ -1,15 +1,33 @@
-use bytes::BytesMut;
-use std::time::Duration;
+use bytes::{Bytes, BytesMut};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{FromRequest, Request, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hmac::{Hmac, Mac};
 use log::{debug, error};
 use prost::Message;
 use serde::ser::{SerializeSeq, Serializer};
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
 use tokio::sync::broadcast::error::RecvError;
 
-use crate::proto::meshtastic::CrisislabMessage;
-use crate::MeshInterface;
+use crate::config::CONFIG;
+use crate::persistence;
+use crate::proto::meshtastic::{CrisislabMessage, SignedCrisislabMessage};
+use crate::{AppState, MeshInterface};
+
+/// Current time as seconds since the unix epoch, matching the timestamps nodes put in telemetry
+pub fn unix_time_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
 
 pub struct RingBuffer<T> {
     items: Vec<T>,
@@ -26,15 +44,53 @@ impl<T> RingBuffer<T> {
         }
     }
 
-    pub fn write(&mut self, item: T) {
-        if self.items.len() < self.capacity {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Removes the items that `keep` returns `false` for, keeping the rest in order
+    pub fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        // put the oldest item first so that the buffer can be refilled from the start
+        self.items.rotate_left(self.next_insertion_index);
+        self.items.retain(keep);
+        self.next_insertion_index = self.items.len() % self.capacity.max(1);
+    }
+
+    /// Removes and returns the oldest items for as long as `take` returns `true` for them
+    pub fn take_oldest_while(&mut self, mut take: impl FnMut(&T) -> bool) -> Vec<T> {
+        let count = self.into_iter().take_while(|item| take(item)).count();
+
+        if count == 0 {
+            return Vec::new();
+        }
+
+        self.items.rotate_left(self.next_insertion_index);
+        let taken = self.items.drain(..count).collect();
+        self.next_insertion_index = self.items.len() % self.capacity.max(1);
+
+        taken
+    }
+
+    /// Adds an item, returning the oldest item if it had to be overwritten to make room
+    pub fn write(&mut self, item: T) -> Option<T> {
+        let evicted = if self.items.len() < self.capacity {
             self.items.push(item);
+            None
         } else {
-            self.items[self.next_insertion_index] = item;
-        }
+            Some(std::mem::replace(
+                &mut self.items[self.next_insertion_index],
+                item,
+            ))
+        };
 
         self.next_insertion_index += 1;
         self.next_insertion_index %= self.capacity;
+
+        evicted
     }
 }
 
@@ -78,6 +134,9 @@ pub enum FallibleJsonResponse<T: Serialize> {
 #[derive(Serialize)]
 struct SingletonError {
     error: String,
+    /// path to the field in the request body which was at fault, e.g. `rules[0].threshold`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
 }
 
 impl<T: Serialize> IntoResponse for FallibleJsonResponse<T> {
@@ -85,7 +144,12 @@ impl<T: Serialize> IntoResponse for FallibleJsonResponse<T> {
         match self {
             FallibleJsonResponse::Ok(data) => (StatusCode::OK, Json(data)).into_response(),
             FallibleJsonResponse::Err(status_code, message) => {
-                (status_code, Json(SingletonError { error: message })).into_response()
+                let error = SingletonError {
+                    error: message,
+                    field: None,
+                };
+
+                (status_code, Json(error)).into_response()
             }
         }
     }
@@ -101,6 +165,106 @@ impl<T: Serialize> FallibleJsonResponse<T> {
     }
 }
 
+/// A request body which couldn't be used, reported in the same shape as `FallibleJsonResponse`'s
+/// errors rather than as axum's plain text rejections
+pub struct BodyRejection {
+    status_code: StatusCode,
+    error: SingletonError,
+}
+
+impl BodyRejection {
+    fn unprocessable(message: String, field: Option<String>) -> Self {
+        Self {
+            status_code: StatusCode::UNPROCESSABLE_ENTITY,
+            error: SingletonError {
+                error: message,
+                field,
+            },
+        }
+    }
+}
+
+impl IntoResponse for BodyRejection {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code, Json(self.error)).into_response()
+    }
+}
+
+/// Drop-in replacement for axum's `Json` extractor for request bodies, which rejects malformed
+/// bodies with 422 Unprocessable Entity and says which field was at fault
+pub struct JsonBody<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for JsonBody<T> {
+    type Rejection = BodyRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|mime_type| mime_type.trim().to_ascii_lowercase())
+            .is_some_and(|mime_type| {
+                mime_type == "application/json" || mime_type.ends_with("+json")
+            });
+
+        if !is_json {
+            return Err(BodyRejection::unprocessable(
+                "Expected a JSON body with a Content-Type of application/json".to_owned(),
+                None,
+            ));
+        }
+
+        // e.g. the body being too big, which keeps its own status code
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(|rejection| BodyRejection {
+                status_code: rejection.status(),
+                error: SingletonError {
+                    error: rejection.body_text(),
+                    field: None,
+                },
+            })?;
+
+        let mut deserializer = serde_json::Deserializer::from_slice(&body);
+
+        let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+            let path = error.path().to_string();
+
+            BodyRejection::unprocessable(
+                error.inner().to_string(),
+                // the path is just "." when the problem is with the whole body
+                (path != ".").then_some(path),
+            )
+        })?;
+
+        deserializer
+            .end()
+            .map_err(|error| BodyRejection::unprocessable(error.to_string(), None))?;
+
+        Ok(JsonBody(value))
+    }
+}
+
+/// Middleware which gives up on requests taking longer than `REQUEST_TIMEOUT_SECONDS`, so that a
+/// slow upload or a stuck handler can't hold on to a connection forever. Websockets aren't
+/// affected once they've been upgraded.
+pub async fn time_out_requests(request: Request, next: Next) -> Response {
+    let timeout_duration = Duration::from_secs(CONFIG.request_timeout_seconds);
+
+    match tokio::time::timeout(timeout_duration, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => FallibleJsonResponse::<()>::Err(
+            StatusCode::REQUEST_TIMEOUT,
+            format!(
+                "Request took longer than {} seconds",
+                timeout_duration.as_secs()
+            ),
+        )
+        .into_response(),
+    }
+}
+
 pub enum StringOrEmptyResponse {
     Ok,
     Err(StatusCode, String),
@@ -172,24 +336,41 @@ pub async fn await_mesh_response<T>(
     )))
 }
 
-/// Encodes a given CrisislabMessage and sends it to the Tokio task responsible for publishing
-/// messages to the MQTT broker. May return an `Err(String)` if encoding or sending fails.
-pub async fn send_command_protobuf(
-    message: CrisislabMessage,
-    mesh_interface: &MeshInterface,
-) -> Result<(), String> {
-    // buffer for the encoded protobuf
-    let mut buffer = BytesMut::with_capacity(message.encoded_len());
+/// Wraps an encoded CrisislabMessage in an envelope with its HMAC
+fn sign_command(
+    encoded_message: Vec<u8>,
+    key: &[u8],
+    counter: u64,
+    timestamp: u64,
+) -> SignedCrisislabMessage {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    mac.update(&timestamp.to_be_bytes());
+    mac.update(&encoded_message);
 
-    if let Err(error) = message.encode(&mut buffer) {
-        return Err(format!("Failed to encode command as protobuf: {:?}", error));
+    SignedCrisislabMessage {
+        message: encoded_message,
+        key_id: CONFIG.mesh_signing_key_id,
+        signature: mac.finalize().into_bytes().to_vec(),
+        counter,
+        timestamp,
     }
+}
+
+/// The counter sent with signed commands, which gateways use to reject replayed commands
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+pub struct CommandCounter {
+    /// the counter of the last signed command sent
+    pub last_counter: u64,
+    /// seconds since unix epoch that the last signed command was sent
+    pub last_sent_at: Option<u64>,
+}
 
+async fn publish(payload: Bytes, mesh_interface: &MeshInterface) -> Result<(), String> {
     if let Err(error) = mesh_interface
         // the Tokio channel sender which goes to the publisher task
         .clone_sender_to_publisher()
-        // that channel expects a non-mutable Bytes buffer hence .freeze()
-        .send(buffer.freeze())
+        .send(payload)
         .await
     {
         Err(format!(
@@ -201,3 +382,45 @@ pub async fn send_command_protobuf(
         Ok(())
     }
 }
+
+/// Encodes a given CrisislabMessage (signing it if `MESH_SIGNING_KEY` is set) and sends it to the
+/// Tokio task responsible for publishing messages to the MQTT broker. May return an `Err(String)`
+/// if encoding or sending fails.
+pub async fn send_command_protobuf(
+    message: CrisislabMessage,
+    mesh_interface: &MeshInterface,
+) -> Result<(), String> {
+    // buffer for the encoded protobuf
+    let mut buffer = BytesMut::with_capacity(message.encoded_len());
+
+    if let Err(error) = message.encode(&mut buffer) {
+        return Err(format!("Failed to encode command as protobuf: {:?}", error));
+    }
+
+    let Some(key) = &CONFIG.mesh_signing_key else {
+        // the publisher channel expects a non-mutable Bytes buffer hence .freeze()
+        return publish(buffer.freeze(), mesh_interface).await;
+    };
+
+    // held until the command has been queued so that commands are published in counter order
+    let mut command_counter = mesh_interface.command_counter().lock().await;
+
+    let now = unix_time_seconds();
+    let next_command_counter = CommandCounter {
+        last_counter: command_counter.last_counter + 1,
+        last_sent_at: Some(now),
+    };
+
+    // saved before sending so that the counter can't be reused if the server crashes
+    persistence::save_command_counter(&next_command_counter).await?;
+    *command_counter = next_command_counter;
+
+    let signed_command = sign_command(buffer.to_vec(), key, next_command_counter.last_counter, now);
+
+    publish(signed_command.encode_to_vec().into(), mesh_interface).await
+}
+
+/// /info/command-counter
+pub async fn get_command_counter(State(state): State<AppState>) -> Json<CommandCounter> {
+    Json(*state.mesh_interface.command_counter().lock().await)
+}
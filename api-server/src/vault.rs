@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use log::info;
+use once_cell::sync::OnceCell;
+use serde_json::{Map, Value};
+
+use crate::config::read_secret_file;
+
+/// Secrets fetched from Vault on startup, by environment variable name
+static VAULT_SECRETS: OnceCell<HashMap<String, String>> = OnceCell::new();
+
+/// Fetches the secret at `VAULT_SECRET_PATH` (e.g. `secret/data/meshtastic-server`) from the
+/// Vault server at `VAULT_ADDR`, if they're set. Its keys are the names of the environment
+/// variables they're used in place of. This has to happen before the config is loaded.
+pub async fn fetch_secrets() -> Result<(), String> {
+    let (Ok(address), Ok(path)) = (
+        std::env::var("VAULT_ADDR"),
+        std::env::var("VAULT_SECRET_PATH"),
+    ) else {
+        return Ok(());
+    };
+
+    let token = match (
+        std::env::var("VAULT_TOKEN"),
+        std::env::var("VAULT_TOKEN_FILE"),
+    ) {
+        (Ok(token), _) => token,
+        (_, Ok(token_path)) => read_secret_file("VAULT_TOKEN_FILE", &token_path)?,
+        _ => return Err("VAULT_TOKEN or VAULT_TOKEN_FILE must be set to use Vault".to_owned()),
+    };
+
+    let url = format!(
+        "{}/v1/{}",
+        address.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header("X-Vault-Token", token)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|error| format!("Failed to fetch secrets from Vault: {}", error))?
+        .json::<Value>()
+        .await
+        .map_err(|error| format!("Failed to parse secrets from Vault: {}", error))?;
+
+    // version 2 of the key/value engine nests the secret in another `data` along with its
+    // metadata, whereas version 1 doesn't
+    let data = &response["data"];
+    let secret = match data.get("metadata") {
+        Some(_) => &data["data"],
+        None => data,
+    };
+
+    let secrets = secret
+        .as_object()
+        .unwrap_or(&Map::new())
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_owned())))
+        .collect::<HashMap<_, _>>();
+
+    info!("Fetched {} secrets from Vault", secrets.len());
+
+    VAULT_SECRETS.set(secrets).ok();
+
+    Ok(())
+}
+
+pub fn get_secret(name: &str) -> Option<String> {
+    VAULT_SECRETS.get()?.get(name).cloned()
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    auth::AuthedUser,
+    config::CONFIG,
+    persistence,
+    utils::{unix_time_seconds, FallibleJsonResponse, JsonBody, StringOrEmptyResponse},
+    AppState,
+};
+
+/// names the source a webhook is from, so that the server knows which secret to check it with
+const SOURCE_HEADER: &str = "x-webhook-source";
+/// `sha256=<hex HMAC-SHA256 of the body>`, like GitHub's webhooks
+const SIGNATURE_HEADER: &str = "x-signature";
+/// when the webhook was sent (seconds since unix epoch). The signature is of `<timestamp>.<body>`,
+/// so that an old request can't be replayed.
+const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct WebhookSource {
+    /// e.g. the name of the EEW provider
+    pub name: String,
+    pub created_by: String,
+    /// seconds since unix epoch
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoredWebhookSource {
+    #[serde(flatten)]
+    source: WebhookSource,
+    /// the secret has to be kept (rather than a hash) to check signatures with
+    secret: String,
+}
+
+/// External systems which can post to the `/integrations/*` routes, each with the secret their
+/// payloads must be signed with
+#[derive(Default)]
+pub struct WebhookSourceStore {
+    /// name -> source
+    sources: HashMap<String, StoredWebhookSource>,
+}
+
+impl WebhookSourceStore {
+    pub fn restore(&mut self, sources: Vec<StoredWebhookSource>) {
+        self.sources = sources
+            .into_iter()
+            .map(|stored_source| (stored_source.source.name.clone(), stored_source))
+            .collect();
+    }
+
+    pub fn stored_sources(&self) -> Vec<&StoredWebhookSource> {
+        self.sources.values().collect()
+    }
+
+    /// Whether the signature is the HMAC of the timestamp and body using the source's secret
+    fn verify(&self, name: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+        let Some(stored_source) = self.sources.get(name) else {
+            return false;
+        };
+
+        let Some(signature) = signature
+            .strip_prefix("sha256=")
+            .and_then(|signature| hex::decode(signature).ok())
+        else {
+            return false;
+        };
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(stored_source.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+
+        // checked in constant time so that the signature can't be worked out byte by byte
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// The source a webhook was verified to be from, in the request's extensions
+#[derive(Clone, Debug)]
+pub struct VerifiedWebhookSource(pub String);
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Middleware for the `/integrations/*` routes, which rejects requests unless they're signed with
+/// the secret of the source named in their `X-Webhook-Source` header, along with a recent
+/// `X-Webhook-Timestamp`
+pub async fn verify_webhook_signature(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let (Some(source), Some(signature), Some(timestamp)) = (
+        header(&parts.headers, SOURCE_HEADER).map(str::to_owned),
+        header(&parts.headers, SIGNATURE_HEADER).map(str::to_owned),
+        header(&parts.headers, TIMESTAMP_HEADER).map(str::to_owned),
+    ) else {
+        warn!("Rejected unsigned webhook to {}", parts.uri);
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::UNAUTHORIZED,
+            "Missing X-Webhook-Source, X-Signature or X-Webhook-Timestamp header".to_owned(),
+        )
+        .into_response();
+    };
+
+    // the signature is of the raw body, so it has to be read before anything parses it
+    let body = match axum::body::to_bytes(body, CONFIG.max_request_body_bytes).await {
+        Ok(body) => body,
+        Err(error) => {
+            return FallibleJsonResponse::<()>::Err(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Failed to read body: {}", error),
+            )
+            .into_response();
+        }
+    };
+
+    if !state
+        .webhook_sources
+        .lock()
+        .await
+        .verify(&source, &timestamp, &body, &signature)
+    {
+        warn!(
+            "Rejected webhook to {} claiming to be from {} with an invalid signature",
+            parts.uri, source
+        );
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::UNAUTHORIZED,
+            "Unknown source or invalid signature".to_owned(),
+        )
+        .into_response();
+    }
+
+    // this also gives a little leeway for the sender's clock being ahead
+    let is_recent = timestamp.parse::<u64>().is_ok_and(|sent_at| {
+        unix_time_seconds().abs_diff(sent_at) <= CONFIG.webhook_max_age_seconds
+    });
+
+    if !is_recent {
+        warn!(
+            "Rejected webhook to {} from {} with an old or invalid timestamp: {:?}",
+            parts.uri, source, timestamp
+        );
+
+        return FallibleJsonResponse::<()>::Err(
+            StatusCode::UNAUTHORIZED,
+            format!(
+                "X-Webhook-Timestamp must be within {} seconds of now",
+                CONFIG.webhook_max_age_seconds
+            ),
+        )
+        .into_response();
+    }
+
+    parts.extensions.insert(VerifiedWebhookSource(source));
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[derive(Serialize)]
+pub struct PingResponse {
+    source: String,
+}
+
+/// /integrations/ping
+pub async fn ping(
+    Extension(VerifiedWebhookSource(source)): Extension<VerifiedWebhookSource>,
+) -> Json<PingResponse> {
+    Json(PingResponse { source })
+}
+
+#[derive(Deserialize)]
+pub struct WebhookSourceBody {
+    name: String,
+    /// generated if it isn't given
+    secret: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NewWebhookSource {
+    #[serde(flatten)]
+    source: WebhookSource,
+    secret: String,
+}
+
+/// /admin/webhook-sources (POST)
+pub async fn set_webhook_source(
+    State(state): State<AppState>,
+    user: AuthedUser,
+    JsonBody(body): JsonBody<WebhookSourceBody>,
+) -> FallibleJsonResponse<NewWebhookSource> {
+    if body.name.is_empty() {
+        return FallibleJsonResponse::Err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "name can't be empty".to_owned(),
+        );
+    }
+
+    let secret = body
+        .secret
+        .unwrap_or_else(|| hex::encode(rand::random::<[u8; 32]>()));
+
+    let source = WebhookSource {
+        name: body.name,
+        created_by: user.name.clone(),
+        created_at: unix_time_seconds(),
+    };
+
+    let previous = state.webhook_sources.lock().await.sources.insert(
+        source.name.clone(),
+        StoredWebhookSource {
+            source: source.clone(),
+            secret: secret.clone(),
+        },
+    );
+    let replaced = previous.is_some();
+
+    info!(
+        "{} {} webhook source {}",
+        user,
+        if replaced {
+            "rotated the secret of"
+        } else {
+            "added"
+        },
+        source.name
+    );
+
+    // a secret which wasn't saved would stop working whenever the server restarts
+    if let Err(error_message) = persistence::save_webhook_sources(&state).await {
+        let mut webhook_sources = state.webhook_sources.lock().await;
+
+        match previous {
+            Some(previous) => webhook_sources.sources.insert(source.name, previous),
+            None => webhook_sources.sources.remove(&source.name),
+        };
+
+        return FallibleJsonResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    FallibleJsonResponse::Ok(NewWebhookSource { source, secret })
+}
+
+/// /admin/webhook-sources (GET)
+pub async fn get_webhook_sources(State(state): State<AppState>) -> Json<Vec<WebhookSource>> {
+    Json(
+        state
+            .webhook_sources
+            .lock()
+            .await
+            .sources
+            .values()
+            .map(|stored_source| stored_source.source.clone())
+            .collect(),
+    )
+}
+
+/// /admin/webhook-sources/{name}
+pub async fn remove_webhook_source(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    user: AuthedUser,
+) -> StringOrEmptyResponse {
+    if state
+        .webhook_sources
+        .lock()
+        .await
+        .sources
+        .remove(&name)
+        .is_none()
+    {
+        return StringOrEmptyResponse::Err(
+            StatusCode::NOT_FOUND,
+            format!("No webhook source named {}", name),
+        );
+    }
+
+    info!("{} removed webhook source {}", user, name);
+
+    if let Err(error_message) = persistence::save_webhook_sources(&state).await {
+        return StringOrEmptyResponse::Err(StatusCode::INTERNAL_SERVER_ERROR, error_message).log();
+    }
+
+    StringOrEmptyResponse::Ok
+}